@@ -1,10 +1,12 @@
 use ::tracing::info;
 use coex::error::ExecutorError;
 use coex::initialization::initialize_coex;
-use coex::{cancellation, check_in, state};
-use coex::{config, events, execution, refresh, traces};
+use coex::middleware::FailoverClient;
+use coex::pool::uniswap_v2::PAIR_FEE_REFRESH_INTERVAL;
+use coex::{cancellation, check_in, mempool, state};
+use coex::{config, events, execution, refresh, routing, traces};
 use ethers::prelude::NonceManagerMiddleware;
-use ethers::providers::{Http, Provider, Ws};
+use ethers::providers::{Provider, Ws};
 use std::collections::HashSet;
 use std::error::Error;
 use std::sync::Arc;
@@ -18,7 +20,7 @@ async fn main() -> Result<(), Box<dyn Error>> {
     traces::init_tracing();
 
     let (configuration, state, pending_transactions_sender, stream_provider_endpoint, middleware) =
-        initialize_coex::<NonceManagerMiddleware<ethers::providers::Provider<Http>>>()
+        initialize_coex::<NonceManagerMiddleware<Provider<FailoverClient>>>()
             .await
             .unwrap();
 
@@ -73,89 +75,189 @@ async fn run_loop<M: 'static + Middleware>(
     //Initialize a new stream provider and create a new stream of block headers
     let stream_provider = Provider::<Ws>::connect(stream_provider_endpoint.clone()).await?;
     let mut block_stream = stream_provider.subscribe_blocks().await?;
-    let block_filter = events::initialize_block_filter(&configuration.dexes);
+    let block_filter = events::initialize_block_filter(
+        &configuration.dexes,
+        &configuration.v2_pair_fees,
+        &configuration.algebra_dexes,
+        &configuration.solidly_dexes,
+        &configuration.curve_dexes,
+        &configuration.balancer_dexes,
+    );
 
     //Get a mapping of event signature to event for quick lookup
-    let event_sig_to_belt_event = events::get_event_signature_to_belt_event();
+    let event_sig_to_belt_event =
+        events::get_event_signature_to_belt_event(&configuration.v2_pair_fees);
+
+    //Subscribe to pending transactions to pre-position executions ahead of swaps that move orders to execution price.
+    //Pending transactions are fetched and decoded in a separate task so that block handling is not held up
+    let mut pending_transaction_receiver = if configuration.mempool_watching {
+        Some(mempool::spawn_pending_transaction_decoder(
+            stream_provider_endpoint.clone(),
+            configuration.wallet_address,
+            middleware.clone(),
+        ))
+    } else {
+        None
+    };
+    let mut mempool_watcher = mempool::MempoolWatcher::new();
+    let mut last_pair_fee_refresh_block = last_synced_block;
+
+    //Gas price that pending transactions are simulated with, updated once per block
+    let mut gas_price = middleware
+        .get_gas_price()
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
 
     tracing::info!("Listening for execution conditions...");
     //Listen for new blocks to be published. On every block, check for sync logs, update weights and run bellman ford
-    while let Some(block) = block_stream.next().await {
-        let block_number = block.number.expect("Could not unwrap block number");
+    loop {
+        tokio::select! {
+            block = block_stream.next() => {
+                let block = match block {
+                    Some(block) => block,
+                    None => break,
+                };
+                let block_number = block.number.expect("Could not unwrap block number");
 
-        if last_synced_block < block_number {
-            let current_block_number = middleware
-                .get_block_number()
-                .await
-                .map_err(ExecutorError::MiddlewareError)?;
-
-            tracing::info!("Checking block {:?}", current_block_number);
-
-            //Sort the events into order events and pool events
-            let (order_events, pool_events) = events::sort_events(
-                &middleware
-                    .get_logs(
-                        &block_filter
-                            .clone()
-                            .from_block(last_synced_block)
-                            .to_block(current_block_number),
+                if last_synced_block < block_number {
+                    let current_block_number = middleware
+                        .get_block_number()
+                        .await
+                        .map_err(ExecutorError::MiddlewareError)?;
+
+                    tracing::info!("Checking block {:?}", current_block_number);
+
+                    //Sort the events into order events and pool events
+                    let (order_events, pool_events) = events::sort_events(
+                        &middleware
+                            .get_logs(
+                                &block_filter
+                                    .clone()
+                                    .from_block(last_synced_block)
+                                    .to_block(current_block_number),
+                            )
+                            .await
+                            .map_err(ExecutorError::MiddlewareError)?,
+                        &event_sig_to_belt_event,
+                    );
+
+                    last_synced_block = current_block_number;
+
+                    //Pending transactions from previous blocks have either been included or dropped
+                    mempool_watcher.clear_handled_transactions();
+                    if configuration.mempool_watching {
+                        gas_price = middleware
+                            .get_gas_price()
+                            .await
+                            .map_err(ExecutorError::MiddlewareError)?;
+                    }
+
+                    //Handle order updates
+                    let mut affected_markets = state
+                        .handle_order_updates(
+                            order_events,
+                            configuration.sandbox_limit_order_book,
+                            configuration.limit_order_book,
+                            configuration.sandbox_limit_order_router,
+                            configuration.executor_address,
+                            configuration.weth_address,
+                            &configuration.connectors,
+                            &configuration.dexes,
+                            &configuration.v2_pair_fees,
+                            &configuration.algebra_dexes,
+                            &configuration.solidly_dexes,
+                            &configuration.curve_dexes,
+                            &configuration.balancer_dexes,
+                            middleware.clone(),
+                        )
+                        .await?;
+
+                    //Update markets
+                    affected_markets.extend(state.handle_market_updates(&pool_events));
+
+                    //Refresh the fees of UniswapV2 pairs on forks with a fee for each pair that do not log fee changes
+                    if current_block_number
+                        >= last_pair_fee_refresh_block + PAIR_FEE_REFRESH_INTERVAL
+                    {
+                        affected_markets
+                            .extend(state.refresh_uniswap_v2_fees(middleware.clone()).await);
+                        last_pair_fee_refresh_block = current_block_number;
+                    }
+
+                    //Fetch the tick data that routing needs for the updated markets, so that orders are simulated without any RPC calls
+                    routing::prefetch::prefetch_markets(
+                        &affected_markets,
+                        &mut state.markets,
+                        middleware.clone(),
                     )
-                    .await
-                    .map_err(ExecutorError::MiddlewareError)?,
-                &event_sig_to_belt_event,
-            );
-
-            last_synced_block = current_block_number;
-
-            //Handle order updates
-            let mut affected_markets = state
-                .handle_order_updates(
-                    order_events,
-                    configuration.sandbox_limit_order_book,
-                    configuration.limit_order_book,
-                    configuration.weth_address,
-                    &configuration.dexes,
-                    middleware.clone(),
-                )
-                .await?;
+                    .await;
 
-            //Update markets
-            affected_markets.extend(state.handle_market_updates(&pool_events));
+                    //Check orders for cancellation
+                    if configuration.order_cancellation {
+                        cancellation::check_orders_for_cancellation(
+                            &configuration,
+                            &state,
+                            block.timestamp,
+                            pending_transactions_sender.clone(),
+                            middleware.clone(),
+                        )
+                        .await?;
+                    }
 
-            //Check orders for cancellation
-            if configuration.order_cancellation {
-                cancellation::check_orders_for_cancellation(
-                    &configuration,
-                    &state,
-                    block.timestamp,
-                    pending_transactions_sender.clone(),
-                    middleware.clone(),
-                )
-                .await?;
-            }
+                    //Check orders that are ready to be refreshed and send a refresh tx
+                    if configuration.order_refresh {
+                        refresh::check_orders_for_refresh(
+                            &configuration,
+                            &state,
+                            block.timestamp,
+                            pending_transactions_sender.clone(),
+                            middleware.clone(),
+                        )
+                        .await?;
+                    }
 
-            //Check orders that are ready to be refreshed and send a refresh tx
-            if configuration.order_refresh {
-                refresh::check_orders_for_refresh(
-                    &configuration,
-                    &state,
-                    block.timestamp,
-                    pending_transactions_sender.clone(),
-                    middleware.clone(),
-                )
-                .await?;
+                    //Evaluate orders for execution
+                    if !affected_markets.is_empty() {
+                        execution::fill_orders_at_execution_price(
+                            &configuration,
+                            &state,
+                            affected_markets,
+                            pending_transactions_sender.clone(),
+                            middleware.clone(),
+                        )
+                        .await?;
+                    }
+                }
             }
 
-            //Evaluate orders for execution
-            if !affected_markets.is_empty() {
-                execution::fill_orders_at_execution_price(
+            pending_transaction = async {
+                match pending_transaction_receiver.as_mut() {
+                    Some(pending_transaction_receiver) => pending_transaction_receiver.recv().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                let pending_transaction = match pending_transaction {
+                    Some(pending_transaction) => pending_transaction,
+                    None => {
+                        pending_transaction_receiver = None;
+                        continue;
+                    }
+                };
+
+                let tx_hash = pending_transaction.tx.hash;
+                if let Err(err) = mempool::handle_pending_transaction(
+                    pending_transaction,
+                    &mut mempool_watcher,
                     &configuration,
-                    &state,
-                    affected_markets,
+                    &mut state,
+                    gas_price,
                     pending_transactions_sender.clone(),
                     middleware.clone(),
                 )
-                .await?;
+                .await
+                {
+                    tracing::warn!("Could not handle pending transaction {:?}: {:?}", tx_hash, err);
+                }
             }
         }
     }
@@ -56,6 +56,11 @@ abigen!(
         event Sync(uint112 reserve0, uint112 reserve1)
     ]"#;
 
+    IUniswapV2PairSwapFee,
+    r#"[
+        function swapFee() external view returns (uint32)
+    ]"#;
+
     IUniswapV3Factory,
     r#"[
         function getPool(address tokenA, address tokenB, uint24 fee) external view returns (address pool)
@@ -69,7 +74,111 @@ abigen!(
         function liquidity() external view returns (uint128)
         function slot0() external view returns (uint160, int24, uint16, uint16, uint16, uint8, bool)
         function fee() external view returns (uint24)
-        event Swap(address sender, address recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)
+        function tickSpacing() external view returns (int24)
+        function tickBitmap(int16 wordPosition) external view returns (uint256)
+        function ticks(int24 tick) external view returns (uint128 liquidityGross, int128 liquidityNet, uint256 feeGrowthOutside0X128, uint256 feeGrowthOutside1X128, int56 tickCumulativeOutside, uint160 secondsPerLiquidityOutsideX128, uint32 secondsOutside, bool initialized)
+        event Swap(address indexed sender, address indexed recipient, int256 amount0, int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)
+        event Mint(address sender, address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
+        event Burn(address indexed owner, int24 indexed tickLower, int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
+        ]"#;
+
+    IAlgebraFactory,
+    r#"[
+        function poolByPair(address tokenA, address tokenB) external view returns (address pool)
+        ]"#;
+
+    IAlgebraPool,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function liquidity() external view returns (uint128)
+        function globalState() external view returns (uint160 price, int24 tick, uint16 fee, uint16 timepointIndex, uint8 communityFeeToken0, uint8 communityFeeToken1, bool unlocked)
+        function tickSpacing() external view returns (int24)
+        function tickTable(int16 wordPosition) external view returns (uint256)
+        function ticks(int24 tick) external view returns (uint128 liquidityTotal, int128 liquidityDelta)
+        event Fee(uint16 fee)
+        ]"#;
+
+    IAlgebraPoolDirectionalFee,
+    r#"[
+        function globalState() external view returns (uint160 price, int24 tick, uint16 feeZto, uint16 feeOtz, uint16 timepointIndex, uint8 communityFeeToken0, uint8 communityFeeToken1, bool unlocked)
+        event Fee(uint16 feeZto, uint16 feeOtz)
+        ]"#;
+
+    IAlgebraSwapRouter,
+    r#"[
+        struct AlgebraExactInputSingleParams { address tokenIn; address tokenOut; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; uint160 limitSqrtPrice; }
+        function exactInputSingle(AlgebraExactInputSingleParams calldata params) external payable returns (uint256 amountOut)
+        ]"#;
+
+    ISolidlyFactory,
+    r#"[
+        function getPair(address tokenA, address tokenB, bool stable) external view returns (address)
+        function getFee(bool stable) external view returns (uint256)
+        ]"#;
+
+    ISolidlyPair,
+    r#"[
+        function metadata() external view returns (uint256 dec0, uint256 dec1, uint256 r0, uint256 r1, bool st, address t0, address t1)
+        function swap(uint256 amount0Out, uint256 amount1Out, address to, bytes calldata data) external
+        event Sync(uint256 reserve0, uint256 reserve1)
+        ]"#;
+
+    ICamelotFactory,
+    r#"[
+        function getPair(address tokenA, address tokenB) external view returns (address)
+        ]"#;
+
+    ICamelotPair,
+    r#"[
+        function token0() external view returns (address)
+        function token1() external view returns (address)
+        function stableSwap() external view returns (bool)
+        function getReserves() external view returns (uint112 reserve0, uint112 reserve1, uint16 token0FeePercent, uint16 token1FeePercent)
+        event Sync(uint112 reserve0, uint112 reserve1)
+        event FeePercentUpdated(uint16 token0FeePercent, uint16 token1FeePercent)
+        event SetStableSwap(bool prevStableSwap, bool stableSwap)
+        ]"#;
+
+    ICurveRegistry,
+    r#"[
+        function find_pool_for_coins(address from, address to, uint256 i) external view returns (address)
+        function is_meta(address pool) external view returns (bool)
+        ]"#;
+
+    ICurvePool,
+    r#"[
+        function coins(uint256 i) external view returns (address)
+        function balances(uint256 i) external view returns (uint256)
+        function A() external view returns (uint256)
+        function A_precise() external view returns (uint256)
+        function fee() external view returns (uint256)
+        function admin_fee() external view returns (uint256)
+        function base_pool() external view returns (address)
+        function get_virtual_price() external view returns (uint256)
+        function get_dy(int128 i, int128 j, uint256 dx) external view returns (uint256)
+        function exchange(int128 i, int128 j, uint256 dx, uint256 min_dy) external returns (uint256)
+        event TokenExchange(address indexed buyer, int128 sold_id, uint256 tokens_sold, int128 bought_id, uint256 tokens_bought)
+        ]"#;
+
+    IBalancerVault,
+    r#"[
+        struct SingleSwap { bytes32 poolId; uint8 kind; address assetIn; address assetOut; uint256 amount; bytes userData; }
+        struct FundManagement { address sender; bool fromInternalBalance; address recipient; bool toInternalBalance; }
+        function getPoolTokens(bytes32 poolId) external view returns (address[] tokens, uint256[] balances, uint256 lastChangeBlock)
+        function swap(SingleSwap memory singleSwap, FundManagement memory funds, uint256 limit, uint256 deadline) external payable returns (uint256)
+        event TokensRegistered(bytes32 indexed poolId, address[] tokens, address[] assetManagers)
+        event Swap(bytes32 indexed poolId, address indexed tokenIn, address indexed tokenOut, uint256 amountIn, uint256 amountOut)
+        event PoolBalanceChanged(bytes32 indexed poolId, address indexed liquidityProvider, address[] tokens, int256[] deltas, uint256[] protocolFeeAmounts)
+        ]"#;
+
+    IBalancerPool,
+    r#"[
+        function getNormalizedWeights() external view returns (uint256[])
+        function getAmplificationParameter() external view returns (uint256 value, bool isUpdating, uint256 precision)
+        function getSwapFeePercentage() external view returns (uint256)
+        function getScalingFactors() external view returns (uint256[])
+        event SwapFeePercentageChanged(uint256 swapFeePercentage)
         ]"#;
 
     IUniswapV3Quoter,
@@ -77,11 +186,36 @@ abigen!(
         function quoteExactInputSingle(address tokenIn, address tokenOut,uint24 fee, uint256 amountIn, uint160 sqrtPriceLimitX96) external returns (uint256 amountOut)
         ]"#;
 
+    IUniswapV2Router,
+    r#"[
+        function factory() external view returns (address)
+        function swapExactTokensForTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactETHForTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable returns (uint256[] memory amounts)
+        function swapExactTokensForETH(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external returns (uint256[] memory amounts)
+        function swapExactTokensForTokensSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+        function swapExactETHForTokensSupportingFeeOnTransferTokens(uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external payable
+        function swapExactTokensForETHSupportingFeeOnTransferTokens(uint256 amountIn, uint256 amountOutMin, address[] calldata path, address to, uint256 deadline) external
+        ]"#;
+
+    IUniswapV3SwapRouter,
+    r#"[
+        function factory() external view returns (address)
+        struct ExactInputSingleParams { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 deadline; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }
+        function exactInputSingle(ExactInputSingleParams calldata params) external payable returns (uint256 amountOut)
+        ]"#;
+
+    IUniswapV3SwapRouter02,
+    r#"[
+        struct ExactInputSingleParams02 { address tokenIn; address tokenOut; uint24 fee; address recipient; uint256 amountIn; uint256 amountOutMinimum; uint160 sqrtPriceLimitX96; }
+        function exactInputSingle(ExactInputSingleParams02 calldata params) external payable returns (uint256 amountOut)
+        ]"#;
+
     IErc20,
     r#"[
         function balanceOf(address account) external view returns (uint256)
         function decimals() external view returns (uint8)
         function transfer(address to, uint256 amount) external returns (bool)
+        function approve(address spender, uint256 amount) external returns (bool)
 
     ]"#;
 
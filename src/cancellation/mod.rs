@@ -26,6 +26,15 @@ pub async fn check_orders_for_cancellation<M: Middleware>(
     //TODO: Right now this implementation was used to get things on its feet and functional at the very least but this is very slow
 
     for (order_id, order) in state.active_orders.iter() {
+        //Cancelling orders on tokens that failed screening wastes gas, so they are flagged instead
+        if !state.order_tokens_are_tradable(order) {
+            tracing::debug!(
+                "Skipping cancellation of order {:?} on a token that failed screening",
+                order_id
+            );
+            continue;
+        }
+
         let owner_balance = abi::IErc20::new(order.token_in(), middleware.clone())
             .balance_of(order.owner())
             .call()
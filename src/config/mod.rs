@@ -1,7 +1,7 @@
-use std::{fs::read_to_string, str::FromStr, vec};
+use std::{collections::HashMap, fs::read_to_string, str::FromStr, vec};
 
 use ethers::{
-    signers::LocalWallet,
+    signers::{LocalWallet, Signer},
     types::{BlockNumber, H160},
 };
 
@@ -10,6 +10,13 @@ use cfmms::dex::{Dex, DexVariant};
 use serde::Deserialize;
 
 use clap::Parser;
+
+use crate::{
+    order::sandbox_limit_order::{min_fill_percent_64_x_64, DEFAULT_MIN_FILL_PERCENT},
+    pool::{AlgebraDex, BalancerDex, CurveDex, SolidlyDex, SolidlyVariant, UniswapV2PairFee},
+    routing::graph::{Connectors, DEFAULT_MAX_HOPS},
+};
+
 #[derive(Parser, Default, Debug)]
 pub struct Args {
     #[clap(short, long, help = "Path to the config file for the chain")]
@@ -20,12 +27,26 @@ pub struct Args {
 pub struct Toml {
     pub chain_name: String,
     pub http_endpoint: String,
+    #[serde(default)]
+    pub fallback_http_endpoints: Vec<String>,
+    #[serde(default)]
+    pub rpc_quorum: Option<usize>,
+    #[serde(default)]
+    pub rpc_quorum_methods: Option<Vec<String>>,
     pub ws_endpoint: String,
     pub wallet_address: String,
     pub private_key: String,
     pub taxed_tokens: bool,
     pub order_cancellation: bool,
     pub order_refresh: bool,
+    #[serde(default)]
+    pub mempool_watching: bool,
+    #[serde(default)]
+    pub max_hops: Option<usize>,
+    #[serde(default)]
+    pub min_fill_percent: Option<u8>,
+    #[serde(default)]
+    pub limit_order_router_dexes: Option<Vec<String>>,
 }
 
 #[derive(Debug)]
@@ -34,11 +55,30 @@ pub struct Config {
     pub weth_address: H160,
     pub weth_decimals: u8,
     pub http_endpoint: String,
+    pub fallback_http_endpoints: Vec<String>,
+    pub rpc_quorum: Option<usize>,
+    pub rpc_quorum_methods: Vec<String>,
     pub ws_endpoint: String,
     pub limit_order_book: H160,
     pub sandbox_limit_order_book: H160,
     pub sandbox_limit_order_router: H160,
     pub dexes: Vec<Dex>,
+    //UniswapV2 forks with a fee for each pair, keyed by factory address. Pairs on other dexes, or pairs that do not expose a fee, are charged the fee of the dex
+    pub v2_pair_fees: HashMap<H160, UniswapV2PairFee>,
+    //Algebra dexes are not supported by cfmms, so they are configured separately from `dexes`
+    pub algebra_dexes: Vec<AlgebraDex>,
+    //Solidly style dexes (ie. Velodrome, Thena, Camelot V2) with stable and volatile pairs
+    pub solidly_dexes: Vec<SolidlyDex>,
+    //Curve registries and factories used to discover stableswap pools
+    pub curve_dexes: Vec<CurveDex>,
+    //Balancer V2 vaults, with the block the vault was deployed at to discover pools from
+    pub balancer_dexes: Vec<BalancerDex>,
+    //Tokens that sandbox limit orders can route through (ie. weth, usdc, usdt) and the max number of swaps in a route
+    pub connectors: Connectors,
+    //Smallest fraction of the amount in remaining that sandbox limit orders are partially filled with, as a Q64.64 fixed point number
+    pub min_fill_percent: u128,
+    //Factories of the UniswapV2 and UniswapV3 dexes that the LimitOrderRouter swaps through. Limit orders are only priced and routed through pools from these factories
+    pub limit_order_router_dexes: Vec<H160>,
     pub executor_address: H160,
     pub protocol_creation_block: BlockNumber,
     pub wallet_address: H160,
@@ -47,6 +87,7 @@ pub struct Config {
     pub taxed_tokens: bool,
     pub order_cancellation: bool,
     pub order_refresh: bool,
+    pub mempool_watching: bool,
 }
 
 impl Default for Config {
@@ -56,11 +97,22 @@ impl Default for Config {
             weth_address: H160::zero(),
             weth_decimals: 0,
             http_endpoint: Default::default(),
+            fallback_http_endpoints: vec![],
+            rpc_quorum: None,
+            rpc_quorum_methods: vec![],
             ws_endpoint: Default::default(),
             limit_order_book: H160::zero(),
             sandbox_limit_order_book: H160::zero(),
             sandbox_limit_order_router: H160::zero(),
             dexes: vec![],
+            v2_pair_fees: HashMap::new(),
+            algebra_dexes: vec![],
+            solidly_dexes: vec![],
+            curve_dexes: vec![],
+            balancer_dexes: vec![],
+            connectors: Connectors::default(),
+            min_fill_percent: min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT),
+            limit_order_router_dexes: vec![],
             executor_address: H160::zero(),
             protocol_creation_block: BlockNumber::Latest,
             wallet_address: H160::zero(),
@@ -69,6 +121,7 @@ impl Default for Config {
             taxed_tokens: false,
             order_cancellation: false,
             order_refresh: false,
+            mempool_watching: false,
         }
     }
 }
@@ -153,9 +206,45 @@ impl Config {
             .parse()
             .expect("Could not parse private key");
 
+        //Execution transactions are signed by the wallet key and sent directly to the routers, so the wallet address is the EOA that sends them.
+        //The LimitOrderRouter reverts stop loss executions that are not sent by an EOA, and transactions are simulated from the wallet address
+        if config.wallet_key.address() != config.wallet_address {
+            panic!(
+                "`wallet_address` {:?} is not the address of `private_key` {:?}",
+                config.wallet_address,
+                config.wallet_key.address()
+            );
+        }
+
         config.taxed_tokens = coex_toml.taxed_tokens;
         config.order_refresh = coex_toml.order_refresh;
         config.order_cancellation = coex_toml.order_cancellation;
+        config.mempool_watching = coex_toml.mempool_watching;
+
+        config.fallback_http_endpoints = coex_toml.fallback_http_endpoints;
+        config.rpc_quorum = coex_toml.rpc_quorum;
+        //Order state reads are covered by the order book call targets, so no method requires quorum on every call by default
+        config.rpc_quorum_methods = coex_toml.rpc_quorum_methods.unwrap_or_default();
+
+        let max_hops = coex_toml.max_hops.unwrap_or(DEFAULT_MAX_HOPS);
+
+        let min_fill_percent = coex_toml
+            .min_fill_percent
+            .unwrap_or(DEFAULT_MIN_FILL_PERCENT);
+        if min_fill_percent > 100 {
+            panic!(
+                "`min_fill_percent` must be between 0 and 100, got {}",
+                min_fill_percent
+            );
+        }
+        config.min_fill_percent = min_fill_percent_64_x_64(min_fill_percent);
+
+        let limit_order_router_dexes = coex_toml.limit_order_router_dexes.map(|dexes| {
+            dexes
+                .iter()
+                .map(|dex| H160::from_str(dex).expect("Could not parse limit order router dex"))
+                .collect::<Vec<H160>>()
+        });
 
         let chain = Chain::from_str(&coex_toml.chain_name);
         config.chain = chain;
@@ -179,6 +268,21 @@ impl Config {
                 // limit order book creation block
                 config.protocol_creation_block = BlockNumber::Number(16616601.into());
 
+                config.connectors = Connectors::new(
+                    vec![
+                        config.weth_address,
+                        // USDC
+                        H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap(),
+                        // USDT
+                        H160::from_str("0xdAC17F958D2ee523a2206206994597C13D831ec7").unwrap(),
+                        // DAI
+                        H160::from_str("0x6B175474E89094C44Da98b954EedeAC495271d0F").unwrap(),
+                        // WBTC
+                        H160::from_str("0x2260FAC5E5542a773Aa44fBCfeDf7C193bc2C599").unwrap(),
+                    ],
+                    max_hops,
+                );
+
                 config.dexes = vec![
                     // Sushiswap
                     Dex::new(
@@ -209,6 +313,25 @@ impl Config {
                         Some(300),
                     ),
                 ];
+
+                config.curve_dexes = vec![
+                    // Curve main registry
+                    CurveDex::new(
+                        H160::from_str("0x90E00ACe148ca3b23Ac1bC8C240C2a7Dd9c2d7f5").unwrap(),
+                    ),
+                    // Curve metapool factory
+                    CurveDex::new(
+                        H160::from_str("0xB9fC157394Af804a3578134A6585C0dc9cc990d4").unwrap(),
+                    ),
+                ];
+
+                config.balancer_dexes = vec![
+                    // Balancer V2
+                    BalancerDex::new(
+                        H160::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap(),
+                        12272146,
+                    ),
+                ];
             }
 
             Chain::Polygon => {
@@ -229,6 +352,23 @@ impl Config {
                     // limit order book creation block
                 config.protocol_creation_block = BlockNumber::Number(39229433.into());
 
+                config.connectors = Connectors::new(
+                    vec![
+                        config.weth_address,
+                        // USDC
+                        H160::from_str("0x2791Bca1f2de4661ED88A30C99A7a9449Aa84174").unwrap(),
+                        // USDT
+                        H160::from_str("0xc2132D05D31c914a87C6611C10748AEb04B58e8F").unwrap(),
+                        // DAI
+                        H160::from_str("0x8f3Cf7ad23Cd3CaDbD9735AFf958023239c6A063").unwrap(),
+                        // WBTC
+                        H160::from_str("0x1BFD67037B42Cf73acF2047067bd4F2C47D9BfD6").unwrap(),
+                        // WETH
+                        H160::from_str("0x7ceB23fD6bC0adD59E62ac25578270cFf1b9f619").unwrap(),
+                    ],
+                    max_hops,
+                );
+
                 config.dexes = vec![
                     // Sushiswap
                     Dex::new(
@@ -266,6 +406,30 @@ impl Config {
                         Some(300),
                     ),
                 ];
+
+                config.algebra_dexes = vec![
+                    // Quickswap V3
+                    AlgebraDex::new(
+                        H160::from_str("0x411b0fAcC3489691f28ad58c47006AF5E3Ab3A28").unwrap(),
+                        H160::from_str("0xf5b509bB0909a69B1c207E495f687a596C168E12").unwrap(),
+                        false,
+                    ),
+                ];
+
+                config.curve_dexes = vec![
+                    // Curve main registry
+                    CurveDex::new(
+                        H160::from_str("0x094d12e5b541784701FD8d65F11fc0598FBC6332").unwrap(),
+                    ),
+                ];
+
+                config.balancer_dexes = vec![
+                    // Balancer V2
+                    BalancerDex::new(
+                        H160::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap(),
+                        15832990,
+                    ),
+                ];
             }
 
             Chain::Arbitrum => {
@@ -286,6 +450,23 @@ impl Config {
                     // limit order book creation block
                 config.protocol_creation_block = BlockNumber::Number(71267.into());
 
+                config.connectors = Connectors::new(
+                    vec![
+                        config.weth_address,
+                        // USDC
+                        H160::from_str("0xaf88d065e77c8cC2239327C5EDb3A432268e5831").unwrap(),
+                        // USDC.e
+                        H160::from_str("0xFF970A61A04b1cA14834A43f5dE4533eBDDB5CC8").unwrap(),
+                        // USDT
+                        H160::from_str("0xFd086bC7CD5C481DCC9C85ebE478A1C0b69FCbb9").unwrap(),
+                        // DAI
+                        H160::from_str("0xDA10009cBd5D07dd0CeCc66161FC93D7c9000da1").unwrap(),
+                        // WBTC
+                        H160::from_str("0x2f2a2543B76A4166549F7aaB2e75Bef0aefC5B0f").unwrap(),
+                    ],
+                    max_hops,
+                );
+
                 config.dexes = vec![
                     // Sushiswap
                     Dex::new(
@@ -301,15 +482,39 @@ impl Config {
                         35,
                         None,
                     ),
+                ];
+
+                config.algebra_dexes = vec![
+                    // Camelot V3
+                    AlgebraDex::new(
+                        H160::from_str("0x1a3c9B1d2F0529D97f2afC5136Cc23e58f1FD35B").unwrap(),
+                        H160::from_str("0x1F721E2E82F6676FCE4eA07A5958cF098D339e18").unwrap(),
+                        true,
+                    ),
+                ];
+
+                config.solidly_dexes = vec![
                     // Camelot
-                    Dex::new(
+                    SolidlyDex::new(
                         H160::from_str("0x6EcCab422D763aC031210895C81787E87B43A652").unwrap(),
-                        DexVariant::UniswapV2,
-                        20702,
-                        Some(300),
+                        SolidlyVariant::Camelot,
+                    ),
+                ];
+
+                config.curve_dexes = vec![
+                    // Curve main registry
+                    CurveDex::new(
+                        H160::from_str("0x445FE580eF8d70FF569aB36e80c647af338db351").unwrap(),
                     ),
                 ];
 
+                config.balancer_dexes = vec![
+                    // Balancer V2
+                    BalancerDex::new(
+                        H160::from_str("0xBA12222222228d8Ba445958a75a0704d566BF2C8").unwrap(),
+                        222832,
+                    ),
+                ];
             }
             Chain::Bsc => {
                 config.http_endpoint = coex_toml.http_endpoint;
@@ -328,6 +533,23 @@ impl Config {
                     H160::from_str("0x902c9e3202F5191db0B6edF5c038F4941Dfd6641").unwrap();
                 config.protocol_creation_block = BlockNumber::Number(25617424.into());
 
+                config.connectors = Connectors::new(
+                    vec![
+                        config.weth_address,
+                        // USDT
+                        H160::from_str("0x55d398326f99059fF775485246999027B3197955").unwrap(),
+                        // USDC
+                        H160::from_str("0x8AC76a51cc950d9822D68b83fE1Ad97B32Cd580d").unwrap(),
+                        // BUSD
+                        H160::from_str("0xe9e7CEA3DedcA5984780Bafc599bD69ADd087D56").unwrap(),
+                        // BTCB
+                        H160::from_str("0x7130d2A12B9BCbFAe4f2634d864A1Ee1Ce3Ead9c").unwrap(),
+                        // ETH
+                        H160::from_str("0x2170Ed0880ac9A755fd29B2688956BD959F933F8").unwrap(),
+                    ],
+                    max_hops,
+                );
+
                 config.dexes = vec![
                     // Pancakeswap v2
                     Dex::new(
@@ -379,6 +601,20 @@ impl Config {
                         Some(300),
                     ),
                 ];
+
+                config.v2_pair_fees = HashMap::from([(
+                    // Biswap, swapFee() is out of 1000. No fee event is configured, so pair fees are polled
+                    H160::from_str("0x858E3312ed3A876947EA49d572A7C42DE08af7EE").unwrap(),
+                    UniswapV2PairFee::new(1000, None),
+                )]);
+
+                config.solidly_dexes = vec![
+                    // Thena
+                    SolidlyDex::new(
+                        H160::from_str("0xAFD89d21BdB66d00817d4153E055830B1c2B3970").unwrap(),
+                        SolidlyVariant::Velodrome,
+                    ),
+                ];
             }
             Chain::Cronos => {
                 todo!("Cronos configuration not yet implemented");
@@ -387,6 +623,35 @@ impl Config {
                 todo!("Optimism configuration not yet implemented");
             }
         }
+
+        //Unless configured, the LimitOrderRouter is assumed to swap through every UniswapV2 and UniswapV3 dex of the chain
+        config.limit_order_router_dexes = limit_order_router_dexes.unwrap_or_else(|| {
+            config
+                .dexes
+                .iter()
+                .map(|dex| dex.factory_address())
+                .collect()
+        });
+
         config
     }
+
+    //Order state is read through eth_calls to the order books, which require quorum when it is configured.
+    //Other eth_calls (ie. screening, prefetching and factory lookups) fail over without quorum, since nodes at different block heights would not agree
+    pub fn rpc_quorum_call_targets(&self) -> Vec<H160> {
+        vec![self.limit_order_book, self.sandbox_limit_order_book]
+    }
+
+    //Returns the primary http endpoint followed by the fallback endpoints
+    pub fn http_endpoints(&self) -> Vec<String> {
+        let mut http_endpoints = vec![self.http_endpoint.clone()];
+
+        for endpoint in self.fallback_http_endpoints.iter() {
+            if !http_endpoints.contains(endpoint) {
+                http_endpoints.push(endpoint.clone());
+            }
+        }
+
+        http_endpoints
+    }
 }
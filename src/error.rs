@@ -7,6 +7,8 @@ use ethers::{
 use thiserror::Error;
 use tokio::task::JoinError;
 
+use crate::{pool::uniswap_v3::UniswapV3SimulationError, routing::RoutingError};
+
 #[derive(Error, Debug)]
 pub enum ExecutorError<M>
 where
@@ -36,4 +38,10 @@ where
     MarketDoesNotExistForPair(H160, H160),
     #[error("Eth ABI error")]
     EthABIError(#[from] ethers::abi::Error),
+    #[error("Concentrated liquidity simulation error")]
+    SimulationError(#[from] UniswapV3SimulationError),
+    #[error("Could not simulate swap through pool")]
+    PoolSimulationError(H160),
+    #[error("Routing error")]
+    RoutingError(#[from] RoutingError),
 }
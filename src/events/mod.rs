@@ -3,10 +3,18 @@ use std::collections::HashMap;
 use cfmms::dex::Dex;
 use ethers::{
     abi::Event,
-    types::{Filter, Log, H256},
+    prelude::EthEvent,
+    types::{Filter, Log, H160, H256},
 };
 
-use crate::abi;
+use crate::{
+    abi::{self, i_balancer_vault},
+    pool::{
+        balancer,
+        curve::{self, MAX_LIQUIDITY_EVENT_COINS, MIN_LIQUIDITY_EVENT_COINS},
+        uniswap_v2, AlgebraDex, BalancerDex, CurveDex, SolidlyDex, UniswapV2PairFee,
+    },
+};
 
 #[derive(Copy, Clone)]
 pub enum BeltEvent {
@@ -18,7 +26,24 @@ pub enum BeltEvent {
     OrderRefreshed,
     OrderExecutionCreditUpdated,
     UniswapV2PoolUpdate,
+    //Fee event of UniswapV2 forks with a fee for each pair, as configured for the fork
+    UniswapV2PoolFeeUpdate(&'static str),
     UniswapV3PoolUpdate,
+    UniswapV3PoolMint,
+    UniswapV3PoolBurn,
+    AlgebraPoolFee,
+    AlgebraPoolDirectionalFee,
+    SolidlyPoolUpdate,
+    CamelotPoolFeeUpdate,
+    CamelotPoolStableSwapUpdate,
+    CurvePoolTokenExchange,
+    //Curve liquidity events are sized to the number of coins in the pool
+    CurvePoolAddLiquidity(usize),
+    CurvePoolRemoveLiquidity(usize),
+    CurvePoolRemoveLiquidityImbalance(usize),
+    BalancerVaultSwap,
+    BalancerVaultPoolBalanceChanged,
+    BalancerPoolSwapFeeUpdate,
 }
 
 impl BeltEvent {
@@ -61,9 +86,50 @@ impl BeltEvent {
             BeltEvent::UniswapV2PoolUpdate => {
                 abi::IUNISWAPV2PAIR_ABI.event("Sync").unwrap().to_owned()
             }
+            BeltEvent::UniswapV2PoolFeeUpdate(fee_event) => uniswap_v2::fee_update_event(fee_event),
             BeltEvent::UniswapV3PoolUpdate => {
                 abi::IUNISWAPV3POOL_ABI.event("Swap").unwrap().to_owned()
             }
+            BeltEvent::UniswapV3PoolMint => {
+                abi::IUNISWAPV3POOL_ABI.event("Mint").unwrap().to_owned()
+            }
+            BeltEvent::UniswapV3PoolBurn => {
+                abi::IUNISWAPV3POOL_ABI.event("Burn").unwrap().to_owned()
+            }
+            BeltEvent::AlgebraPoolFee => abi::IALGEBRAPOOL_ABI.event("Fee").unwrap().to_owned(),
+            BeltEvent::AlgebraPoolDirectionalFee => abi::IALGEBRAPOOLDIRECTIONALFEE_ABI
+                .event("Fee")
+                .unwrap()
+                .to_owned(),
+            BeltEvent::SolidlyPoolUpdate => abi::ISOLIDLYPAIR_ABI.event("Sync").unwrap().to_owned(),
+            BeltEvent::CamelotPoolFeeUpdate => abi::ICAMELOTPAIR_ABI
+                .event("FeePercentUpdated")
+                .unwrap()
+                .to_owned(),
+            BeltEvent::CamelotPoolStableSwapUpdate => abi::ICAMELOTPAIR_ABI
+                .event("SetStableSwap")
+                .unwrap()
+                .to_owned(),
+            BeltEvent::CurvePoolTokenExchange => abi::ICURVEPOOL_ABI
+                .event("TokenExchange")
+                .unwrap()
+                .to_owned(),
+            BeltEvent::CurvePoolAddLiquidity(n_coins) => curve::add_liquidity_event(*n_coins),
+            BeltEvent::CurvePoolRemoveLiquidity(n_coins) => curve::remove_liquidity_event(*n_coins),
+            BeltEvent::CurvePoolRemoveLiquidityImbalance(n_coins) => {
+                curve::remove_liquidity_imbalance_event(*n_coins)
+            }
+            BeltEvent::BalancerVaultSwap => {
+                abi::IBALANCERVAULT_ABI.event("Swap").unwrap().to_owned()
+            }
+            BeltEvent::BalancerVaultPoolBalanceChanged => abi::IBALANCERVAULT_ABI
+                .event("PoolBalanceChanged")
+                .unwrap()
+                .to_owned(),
+            BeltEvent::BalancerPoolSwapFeeUpdate => abi::IBALANCERPOOL_ABI
+                .event("SwapFeePercentageChanged")
+                .unwrap()
+                .to_owned(),
         }
     }
     pub fn event_signature(&self) -> H256 {
@@ -91,11 +157,40 @@ impl BeltEvent {
             }
             BeltEvent::UniswapV2PoolUpdate => cfmms::pool::uniswap_v2::SYNC_EVENT_SIGNATURE,
             BeltEvent::UniswapV3PoolUpdate => cfmms::pool::uniswap_v3::SWAP_EVENT_SIGNATURE,
+            BeltEvent::UniswapV3PoolMint => abi::IUNISWAPV3POOL_ABI.events["Mint"][0].signature(),
+            BeltEvent::UniswapV3PoolBurn => abi::IUNISWAPV3POOL_ABI.events["Burn"][0].signature(),
+            BeltEvent::AlgebraPoolFee => abi::IALGEBRAPOOL_ABI.events["Fee"][0].signature(),
+            BeltEvent::AlgebraPoolDirectionalFee => {
+                abi::IALGEBRAPOOLDIRECTIONALFEE_ABI.events["Fee"][0].signature()
+            }
+            BeltEvent::SolidlyPoolUpdate => abi::ISOLIDLYPAIR_ABI.events["Sync"][0].signature(),
+            BeltEvent::CamelotPoolFeeUpdate => {
+                abi::ICAMELOTPAIR_ABI.events["FeePercentUpdated"][0].signature()
+            }
+            BeltEvent::CamelotPoolStableSwapUpdate => {
+                abi::ICAMELOTPAIR_ABI.events["SetStableSwap"][0].signature()
+            }
+            BeltEvent::CurvePoolTokenExchange => {
+                abi::ICURVEPOOL_ABI.events["TokenExchange"][0].signature()
+            }
+            BeltEvent::UniswapV2PoolFeeUpdate(_)
+            | BeltEvent::CurvePoolAddLiquidity(_)
+            | BeltEvent::CurvePoolRemoveLiquidity(_)
+            | BeltEvent::CurvePoolRemoveLiquidityImbalance(_) => self.to_event().signature(),
+            BeltEvent::BalancerVaultSwap => abi::IBALANCERVAULT_ABI.events["Swap"][0].signature(),
+            BeltEvent::BalancerVaultPoolBalanceChanged => {
+                abi::IBALANCERVAULT_ABI.events["PoolBalanceChanged"][0].signature()
+            }
+            BeltEvent::BalancerPoolSwapFeeUpdate => {
+                abi::IBALANCERPOOL_ABI.events["SwapFeePercentageChanged"][0].signature()
+            }
         }
     }
 }
 
-pub fn get_event_signature_to_belt_event() -> HashMap<H256, BeltEvent> {
+pub fn get_event_signature_to_belt_event(
+    v2_pair_fees: &HashMap<H160, UniswapV2PairFee>,
+) -> HashMap<H256, BeltEvent> {
     let mut sig_to_belt_event = HashMap::new();
 
     sig_to_belt_event.insert(
@@ -125,29 +220,154 @@ pub fn get_event_signature_to_belt_event() -> HashMap<H256, BeltEvent> {
         BeltEvent::UniswapV2PoolUpdate.event_signature(),
         BeltEvent::UniswapV2PoolUpdate,
     );
+    for fee_event in v2_pair_fees
+        .values()
+        .filter_map(|pair_fee| pair_fee.fee_event)
+    {
+        let belt_event = BeltEvent::UniswapV2PoolFeeUpdate(fee_event);
+        sig_to_belt_event.insert(belt_event.event_signature(), belt_event);
+    }
     sig_to_belt_event.insert(
         BeltEvent::UniswapV3PoolUpdate.event_signature(),
         BeltEvent::UniswapV3PoolUpdate,
     );
+    sig_to_belt_event.insert(
+        BeltEvent::UniswapV3PoolMint.event_signature(),
+        BeltEvent::UniswapV3PoolMint,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::UniswapV3PoolBurn.event_signature(),
+        BeltEvent::UniswapV3PoolBurn,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::AlgebraPoolFee.event_signature(),
+        BeltEvent::AlgebraPoolFee,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::AlgebraPoolDirectionalFee.event_signature(),
+        BeltEvent::AlgebraPoolDirectionalFee,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::SolidlyPoolUpdate.event_signature(),
+        BeltEvent::SolidlyPoolUpdate,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::CamelotPoolFeeUpdate.event_signature(),
+        BeltEvent::CamelotPoolFeeUpdate,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::CamelotPoolStableSwapUpdate.event_signature(),
+        BeltEvent::CamelotPoolStableSwapUpdate,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::CurvePoolTokenExchange.event_signature(),
+        BeltEvent::CurvePoolTokenExchange,
+    );
+    for n_coins in MIN_LIQUIDITY_EVENT_COINS..=MAX_LIQUIDITY_EVENT_COINS {
+        for belt_event in [
+            BeltEvent::CurvePoolAddLiquidity(n_coins),
+            BeltEvent::CurvePoolRemoveLiquidity(n_coins),
+            BeltEvent::CurvePoolRemoveLiquidityImbalance(n_coins),
+        ] {
+            sig_to_belt_event.insert(belt_event.event_signature(), belt_event);
+        }
+    }
+    sig_to_belt_event.insert(
+        BeltEvent::BalancerVaultSwap.event_signature(),
+        BeltEvent::BalancerVaultSwap,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::BalancerVaultPoolBalanceChanged.event_signature(),
+        BeltEvent::BalancerVaultPoolBalanceChanged,
+    );
+    sig_to_belt_event.insert(
+        BeltEvent::BalancerPoolSwapFeeUpdate.event_signature(),
+        BeltEvent::BalancerPoolSwapFeeUpdate,
+    );
 
     sig_to_belt_event
 }
 
 //Initializes a new filter to listen for price updates
 //Returns a Filter and Hashset to check
-pub fn initialize_block_filter(dexes: &[Dex]) -> Filter {
+pub fn initialize_block_filter(
+    dexes: &[Dex],
+    v2_pair_fees: &HashMap<H160, UniswapV2PairFee>,
+    algebra_dexes: &[AlgebraDex],
+    solidly_dexes: &[SolidlyDex],
+    curve_dexes: &[CurveDex],
+    balancer_dexes: &[BalancerDex],
+) -> Filter {
     //Create the event log signature
     let mut event_signatures: Vec<H256> = vec![];
 
-    //Add the swap/sync event signature for each dex variant
+    //Add the sync event signature for v2 pools, and the swap, mint and burn event signatures for v3 pools to keep the tick data up to date
     for dex in dexes {
-        let sync_event_signature = match dex {
-            Dex::UniswapV2(_) => cfmms::dex::uniswap_v2::PAIR_CREATED_EVENT_SIGNATURE,
-            Dex::UniswapV3(_) => cfmms::dex::uniswap_v3::POOL_CREATED_EVENT_SIGNATURE,
+        let pool_event_signatures = match dex {
+            Dex::UniswapV2(_) => vec![BeltEvent::UniswapV2PoolUpdate.event_signature()],
+            Dex::UniswapV3(_) => vec![
+                BeltEvent::UniswapV3PoolUpdate.event_signature(),
+                BeltEvent::UniswapV3PoolMint.event_signature(),
+                BeltEvent::UniswapV3PoolBurn.event_signature(),
+            ],
         };
 
-        if !event_signatures.contains(&sync_event_signature) {
-            event_signatures.push(sync_event_signature);
+        for event_signature in pool_event_signatures {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
+        }
+    }
+
+    //UniswapV2 forks with a fee for each pair that log fee changes are updated from the fee event, the fees of other pairs are polled
+    for pair_fee in v2_pair_fees.values() {
+        if let Some(event_signature) = pair_fee.fee_event_signature() {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
+        }
+    }
+
+    //Algebra pools emit the same swap, mint and burn events as v3 pools, as well as an event when the dynamic fee changes
+    for algebra_dex in algebra_dexes {
+        let pool_event_signatures = vec![
+            BeltEvent::UniswapV3PoolUpdate.event_signature(),
+            BeltEvent::UniswapV3PoolMint.event_signature(),
+            BeltEvent::UniswapV3PoolBurn.event_signature(),
+            algebra_dex.fee_event_signature(),
+        ];
+
+        for event_signature in pool_event_signatures {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
+        }
+    }
+
+    //Solidly pairs emit a Sync event like v2 pools, Camelot pairs also emit events when the fees or the curve change
+    for solidly_dex in solidly_dexes {
+        for event_signature in solidly_dex.event_signatures() {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
+        }
+    }
+
+    //Curve pools emit a TokenExchange event on swaps and an event for each kind of liquidity change
+    for curve_dex in curve_dexes {
+        for event_signature in curve_dex.event_signatures() {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
+        }
+    }
+
+    //Balancer pool balances are updated from the vault Swap and PoolBalanceChanged events, and the swap fee from an event emitted by the pool
+    for balancer_dex in balancer_dexes {
+        for event_signature in balancer_dex.event_signatures() {
+            if !event_signatures.contains(&event_signature) {
+                event_signatures.push(event_signature);
+            }
         }
     }
 
@@ -167,6 +387,19 @@ pub fn initialize_block_filter(dexes: &[Dex]) -> Filter {
     Filter::new().topic0(event_signatures)
 }
 
+//Returns the address of the pool that a pool event log updates. Balancer vault logs are emitted by the vault, so they are attributed to the pool in the pool id topic
+pub fn get_pool_address_from_log(log: &Log) -> H160 {
+    if log.topics[0] == i_balancer_vault::SwapFilter::signature()
+        || log.topics[0] == i_balancer_vault::PoolBalanceChangedFilter::signature()
+    {
+        if let Some(pool_id) = log.topics.get(1) {
+            return balancer::pool_address_from_pool_id(*pool_id);
+        }
+    }
+
+    log.address
+}
+
 pub fn sort_events(
     event_logs: &[Log],
     event_sig_to_belt_event: &HashMap<H256, BeltEvent>,
@@ -177,8 +410,27 @@ pub fn sort_events(
     for log in event_logs {
         if let Some(belt_event) = event_sig_to_belt_event.get(&log.topics[0]) {
             match belt_event {
-                BeltEvent::UniswapV2PoolUpdate => pool_events.push(log.to_owned()),
-                BeltEvent::UniswapV3PoolUpdate => pool_events.push(log.to_owned()),
+                BeltEvent::UniswapV2PoolUpdate | BeltEvent::UniswapV2PoolFeeUpdate(_) => {
+                    pool_events.push(log.to_owned())
+                }
+                BeltEvent::UniswapV3PoolUpdate
+                | BeltEvent::UniswapV3PoolMint
+                | BeltEvent::UniswapV3PoolBurn => pool_events.push(log.to_owned()),
+                BeltEvent::AlgebraPoolFee | BeltEvent::AlgebraPoolDirectionalFee => {
+                    pool_events.push(log.to_owned())
+                }
+                BeltEvent::SolidlyPoolUpdate
+                | BeltEvent::CamelotPoolFeeUpdate
+                | BeltEvent::CamelotPoolStableSwapUpdate => pool_events.push(log.to_owned()),
+                BeltEvent::CurvePoolTokenExchange
+                | BeltEvent::CurvePoolAddLiquidity(_)
+                | BeltEvent::CurvePoolRemoveLiquidity(_)
+                | BeltEvent::CurvePoolRemoveLiquidityImbalance(_) => {
+                    pool_events.push(log.to_owned())
+                }
+                BeltEvent::BalancerVaultSwap
+                | BeltEvent::BalancerVaultPoolBalanceChanged
+                | BeltEvent::BalancerPoolSwapFeeUpdate => pool_events.push(log.to_owned()),
                 _ => order_events.push((*belt_event, log.to_owned())),
             }
         }
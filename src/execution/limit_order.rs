@@ -7,7 +7,12 @@ use super::ExecutionCalldata;
 use ethers::abi::ethabi::Bytes;
 use ethers::abi::Token;
 use ethers::providers::Middleware;
-use ethers::types::H256;
+use ethers::types::{Transaction, H256, U256};
+
+//Estimated gas of executing a limit order group, excluding the orders in it
+pub const LIMIT_ORDER_GROUP_GAS_OVERHEAD: u64 = 50000;
+//Estimated gas of transferring the quantity of an order, paying out the amount out and the execution credit, excluding its swaps
+pub const LIMIT_ORDER_GAS_OVERHEAD: u64 = 100000;
 
 #[derive(Default, Debug)]
 pub struct LimitOrderExecutionBundle {
@@ -57,6 +62,10 @@ impl ExecutionCalldata for LimitOrderExecutionBundle {
 #[derive(Default, Debug)]
 pub struct LimitOrderExecutionOrderIds {
     pub order_ids: Vec<[u8; 32]>, // bytes32[] calldata orderIds
+    //The router requires every order in a group to have the same stop loss status
+    pub stop_loss: bool,
+    //Estimated gas of the swaps of the orders in the group
+    pub gas_estimate: U256,
 }
 
 impl LimitOrderExecutionOrderIds {
@@ -67,6 +76,23 @@ impl LimitOrderExecutionOrderIds {
     pub fn add_order_id(&mut self, order_id: H256) {
         self.order_ids.push(order_id.to_fixed_bytes());
     }
+
+    pub fn add_gas_estimate(&mut self, gas_estimate: U256) {
+        self.gas_estimate += gas_estimate;
+    }
+
+    //Returns the estimated gas of executing the group
+    pub fn execution_gas_estimate(&self) -> U256 {
+        U256::from(LIMIT_ORDER_GROUP_GAS_OVERHEAD)
+            + U256::from(LIMIT_ORDER_GAS_OVERHEAD) * self.order_ids.len()
+            + self.gas_estimate
+    }
+}
+
+//Returns the estimated gas that each order of a group of the size adds to the execution, excluding its swaps. Orders share the overhead of the group evenly
+pub fn order_gas_overhead(group_size: usize) -> U256 {
+    U256::from(LIMIT_ORDER_GAS_OVERHEAD)
+        + U256::from(LIMIT_ORDER_GROUP_GAS_OVERHEAD) / U256::from(group_size.max(1))
 }
 
 impl ExecutionCalldata for LimitOrderExecutionOrderIds {
@@ -123,3 +149,51 @@ pub async fn execute_limit_order_groups<M: Middleware>(
 
     Ok(())
 }
+
+//Sends the limit order groups priced to land directly after the pending trigger transaction
+pub async fn backrun_limit_order_groups<M: Middleware>(
+    limit_order_execution_bundle: LimitOrderExecutionBundle,
+    trigger_tx: &Transaction,
+    configuration: &config::Config,
+    pending_transactions_sender: Arc<tokio::sync::mpsc::Sender<(H256, Vec<H256>)>>,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    for order_group in limit_order_execution_bundle.order_groups {
+        if !order_group.order_ids.is_empty() {
+            let tx = transactions::construct_lo_backrun_transaction(
+                configuration,
+                order_group.order_ids.clone(),
+                order_group.execution_gas_estimate(),
+                trigger_tx,
+                middleware.clone(),
+            )
+            .await?;
+
+            let pending_tx_hash = transactions::sign_and_send_transaction(
+                tx,
+                &configuration.wallet_key,
+                &configuration.chain,
+                middleware.clone(),
+            )
+            .await?;
+
+            tracing::info!(
+                "Pending limit order backrun tx: {:?}, trigger tx: {:?}",
+                pending_tx_hash,
+                trigger_tx.hash
+            );
+
+            let order_ids = order_group
+                .order_ids
+                .iter()
+                .map(|f| H256::from_slice(f.as_slice()))
+                .collect::<Vec<H256>>();
+
+            pending_transactions_sender
+                .send((pending_tx_hash, order_ids))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
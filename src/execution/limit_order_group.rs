@@ -0,0 +1,260 @@
+use std::collections::{HashMap, HashSet};
+
+use ethers::types::{H160, H256};
+use thiserror::Error;
+
+use crate::order::limit_order::LimitOrder;
+
+use super::limit_order::LimitOrderExecutionOrderIds;
+
+//Grouping rules that the LimitOrderRouter checks between each order and the next order in a group, reverting the whole group if an order breaks one
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitOrderGroupError {
+    #[error("Duplicate order ids in order group")]
+    DuplicateOrderIdsInOrderGroup(H256),
+    #[error("Incongruent input token in order group")]
+    IncongruentInputTokenInOrderGroup(H160, H160),
+    #[error("Incongruent output token in order group")]
+    IncongruentOutputTokenInOrderGroup(H160, H160),
+    #[error("Incongruent stoploss status in order group")]
+    IncongruentStoplossStatusInOrderGroup,
+    #[error("Incongruent taxed token in order group")]
+    IncongruentTaxedTokenInOrderGroup,
+    #[error("Incongruent fee in in order group")]
+    IncongruentFeeInInOrderGroup,
+    #[error("Incongruent fee out in order group")]
+    IncongruentFeeOutInOrderGroup,
+    #[error("Incongruent buy sell status in order group")]
+    IncongruentBuySellStatusInOrderGroup,
+    #[error("Invalid order group sequence")]
+    InvalidOrderGroupSequence,
+}
+
+//Fields that every order in a group must share
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct LimitOrderGroupKey {
+    token_in: H160,
+    token_out: H160,
+    buy: bool,
+    taxed: bool,
+    stop_loss: bool,
+    fee_in: u32,
+    fee_out: u32,
+}
+
+impl LimitOrderGroupKey {
+    fn new(order: &LimitOrder) -> LimitOrderGroupKey {
+        LimitOrderGroupKey {
+            token_in: order.token_in,
+            token_out: order.token_out,
+            buy: order.buy,
+            taxed: order.taxed,
+            stop_loss: order.stop_loss,
+            fee_in: order.fee_in,
+            fee_out: order.fee_out,
+        }
+    }
+}
+
+//Checks each order against the next order in the group like the router does, so that a group that would revert is never sent
+pub fn validate_limit_order_group(orders: &[&LimitOrder]) -> Result<(), LimitOrderGroupError> {
+    let mut order_ids = HashSet::new();
+    for order in orders {
+        if !order_ids.insert(order.order_id) {
+            return Err(LimitOrderGroupError::DuplicateOrderIdsInOrderGroup(
+                order.order_id,
+            ));
+        }
+    }
+
+    for pair in orders.windows(2) {
+        let (current_order, next_order) = (pair[0], pair[1]);
+
+        if current_order.token_in != next_order.token_in {
+            return Err(LimitOrderGroupError::IncongruentInputTokenInOrderGroup(
+                next_order.token_in,
+                current_order.token_in,
+            ));
+        }
+
+        if current_order.token_out != next_order.token_out {
+            return Err(LimitOrderGroupError::IncongruentOutputTokenInOrderGroup(
+                next_order.token_out,
+                current_order.token_out,
+            ));
+        }
+
+        if current_order.stop_loss != next_order.stop_loss {
+            return Err(LimitOrderGroupError::IncongruentStoplossStatusInOrderGroup);
+        }
+
+        if current_order.taxed != next_order.taxed {
+            return Err(LimitOrderGroupError::IncongruentTaxedTokenInOrderGroup);
+        }
+
+        if current_order.fee_in != next_order.fee_in {
+            return Err(LimitOrderGroupError::IncongruentFeeInInOrderGroup);
+        }
+
+        if current_order.fee_out != next_order.fee_out {
+            return Err(LimitOrderGroupError::IncongruentFeeOutInOrderGroup);
+        }
+
+        if current_order.buy != next_order.buy {
+            return Err(LimitOrderGroupError::IncongruentBuySellStatusInOrderGroup);
+        }
+
+        //Orders are executed in ascending order of quantity
+        if current_order.quantity > next_order.quantity {
+            return Err(LimitOrderGroupError::InvalidOrderGroupSequence);
+        }
+    }
+
+    Ok(())
+}
+
+//Splits the orders into groups that are valid by construction. Orders are grouped by every field that the router compares, duplicate orders are dropped
+//and each group is sorted by quantity. Groups are returned in the order that their first order appears in, so the most profitable groups are sent first
+pub fn group_limit_orders(orders: Vec<&LimitOrder>) -> Vec<Vec<&LimitOrder>> {
+    let mut order_groups: Vec<Vec<&LimitOrder>> = vec![];
+    let mut group_indices: HashMap<LimitOrderGroupKey, usize> = HashMap::new();
+    let mut order_ids = HashSet::new();
+
+    for order in orders {
+        if !order_ids.insert(order.order_id) {
+            continue;
+        }
+
+        let group_index = *group_indices
+            .entry(LimitOrderGroupKey::new(order))
+            .or_insert_with(|| {
+                order_groups.push(vec![]);
+                order_groups.len() - 1
+            });
+
+        order_groups[group_index].push(order);
+    }
+
+    for order_group in order_groups.iter_mut() {
+        order_group.sort_by_key(|order| order.quantity);
+    }
+
+    order_groups
+}
+
+//Returns the order ids of the group for the execution calldata, along with the stop loss status of the group
+pub fn to_execution_order_ids(orders: &[&LimitOrder]) -> LimitOrderExecutionOrderIds {
+    let mut order_ids = LimitOrderExecutionOrderIds::new();
+    order_ids.stop_loss = orders.iter().any(|order| order.stop_loss);
+
+    for order in orders {
+        order_ids.add_order_id(order.order_id);
+    }
+
+    order_ids
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, H256};
+
+    use crate::order::limit_order::LimitOrder;
+
+    use super::{group_limit_orders, validate_limit_order_group, LimitOrderGroupError};
+
+    fn limit_order(order_id: u64, quantity: u128) -> LimitOrder {
+        LimitOrder::new(
+            false,
+            false,
+            false,
+            0,
+            u32::MAX,
+            500,
+            500,
+            0,
+            0,
+            0,
+            quantity,
+            0,
+            H160::zero(),
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            H256::from_low_u64_be(order_id),
+        )
+    }
+
+    #[test]
+    fn test_validate_limit_order_group() {
+        let a = limit_order(1, 100);
+        let b = limit_order(2, 200);
+        assert_eq!(validate_limit_order_group(&[&a, &b]), Ok(()));
+        assert_eq!(
+            validate_limit_order_group(&[&b, &a]),
+            Err(LimitOrderGroupError::InvalidOrderGroupSequence)
+        );
+        assert_eq!(
+            validate_limit_order_group(&[&a, &b, &a]),
+            Err(LimitOrderGroupError::DuplicateOrderIdsInOrderGroup(
+                a.order_id
+            ))
+        );
+
+        let mut c = limit_order(3, 300);
+        c.buy = true;
+        assert_eq!(
+            validate_limit_order_group(&[&a, &c]),
+            Err(LimitOrderGroupError::IncongruentBuySellStatusInOrderGroup)
+        );
+
+        let mut c = limit_order(3, 300);
+        c.stop_loss = true;
+        assert_eq!(
+            validate_limit_order_group(&[&a, &c]),
+            Err(LimitOrderGroupError::IncongruentStoplossStatusInOrderGroup)
+        );
+
+        let mut c = limit_order(3, 300);
+        c.fee_out = 3000;
+        assert_eq!(
+            validate_limit_order_group(&[&a, &c]),
+            Err(LimitOrderGroupError::IncongruentFeeOutInOrderGroup)
+        );
+
+        let mut c = limit_order(3, 300);
+        c.token_in = H160::from_low_u64_be(3);
+        assert_eq!(
+            validate_limit_order_group(&[&a, &c]),
+            Err(LimitOrderGroupError::IncongruentInputTokenInOrderGroup(
+                c.token_in, a.token_in
+            ))
+        );
+    }
+
+    #[test]
+    fn test_group_limit_orders() {
+        let a = limit_order(1, 300);
+        let b = limit_order(2, 100);
+        let mut c = limit_order(3, 200);
+        c.taxed = true;
+        let mut d = limit_order(4, 50);
+        d.buy = true;
+
+        let order_groups = group_limit_orders(vec![&a, &c, &b, &a, &d]);
+
+        //Groups keep the order of their first order, and each group is sorted by quantity without duplicates
+        assert_eq!(order_groups.len(), 3);
+        assert_eq!(
+            order_groups[0]
+                .iter()
+                .map(|order| order.order_id)
+                .collect::<Vec<H256>>(),
+            vec![b.order_id, a.order_id]
+        );
+        assert_eq!(order_groups[1][0].order_id, c.order_id);
+        assert_eq!(order_groups[2][0].order_id, d.order_id);
+
+        assert!(order_groups
+            .iter()
+            .all(|order_group| validate_limit_order_group(order_group).is_ok()));
+    }
+}
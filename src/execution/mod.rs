@@ -1,4 +1,5 @@
 pub mod limit_order;
+pub mod limit_order_group;
 pub mod sandbox_limit_order;
 
 use std::{
@@ -17,6 +18,7 @@ use crate::{
     error::ExecutorError,
     markets,
     order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder, Order},
+    routing::{graph::Connectors, tax::TransferTaxes},
     simulation, state,
 };
 
@@ -49,33 +51,29 @@ pub async fn fill_all_orders_at_execution_price<M: 'static + Middleware>(
     let mut lo_at_execution_price: HashMap<H256, &LimitOrder> = HashMap::new();
 
     for order in state.active_orders.values() {
-        if order.can_execute(&state.markets, configuration.weth_address)
-            && order.has_sufficient_balance(middleware.clone()).await?
-        {
-            let a_to_weth_market_id =
-                markets::get_market_id(order.token_in(), configuration.weth_address);
-
-            if let Some(market) = state.markets.get(&a_to_weth_market_id) {
-                //Add the market to the simulation markets structure
-                simulated_markets.insert(a_to_weth_market_id, market.clone());
-            }
+        //Skip orders on tokens that failed screening, since the transfers of the execution would revert
+        if !state.order_tokens_are_tradable(order) {
+            continue;
+        }
 
-            let weth_to_b_market_id =
-                markets::get_market_id(configuration.weth_address, order.token_out());
-            if let Some(market) = state.markets.get(&weth_to_b_market_id) {
-                //Add the market to the simulation markets structure
-                simulated_markets.insert(weth_to_b_market_id, market.clone());
+        if order.can_execute(
+            &state.markets,
+            configuration.weth_address,
+            &configuration.limit_order_router_dexes,
+            &configuration.connectors,
+            configuration.min_fill_percent,
+        ) && order.has_sufficient_balance(middleware.clone()).await?
+        {
+            //Add the markets that the order can route through to the simulation markets structure
+            for market_id in order.market_ids(configuration.weth_address, &configuration.connectors)
+            {
+                if let Some(market) = state.markets.get(&market_id) {
+                    simulated_markets.insert(market_id, market.clone());
+                }
             }
 
             match order {
                 Order::SandboxLimitOrder(sandbox_limit_order) => {
-                    let a_to_b_market_id =
-                        markets::get_market_id(order.token_in(), order.token_out());
-                    if let Some(market) = state.markets.get(&a_to_b_market_id) {
-                        //Add the market to the simulation markets structure
-                        simulated_markets.insert(a_to_b_market_id, market.clone());
-                    }
-
                     if slo_at_execution_price
                         .get(&sandbox_limit_order.order_id)
                         .is_none()
@@ -93,17 +91,33 @@ pub async fn fill_all_orders_at_execution_price<M: 'static + Middleware>(
         }
     }
 
+    if !configuration.taxed_tokens {
+        remove_taxed_orders(
+            &mut slo_at_execution_price,
+            &mut lo_at_execution_price,
+            &state.transfer_taxes,
+        );
+    }
+
     //Simulate sandbox limit orders and generate execution transaction calldata
+    //The estimated gas of each route is priced in weth, so that orders are only executed when the remainder paid to the coex covers the swaps
+    let gas_price = middleware
+        .get_gas_price()
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
+
     let sandbox_execution_bundles = simulation::simulate_and_batch_sandbox_limit_orders(
         slo_at_execution_price,
         &mut simulated_markets,
         configuration.weth_address,
+        &configuration.connectors,
+        configuration.min_fill_percent,
         configuration.executor_address,
         configuration.sandbox_limit_order_router,
         configuration.wallet_address,
-        middleware.clone(),
-    )
-    .await?;
+        gas_price,
+        &state.transfer_taxes,
+    );
 
     //simulate and batch limit orders
     //:: Simulate sandbox limit orders and generate execution transaction calldata
@@ -112,9 +126,10 @@ pub async fn fill_all_orders_at_execution_price<M: 'static + Middleware>(
             lo_at_execution_price,
             &mut simulated_markets,
             configuration.weth_address,
-            middleware.clone(),
-        )
-        .await?;
+            &configuration.limit_order_router_dexes,
+            gas_price,
+            &state.transfer_taxes,
+        );
 
     //Execute orders if there are any order groups
     if !sandbox_execution_bundles.is_empty() {
@@ -146,15 +161,42 @@ pub async fn fill_all_orders_at_execution_price<M: 'static + Middleware>(
     Ok(())
 }
 
-pub fn group_orders_at_execution_price(
-    state: &state::State,
+//Simulated markets, sandbox limit orders and limit orders at execution price
+pub type OrdersAtExecutionPrice<'a> = (
+    HashMap<U256, markets::Market>,
+    HashMap<H256, &'a SandboxLimitOrder>,
+    HashMap<H256, &'a LimitOrder>,
+);
+
+pub fn group_orders_at_execution_price<'a>(
+    state: &'a state::State,
     affected_markets: HashSet<U256>,
     weth_address: H160,
-) -> (
-    HashMap<U256, markets::Market>,
-    HashMap<H256, &SandboxLimitOrder>,
-    HashMap<H256, &LimitOrder>,
-) {
+    limit_order_router_dexes: &[H160],
+    connectors: &Connectors,
+    min_fill_percent: u128,
+) -> OrdersAtExecutionPrice<'a> {
+    group_orders_at_execution_price_in_markets(
+        state,
+        &state.markets,
+        affected_markets,
+        weth_address,
+        limit_order_router_dexes,
+        connectors,
+        min_fill_percent,
+    )
+}
+
+//Groups the orders affected by the markets that are at execution price within the provided markets, which can differ from the markets in state (ie. markets with pending swaps applied)
+pub fn group_orders_at_execution_price_in_markets<'a>(
+    state: &'a state::State,
+    markets: &HashMap<U256, markets::Market>,
+    affected_markets: HashSet<U256>,
+    weth_address: H160,
+    limit_order_router_dexes: &[H160],
+    connectors: &Connectors,
+    min_fill_percent: u128,
+) -> OrdersAtExecutionPrice<'a> {
     let pending_order_ids = state
         .pending_order_ids
         .lock()
@@ -169,43 +211,31 @@ pub fn group_orders_at_execution_price(
             for order_id in affected_orders {
                 if pending_order_ids.get(order_id).is_none() {
                     if let Some(order) = state.active_orders.get(order_id) {
-                        if order.can_execute(&state.markets, weth_address) {
-                            let a_to_weth_market_id =
-                                markets::get_market_id(order.token_in(), weth_address);
-
-                            if let Some(market) = state.markets.get(&a_to_weth_market_id) {
-                                simulated_markets.insert(a_to_weth_market_id, market.clone());
-                            }
-
-                            let weth_to_b_market_id =
-                                markets::get_market_id(weth_address, order.token_out());
-                            if let Some(market) = state.markets.get(&weth_to_b_market_id) {
-                                simulated_markets.insert(weth_to_b_market_id, market.clone());
+                        if state.order_tokens_are_tradable(order)
+                            && order.can_execute(
+                                markets,
+                                weth_address,
+                                limit_order_router_dexes,
+                                connectors,
+                                min_fill_percent,
+                            )
+                        {
+                            for market_id in order.market_ids(weth_address, connectors) {
+                                if let Some(market) = markets.get(&market_id) {
+                                    simulated_markets.insert(market_id, market.clone());
+                                }
                             }
 
                             match order {
                                 Order::SandboxLimitOrder(sandbox_limit_order) => {
-                                    let a_to_b_market_id =
-                                        markets::get_market_id(order.token_in(), order.token_out());
-                                    if let Some(market) = state.markets.get(&a_to_b_market_id) {
-                                        simulated_markets.insert(a_to_b_market_id, market.clone());
-                                    }
-
-                                    if slo_at_execution_price
-                                        .get(&sandbox_limit_order.order_id)
-                                        .is_none()
-                                    {
-                                        slo_at_execution_price.insert(
-                                            sandbox_limit_order.order_id,
-                                            sandbox_limit_order,
-                                        );
-                                    }
+                                    slo_at_execution_price
+                                        .entry(sandbox_limit_order.order_id)
+                                        .or_insert(sandbox_limit_order);
                                 }
                                 Order::LimitOrder(limit_order) => {
-                                    if lo_at_execution_price.get(&limit_order.order_id).is_none() {
-                                        lo_at_execution_price
-                                            .insert(limit_order.order_id, limit_order);
-                                    }
+                                    lo_at_execution_price
+                                        .entry(limit_order.order_id)
+                                        .or_insert(limit_order);
                                 }
                             }
                         }
@@ -221,6 +251,22 @@ pub fn group_orders_at_execution_price(
     )
 }
 
+//Removes orders that transfer a fee on transfer token, which are only executed when taxed tokens are enabled in the config
+pub fn remove_taxed_orders(
+    slo_at_execution_price: &mut HashMap<H256, &SandboxLimitOrder>,
+    lo_at_execution_price: &mut HashMap<H256, &LimitOrder>,
+    transfer_taxes: &TransferTaxes,
+) {
+    slo_at_execution_price.retain(|_, order| {
+        !transfer_taxes.is_taxed(order.token_in) && !transfer_taxes.is_taxed(order.token_out)
+    });
+    lo_at_execution_price.retain(|_, order| {
+        !order.taxed
+            && !transfer_taxes.is_taxed(order.token_in)
+            && !transfer_taxes.is_taxed(order.token_out)
+    });
+}
+
 pub async fn fill_orders_at_execution_price<M: 'static + Middleware>(
     configuration: &config::Config,
     state: &state::State,
@@ -230,20 +276,43 @@ pub async fn fill_orders_at_execution_price<M: 'static + Middleware>(
 ) -> Result<(), ExecutorError<M>> {
     //Get the simulated markets that the orders at execution price could potentially route through
     //Additionally, this function collects all of the sandbox orders and limit orders at execution price
-    let (mut simulated_markets, slo_at_execution_price, lo_at_execution_price) =
-        group_orders_at_execution_price(state, affected_markets, configuration.weth_address);
+    let (mut simulated_markets, mut slo_at_execution_price, mut lo_at_execution_price) =
+        group_orders_at_execution_price(
+            state,
+            affected_markets,
+            configuration.weth_address,
+            &configuration.limit_order_router_dexes,
+            &configuration.connectors,
+            configuration.min_fill_percent,
+        );
+
+    if !configuration.taxed_tokens {
+        remove_taxed_orders(
+            &mut slo_at_execution_price,
+            &mut lo_at_execution_price,
+            &state.transfer_taxes,
+        );
+    }
 
     //Simulate sandbox limit orders and generate execution transaction calldata
+    //The estimated gas of each route is priced in weth, so that orders are only executed when the remainder paid to the coex covers the swaps
+    let gas_price = middleware
+        .get_gas_price()
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
+
     let sandbox_execution_bundles = simulation::simulate_and_batch_sandbox_limit_orders(
         slo_at_execution_price,
         &mut simulated_markets,
         configuration.weth_address,
+        &configuration.connectors,
+        configuration.min_fill_percent,
         configuration.executor_address,
         configuration.sandbox_limit_order_router,
         configuration.wallet_address,
-        middleware.clone(),
-    )
-    .await?;
+        gas_price,
+        &state.transfer_taxes,
+    );
 
     //simulate and batch limit orders
     let limit_order_execution_bundle: LimitOrderExecutionBundle =
@@ -251,9 +320,10 @@ pub async fn fill_orders_at_execution_price<M: 'static + Middleware>(
             lo_at_execution_price,
             &mut simulated_markets,
             configuration.weth_address,
-            middleware.clone(),
-        )
-        .await?;
+            &configuration.limit_order_router_dexes,
+            gas_price,
+            &state.transfer_taxes,
+        );
 
     //Execute orders if there are any order groups
     if !sandbox_execution_bundles.is_empty() {
@@ -1,14 +1,24 @@
-use std::sync::Arc;
+use std::{collections::HashSet, sync::Arc};
 
-use cfmms::pool::Pool;
 use ethers::abi::ethabi::Bytes;
-use ethers::abi::Token;
 use ethers::providers::Middleware;
-use ethers::types::{H160, H256, I256, U256};
+use ethers::types::{BlockNumber, Transaction, H160, H256, U256};
 
 use crate::error::ExecutorError;
 use crate::order::sandbox_limit_order::SandboxLimitOrder;
-use crate::{abi, config, transactions};
+use crate::{
+    abi, config,
+    pool::{self, AutomatedMarketMaker, Pool},
+    routing::Hop,
+    transactions,
+};
+
+//Estimated gas of executing a sandbox multicall, excluding the orders in it
+pub const SANDBOX_MULTICALL_GAS_OVERHEAD: u64 = 50000;
+//Estimated gas of transferring the fill amount of an order, paying out the amount due and fee, and validating the order, excluding its swaps
+pub const SANDBOX_ORDER_GAS_OVERHEAD: u64 = 100000;
+//Batches use at most the block gas limit divided by this, so that they can be included alongside other transactions
+pub const BLOCK_GAS_LIMIT_DIVISOR: u64 = 2;
 
 #[derive(Debug, Default)]
 
@@ -19,6 +29,10 @@ pub struct SandboxLimitOrderExecutionBundle {
     pub fill_amounts: Vec<u128>,          // uint128[] fillAmounts
     pub transfer_addresses: Vec<H160>,    // address[] transferAddresses
     pub calls: Vec<Call>,                 // Call[] calls
+    //Estimated gas of the swaps in the calls
+    pub gas_estimate: U256,
+    //Pools that the calls swap through
+    pub pools: HashSet<H160>,
 }
 
 impl SandboxLimitOrderExecutionBundle {
@@ -87,11 +101,63 @@ impl SandboxLimitOrderExecutionBundle {
         self.calls.push(call);
     }
 
+    pub fn add_gas_estimate(&mut self, gas_estimate: U256) {
+        self.gas_estimate += gas_estimate;
+    }
+
+    //Returns the estimated gas of executing the bundle as a sandbox multicall
+    pub fn multicall_gas_estimate(&self) -> U256 {
+        U256::from(SANDBOX_MULTICALL_GAS_OVERHEAD)
+            + U256::from(SANDBOX_ORDER_GAS_OVERHEAD) * self.fill_amounts.len()
+            + self.gas_estimate
+    }
+
+    //Bundles can be merged if neither pays a pool before the calls that the other swaps through, since the fill amounts of every order are transferred before any call is made.
+    //A pool that was paid early would swap the amount in of both orders in the first swap, and the second swap would revert.
+    //Bundles that fill the same order are not merged, since the fill of the second bundle is simulated against the order left after the first fill
+    pub fn can_merge(&self, other: &SandboxLimitOrderExecutionBundle, max_gas: U256) -> bool {
+        self.order_id_bundles.iter().flatten().all(|order_id| {
+            !other
+                .order_id_bundles
+                .iter()
+                .flatten()
+                .any(|id| id == order_id)
+        }) && self
+            .transfer_addresses
+            .iter()
+            .all(|transfer_address| !other.pools.contains(transfer_address))
+            && other
+                .transfer_addresses
+                .iter()
+                .all(|transfer_address| !self.pools.contains(transfer_address))
+            && self.multicall_gas_estimate() + other.multicall_gas_estimate()
+                - U256::from(SANDBOX_MULTICALL_GAS_OVERHEAD)
+                <= max_gas
+    }
+
+    //Appends the orders of the other bundle as new order id bundles, along with their fill amounts, transfer addresses and calls.
+    //The calls of the other bundle are made after the calls of this bundle, matching the order that they were simulated in
+    pub fn merge(&mut self, other: SandboxLimitOrderExecutionBundle) {
+        for order_ids in other.order_id_bundles {
+            if !self.order_id_bundles[self.order_id_bundle_idx].is_empty() {
+                self.add_new_order_id_bundle();
+            }
+
+            for order_id in order_ids {
+                self.add_order_id_to_current_bundle(order_id);
+            }
+        }
+
+        self.fill_amounts.extend(other.fill_amounts);
+        self.transfer_addresses.extend(other.transfer_addresses);
+        self.calls.extend(other.calls);
+        self.gas_estimate += other.gas_estimate;
+        self.pools.extend(other.pools);
+    }
+
     pub fn add_route_to_calls(
         &mut self,
-        route: Vec<Pool>,
-        amounts_in: &[U256],
-        amounts_out: &[U256],
+        route: Vec<Hop>,
         order: &SandboxLimitOrder,
         sandbox_limit_order_router: H160,
     ) {
@@ -99,67 +165,44 @@ impl SandboxLimitOrderExecutionBundle {
 
         //Add calls for each swap throughout the route
         let mut token_in = order.token_in;
-        for (i, pool) in route.iter().enumerate() {
-            match pool {
-                Pool::UniswapV2(uniswap_v2_pool) => {
-                    let to_address = if i == route.len() - 1 {
-                        sandbox_limit_order_router
-                    } else {
-                        match route[i + 1] {
-                            Pool::UniswapV2(next_pool) => next_pool.address,
-                            Pool::UniswapV3(_) => sandbox_limit_order_router,
-                        }
-                    };
-
-                    self.add_uniswap_v2_swap_to_calls(
+        for (i, hop) in route.iter().enumerate() {
+            //Pairs that are paid by transferring the amount in before the swap receive the amount out of the previous swap directly
+            let to_address = match route
+                .get(i + 1)
+                .and_then(|hop| hop.transfer_before_swap_pool())
+            {
+                Some(next_pool) => next_pool.address(),
+                None => sandbox_limit_order_router,
+            };
+
+            for swap in hop.swaps.iter() {
+                self.pools.insert(swap.pool.address());
+
+                //The amount in of a split hop is held by the sandbox router, so pools that are paid before the swap are paid their part of the amount in
+                if hop.is_split() && swap.pool.transfer_before_swap() {
+                    self.calls.push(pool::transfer_call(
                         token_in,
-                        amounts_out[i],
-                        to_address,
-                        uniswap_v2_pool,
-                    );
+                        swap.pool.address(),
+                        swap.amount_in,
+                    ));
                 }
 
-                Pool::UniswapV3(uniswap_v3_pool) => {
-                    let to_address = if i == route.len() - 1 {
-                        sandbox_limit_order_router
-                    } else {
-                        match route[i + 1] {
-                            Pool::UniswapV2(next_pool) => next_pool.address,
-                            Pool::UniswapV3(_) => sandbox_limit_order_router,
-                        }
-                    };
-                    self.add_uniswap_v3_swap_to_calls(
-                        token_in,
-                        amounts_in[i],
-                        to_address,
-                        sandbox_limit_order_router,
-                        uniswap_v3_pool,
-                    );
-                }
+                self.calls.extend(swap.pool.swap_calls(
+                    token_in,
+                    swap.amount_in,
+                    swap.amount_out,
+                    to_address,
+                    sandbox_limit_order_router,
+                ));
             }
+
             //Update the token in
-            token_in = self.get_next_token_in(token_in, pool);
+            token_in = self.get_next_token_in(token_in, &hop.swaps[0].pool);
         }
     }
 
     fn get_next_token_in(&self, prev_token_in: H160, pool: &Pool) -> H160 {
-        match pool {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                if prev_token_in == uniswap_v2_pool.token_a {
-                    uniswap_v2_pool.token_b
-                } else {
-                    uniswap_v2_pool.token_a
-                }
-            }
-
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                if prev_token_in == uniswap_v3_pool.token_a {
-                    uniswap_v3_pool.token_b
-                } else {
-                    uniswap_v3_pool.token_a
-                }
-            }
-        }
+        pool.token_out(prev_token_in)
     }
 
     pub fn add_swap_to_calls(
@@ -169,71 +212,44 @@ impl SandboxLimitOrderExecutionBundle {
         amount_out: U256,
         to: H160,
         from: H160,
-        pool: &cfmms::pool::Pool,
+        pool: &Pool,
     ) {
-        match pool {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                self.add_uniswap_v2_swap_to_calls(token_in, amount_out, to, uniswap_v2_pool);
-            }
+        self.pools.insert(pool.address());
+        self.calls
+            .extend(pool.swap_calls(token_in, amount_in, amount_out, to, from));
+    }
+}
 
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                self.add_uniswap_v3_swap_to_calls(token_in, amount_in, to, from, uniswap_v3_pool);
-            }
+//Merges consecutive bundles into sandbox multicalls that stay under the max gas. Only consecutive bundles are merged so that the multicalls,
+//which are sent in nonce order, execute the orders in the same order that they were simulated against the markets
+pub fn batch_sandbox_limit_order_bundles(
+    slo_bundles: Vec<SandboxLimitOrderExecutionBundle>,
+    max_gas: U256,
+) -> Vec<SandboxLimitOrderExecutionBundle> {
+    let mut batches: Vec<SandboxLimitOrderExecutionBundle> = vec![];
+
+    for bundle in slo_bundles {
+        match batches.last_mut() {
+            Some(batch) if batch.can_merge(&bundle, max_gas) => batch.merge(bundle),
+            _ => batches.push(bundle),
         }
     }
 
-    pub fn add_uniswap_v2_swap_to_calls(
-        &mut self,
-        token_in: H160,
-        amount_out: U256,
-        to: H160,
-        pool: &cfmms::pool::UniswapV2Pool,
-    ) {
-        let (amount_0_out, amount_1_out) = if pool.token_a == token_in {
-            (U256::zero(), amount_out)
-        } else {
-            (amount_out, U256::zero())
-        };
-
-        self.add_call(Call::new(
-            pool.address,
-            pool.swap_calldata(amount_0_out, amount_1_out, to, vec![]),
-        ));
-    }
+    batches
+}
 
-    pub fn add_uniswap_v3_swap_to_calls(
-        &mut self,
-        token_in: H160,
-        amount_in: U256,
-        to: H160,
-        from: H160,
-        pool: &cfmms::pool::UniswapV3Pool,
-    ) {
-        let zero_for_one = pool.token_a == token_in;
-
-        let sqrt_price_limit_x_96 = if zero_for_one {
-            uniswap_v3_math::tick_math::MIN_SQRT_RATIO + 1
-        } else {
-            uniswap_v3_math::tick_math::MAX_SQRT_RATIO - 1
-        };
-
-        let calldata = ethers::abi::encode(&[
-            Token::Bool(zero_for_one),
-            Token::Address(token_in),
-            Token::Address(from),
-        ]);
-
-        self.add_call(Call::new(
-            pool.address,
-            pool.swap_calldata(
-                to,
-                zero_for_one,
-                I256::from_raw(amount_in),
-                sqrt_price_limit_x_96,
-                calldata,
-            ),
-        ));
-    }
+//Returns the max gas of a sandbox multicall, based on the gas limit of the latest block
+pub async fn get_max_batch_gas<M: Middleware>(
+    middleware: Arc<M>,
+) -> Result<U256, ExecutorError<M>> {
+    let block = middleware
+        .get_block(BlockNumber::Latest)
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
+
+    Ok(block
+        .map(|block| block.gas_limit / BLOCK_GAS_LIMIT_DIVISOR)
+        .unwrap_or_default())
 }
 
 impl Call {
@@ -248,7 +264,9 @@ pub async fn execute_sandbox_limit_order_bundles<M: Middleware>(
     pending_transactions_sender: Arc<tokio::sync::mpsc::Sender<(H256, Vec<H256>)>>,
     middleware: Arc<M>,
 ) -> Result<(), ExecutorError<M>> {
-    for bundle in slo_bundles {
+    let max_batch_gas = get_max_batch_gas(middleware.clone()).await?;
+
+    for bundle in batch_sandbox_limit_order_bundles(slo_bundles, max_batch_gas) {
         let order_id_bundles = bundle.order_id_bundles.clone();
 
         match transactions::construct_and_simulate_slo_execution_transaction(
@@ -289,3 +307,145 @@ pub async fn execute_sandbox_limit_order_bundles<M: Middleware>(
 
     Ok(())
 }
+
+//Sends the sandbox limit order execution bundles priced to land directly after the pending trigger transaction
+pub async fn backrun_sandbox_limit_order_bundles<M: Middleware>(
+    slo_bundles: Vec<SandboxLimitOrderExecutionBundle>,
+    trigger_tx: &Transaction,
+    configuration: &config::Config,
+    pending_transactions_sender: Arc<tokio::sync::mpsc::Sender<(H256, Vec<H256>)>>,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    let max_batch_gas = get_max_batch_gas(middleware.clone()).await?;
+
+    for bundle in batch_sandbox_limit_order_bundles(slo_bundles, max_batch_gas) {
+        let order_id_bundles = bundle.order_id_bundles.clone();
+
+        let tx = transactions::construct_slo_backrun_transaction(
+            configuration,
+            bundle,
+            trigger_tx,
+            middleware.clone(),
+        )
+        .await?;
+
+        let pending_tx_hash = transactions::sign_and_send_transaction(
+            tx,
+            &configuration.wallet_key,
+            &configuration.chain,
+            middleware.clone(),
+        )
+        .await?;
+
+        tracing::info!(
+            "Pending sandbox limit order backrun tx: {:?}, trigger tx: {:?}",
+            pending_tx_hash,
+            trigger_tx.hash
+        );
+
+        for order_ids in order_id_bundles {
+            pending_transactions_sender
+                .send((pending_tx_hash, order_ids))
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, H256, U256};
+
+    use super::{
+        batch_sandbox_limit_order_bundles, SandboxLimitOrderExecutionBundle,
+        SANDBOX_MULTICALL_GAS_OVERHEAD, SANDBOX_ORDER_GAS_OVERHEAD,
+    };
+
+    fn router() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    //Bundle of a single order that is transferred to the transfer address and swaps through the pools
+    fn bundle(
+        order_id: u64,
+        transfer_address: H160,
+        pools: &[H160],
+    ) -> SandboxLimitOrderExecutionBundle {
+        let mut bundle = SandboxLimitOrderExecutionBundle::new();
+        bundle.add_order_id_to_current_bundle(H256::from_low_u64_be(order_id));
+        bundle.add_fill_amount(order_id as u128);
+        bundle.add_transfer_address(transfer_address);
+        bundle.add_gas_estimate(U256::from(100000));
+        bundle.pools.extend(pools.iter().copied());
+        bundle
+    }
+
+    #[test]
+    fn test_batch_sandbox_limit_order_bundles() {
+        let pool_a = H160::from_low_u64_be(2);
+        let pool_b = H160::from_low_u64_be(3);
+
+        let batches = batch_sandbox_limit_order_bundles(
+            vec![
+                bundle(1, pool_a, &[pool_a]),
+                bundle(2, router(), &[pool_b]),
+                bundle(3, pool_b, &[pool_b]),
+            ],
+            U256::from(30000000),
+        );
+
+        //The third order pays pool b before the calls of the second order swap through it
+        assert_eq!(batches.len(), 2);
+        assert_eq!(
+            batches[0].order_id_bundles,
+            vec![
+                vec![H256::from_low_u64_be(1)],
+                vec![H256::from_low_u64_be(2)]
+            ]
+        );
+        assert_eq!(batches[0].fill_amounts, vec![1, 2]);
+        assert_eq!(batches[0].transfer_addresses, vec![pool_a, router()]);
+        assert_eq!(
+            batches[0].multicall_gas_estimate(),
+            U256::from(SANDBOX_MULTICALL_GAS_OVERHEAD + 2 * SANDBOX_ORDER_GAS_OVERHEAD + 200000)
+        );
+        assert_eq!(
+            batches[1].order_id_bundles,
+            vec![vec![H256::from_low_u64_be(3)]]
+        );
+    }
+
+    #[test]
+    fn test_batch_sandbox_limit_order_bundles_max_gas() {
+        let bundles = (1..=3)
+            .map(|order_id| bundle(order_id, router(), &[H160::from_low_u64_be(order_id + 1)]))
+            .collect();
+
+        //Two orders fit under the max gas, so the third order is sent in a separate multicall
+        let max_gas =
+            U256::from(SANDBOX_MULTICALL_GAS_OVERHEAD + 2 * SANDBOX_ORDER_GAS_OVERHEAD + 200000);
+        let batches = batch_sandbox_limit_order_bundles(bundles, max_gas);
+
+        assert_eq!(batches.len(), 2);
+        assert_eq!(batches[0].fill_amounts, vec![1, 2]);
+        assert_eq!(batches[1].fill_amounts, vec![3]);
+        assert!(batches
+            .iter()
+            .all(|batch| batch.multicall_gas_estimate() <= max_gas));
+    }
+
+    #[test]
+    fn test_batch_sandbox_limit_order_bundles_same_order() {
+        //The residual of a partially filled order is filled again in a separate multicall
+        let batches = batch_sandbox_limit_order_bundles(
+            vec![
+                bundle(1, router(), &[H160::from_low_u64_be(2)]),
+                bundle(1, router(), &[H160::from_low_u64_be(3)]),
+            ],
+            U256::from(30000000),
+        );
+
+        assert_eq!(batches.len(), 2);
+    }
+}
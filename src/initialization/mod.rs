@@ -4,6 +4,7 @@ use crate::{
     abi::{self, OrderPlacedFilter},
     config,
     error::ExecutorError,
+    middleware::FailoverClient,
     order::{self},
     state, transactions,
 };
@@ -11,7 +12,7 @@ use crate::{
 use ethers::{
     abi::RawLog,
     prelude::{EthLogDecode, NonceManagerMiddleware},
-    providers::{Http, Middleware, Provider},
+    providers::{Middleware, Provider},
     types::{BlockNumber, Filter, ValueOrArray, H160, H256},
 };
 
@@ -23,18 +24,25 @@ pub async fn initialize_coex<M: Middleware>() -> Result<
         state::State,
         Arc<Sender<(H256, Vec<H256>)>>,
         String,
-        Arc<NonceManagerMiddleware<Provider<Http>>>,
+        Arc<NonceManagerMiddleware<Provider<FailoverClient>>>,
     ),
     ExecutorError<M>,
 > {
     //Initialize a new configuration
     let configuration = config::Config::new();
-    //Initialize the providers
-    let provider: Provider<Http> = Provider::try_from(&configuration.http_endpoint)
-        .expect("Could not initialize HTTP provider");
+    //Initialize the providers, failing over across all configured http endpoints
+    let provider = Provider::new(
+        FailoverClient::new(
+            &configuration.http_endpoints(),
+            configuration.rpc_quorum,
+            &configuration.rpc_quorum_methods,
+            &configuration.rpc_quorum_call_targets(),
+        )
+        .expect("Could not initialize HTTP provider"),
+    );
     let stream_provider_endpoint = configuration.ws_endpoint.to_owned();
 
-    let nonce_manager = NonceManagerMiddleware::new(provider.clone(), configuration.wallet_address);
+    let nonce_manager = NonceManagerMiddleware::new(provider, configuration.wallet_address);
     let middleware = Arc::new(nonce_manager);
 
     //Initialize the markets and order structures
@@ -79,18 +87,40 @@ async fn initialize_state<M: 'static + Middleware>(
 
     tracing::info!("Initializing markets...");
     for (_, order) in active_orders.iter() {
+        state.transfer_taxes.update_from_order(order);
+
         //Add markets for order
         state
             .add_markets_for_order(
                 order,
                 configuration.weth_address,
+                &configuration.connectors,
                 &configuration.dexes,
+                &configuration.v2_pair_fees,
+                &configuration.algebra_dexes,
+                &configuration.solidly_dexes,
+                &configuration.curve_dexes,
+                &configuration.balancer_dexes,
                 middleware.clone(),
             )
             .await?;
 
+        state
+            .screen_tokens_for_order(
+                order,
+                configuration.weth_address,
+                configuration.sandbox_limit_order_router,
+                configuration.executor_address,
+                middleware.clone(),
+            )
+            .await;
+
         //Add order to market to affected orders
-        state.add_order_to_market_to_affected_orders(order, configuration.weth_address);
+        state.add_order_to_market_to_affected_orders(
+            order,
+            configuration.weth_address,
+            &configuration.connectors,
+        );
     }
 
     tracing::info!("Markets initialized");
@@ -8,9 +8,14 @@ pub mod events;
 pub mod execution;
 pub mod initialization;
 pub mod markets;
+pub mod mempool;
+pub mod middleware;
 pub mod order;
+pub mod pool;
+pub mod quoter;
 pub mod refresh;
 pub mod routing;
+pub mod screening;
 pub mod simulation;
 pub mod state;
 pub mod traces;
@@ -1,32 +1,43 @@
 use std::{collections::HashMap, sync::Arc};
 
-use cfmms::{dex::Dex, pool::Pool};
+use cfmms::dex::Dex;
 use ethers::{
     providers::Middleware,
-    types::{H160, U256},
+    types::{H160, U256, U512},
     utils::keccak256,
 };
 
-use crate::error::ExecutorError;
+use crate::{
+    error::ExecutorError,
+    pool::{
+        AlgebraDex, AutomatedMarketMaker, BalancerDex, CurveDex, Pool, SolidlyDex, UniswapV2PairFee,
+    },
+};
 
 pub type Market = HashMap<H160, Pool>;
 
 pub fn get_market_id(token_a: H160, token_b: H160) -> U256 {
     if token_a > token_b {
         U256::from_little_endian(&keccak256(
-            vec![token_a.as_bytes(), token_b.as_bytes()].concat(),
+            [token_a.as_bytes(), token_b.as_bytes()].concat(),
         ))
     } else {
         U256::from_little_endian(&keccak256(
-            vec![token_b.as_bytes(), token_a.as_bytes()].concat(),
+            [token_b.as_bytes(), token_a.as_bytes()].concat(),
         ))
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 pub async fn get_market<M: 'static + Middleware>(
     token_a: H160,
     token_b: H160,
     dexes: &[Dex],
+    v2_pair_fees: &HashMap<H160, UniswapV2PairFee>,
+    algebra_dexes: &[AlgebraDex],
+    solidly_dexes: &[SolidlyDex],
+    curve_dexes: &[CurveDex],
+    balancer_dexes: &[BalancerDex],
     middleware: Arc<M>,
 ) -> Result<Option<HashMap<H160, Pool>>, ExecutorError<M>> {
     let mut market = HashMap::new();
@@ -36,12 +47,52 @@ pub async fn get_market<M: 'static + Middleware>(
             .get_all_pools_for_pair(token_a, token_b, middleware.clone())
             .await?
         {
+            let v2_pair_fee = v2_pair_fees.get(&dex.factory_address()).copied();
+
             for pool in pools {
+                let pool =
+                    Pool::new_from_cfmms_pool(pool, dex, v2_pair_fee, middleware.clone()).await?;
                 market.insert(pool.address(), pool);
             }
         }
     }
 
+    for algebra_dex in algebra_dexes {
+        if let Some(algebra_pool) = algebra_dex
+            .get_pool_for_pair(token_a, token_b, middleware.clone())
+            .await?
+        {
+            market.insert(algebra_pool.address, Pool::Algebra(algebra_pool));
+        }
+    }
+
+    for solidly_dex in solidly_dexes {
+        for solidly_pool in solidly_dex
+            .get_pools_for_pair(token_a, token_b, middleware.clone())
+            .await?
+        {
+            market.insert(solidly_pool.address, Pool::Solidly(solidly_pool));
+        }
+    }
+
+    for curve_dex in curve_dexes {
+        for curve_pool in curve_dex
+            .get_pools_for_pair(token_a, token_b, middleware.clone())
+            .await?
+        {
+            market.insert(curve_pool.address, Pool::Curve(curve_pool));
+        }
+    }
+
+    for balancer_dex in balancer_dexes {
+        for balancer_pool in balancer_dex
+            .get_pools_for_pair(token_a, token_b, middleware.clone())
+            .await?
+        {
+            market.insert(balancer_pool.address, Pool::Balancer(balancer_pool));
+        }
+    }
+
     if !market.is_empty() {
         Ok(Some(market))
     } else {
@@ -49,28 +100,478 @@ pub async fn get_market<M: 'static + Middleware>(
     }
 }
 
+//Returns the best spot price of the base token denominated in the quote token across all pools in the market as a Q64.64 fixed point number.
+//Returns None if the market does not exist or no pool in the market has a price.
 pub fn get_best_market_price(
     buy: bool,
     base_token: H160,
     quote_token: H160,
     markets: &HashMap<U256, HashMap<H160, Pool>>,
-) -> f64 {
-    let mut best_price = if buy { f64::MAX } else { 0.0 };
-
-    let market_id = get_market_id(base_token, quote_token);
-    if let Some(market) = markets.get(&market_id) {
-        for (_, pool) in market {
-            let price = pool.calculate_price(base_token).unwrap_or(0.0);
-
-            if buy {
-                if price < best_price {
-                    best_price = price;
-                }
-            } else if price > best_price {
-                best_price = price;
+) -> Option<U256> {
+    //A token is always worth exactly one of itself (ie. the weth leg of an order where weth is the token in or token out)
+    if base_token == quote_token {
+        return Some(U256::one() << 64);
+    }
+
+    get_best_market_price_pool(buy, base_token, quote_token, markets).map(|(price, _)| price)
+}
+
+//Returns the pool with the best spot price of the base token denominated in the quote token, along with its price as a Q64.64 fixed point number.
+//Buy orders look for the lowest price and sell orders look for the highest price. Returns None if the market does not exist or no pool in the market has a price.
+pub fn get_best_market_price_pool(
+    buy: bool,
+    base_token: H160,
+    quote_token: H160,
+    markets: &HashMap<U256, HashMap<H160, Pool>>,
+) -> Option<(U256, &Pool)> {
+    let mut best_price_pool: Option<(U256, &Pool)> = None;
+
+    for pool in markets
+        .get(&get_market_id(base_token, quote_token))?
+        .values()
+    {
+        if let Some(price) = pool.price_64_x_64(base_token) {
+            let is_better_price = match best_price_pool {
+                Some((best_price, _)) if buy => price < best_price,
+                Some((best_price, _)) => price > best_price,
+                None => true,
+            };
+
+            if is_better_price {
+                best_price_pool = Some((price, pool));
+            }
+        }
+    }
+
+    best_price_pool
+}
+
+//Returns the best amount out for the amount in across all pools in the market, simulated with local pool state only.
+//Returns None if the market does not exist or no pool in the market can be simulated.
+pub fn get_best_market_amount_out(
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    markets: &HashMap<U256, HashMap<H160, Pool>>,
+) -> Option<U256> {
+    if token_in == token_out {
+        return Some(amount_in);
+    }
+
+    markets
+        .get(&get_market_id(token_in, token_out))?
+        .values()
+        .filter_map(|pool| pool.simulate_swap_local(token_in, amount_in))
+        .max()
+}
+
+//Converts a concentrated liquidity sqrt price to the normalized price of the base token as a Q64.64 fixed point number.
+//The price of token_a in token_b is sqrt_price^2 / 2^192
+pub fn sqrt_price_64_x_64(
+    sqrt_price: U256,
+    base_is_token_a: bool,
+    token_a_decimals: u8,
+    token_b_decimals: u8,
+) -> Option<U256> {
+    let sqrt_price = U512::from(sqrt_price);
+    let q192 = U512::one() << 192;
+
+    if base_is_token_a {
+        normalized_price_64_x_64(
+            q192,
+            token_a_decimals,
+            sqrt_price * sqrt_price,
+            token_b_decimals,
+            false,
+        )
+    } else {
+        normalized_price_64_x_64(
+            sqrt_price * sqrt_price,
+            token_b_decimals,
+            q192,
+            token_a_decimals,
+            false,
+        )
+    }
+}
+
+//Calculates (amount_quote / 10**quote_decimals) / (amount_base / 10**base_decimals) as a Q64.64 fixed point number, rounding down unless round_up is true.
+//Returns None if the base amount is zero or the result does not fit in a U256.
+pub fn normalized_price_64_x_64(
+    amount_base: U512,
+    base_decimals: u8,
+    amount_quote: U512,
+    quote_decimals: u8,
+    round_up: bool,
+) -> Option<U256> {
+    let numerator = amount_quote
+        .checked_mul(U512::from(10).checked_pow(U512::from(base_decimals))?)?
+        .checked_mul(U512::one() << 64)?;
+    let denominator =
+        amount_base.checked_mul(U512::from(10).checked_pow(U512::from(quote_decimals))?)?;
+
+    if denominator.is_zero() {
+        return None;
+    }
+
+    let (mut price, remainder) = numerator.div_mod(denominator);
+    if round_up && !remainder.is_zero() {
+        price += U512::one();
+    }
+
+    U256::try_from(price).ok()
+}
+
+//Multiplies two Q64.64 fixed point numbers, rounding down. Returns None if the result does not fit in a U256.
+pub fn mul_64_x_64(x: U256, y: U256) -> Option<U256> {
+    U256::try_from((U512::from(x) * U512::from(y)) >> 64).ok()
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{collections::HashMap, str::FromStr, sync::Arc};
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use crate::pool::{AutomatedMarketMaker, Pool, SolidlyPool, UniswapV2Pool, UniswapV3Pool};
+    use ethers::types::{H160, U256, U512};
+
+    use crate::{pool::uniswap_v3::TickData, simulation::div_uu};
+
+    use super::{
+        get_best_market_amount_out, get_best_market_price, get_market_id, mul_64_x_64,
+        normalized_price_64_x_64,
+    };
+
+    //Port of the router's V2 spot price: both reserves are converted to a common base of 18 decimals,
+    //then the quote reserve is divided by the base reserve with ABDK divUU
+    pub(crate) fn router_v2_spot_price_64_x_64(
+        reserve_base: u128,
+        base_decimals: u8,
+        reserve_quote: u128,
+        quote_decimals: u8,
+    ) -> U256 {
+        let to_common_base = |reserve: u128, decimals: u8| {
+            if decimals > 18 {
+                U256::from(reserve) / U256::exp10(decimals as usize - 18)
+            } else {
+                U256::from(reserve) * U256::exp10(18 - decimals as usize)
             }
+        };
+
+        U256::from(
+            div_uu(
+                to_common_base(reserve_quote, quote_decimals),
+                to_common_base(reserve_base, base_decimals),
+            )
+            .unwrap(),
+        )
+    }
+
+    //Port of ConveyorMath mul64x64, which the router uses to combine the a -> weth and weth -> b prices
+    pub(crate) fn router_mul_64_x_64(x: U256, y: U256) -> U256 {
+        let answer = (x * y) >> 64;
+        assert!(answer <= U256::from(u128::MAX));
+        answer
+    }
+
+    fn usdc() -> H160 {
+        H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap()
+    }
+
+    fn weth() -> H160 {
+        H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap()
+    }
+
+    fn usdc_weth_v2_pool(reserve_0: u128, reserve_1: u128) -> Pool {
+        Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_str("0xB4e16d0168e52d35CaCD2c6185b44281Ec28C9Dc").unwrap(),
+            token_a: usdc(),
+            token_a_decimals: 6,
+            token_b: weth(),
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+        }))
+    }
+
+    fn usdc_weth_v3_pool(sqrt_price: U256) -> Pool {
+        Pool::UniswapV3(UniswapV3Pool::new(cfmms::pool::UniswapV3Pool {
+            address: H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+            token_a: usdc(),
+            token_a_decimals: 6,
+            token_b: weth(),
+            token_b_decimals: 18,
+            sqrt_price,
+            fee: 500,
+            ..Default::default()
+        }))
+    }
+
+    #[test]
+    fn test_price_64_x_64_v2() {
+        //Expected values are the divUU results used by the on chain spot price calculation
+        let pool = usdc_weth_v2_pool(47092140895915, 28396598565590008529300);
+
+        assert_eq!(
+            router_v2_spot_price_64_x_64(47092140895915, 6, 28396598565590008529300, 18),
+            U256::from(11123401407064628_u128)
+        );
+        assert_eq!(
+            router_v2_spot_price_64_x_64(28396598565590008529300, 18, 47092140895915, 6),
+            U256::from(30591574867092394336528_u128)
+        );
+
+        assert_eq!(
+            pool.price_64_x_64(usdc()),
+            Some(U256::from(11123401407064628_u128))
+        );
+        assert_eq!(
+            pool.price_64_x_64(weth()),
+            Some(U256::from(30591574867092394336528_u128))
+        );
+    }
+
+    #[test]
+    fn test_price_64_x_64_v2_router_port() {
+        let mut rng = StdRng::seed_from_u64(28);
+
+        //Reserves above 18 decimals lose precision when the router converts them to the common base, so only tokens up to 18 decimals are compared
+        for _ in 0..1_000 {
+            let (decimals_a, decimals_b) = (rng.gen_range(0..=18), rng.gen_range(0..=18));
+            let reserve_a = rng.gen_range(1..=u64::MAX as u128);
+            let reserve_b = rng.gen_range(1..=u64::MAX as u128);
+
+            let pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+                token_a: usdc(),
+                token_a_decimals: decimals_a,
+                token_b: weth(),
+                token_b_decimals: decimals_b,
+                reserve_0: reserve_a,
+                reserve_1: reserve_b,
+                ..Default::default()
+            }));
+
+            assert_eq!(
+                pool.price_64_x_64(usdc()),
+                Some(router_v2_spot_price_64_x_64(
+                    reserve_a, decimals_a, reserve_b, decimals_b
+                ))
+            );
+            assert_eq!(
+                pool.price_64_x_64(weth()),
+                Some(router_v2_spot_price_64_x_64(
+                    reserve_b, decimals_b, reserve_a, decimals_a
+                ))
+            );
         }
     }
 
-    best_price
+    #[test]
+    fn test_price_64_x_64_v3() {
+        let pool =
+            usdc_weth_v3_pool(U256::from_dec_str("1982660183028911924478930036489469").unwrap());
+
+        assert_eq!(
+            pool.price_64_x_64(usdc()),
+            Some(U256::from(11551998526804526_u128))
+        );
+        assert_eq!(
+            pool.price_64_x_64(weth()),
+            Some(U256::from(29456579840394610783631_u128))
+        );
+
+        //A sqrt price of 2^96 with equal decimals is a price of exactly one in both directions
+        let mut pool = usdc_weth_v3_pool(U256::one() << 96);
+        if let Pool::UniswapV3(uniswap_v3_pool) = &mut pool {
+            uniswap_v3_pool.token_b_decimals = 6;
+        }
+
+        assert_eq!(pool.price_64_x_64(usdc()), Some(U256::one() << 64));
+        assert_eq!(pool.price_64_x_64(weth()), Some(U256::one() << 64));
+    }
+
+    #[test]
+    fn test_price_64_x_64_empty_pool() {
+        let pool = usdc_weth_v2_pool(0, 0);
+
+        assert_eq!(pool.price_64_x_64(usdc()), None);
+        assert_eq!(pool.price_64_x_64(weth()), None);
+    }
+
+    #[test]
+    fn test_price_64_x_64_solidly() {
+        let mut pool = SolidlyPool {
+            token_a: weth(),
+            token_a_decimals: 18,
+            token_b: usdc(),
+            token_b_decimals: 6,
+            reserve_0: 1_000_000_000_000_000_000_000_000,
+            reserve_1: 1_000_000_000_000,
+            stable: true,
+            ..Default::default()
+        };
+
+        //Balanced stable pairs are priced at exactly one, regardless of token decimals
+        assert_eq!(
+            Pool::Solidly(pool).price_64_x_64(weth()),
+            Some(U256::one() << 64)
+        );
+
+        //Stable pairs stay much closer to the peg than volatile pairs with the same reserves
+        pool.reserve_1 = 2_000_000_000_000;
+        let stable_price = Pool::Solidly(pool).price_64_x_64(weth()).unwrap();
+        pool.stable = false;
+        let volatile_price = Pool::Solidly(pool).price_64_x_64(weth()).unwrap();
+
+        assert_eq!(volatile_price, U256::from(2) << 64);
+        assert!(stable_price > U256::one() << 64 && stable_price < volatile_price);
+    }
+
+    #[test]
+    fn test_get_best_market_price() {
+        let v2_pool = usdc_weth_v2_pool(47092140895915, 28396598565590008529300);
+        let v3_pool =
+            usdc_weth_v3_pool(U256::from_dec_str("1982660183028911924478930036489469").unwrap());
+
+        let mut markets = HashMap::new();
+        markets.insert(
+            get_market_id(usdc(), weth()),
+            HashMap::from([
+                (v2_pool.address(), v2_pool.clone()),
+                (v3_pool.address(), v3_pool.clone()),
+            ]),
+        );
+
+        //Buy orders look for the lowest price, sell orders look for the highest price
+        assert_eq!(
+            get_best_market_price(true, weth(), usdc(), &markets),
+            Some(U256::from(29456579840394610783631_u128))
+        );
+        assert_eq!(
+            get_best_market_price(false, weth(), usdc(), &markets),
+            Some(U256::from(30591574867092394336528_u128))
+        );
+
+        assert_eq!(
+            get_best_market_price(true, weth(), weth(), &markets),
+            Some(U256::one() << 64)
+        );
+        assert_eq!(
+            get_best_market_price(true, weth(), H160::zero(), &markets),
+            None
+        );
+    }
+
+    #[test]
+    fn test_normalized_price_64_x_64_rounding() {
+        //1 / 3 is not representable in Q64.64, so rounding up adds a single unit
+        let rounded_down =
+            normalized_price_64_x_64(U512::from(3), 18, U512::from(1), 18, false).unwrap();
+        let rounded_up =
+            normalized_price_64_x_64(U512::from(3), 18, U512::from(1), 18, true).unwrap();
+
+        assert_eq!(rounded_down, U256::from(6148914691236517205_u128));
+        assert_eq!(rounded_up, rounded_down + 1);
+
+        //Exact prices are not affected by rounding
+        assert_eq!(
+            normalized_price_64_x_64(U512::from(4), 18, U512::from(2), 18, true),
+            Some(U256::one() << 63)
+        );
+    }
+
+    #[test]
+    fn test_mul_64_x_64() {
+        let one = U256::one() << 64;
+        let one_and_a_half = U256::from(3) << 63;
+
+        assert_eq!(mul_64_x_64(one, one_and_a_half), Some(one_and_a_half));
+        assert_eq!(
+            mul_64_x_64(one_and_a_half, one_and_a_half),
+            Some(U256::from(9) << 62)
+        );
+        assert_eq!(
+            mul_64_x_64(
+                U256::from(30591574867092394336528_u128),
+                U256::from(11068046444225730_u128)
+            ),
+            Some(U256::from(18354944920255434993_u128))
+        );
+        assert_eq!(mul_64_x_64(U256::MAX, U256::MAX), None);
+    }
+
+    #[test]
+    fn test_simulate_swap_local() {
+        let v2_pool = usdc_weth_v2_pool(47092140895915, 28396598565590008529300);
+        assert_eq!(
+            v2_pool.simulate_swap_local(usdc(), U256::from(1_000_000)),
+            Some(U256::from(601191783424756_u128))
+        );
+        assert_eq!(
+            usdc_weth_v2_pool(0, 0).simulate_swap_local(usdc(), U256::from(1_000_000)),
+            None
+        );
+
+        //Pools without tick data can not be simulated locally, even with in range liquidity
+        let mut v3_pool =
+            usdc_weth_v3_pool(U256::from_dec_str("1982660183028911924478930036489469").unwrap());
+        if let Pool::UniswapV3(uniswap_v3_pool) = &mut v3_pool {
+            uniswap_v3_pool.liquidity = 20_000_000_000_000_000_000;
+            uniswap_v3_pool.tick_spacing = 10;
+        }
+        assert_eq!(
+            v3_pool.simulate_swap_local(usdc(), U256::from(1_000_000)),
+            None
+        );
+
+        //Larger swaps have a worse average price than smaller swaps. Every tick bitmap word is tracked and has no initialized ticks
+        if let Pool::UniswapV3(uniswap_v3_pool) = &mut v3_pool {
+            uniswap_v3_pool.tick_data = Arc::new(TickData {
+                word_range: Some((i16::MIN, i16::MAX)),
+                ..Default::default()
+            });
+        }
+        let small_amount_out = v3_pool
+            .simulate_swap_local(usdc(), U256::from(1_000_000))
+            .unwrap();
+        let large_amount_out = v3_pool
+            .simulate_swap_local(usdc(), U256::from(1_000_000_000_000_u128))
+            .unwrap();
+        assert!(large_amount_out < small_amount_out * 1_000_000);
+    }
+
+    #[test]
+    fn test_get_best_market_amount_out() {
+        let small_pool = usdc_weth_v2_pool(47092140895915, 28396598565590008529300);
+        let mut large_pool = usdc_weth_v2_pool(470921408959150, 283965985655900085293000);
+        if let Pool::UniswapV2(uniswap_v2_pool) = &mut large_pool {
+            uniswap_v2_pool.address = H160::from_low_u64_be(1);
+        }
+
+        let mut markets = HashMap::new();
+        markets.insert(
+            get_market_id(usdc(), weth()),
+            HashMap::from([
+                (small_pool.address(), small_pool.clone()),
+                (large_pool.address(), large_pool.clone()),
+            ]),
+        );
+
+        //At the same spot price, the deeper pool has less price impact
+        let amount_in = U256::from(10_000_000_000_000_u128);
+        assert_eq!(
+            get_best_market_amount_out(usdc(), weth(), amount_in, &markets),
+            large_pool.simulate_swap_local(usdc(), amount_in)
+        );
+        assert_eq!(
+            get_best_market_amount_out(weth(), weth(), amount_in, &markets),
+            Some(amount_in)
+        );
+        assert_eq!(
+            get_best_market_amount_out(usdc(), H160::zero(), amount_in, &markets),
+            None
+        );
+    }
 }
@@ -0,0 +1,851 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use ethers::{
+    abi::Token,
+    providers::{Middleware, Provider, StreamExt, Ws},
+    types::{Transaction, H160, H256, U256},
+};
+use tokio::sync::mpsc::{Receiver, Sender};
+
+use crate::{
+    abi, config,
+    error::ExecutorError,
+    execution::{self, limit_order::LimitOrderExecutionBundle},
+    markets::{self, Market},
+    pool::AutomatedMarketMaker,
+    simulation, state,
+};
+
+//Max number of decoded pending transactions waiting to be handled by the main loop
+pub const PENDING_TRANSACTION_CHANNEL_SIZE: usize = 256;
+
+//A swap decoded from a pending transaction, the pool is only simulated if it is tracked in `State.pool_address_to_market_ids`
+#[derive(Debug, Clone, Copy)]
+pub struct PendingSwap {
+    pub pool_address: H160,
+    pub market_id: U256,
+    pub token_in: H160,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct PendingRoute {
+    pub amount_in: U256,
+    pub amount_out_min: U256,
+    //Swaps along the route up until the first pool that is not tracked
+    pub swaps: Vec<PendingSwap>,
+    //True if every pool along the route is tracked, meaning the amount out of the route can be checked against the amount out min
+    pub complete: bool,
+}
+
+impl PendingRoute {
+    //Returns the swaps along the route up until the first pool that is not tracked, or None if the first pool is not tracked
+    pub fn tracked(mut self, state: &state::State) -> Option<PendingRoute> {
+        if let Some(untracked_swap) = self.swaps.iter().position(|swap| {
+            !state
+                .pool_address_to_market_ids
+                .get(&swap.pool_address)
+                .is_some_and(|market_ids| market_ids.contains(&swap.market_id))
+        }) {
+            self.swaps.truncate(untracked_swap);
+            self.complete = false;
+        }
+
+        if self.swaps.is_empty() {
+            None
+        } else {
+            Some(self)
+        }
+    }
+}
+
+//Pending transaction with the route that it swaps through
+#[derive(Debug, Clone)]
+pub struct PendingTransaction {
+    pub tx: Transaction,
+    pub route: PendingRoute,
+}
+
+//Tracks the pending transactions that were already handled by the main loop
+#[derive(Debug, Default)]
+pub struct MempoolWatcher {
+    //Pending transactions that have already been handled
+    handled_transactions: HashSet<H256>,
+}
+
+impl MempoolWatcher {
+    pub fn new() -> MempoolWatcher {
+        MempoolWatcher::default()
+    }
+
+    //Clears the handled transactions, called on every new block since pending transactions are either included or dropped
+    pub fn clear_handled_transactions(&mut self) {
+        self.handled_transactions.clear();
+    }
+}
+
+//Spawns a task that fetches pending transactions and decodes the routes that they swap through, so that the RPC calls that this takes do not hold up block handling.
+//Decoded transactions are sent to the main loop, which simulates them against the markets
+pub fn spawn_pending_transaction_decoder<M: 'static + Middleware>(
+    stream_provider_endpoint: String,
+    wallet_address: H160,
+    middleware: Arc<M>,
+) -> Receiver<PendingTransaction> {
+    let (pending_transaction_sender, pending_transaction_receiver) =
+        tokio::sync::mpsc::channel(PENDING_TRANSACTION_CHANNEL_SIZE);
+
+    tokio::spawn(async move {
+        if let Err(err) = decode_pending_transactions(
+            stream_provider_endpoint,
+            wallet_address,
+            pending_transaction_sender,
+            middleware,
+        )
+        .await
+        {
+            tracing::warn!(
+                "Pending transaction stream ended, mempool watching disabled: {:?}",
+                err
+            );
+        }
+    });
+
+    pending_transaction_receiver
+}
+
+async fn decode_pending_transactions<M: Middleware>(
+    stream_provider_endpoint: String,
+    wallet_address: H160,
+    pending_transaction_sender: Sender<PendingTransaction>,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    let stream_provider = Provider::<Ws>::connect(stream_provider_endpoint).await?;
+    let mut pending_tx_stream = stream_provider.subscribe_pending_txs().await?;
+    let mut pending_route_decoder = PendingRouteDecoder::new();
+
+    while let Some(tx_hash) = pending_tx_stream.next().await {
+        //Pending transactions can be dropped before they are fetched, so errors are logged instead of stopping the stream
+        let tx = match stream_provider.get_transaction(tx_hash).await {
+            Ok(Some(tx)) => tx,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::debug!(
+                    "Could not fetch pending transaction {:?}: {:?}",
+                    tx_hash,
+                    err
+                );
+                continue;
+            }
+        };
+
+        //Transactions sent by the executor do not need to be backrun
+        if tx.from == wallet_address {
+            continue;
+        }
+
+        let route = match pending_route_decoder
+            .decode_pending_route(&tx, middleware.clone())
+            .await
+        {
+            Ok(Some(route)) => route,
+            Ok(None) => continue,
+            Err(err) => {
+                tracing::warn!(
+                    "Could not decode pending transaction {:?}: {:?}",
+                    tx_hash,
+                    err
+                );
+                continue;
+            }
+        };
+
+        //The main loop has stopped if the receiver was dropped
+        if pending_transaction_sender
+            .send(PendingTransaction { tx, route })
+            .await
+            .is_err()
+        {
+            break;
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Default)]
+pub struct PendingRouteDecoder {
+    //Router address to the factory that the router swaps through, None if the router does not expose a factory
+    router_factories: HashMap<H160, Option<H160>>,
+    //(factory, token_a, token_b, fee) to pool address, fee is 0 for V2 factories
+    factory_pools: HashMap<(H160, H160, H160, u32), H160>,
+}
+
+impl PendingRouteDecoder {
+    pub fn new() -> PendingRouteDecoder {
+        PendingRouteDecoder::default()
+    }
+
+    //Decodes swaps through UniswapV2 style routers and UniswapV3 swap routers into a route of pools
+    pub async fn decode_pending_route<M: Middleware>(
+        &mut self,
+        tx: &Transaction,
+        middleware: Arc<M>,
+    ) -> Result<Option<PendingRoute>, ExecutorError<M>> {
+        let router = match tx.to {
+            Some(router) => router,
+            None => return Ok(None),
+        };
+
+        if tx.input.len() < 4 {
+            return Ok(None);
+        }
+        let (selector, input) = tx.input.split_at(4);
+
+        for function in abi::IUNISWAPV2ROUTER_ABI.functions() {
+            if function.short_signature() != selector || function.name == "factory" {
+                continue;
+            }
+
+            let tokens = match function.decode_input(input) {
+                Ok(tokens) => tokens,
+                Err(_) => return Ok(None),
+            };
+
+            //Swaps from ETH use the transaction value as the amount in
+            let (amount_in, amount_out_min, path) = if function.name.starts_with("swapExactETH") {
+                (Some(tx.value), tokens[0].clone().into_uint(), &tokens[1])
+            } else {
+                (
+                    tokens[0].clone().into_uint(),
+                    tokens[1].clone().into_uint(),
+                    &tokens[2],
+                )
+            };
+
+            let path = path
+                .clone()
+                .into_array()
+                .unwrap_or_default()
+                .into_iter()
+                .filter_map(Token::into_address)
+                .collect::<Vec<H160>>();
+
+            if let (Some(amount_in), Some(amount_out_min)) = (amount_in, amount_out_min) {
+                if !is_swappable_amount(amount_in) {
+                    return Ok(None);
+                }
+
+                return self
+                    .decode_v2_path(router, amount_in, amount_out_min, &path, middleware)
+                    .await;
+            }
+
+            return Ok(None);
+        }
+
+        for (function, params_offset) in [
+            (
+                abi::IUNISWAPV3SWAPROUTER_ABI.function("exactInputSingle"),
+                0,
+            ),
+            //SwapRouter02 does not include a deadline in the params
+            (
+                abi::IUNISWAPV3SWAPROUTER02_ABI.function("exactInputSingle"),
+                1,
+            ),
+        ] {
+            let function = match function {
+                Ok(function) => function,
+                Err(_) => continue,
+            };
+
+            if function.short_signature() != selector {
+                continue;
+            }
+
+            let params = match function.decode_input(input) {
+                Ok(tokens) => tokens[0].clone().into_tuple().unwrap_or_default(),
+                Err(_) => return Ok(None),
+            };
+
+            if let (
+                Some(token_in),
+                Some(token_out),
+                Some(fee),
+                Some(amount_in),
+                Some(amount_out_min),
+            ) = (
+                params[0].clone().into_address(),
+                params[1].clone().into_address(),
+                params[2].clone().into_uint(),
+                params[5 - params_offset].clone().into_uint(),
+                params[6 - params_offset].clone().into_uint(),
+            ) {
+                if !is_swappable_amount(amount_in) {
+                    return Ok(None);
+                }
+
+                return self
+                    .decode_v3_swap(
+                        router,
+                        token_in,
+                        token_out,
+                        fee.as_u32(),
+                        amount_in,
+                        amount_out_min,
+                        middleware,
+                    )
+                    .await;
+            }
+
+            return Ok(None);
+        }
+
+        Ok(None)
+    }
+
+    async fn decode_v2_path<M: Middleware>(
+        &mut self,
+        router: H160,
+        amount_in: U256,
+        amount_out_min: U256,
+        path: &[H160],
+        middleware: Arc<M>,
+    ) -> Result<Option<PendingRoute>, ExecutorError<M>> {
+        let factory = match self.get_router_factory(router, middleware.clone()).await? {
+            Some(factory) => factory,
+            None => return Ok(None),
+        };
+
+        let mut pending_route = PendingRoute {
+            amount_in,
+            amount_out_min,
+            swaps: vec![],
+            complete: true,
+        };
+
+        for hop in path.windows(2) {
+            let pool_address = self
+                .get_factory_pool(factory, hop[0], hop[1], None, middleware.clone())
+                .await?;
+
+            pending_route.swaps.push(PendingSwap {
+                pool_address,
+                market_id: markets::get_market_id(hop[0], hop[1]),
+                token_in: hop[0],
+            });
+        }
+
+        if pending_route.swaps.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(pending_route))
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn decode_v3_swap<M: Middleware>(
+        &mut self,
+        router: H160,
+        token_in: H160,
+        token_out: H160,
+        fee: u32,
+        amount_in: U256,
+        amount_out_min: U256,
+        middleware: Arc<M>,
+    ) -> Result<Option<PendingRoute>, ExecutorError<M>> {
+        let factory = match self.get_router_factory(router, middleware.clone()).await? {
+            Some(factory) => factory,
+            None => return Ok(None),
+        };
+
+        let pool_address = self
+            .get_factory_pool(factory, token_in, token_out, Some(fee), middleware)
+            .await?;
+
+        Ok(Some(PendingRoute {
+            amount_in,
+            amount_out_min,
+            swaps: vec![PendingSwap {
+                pool_address,
+                market_id: markets::get_market_id(token_in, token_out),
+                token_in,
+            }],
+            complete: true,
+        }))
+    }
+
+    async fn get_router_factory<M: Middleware>(
+        &mut self,
+        router: H160,
+        middleware: Arc<M>,
+    ) -> Result<Option<H160>, ExecutorError<M>> {
+        if let Some(factory) = self.router_factories.get(&router) {
+            return Ok(*factory);
+        }
+
+        //Contracts that do not expose a factory are cached as None so that they are not queried again
+        let factory = abi::IUniswapV2Router::new(router, middleware)
+            .factory()
+            .call()
+            .await
+            .ok();
+
+        self.router_factories.insert(router, factory);
+
+        Ok(factory)
+    }
+
+    //Gets the pool from the factory, V2 factories are queried with getPair when the fee is None
+    async fn get_factory_pool<M: Middleware>(
+        &mut self,
+        factory: H160,
+        token_a: H160,
+        token_b: H160,
+        fee: Option<u32>,
+        middleware: Arc<M>,
+    ) -> Result<H160, ExecutorError<M>> {
+        let key = (factory, token_a, token_b, fee.unwrap_or_default());
+        if let Some(pool_address) = self.factory_pools.get(&key) {
+            return Ok(*pool_address);
+        }
+
+        let pool_address = if let Some(fee) = fee {
+            abi::IUniswapV3Factory::new(factory, middleware)
+                .get_pool(token_a, token_b, fee)
+                .call()
+                .await
+        } else {
+            abi::IUniswapV2Factory::new(factory, middleware)
+                .get_pair(token_a, token_b)
+                .call()
+                .await
+        }
+        .unwrap_or_default();
+
+        self.factory_pools.insert(key, pool_address);
+
+        Ok(pool_address)
+    }
+}
+
+//Pools hold their reserves in at most 128 bits, so swaps of larger amounts revert and are not decoded
+fn is_swappable_amount(amount_in: U256) -> bool {
+    amount_in <= U256::from(u128::MAX)
+}
+
+//Applies the pending route to the markets in place with local pool math, returning the markets along the route as they were before the route was applied.
+//Only the markets along the route are cloned, so that they can be restored once the orders that the route affects are simulated.
+//Returns None and leaves the markets unchanged if a pool along the route can not be simulated locally, or if the pending transaction would revert
+//because the amount out of the route is less than the amount out min.
+pub fn apply_pending_route(
+    pending_route: &PendingRoute,
+    markets: &mut HashMap<U256, Market>,
+) -> Option<HashMap<U256, Market>> {
+    let mut original_markets = HashMap::new();
+    for swap in pending_route.swaps.iter() {
+        let market = markets.get(&swap.market_id)?;
+        original_markets
+            .entry(swap.market_id)
+            .or_insert_with(|| market.clone());
+    }
+
+    let mut amount_in = pending_route.amount_in;
+    for swap in pending_route.swaps.iter() {
+        let amount_out = markets
+            .get_mut(&swap.market_id)
+            .and_then(|market| market.get_mut(&swap.pool_address))
+            .and_then(|pool| pool.simulate_swap_local_mut(swap.token_in, amount_in));
+
+        amount_in = match amount_out {
+            Some(amount_out) => amount_out,
+            None => {
+                restore_markets(markets, original_markets);
+                return None;
+            }
+        };
+    }
+
+    if pending_route.complete && amount_in < pending_route.amount_out_min {
+        restore_markets(markets, original_markets);
+        return None;
+    }
+
+    Some(original_markets)
+}
+
+//Puts back the markets returned by `apply_pending_route`
+pub fn restore_markets(
+    markets: &mut HashMap<U256, Market>,
+    original_markets: HashMap<U256, Market>,
+) {
+    markets.extend(original_markets);
+}
+
+//Checks if a pending transaction swaps through tracked pools, and if so, simulates the orders that the swap moves to execution price
+//and sends execution transactions priced to land directly after the pending transaction.
+//The route is applied to the markets of the state in place and the markets are restored before returning, so the markets are not cloned for every pending swap
+pub async fn handle_pending_transaction<M: 'static + Middleware>(
+    pending_transaction: PendingTransaction,
+    mempool_watcher: &mut MempoolWatcher,
+    configuration: &config::Config,
+    state: &mut state::State,
+    gas_price: U256,
+    pending_transactions_sender: Arc<tokio::sync::mpsc::Sender<(H256, Vec<H256>)>>,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    let PendingTransaction { tx, route } = pending_transaction;
+
+    //Skip transactions that have already been handled
+    if !mempool_watcher.handled_transactions.insert(tx.hash) {
+        return Ok(());
+    }
+
+    let pending_route = match route.tracked(state) {
+        Some(pending_route) => pending_route,
+        None => return Ok(()),
+    };
+
+    //Take the markets out of the state so that the state can be borrowed by the orders while the markets are updated
+    let mut markets = std::mem::take(&mut state.markets);
+
+    let result = match apply_pending_route(&pending_route, &mut markets) {
+        Some(original_markets) => {
+            backrun_pending_route(
+                &tx,
+                original_markets,
+                &mut markets,
+                configuration,
+                state,
+                gas_price,
+                pending_transactions_sender,
+                middleware,
+            )
+            .await
+        }
+        None => Ok(()),
+    };
+
+    state.markets = markets;
+
+    result
+}
+
+//Simulates the orders that the applied pending route moves to execution price and sends them behind the pending transaction.
+//The markets are restored to the markets before the route before the orders are sent
+#[allow(clippy::too_many_arguments)]
+async fn backrun_pending_route<M: 'static + Middleware>(
+    tx: &Transaction,
+    original_markets: HashMap<U256, Market>,
+    markets: &mut HashMap<U256, Market>,
+    configuration: &config::Config,
+    state: &state::State,
+    gas_price: U256,
+    pending_transactions_sender: Arc<tokio::sync::mpsc::Sender<(H256, Vec<H256>)>>,
+    middleware: Arc<M>,
+) -> Result<(), ExecutorError<M>> {
+    let affected_markets = original_markets.keys().copied().collect::<HashSet<U256>>();
+
+    tracing::debug!(
+        "Pending swap {:?} affects {:?} markets",
+        tx.hash,
+        affected_markets.len()
+    );
+
+    let (mut simulated_markets, mut slo_at_execution_price, mut lo_at_execution_price) =
+        execution::group_orders_at_execution_price_in_markets(
+            state,
+            markets,
+            affected_markets,
+            configuration.weth_address,
+            &configuration.limit_order_router_dexes,
+            &configuration.connectors,
+            configuration.min_fill_percent,
+        );
+
+    restore_markets(markets, original_markets);
+
+    //Orders that are already at execution price were handled when the last block was checked, only pre-position orders that the pending swap moves to execution price
+    slo_at_execution_price.retain(|_, order| {
+        !order.can_execute(
+            markets,
+            &configuration.connectors,
+            configuration.min_fill_percent,
+        )
+    });
+    lo_at_execution_price.retain(|_, order| {
+        !order.can_execute(
+            order.buy,
+            markets,
+            configuration.weth_address,
+            &configuration.limit_order_router_dexes,
+        )
+    });
+
+    if !configuration.taxed_tokens {
+        execution::remove_taxed_orders(
+            &mut slo_at_execution_price,
+            &mut lo_at_execution_price,
+            &state.transfer_taxes,
+        );
+    }
+
+    if slo_at_execution_price.is_empty() && lo_at_execution_price.is_empty() {
+        return Ok(());
+    }
+
+    //The estimated gas of each route is priced in weth, so that orders are only executed when the remainder paid to the coex covers the swaps
+    let sandbox_execution_bundles = simulation::simulate_and_batch_sandbox_limit_orders(
+        slo_at_execution_price,
+        &mut simulated_markets,
+        configuration.weth_address,
+        &configuration.connectors,
+        configuration.min_fill_percent,
+        configuration.executor_address,
+        configuration.sandbox_limit_order_router,
+        configuration.wallet_address,
+        gas_price,
+        &state.transfer_taxes,
+    );
+
+    let limit_order_execution_bundle: LimitOrderExecutionBundle =
+        simulation::simulate_and_batch_limit_orders(
+            lo_at_execution_price,
+            &mut simulated_markets,
+            configuration.weth_address,
+            &configuration.limit_order_router_dexes,
+            gas_price,
+            &state.transfer_taxes,
+        );
+
+    if !sandbox_execution_bundles.is_empty() {
+        execution::sandbox_limit_order::backrun_sandbox_limit_order_bundles(
+            sandbox_execution_bundles,
+            tx,
+            configuration,
+            pending_transactions_sender.clone(),
+            middleware.clone(),
+        )
+        .await?;
+    }
+
+    if !limit_order_execution_bundle.order_groups.is_empty() {
+        execution::limit_order::backrun_limit_order_groups(
+            limit_order_execution_bundle,
+            tx,
+            configuration,
+            pending_transactions_sender,
+            middleware,
+        )
+        .await?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        sync::Arc,
+    };
+
+    use ethers::{
+        abi::Token,
+        providers::Provider,
+        types::{Transaction, H160, U256},
+    };
+
+    use crate::{
+        abi,
+        markets::{self, Market},
+        pool::{AutomatedMarketMaker, Pool, UniswapV2Pool},
+        state::State,
+    };
+
+    use super::{
+        apply_pending_route, restore_markets, PendingRoute, PendingRouteDecoder, PendingSwap,
+    };
+
+    fn token(id: u64) -> H160 {
+        H160::from_low_u64_be(id)
+    }
+
+    fn pending_swap(pool_id: u64, token_in: H160, token_out: H160) -> PendingSwap {
+        PendingSwap {
+            pool_address: H160::from_low_u64_be(pool_id),
+            market_id: markets::get_market_id(token_in, token_out),
+            token_in,
+        }
+    }
+
+    #[test]
+    fn test_tracked_pending_route() {
+        let pending_route = PendingRoute {
+            amount_in: U256::from(1_000_000),
+            amount_out_min: U256::zero(),
+            swaps: vec![
+                pending_swap(100, token(1), token(2)),
+                pending_swap(101, token(2), token(3)),
+            ],
+            complete: true,
+        };
+
+        let mut state = State::new();
+        assert!(pending_route.clone().tracked(&state).is_none());
+
+        //The route is cut off at the first pool that is not tracked, so its amount out can not be checked
+        state.pool_address_to_market_ids.insert(
+            H160::from_low_u64_be(100),
+            HashSet::from([markets::get_market_id(token(1), token(2))]),
+        );
+        let tracked_route = pending_route.clone().tracked(&state).unwrap();
+        assert_eq!(tracked_route.swaps.len(), 1);
+        assert!(!tracked_route.complete);
+
+        state.pool_address_to_market_ids.insert(
+            H160::from_low_u64_be(101),
+            HashSet::from([markets::get_market_id(token(2), token(3))]),
+        );
+        let tracked_route = pending_route.tracked(&state).unwrap();
+        assert_eq!(tracked_route.swaps.len(), 2);
+        assert!(tracked_route.complete);
+    }
+
+    fn markets() -> HashMap<U256, Market> {
+        let pool = |pool_id: u64, token_a: H160, token_b: H160| {
+            Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+                address: H160::from_low_u64_be(pool_id),
+                token_a,
+                token_a_decimals: 18,
+                token_b,
+                token_b_decimals: 18,
+                reserve_0: 1_000_000_000_000,
+                reserve_1: 2_000_000_000_000,
+                fee: 300,
+            }))
+        };
+
+        HashMap::from([
+            (
+                markets::get_market_id(token(1), token(2)),
+                HashMap::from([(H160::from_low_u64_be(100), pool(100, token(1), token(2)))]),
+            ),
+            (
+                markets::get_market_id(token(2), token(3)),
+                HashMap::from([(H160::from_low_u64_be(101), pool(101, token(2), token(3)))]),
+            ),
+        ])
+    }
+
+    //Amount out of the first pool along the route, which changes when the pending swap is applied
+    fn amount_out(markets: &HashMap<U256, Market>) -> Option<U256> {
+        markets
+            .get(&markets::get_market_id(token(1), token(2)))?
+            .get(&H160::from_low_u64_be(100))?
+            .simulate_swap_local(token(1), U256::from(1_000_000))
+    }
+
+    #[test]
+    fn test_apply_pending_route() {
+        let mut markets = markets();
+        let amount_out_before = amount_out(&markets);
+
+        let mut pending_route = PendingRoute {
+            amount_in: U256::from(100_000_000_000_u128),
+            amount_out_min: U256::zero(),
+            swaps: vec![pending_swap(100, token(1), token(2))],
+            complete: true,
+        };
+
+        //Only the markets along the route are cloned, and restoring them undoes the pending swap
+        let original_markets = apply_pending_route(&pending_route, &mut markets).unwrap();
+        assert_eq!(original_markets.len(), 1);
+        assert!(amount_out(&markets) < amount_out_before);
+
+        restore_markets(&mut markets, original_markets);
+        assert_eq!(amount_out(&markets), amount_out_before);
+        assert_eq!(markets.len(), 2);
+
+        //Pending transactions that would revert leave the markets unchanged
+        pending_route.amount_out_min = U256::MAX;
+        assert!(apply_pending_route(&pending_route, &mut markets).is_none());
+        assert_eq!(amount_out(&markets), amount_out_before);
+
+        //Incomplete routes can not be checked against the amount out min
+        pending_route.complete = false;
+        let original_markets = apply_pending_route(&pending_route, &mut markets).unwrap();
+        restore_markets(&mut markets, original_markets);
+
+        //Routes through pools that are not in the markets are not applied
+        pending_route.swaps = vec![
+            pending_swap(100, token(1), token(2)),
+            pending_swap(102, token(2), token(3)),
+        ];
+        assert!(apply_pending_route(&pending_route, &mut markets).is_none());
+        assert_eq!(amount_out(&markets), amount_out_before);
+
+        //Amounts in that overflow the reserves of a pool revert, so the route is not applied
+        pending_route.swaps = vec![pending_swap(100, token(1), token(2))];
+        pending_route.amount_in = U256::from(u128::MAX);
+        assert!(apply_pending_route(&pending_route, &mut markets).is_none());
+        assert_eq!(amount_out(&markets), amount_out_before);
+    }
+
+    fn swap_exact_tokens_for_tokens(router: H160, amount_in: U256) -> Transaction {
+        let input = abi::IUNISWAPV2ROUTER_ABI
+            .function("swapExactTokensForTokens")
+            .unwrap()
+            .encode_input(&[
+                Token::Uint(amount_in),
+                Token::Uint(U256::zero()),
+                Token::Array(vec![Token::Address(token(1)), Token::Address(token(2))]),
+                Token::Address(H160::zero()),
+                Token::Uint(U256::zero()),
+            ])
+            .unwrap();
+
+        Transaction {
+            to: Some(router),
+            input: input.into(),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_decode_pending_route_amount_in() {
+        let (provider, _) = Provider::mocked();
+        let middleware = Arc::new(provider);
+        let (router, factory) = (H160::from_low_u64_be(200), H160::from_low_u64_be(201));
+
+        //The factory and pair are cached so that the route is decoded without calling the node
+        let mut decoder = PendingRouteDecoder::new();
+        decoder.router_factories.insert(router, Some(factory));
+        decoder
+            .factory_pools
+            .insert((factory, token(1), token(2), 0), H160::from_low_u64_be(100));
+
+        let pending_route = decoder
+            .decode_pending_route(
+                &swap_exact_tokens_for_tokens(router, U256::from(u128::MAX)),
+                middleware.clone(),
+            )
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(pending_route.amount_in, U256::from(u128::MAX));
+        assert_eq!(pending_route.swaps.len(), 1);
+
+        //Amounts in larger than the reserves of any pool can hold are not decoded
+        assert!(decoder
+            .decode_pending_route(
+                &swap_exact_tokens_for_tokens(router, U256::from(u128::MAX) + 1),
+                middleware,
+            )
+            .await
+            .unwrap()
+            .is_none());
+    }
+}
@@ -0,0 +1,780 @@
+use std::{
+    collections::HashSet,
+    fmt::Debug,
+    str::FromStr,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use async_trait::async_trait;
+use ethers::{
+    providers::{Http, JsonRpcClient, JsonRpcError, ProviderError, RpcError},
+    types::H160,
+};
+use futures::future::join_all;
+use serde::{de::DeserializeOwned, Serialize};
+use serde_json::Value;
+use thiserror::Error;
+
+pub const MAX_HEALTH_SCORE: u8 = 100;
+//Endpoints with a score below this threshold are only used once every healthy endpoint has failed
+pub const HEALTHY_SCORE_THRESHOLD: u8 = 50;
+//Score regained on every successful request, failures halve the score
+pub const HEALTH_SCORE_RECOVERY: u8 = 5;
+
+pub const SEND_RAW_TRANSACTION_METHOD: &str = "eth_sendRawTransaction";
+pub const CALL_METHOD: &str = "eth_call";
+
+#[derive(Error, Debug)]
+pub enum FailoverClientError {
+    #[error("No RPC endpoints configured")]
+    NoEndpoints,
+    #[error("Invalid RPC endpoint url {0}")]
+    InvalidEndpointUrl(String),
+    #[error("Quorum of {0} can not be reached with {1} endpoints")]
+    InvalidQuorum(usize, usize),
+    #[error("All RPC endpoints failed for {0}")]
+    AllEndpointsFailed(String, Vec<ProviderError>),
+    #[error("No quorum reached for {0}")]
+    NoQuorumReached(String, Vec<Value>, Vec<ProviderError>),
+}
+
+impl RpcError for FailoverClientError {
+    fn as_error_response(&self) -> Option<&ethers::providers::JsonRpcError> {
+        None
+    }
+
+    fn as_serde_error(&self) -> Option<&serde_json::Error> {
+        None
+    }
+}
+
+impl From<FailoverClientError> for ProviderError {
+    fn from(src: FailoverClientError) -> Self {
+        ProviderError::JsonRpcClientError(Box::new(src))
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct EndpointHealth {
+    pub score: u8,
+    pub consecutive_failures: u32,
+    pub latency: Duration,
+}
+
+impl Default for EndpointHealth {
+    fn default() -> Self {
+        EndpointHealth {
+            score: MAX_HEALTH_SCORE,
+            consecutive_failures: 0,
+            latency: Duration::ZERO,
+        }
+    }
+}
+
+impl EndpointHealth {
+    pub fn is_healthy(&self) -> bool {
+        self.score >= HEALTHY_SCORE_THRESHOLD
+    }
+
+    pub fn record_success(&mut self, latency: Duration) {
+        self.score = self
+            .score
+            .saturating_add(HEALTH_SCORE_RECOVERY)
+            .min(MAX_HEALTH_SCORE);
+        self.consecutive_failures = 0;
+
+        //Exponentially weighted moving average of the request latency, used to break ties between equally healthy endpoints
+        self.latency = if self.latency.is_zero() {
+            latency
+        } else {
+            (self.latency * 7 + latency) / 8
+        };
+    }
+
+    pub fn record_failure(&mut self) {
+        self.score /= 2;
+        self.consecutive_failures += 1;
+    }
+}
+
+#[derive(Debug)]
+pub struct Endpoint<C = Http> {
+    pub url: String,
+    client: C,
+    health: Mutex<EndpointHealth>,
+}
+
+impl Endpoint {
+    pub fn new(url: &str) -> Result<Endpoint, FailoverClientError> {
+        let client = Http::from_str(url)
+            .map_err(|_| FailoverClientError::InvalidEndpointUrl(url.to_owned()))?;
+
+        Ok(Endpoint::new_with_client(url, client))
+    }
+}
+
+impl<C: JsonRpcClient> Endpoint<C> {
+    pub fn new_with_client(url: &str, client: C) -> Endpoint<C> {
+        Endpoint {
+            url: url.to_owned(),
+            client,
+            health: Mutex::new(EndpointHealth::default()),
+        }
+    }
+
+    pub fn health(&self) -> EndpointHealth {
+        *self
+            .health
+            .lock()
+            .expect("Could not acquire lock on endpoint health")
+    }
+
+    //Sends the request to the endpoint, updating the health of the endpoint based on the result.
+    //JSON-RPC error responses (ie. execution reverted) are returned by the node itself and do not count against the endpoint.
+    async fn request(&self, method: &str, params: &RequestParams) -> Result<Value, C::Error> {
+        let start = Instant::now();
+
+        let result = match params {
+            RequestParams::Zst => self.client.request(method, ()).await,
+            RequestParams::Value(params) => self.client.request(method, params).await,
+        };
+
+        let mut health = self
+            .health
+            .lock()
+            .expect("Could not acquire lock on endpoint health");
+
+        match &result {
+            Ok(_) => health.record_success(start.elapsed()),
+            Err(err) if err.is_error_response() => health.record_success(start.elapsed()),
+            Err(_) => {
+                health.record_failure();
+                tracing::warn!(
+                    "RPC endpoint {} failed on {} (health score: {})",
+                    self.url,
+                    method,
+                    health.score
+                );
+            }
+        }
+
+        result
+    }
+}
+
+#[derive(Debug, Clone)]
+enum RequestParams {
+    //Requests without params (ie. `eth_blockNumber`) must be passed as `()` so that the params field is omitted
+    Zst,
+    Value(Value),
+}
+
+//JSON-RPC client that fails over across multiple endpoints, broadcasts raw transactions to every healthy endpoint and optionally requires a quorum for critical reads.
+//Critical reads are requests with one of the quorum methods, or eth_calls to one of the quorum call targets (ie. the order books)
+#[derive(Debug)]
+pub struct FailoverClient<C = Http> {
+    pub endpoints: Vec<Endpoint<C>>,
+    pub quorum: Option<usize>,
+    pub quorum_methods: HashSet<String>,
+    pub quorum_call_targets: HashSet<H160>,
+}
+
+impl FailoverClient {
+    pub fn new(
+        urls: &[String],
+        quorum: Option<usize>,
+        quorum_methods: &[String],
+        quorum_call_targets: &[H160],
+    ) -> Result<FailoverClient, FailoverClientError> {
+        let endpoints = urls
+            .iter()
+            .map(|url| Endpoint::new(url))
+            .collect::<Result<Vec<Endpoint>, FailoverClientError>>()?;
+
+        FailoverClient::new_with_endpoints(endpoints, quorum, quorum_methods, quorum_call_targets)
+    }
+}
+
+impl<C: JsonRpcClient> FailoverClient<C> {
+    //Returns an error if the quorum can never be reached, since every critical read would fail
+    pub fn new_with_endpoints(
+        endpoints: Vec<Endpoint<C>>,
+        quorum: Option<usize>,
+        quorum_methods: &[String],
+        quorum_call_targets: &[H160],
+    ) -> Result<FailoverClient<C>, FailoverClientError> {
+        if endpoints.is_empty() {
+            return Err(FailoverClientError::NoEndpoints);
+        }
+
+        if let Some(quorum) = quorum {
+            if quorum == 0 || quorum > endpoints.len() {
+                return Err(FailoverClientError::InvalidQuorum(quorum, endpoints.len()));
+            }
+        }
+
+        Ok(FailoverClient {
+            endpoints,
+            quorum,
+            quorum_methods: quorum_methods.iter().cloned().collect(),
+            quorum_call_targets: quorum_call_targets.iter().copied().collect(),
+        })
+    }
+
+    //Returns true if the request is a critical read that requires quorum
+    fn requires_quorum(&self, method: &str, params: &RequestParams) -> bool {
+        if self.quorum_methods.contains(method) {
+            return true;
+        }
+
+        if method != CALL_METHOD || self.quorum_call_targets.is_empty() {
+            return false;
+        }
+
+        //The first param of eth_call is the transaction, which holds the address that is called
+        match params {
+            RequestParams::Value(params) => params
+                .get(0)
+                .and_then(|tx| tx.get("to"))
+                .and_then(|to| serde_json::from_value::<H160>(to.clone()).ok())
+                .is_some_and(|to| self.quorum_call_targets.contains(&to)),
+            RequestParams::Zst => false,
+        }
+    }
+
+    //Returns the endpoints ordered by health score, breaking ties with the lowest latency
+    pub fn endpoints_by_health(&self) -> Vec<&Endpoint<C>> {
+        let mut endpoints = self
+            .endpoints
+            .iter()
+            .map(|endpoint| (endpoint, endpoint.health()))
+            .collect::<Vec<(&Endpoint<C>, EndpointHealth)>>();
+
+        endpoints.sort_by(|(_, a), (_, b)| {
+            b.score
+                .cmp(&a.score)
+                .then_with(|| a.latency.cmp(&b.latency))
+        });
+
+        endpoints
+            .into_iter()
+            .map(|(endpoint, _)| endpoint)
+            .collect()
+    }
+
+    //Returns all healthy endpoints, falling back to every endpoint if none are healthy
+    pub fn healthy_endpoints(&self) -> Vec<&Endpoint<C>> {
+        let endpoints = self.endpoints_by_health();
+        let healthy = endpoints
+            .iter()
+            .filter(|endpoint| endpoint.health().is_healthy())
+            .copied()
+            .collect::<Vec<&Endpoint<C>>>();
+
+        if healthy.is_empty() {
+            endpoints
+        } else {
+            healthy
+        }
+    }
+
+    //Tries each endpoint in order of health until one returns a response
+    async fn request_with_failover(
+        &self,
+        method: &str,
+        params: &RequestParams,
+    ) -> Result<Value, ProviderError> {
+        let mut errors = vec![];
+
+        for endpoint in self.endpoints_by_health() {
+            match endpoint.request(method, params).await {
+                Ok(value) => return Ok(value),
+                //Error responses are deterministic across nodes, so there is no reason to try the next endpoint
+                Err(err) if err.is_error_response() => return Err(err.into()),
+                Err(err) => errors.push(err.into()),
+            }
+        }
+
+        if errors.is_empty() {
+            Err(FailoverClientError::NoEndpoints.into())
+        } else {
+            Err(FailoverClientError::AllEndpointsFailed(method.to_owned(), errors).into())
+        }
+    }
+
+    //Sends the request to every healthy endpoint, returning the first successful response
+    async fn broadcast(
+        &self,
+        method: &str,
+        params: &RequestParams,
+    ) -> Result<Value, ProviderError> {
+        let endpoints = self.healthy_endpoints();
+        if endpoints.is_empty() {
+            return Err(FailoverClientError::NoEndpoints.into());
+        }
+
+        let results = join_all(
+            endpoints
+                .iter()
+                .map(|endpoint| endpoint.request(method, params)),
+        )
+        .await;
+
+        let mut error_response = None;
+        let mut errors = vec![];
+        for result in results {
+            match result {
+                Ok(value) => return Ok(value),
+                //Prefer error responses from the node (ie. transaction underpriced) since callers match on them
+                Err(err) if err.is_error_response() => {
+                    if error_response.is_none() {
+                        error_response = Some(err);
+                    }
+                }
+                Err(err) => errors.push(err.into()),
+            }
+        }
+
+        if let Some(err) = error_response {
+            Err(err.into())
+        } else {
+            Err(FailoverClientError::AllEndpointsFailed(method.to_owned(), errors).into())
+        }
+    }
+
+    //Sends the request to every healthy endpoint and returns the response once `quorum` endpoints agree.
+    //Identical error responses (ie. execution reverted) agree with each other, so a revert that every node returns is returned as the error response.
+    //Every endpoint is asked when there are not enough healthy endpoints to reach quorum
+    async fn request_with_quorum(
+        &self,
+        method: &str,
+        params: &RequestParams,
+        quorum: usize,
+    ) -> Result<Value, ProviderError> {
+        let mut endpoints = self.healthy_endpoints();
+        if endpoints.len() < quorum {
+            endpoints = self.endpoints_by_health();
+        }
+
+        let results = join_all(
+            endpoints
+                .iter()
+                .map(|endpoint| endpoint.request(method, params)),
+        )
+        .await;
+
+        let mut responses: Vec<(Result<Value, C::Error>, usize)> = vec![];
+        let mut errors = vec![];
+        for result in results {
+            match result {
+                Err(err) if !err.is_error_response() => errors.push(err.into()),
+                result => {
+                    if let Some((_, count)) = responses
+                        .iter_mut()
+                        .find(|(response, _)| responses_agree(response, &result))
+                    {
+                        *count += 1;
+                    } else {
+                        responses.push((result, 1));
+                    }
+                }
+            }
+        }
+
+        if let Some(index) = responses.iter().position(|(_, count)| *count >= quorum) {
+            return responses.swap_remove(index).0.map_err(|err| err.into());
+        }
+
+        let mut values = vec![];
+        for (response, _) in responses {
+            match response {
+                Ok(value) => values.push(value),
+                Err(err) => errors.push(err.into()),
+            }
+        }
+
+        Err(FailoverClientError::NoQuorumReached(method.to_owned(), values, errors).into())
+    }
+}
+
+//Responses agree if both endpoints returned the same result, or the same error response
+fn responses_agree<E: RpcError>(a: &Result<Value, E>, b: &Result<Value, E>) -> bool {
+    match (a, b) {
+        (Ok(a), Ok(b)) => a == b,
+        (Err(a), Err(b)) => match (a.as_error_response(), b.as_error_response()) {
+            (Some(a), Some(b)) => error_responses_agree(a, b),
+            _ => false,
+        },
+        _ => false,
+    }
+}
+
+fn error_responses_agree(a: &JsonRpcError, b: &JsonRpcError) -> bool {
+    a.code == b.code && a.message == b.message && a.data == b.data
+}
+
+#[async_trait]
+impl<C: JsonRpcClient> JsonRpcClient for FailoverClient<C> {
+    type Error = ProviderError;
+
+    async fn request<T, R>(&self, method: &str, params: T) -> Result<R, Self::Error>
+    where
+        T: Debug + Serialize + Send + Sync,
+        R: DeserializeOwned + Send,
+    {
+        let params = if std::mem::size_of::<T>() == 0 {
+            RequestParams::Zst
+        } else {
+            RequestParams::Value(serde_json::to_value(params)?)
+        };
+
+        let value = if method == SEND_RAW_TRANSACTION_METHOD {
+            self.broadcast(method, &params).await?
+        } else {
+            match self.quorum {
+                Some(quorum) if self.requires_quorum(method, &params) => {
+                    self.request_with_quorum(method, &params, quorum).await?
+                }
+                _ => self.request_with_failover(method, &params).await?,
+            }
+        };
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        fmt::Debug,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    use async_trait::async_trait;
+    use ethers::{
+        providers::{JsonRpcClient, JsonRpcError, ProviderError, RpcError},
+        types::H160,
+    };
+    use serde::{de::DeserializeOwned, Serialize};
+    use serde_json::{json, Value};
+    use thiserror::Error;
+
+    use super::{
+        Endpoint, EndpointHealth, FailoverClient, FailoverClientError, HEALTHY_SCORE_THRESHOLD,
+        MAX_HEALTH_SCORE, SEND_RAW_TRANSACTION_METHOD,
+    };
+
+    #[derive(Error, Debug)]
+    enum MockClientError {
+        #[error("Endpoint unavailable")]
+        Unavailable,
+        #[error(transparent)]
+        ErrorResponse(JsonRpcError),
+    }
+
+    impl RpcError for MockClientError {
+        fn as_error_response(&self) -> Option<&JsonRpcError> {
+            match self {
+                MockClientError::ErrorResponse(err) => Some(err),
+                MockClientError::Unavailable => None,
+            }
+        }
+
+        fn as_serde_error(&self) -> Option<&serde_json::Error> {
+            None
+        }
+    }
+
+    impl From<MockClientError> for ProviderError {
+        fn from(src: MockClientError) -> Self {
+            ProviderError::JsonRpcClientError(Box::new(src))
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    enum MockResponse {
+        Value(Value),
+        //Error response returned by the node, with the given message
+        ErrorResponse(&'static str),
+        //Transport failure, the node could not be reached
+        Unavailable,
+    }
+
+    //Client that always returns the same response and counts the requests it receives
+    #[derive(Debug)]
+    struct MockClient {
+        response: MockResponse,
+        requests: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl JsonRpcClient for MockClient {
+        type Error = MockClientError;
+
+        async fn request<T, R>(&self, _method: &str, _params: T) -> Result<R, Self::Error>
+        where
+            T: Debug + Serialize + Send + Sync,
+            R: DeserializeOwned + Send,
+        {
+            self.requests.fetch_add(1, Ordering::SeqCst);
+
+            match &self.response {
+                MockResponse::Value(value) => Ok(serde_json::from_value(value.clone()).unwrap()),
+                MockResponse::ErrorResponse(message) => {
+                    Err(MockClientError::ErrorResponse(JsonRpcError {
+                        code: 3,
+                        message: message.to_string(),
+                        data: None,
+                    }))
+                }
+                MockResponse::Unavailable => Err(MockClientError::Unavailable),
+            }
+        }
+    }
+
+    fn failover_client(
+        responses: Vec<MockResponse>,
+        quorum: Option<usize>,
+    ) -> FailoverClient<MockClient> {
+        let endpoints = responses
+            .into_iter()
+            .enumerate()
+            .map(|(i, response)| {
+                Endpoint::new_with_client(
+                    &format!("http://endpoint-{i}"),
+                    MockClient {
+                        response,
+                        requests: AtomicUsize::new(0),
+                    },
+                )
+            })
+            .collect();
+
+        FailoverClient::new_with_endpoints(
+            endpoints,
+            quorum,
+            &["eth_getBalance".to_string()],
+            &[order_book()],
+        )
+        .unwrap()
+    }
+
+    fn order_book() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn requests(client: &FailoverClient<MockClient>) -> Vec<usize> {
+        client
+            .endpoints
+            .iter()
+            .map(|endpoint| endpoint.client.requests.load(Ordering::SeqCst))
+            .collect()
+    }
+
+    #[test]
+    fn test_endpoint_health() {
+        let mut health = EndpointHealth::default();
+        assert!(health.is_healthy());
+
+        //Failures halve the score, so a single failure leaves the endpoint at the healthy threshold
+        health.record_failure();
+        assert_eq!(health.score, MAX_HEALTH_SCORE / 2);
+        assert_eq!(health.score, HEALTHY_SCORE_THRESHOLD);
+        assert!(health.is_healthy());
+
+        health.record_failure();
+        assert!(!health.is_healthy());
+        assert_eq!(health.consecutive_failures, 2);
+
+        //Successes recover the score up to the max and reset the consecutive failures
+        for _ in 0..100 {
+            health.record_success(std::time::Duration::from_millis(10));
+        }
+        assert_eq!(health.score, MAX_HEALTH_SCORE);
+        assert_eq!(health.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn test_invalid_quorum() {
+        let endpoints = |n: usize| {
+            (0..n)
+                .map(|i| {
+                    Endpoint::new_with_client(
+                        &format!("http://endpoint-{i}"),
+                        MockClient {
+                            response: MockResponse::Unavailable,
+                            requests: AtomicUsize::new(0),
+                        },
+                    )
+                })
+                .collect::<Vec<Endpoint<MockClient>>>()
+        };
+
+        assert!(matches!(
+            FailoverClient::new_with_endpoints(endpoints(2), Some(3), &[], &[]),
+            Err(FailoverClientError::InvalidQuorum(3, 2))
+        ));
+        assert!(matches!(
+            FailoverClient::new_with_endpoints(endpoints(2), Some(0), &[], &[]),
+            Err(FailoverClientError::InvalidQuorum(0, 2))
+        ));
+        assert!(matches!(
+            FailoverClient::new_with_endpoints(endpoints(0), None, &[], &[]),
+            Err(FailoverClientError::NoEndpoints)
+        ));
+        assert!(FailoverClient::new_with_endpoints(endpoints(2), Some(2), &[], &[]).is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_failover_order() {
+        let client = failover_client(
+            vec![MockResponse::Unavailable, MockResponse::Value(json!(1))],
+            None,
+        );
+
+        let block_number: u64 = client.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block_number, 1);
+        assert_eq!(requests(&client), vec![1, 1]);
+
+        //The failed endpoint is ranked behind the healthy endpoint, so it is not tried while the healthy endpoint responds
+        assert_eq!(client.endpoints_by_health()[0].url, "http://endpoint-1");
+        let block_number: u64 = client.request("eth_blockNumber", ()).await.unwrap();
+        assert_eq!(block_number, 1);
+        assert_eq!(requests(&client), vec![1, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_error_response_is_not_failed_over() {
+        let client = failover_client(
+            vec![
+                MockResponse::ErrorResponse("execution reverted"),
+                MockResponse::Value(json!(1)),
+            ],
+            None,
+        );
+
+        //Error responses are returned by the node itself, so they are returned without trying the next endpoint or lowering the health score
+        let err = client
+            .request::<_, u64>("eth_estimateGas", ())
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.as_error_response().unwrap().message,
+            "execution reverted"
+        );
+        assert_eq!(requests(&client), vec![1, 0]);
+        assert_eq!(client.endpoints[0].health().score, MAX_HEALTH_SCORE);
+    }
+
+    #[tokio::test]
+    async fn test_broadcast() {
+        let client = failover_client(
+            vec![
+                MockResponse::Unavailable,
+                MockResponse::Value(json!("0x01")),
+                MockResponse::Value(json!("0x01")),
+            ],
+            None,
+        );
+
+        let tx_hash: String = client
+            .request(SEND_RAW_TRANSACTION_METHOD, ["0x"])
+            .await
+            .unwrap();
+        assert_eq!(tx_hash, "0x01");
+        assert_eq!(requests(&client), vec![1, 1, 1]);
+
+        let client = failover_client(vec![MockResponse::Unavailable; 2], None);
+        assert!(client
+            .request::<_, String>(SEND_RAW_TRANSACTION_METHOD, ["0x"])
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("All RPC endpoints failed"));
+    }
+
+    #[tokio::test]
+    async fn test_quorum() {
+        let responses = vec![
+            MockResponse::Value(json!(1)),
+            MockResponse::Value(json!(1)),
+            MockResponse::Value(json!(2)),
+        ];
+
+        let client = failover_client(responses.clone(), Some(2));
+        let balance: u64 = client
+            .request("eth_getBalance", ["0x", "latest"])
+            .await
+            .unwrap();
+        assert_eq!(balance, 1);
+        assert_eq!(requests(&client), vec![1, 1, 1]);
+
+        let client = failover_client(responses, Some(3));
+        assert!(client
+            .request::<_, u64>("eth_getBalance", ["0x", "latest"])
+            .await
+            .unwrap_err()
+            .to_string()
+            .contains("No quorum reached"));
+    }
+
+    #[tokio::test]
+    async fn test_quorum_on_error_responses() {
+        //Identical reverts agree, so the revert is returned as the error response
+        let client = failover_client(
+            vec![
+                MockResponse::ErrorResponse("execution reverted"),
+                MockResponse::ErrorResponse("execution reverted"),
+                MockResponse::Unavailable,
+            ],
+            Some(2),
+        );
+        let err = client
+            .request::<_, u64>("eth_getBalance", ["0x", "latest"])
+            .await
+            .unwrap_err();
+        assert_eq!(
+            err.as_error_response().unwrap().message,
+            "execution reverted"
+        );
+
+        //Different error responses do not agree
+        let client = failover_client(
+            vec![
+                MockResponse::ErrorResponse("execution reverted"),
+                MockResponse::ErrorResponse("header not found"),
+            ],
+            Some(2),
+        );
+        let err = client
+            .request::<_, u64>("eth_getBalance", ["0x", "latest"])
+            .await
+            .unwrap_err();
+        assert!(err.as_error_response().is_none());
+        assert!(err.to_string().contains("No quorum reached"));
+    }
+
+    #[tokio::test]
+    async fn test_quorum_call_targets() {
+        let client = failover_client(
+            vec![MockResponse::Value(json!(1)), MockResponse::Value(json!(2))],
+            Some(2),
+        );
+
+        //Calls to the order book require quorum, other calls fail over to the first endpoint that responds
+        let order_book_call = json!([{ "to": order_book(), "data": "0x" }, "latest"]);
+        assert!(client
+            .request::<_, u64>("eth_call", order_book_call)
+            .await
+            .is_err());
+        assert_eq!(requests(&client), vec![1, 1]);
+
+        let other_call = json!([{ "to": H160::from_low_u64_be(2), "data": "0x" }, "latest"]);
+        assert!(client
+            .request::<_, u64>("eth_call", other_call)
+            .await
+            .is_ok());
+        assert_eq!(requests(&client).iter().sum::<usize>(), 3);
+    }
+}
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 
-use cfmms::pool::Pool;
 use ethers::types::{H160, H256, U256};
-use num_bigfloat::BigFloat;
 
-use crate::markets::get_best_market_price;
+use super::ExecutionSimulation;
+use crate::{
+    markets::{get_best_market_price, mul_64_x_64},
+    pool::Pool,
+    routing::{self, tax::TransferTaxes},
+};
 
 //TODO: FIXME: remove the clone copy, this is not needed, only used in ~ one place, need to update to not use clone or copy
 //TODO: regarding clone note, Update when refactoring the codebase
@@ -18,7 +21,8 @@ pub struct LimitOrder {
     pub fee_in: u32,
     pub fee_out: u32,
     pub tax_in: u16,
-    pub price: f64,
+    //Execution price as a Q64.64 fixed point number, matching the on chain representation
+    pub price: u128,
     pub amount_out_min: u128,
     pub quantity: u128,
     pub execution_credit: u128,
@@ -29,6 +33,7 @@ pub struct LimitOrder {
 }
 
 impl LimitOrder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         buy: bool,
         taxed: bool,
@@ -38,7 +43,7 @@ impl LimitOrder {
         fee_in: u32,
         fee_out: u32,
         tax_in: u16,
-        price: f64,
+        price: u128,
         amount_out_min: u128,
         quantity: u128,
         execution_credit: u128,
@@ -87,10 +92,6 @@ impl LimitOrder {
             [u8; 32],
         ),
     ) -> LimitOrder {
-        let price = BigFloat::from_u128(return_data.8)
-            .div(&BigFloat::from_f64(2_f64.powf(64_f64)).sub(&BigFloat::from(1)))
-            .to_f64();
-
         LimitOrder::new(
             return_data.0,
             return_data.1,
@@ -100,7 +101,7 @@ impl LimitOrder {
             return_data.5,
             return_data.6,
             return_data.7,
-            price,
+            return_data.8,
             return_data.9,
             return_data.10,
             return_data.11,
@@ -116,28 +117,335 @@ impl LimitOrder {
         buy: bool,
         markets: &HashMap<U256, HashMap<H160, Pool>>,
         weth: H160,
+        router_dexes: &[H160],
     ) -> bool {
-        if buy {
-            self.get_best_market_price(buy, markets, weth) <= self.price
+        //Orders without a route through weth can not be executed.
+        //Stop loss orders trigger when the price moves against the order, so a stop loss sell executes at or below the order price and a stop loss buy at or above it
+        let at_execution_price = if let Some(market_price) =
+            self.get_best_market_price(buy, markets, weth, router_dexes)
+        {
+            if buy != self.stop_loss {
+                market_price <= U256::from(self.price)
+            } else {
+                market_price >= U256::from(self.price)
+            }
         } else {
-            self.get_best_market_price(buy, markets, weth) >= self.price
-        }
+            false
+        };
+
+        //The spot price ignores price impact, so the quantity is also simulated against the amount out min
+        at_execution_price
+            && self
+                .simulate_execution(markets, weth, router_dexes)
+                .is_some_and(|simulation| simulation.can_execute())
+    }
+
+    //Simulates the order quantity along a -> weth -> b with local pool math, through the pools that the router swaps the order through
+    pub fn simulate_execution(
+        &self,
+        markets: &HashMap<U256, HashMap<H160, Pool>>,
+        weth: H160,
+        router_dexes: &[H160],
+    ) -> Option<ExecutionSimulation> {
+        let amount_in = U256::from(self.quantity);
+        let (_, amounts_out, _, _) = routing::find_limit_order_route(
+            self,
+            amount_in,
+            weth,
+            router_dexes,
+            markets,
+            &TransferTaxes::new(),
+        )
+        .ok()?;
+
+        Some(ExecutionSimulation::new(
+            amount_in,
+            *amounts_out.last()?,
+            U256::from(self.amount_out_min),
+        ))
     }
 
+    //Returns the best a -> weth -> b price across the pools that the router swaps the order through as a Q64.64 fixed point number
     pub fn get_best_market_price(
         &self,
         buy: bool,
         markets: &HashMap<U256, HashMap<H160, Pool>>,
         weth: H160,
-    ) -> f64 {
+        router_dexes: &[H160],
+    ) -> Option<U256> {
+        let markets = &routing::get_limit_order_markets(self, weth, router_dexes, markets);
+
         //Check a -> weth -> b price
 
         //We are first swapping token_a to weth, so we need the price of weth per 1 token_a
-        let a_to_weth_price = get_best_market_price(buy, weth, self.token_in, markets);
+        let a_to_weth_price = get_best_market_price(buy, weth, self.token_in, markets)?;
 
         //Then we are swapping weth to token_b, meaning we need the price of 1 token_b per weth
-        let weth_to_b_price = get_best_market_price(buy, self.token_out, weth, markets);
+        let weth_to_b_price = get_best_market_price(buy, self.token_out, weth, markets)?;
+
+        mul_64_x_64(a_to_weth_price, weth_to_b_price)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, str::FromStr};
+
+    use crate::pool::{AutomatedMarketMaker, Pool, UniswapV2Pool};
+    use ethers::types::{H160, H256, I256, U256};
+
+    use super::LimitOrder;
+    use crate::markets::{
+        get_market_id,
+        tests::{router_mul_64_x_64, router_v2_spot_price_64_x_64},
+    };
+
+    fn usdc() -> H160 {
+        H160::from_str("0xA0b86991c6218b36c1d19D4a2e9Eb0cE3606eB48").unwrap()
+    }
+
+    fn dai() -> H160 {
+        H160::from_str("0x6B175474E89094C44Da98b954EedeAC495271d0F").unwrap()
+    }
+
+    fn weth() -> H160 {
+        H160::from_str("0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2").unwrap()
+    }
+
+    //Pools built in tests are not discovered through a factory, so the router is deployed with the zero address
+    fn router_dexes() -> Vec<H160> {
+        vec![H160::zero()]
+    }
+
+    fn markets() -> HashMap<U256, HashMap<H160, Pool>> {
+        let usdc_weth = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(1),
+            token_a: usdc(),
+            token_a_decimals: 6,
+            token_b: weth(),
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            fee: 300,
+        }));
+
+        let dai_weth = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(2),
+            token_a: dai(),
+            token_a_decimals: 18,
+            token_b: weth(),
+            token_b_decimals: 18,
+            reserve_0: 10_000_000_000_000_000_000_000_000,
+            reserve_1: 6_000_000_000_000_000_000_000,
+            fee: 300,
+        }));
+
+        HashMap::from([
+            (
+                get_market_id(usdc(), weth()),
+                HashMap::from([(usdc_weth.address(), usdc_weth)]),
+            ),
+            (
+                get_market_id(dai(), weth()),
+                HashMap::from([(dai_weth.address(), dai_weth)]),
+            ),
+        ])
+    }
+
+    fn limit_order(buy: bool, price: u128) -> LimitOrder {
+        stop_loss_order(buy, false, price)
+    }
+
+    fn stop_loss_order(buy: bool, stop_loss: bool, price: u128) -> LimitOrder {
+        LimitOrder::new(
+            buy,
+            false,
+            stop_loss,
+            0,
+            u32::MAX,
+            500,
+            500,
+            0,
+            price,
+            0,
+            1_000_000,
+            0,
+            H160::zero(),
+            usdc(),
+            dai(),
+            H256::zero(),
+        )
+    }
+
+    //The a -> weth -> b price is the product of the weth/usdc and dai/weth Q64.64 prices, rounded down
+    const MARKET_PRICE: u128 = 18354944920255434993;
+
+    #[test]
+    fn test_market_price_router_port() {
+        //The router prices weth in usdc and dai in weth from the pool reserves, then multiplies the two prices
+        let weth_price =
+            router_v2_spot_price_64_x_64(28396598565590008529300, 18, 47092140895915, 6);
+        let dai_price = router_v2_spot_price_64_x_64(
+            10_000_000_000_000_000_000_000_000,
+            18,
+            6_000_000_000_000_000_000_000,
+            18,
+        );
+
+        assert_eq!(
+            router_mul_64_x_64(weth_price, dai_price),
+            U256::from(MARKET_PRICE)
+        );
+    }
+
+    #[test]
+    fn test_get_best_market_price() {
+        let order = limit_order(false, MARKET_PRICE);
+
+        assert_eq!(
+            order.get_best_market_price(false, &markets(), weth(), &router_dexes()),
+            Some(U256::from(MARKET_PRICE))
+        );
+
+        //Orders without a weth market for either token can not be priced
+        let mut order = order;
+        order.token_out = H160::zero();
+        assert_eq!(
+            order.get_best_market_price(false, &markets(), weth(), &router_dexes()),
+            None
+        );
+        assert!(!order.can_execute(false, &markets(), weth(), &router_dexes()));
+    }
+
+    #[test]
+    fn test_can_execute_at_price_boundary() {
+        //Sell orders execute when the market price is at or above the order price
+        assert!(limit_order(false, MARKET_PRICE).can_execute(
+            false,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(!limit_order(false, MARKET_PRICE + 1).can_execute(
+            false,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+
+        //Buy orders execute when the market price is at or below the order price
+        assert!(limit_order(true, MARKET_PRICE).can_execute(
+            true,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(!limit_order(true, MARKET_PRICE - 1).can_execute(
+            true,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+    }
+
+    #[test]
+    fn test_can_execute_stop_loss() {
+        //Stop loss sell orders execute when the market price is at or below the order price
+        assert!(stop_loss_order(false, true, MARKET_PRICE).can_execute(
+            false,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(stop_loss_order(false, true, MARKET_PRICE + 1).can_execute(
+            false,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(!stop_loss_order(false, true, MARKET_PRICE - 1).can_execute(
+            false,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+
+        //Stop loss buy orders execute when the market price is at or above the order price
+        assert!(stop_loss_order(true, true, MARKET_PRICE).can_execute(
+            true,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(stop_loss_order(true, true, MARKET_PRICE - 1).can_execute(
+            true,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+        assert!(!stop_loss_order(true, true, MARKET_PRICE + 1).can_execute(
+            true,
+            &markets(),
+            weth(),
+            &router_dexes()
+        ));
+    }
+
+    #[test]
+    fn test_can_execute_below_f64_precision() {
+        //Prices one unit apart in Q64.64 are indistinguishable as f64, but must not both be executable
+        let price = MARKET_PRICE + 1;
+        assert_eq!(
+            MARKET_PRICE as f64 / 2_f64.powi(64),
+            price as f64 / 2_f64.powi(64)
+        );
+
+        assert!(!limit_order(false, price).can_execute(false, &markets(), weth(), &router_dexes()));
+    }
+
+    #[test]
+    fn test_simulate_execution() {
+        let mut order = limit_order(false, MARKET_PRICE);
+        order.amount_out_min = 998980246994639528;
+
+        //1 usdc -> 601191783424756 weth -> 998980246994639528 dai through the v2 pools
+        let simulation = order
+            .simulate_execution(&markets(), weth(), &router_dexes())
+            .unwrap();
+        assert_eq!(simulation.amount_in, U256::from(1_000_000));
+        assert_eq!(
+            simulation.expected_amount_out,
+            U256::from(998980246994639528_u128)
+        );
+        assert_eq!(simulation.margin, I256::zero());
+        assert!(order.can_execute(false, &markets(), weth(), &router_dexes()));
+
+        order.amount_out_min += 1;
+        assert_eq!(
+            order
+                .simulate_execution(&markets(), weth(), &router_dexes())
+                .unwrap()
+                .margin,
+            I256::minus_one()
+        );
+        assert!(!order.can_execute(false, &markets(), weth(), &router_dexes()));
+    }
+
+    #[test]
+    fn test_can_execute_with_price_impact() {
+        //The order is at execution price, but 10m usdc moves the pools far past the amount out min
+        let mut order = limit_order(false, MARKET_PRICE);
+        order.quantity = 10_000_000_000_000;
+        order.amount_out_min = 9_000_000_000_000_000_000_000_000;
 
-        a_to_weth_price * weth_to_b_price
+        let simulation = order
+            .simulate_execution(&markets(), weth(), &router_dexes())
+            .unwrap();
+        assert_eq!(
+            simulation.expected_amount_out,
+            U256::from(4518856206992109152649469_u128)
+        );
+        assert!(simulation.margin.is_negative());
+        assert!(!order.can_execute(false, &markets(), weth(), &router_dexes()));
     }
 }
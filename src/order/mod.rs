@@ -1,18 +1,24 @@
 pub mod limit_order;
 pub mod sandbox_limit_order;
 
-use std::{collections::HashMap, fmt::Debug, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::Debug,
+    sync::Arc,
+};
 
-use cfmms::pool::Pool;
 use ethers::{
     providers::Middleware,
-    types::{H160, H256, U256},
+    types::{H160, H256, I256, U256},
 };
 
 use crate::{
     abi::{self},
     error::ExecutorError,
+    markets,
     order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder},
+    pool::Pool,
+    routing::graph::Connectors,
 };
 
 #[derive(Debug)]
@@ -27,21 +33,102 @@ pub enum OrderVariant {
     SandboxLimitOrder,
 }
 
+//Result of simulating the order quantity through local pool math
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExecutionSimulation {
+    pub amount_in: U256,
+    pub expected_amount_out: U256,
+    //Expected amount out over the amount out required by the order (amount_out_min or amount_out_remaining), negative when price impact makes the order unfillable
+    pub margin: I256,
+}
+
+impl ExecutionSimulation {
+    pub fn new(
+        amount_in: U256,
+        expected_amount_out: U256,
+        amount_out_required: U256,
+    ) -> ExecutionSimulation {
+        let margin = I256::try_from(expected_amount_out)
+            .unwrap_or(I256::MAX)
+            .saturating_sub(I256::try_from(amount_out_required).unwrap_or(I256::MAX));
+
+        ExecutionSimulation {
+            amount_in,
+            expected_amount_out,
+            margin,
+        }
+    }
+
+    pub fn can_execute(&self) -> bool {
+        !self.margin.is_negative()
+    }
+}
+
 //TODO: impl from bytes for each order variant instead of a match statement in order, or in addition
 
 impl Order {
-    pub fn can_execute(&self, markets: &HashMap<U256, HashMap<H160, Pool>>, weth: H160) -> bool {
+    pub fn can_execute(
+        &self,
+        markets: &HashMap<U256, HashMap<H160, Pool>>,
+        weth: H160,
+        limit_order_router_dexes: &[H160],
+        connectors: &Connectors,
+        min_fill_percent: u128,
+    ) -> bool {
+        match self {
+            Order::SandboxLimitOrder(sandbox_limit_order) => {
+                sandbox_limit_order.can_execute(markets, connectors, min_fill_percent)
+            }
+
+            Order::LimitOrder(limit_order) => {
+                limit_order.can_execute(limit_order.buy, markets, weth, limit_order_router_dexes)
+            }
+        }
+    }
+
+    pub fn simulate_execution(
+        &self,
+        markets: &HashMap<U256, HashMap<H160, Pool>>,
+        weth: H160,
+        limit_order_router_dexes: &[H160],
+        connectors: &Connectors,
+    ) -> Option<ExecutionSimulation> {
         match self {
             Order::SandboxLimitOrder(sandbox_limit_order) => {
-                sandbox_limit_order.can_execute(markets, weth)
+                sandbox_limit_order.simulate_execution(markets, connectors)
             }
 
             Order::LimitOrder(limit_order) => {
-                limit_order.can_execute(limit_order.buy, markets, weth)
+                limit_order.simulate_execution(markets, weth, limit_order_router_dexes)
             }
         }
     }
 
+    //Returns the pairs of the markets that the order can route through. Limit orders are routed a -> weth -> b by the LimitOrderRouter,
+    //while sandbox limit orders can route through the connector tokens and need the token out to weth market to pay the fee
+    pub fn market_pairs(&self, weth: H160, connectors: &Connectors) -> Vec<(H160, H160)> {
+        let (token_in, token_out) = (self.token_in(), self.token_out());
+
+        let mut pairs = vec![(token_in, weth), (weth, token_out)];
+        if let Order::SandboxLimitOrder(_) = self {
+            pairs.extend(connectors.market_pairs(token_in, token_out));
+        }
+
+        let mut market_ids = HashSet::new();
+        pairs.retain(|(token_a, token_b)| {
+            token_a != token_b && market_ids.insert(markets::get_market_id(*token_a, *token_b))
+        });
+
+        pairs
+    }
+
+    pub fn market_ids(&self, weth: H160, connectors: &Connectors) -> Vec<U256> {
+        self.market_pairs(weth, connectors)
+            .into_iter()
+            .map(|(token_a, token_b)| markets::get_market_id(token_a, token_b))
+            .collect()
+    }
+
     pub fn owner(&self) -> H160 {
         match self {
             Order::SandboxLimitOrder(sandbox_limit_order) => sandbox_limit_order.owner,
@@ -75,6 +162,16 @@ impl Order {
             Order::LimitOrder(limit_order) => limit_order.quantity,
         }
     }
+
+    pub fn amount_out(&self) -> u128 {
+        match self {
+            Order::SandboxLimitOrder(sandbox_limit_order) => {
+                sandbox_limit_order.amount_out_remaining
+            }
+            Order::LimitOrder(limit_order) => limit_order.amount_out_min,
+        }
+    }
+
     pub fn token_in(&self) -> H160 {
         match self {
             Order::SandboxLimitOrder(sandbox_limit_order) => sandbox_limit_order.token_in,
@@ -139,7 +236,7 @@ pub async fn get_remote_order<M: Middleware>(
                 .await?;
 
             Ok(Order::SandboxLimitOrder(
-                SandboxLimitOrder::new_from_return_data(return_data, middleware).await?,
+                SandboxLimitOrder::new_from_return_data(return_data),
             ))
         }
 
@@ -1,13 +1,23 @@
-use std::{collections::HashMap, sync::Arc};
+use std::collections::HashMap;
 
-use cfmms::pool::Pool;
-use ethers::{
-    providers::Middleware,
-    types::{H160, H256, U256},
+use ethers::types::{H160, H256, U256};
+
+use crate::{
+    pool::Pool,
+    routing::graph::{self, Connectors},
+    simulation::{div_uu, mul_64_u, FixedPointError},
 };
-use num_bigfloat::BigFloat;
 
-use crate::{abi, error::ExecutorError, markets::get_best_market_price};
+use super::ExecutionSimulation;
+
+//Default smallest percent of the amount in remaining that is partially filled, so that dust fills are not executed.
+//The router accepts fills of any size, so the minimum is set by the `min_fill_percent` config value
+pub const DEFAULT_MIN_FILL_PERCENT: u8 = 1;
+
+//Converts a percent to a fraction of the amount in remaining as a Q64.64 fixed point number
+pub fn min_fill_percent_64_x_64(min_fill_percent: u8) -> u128 {
+    (1 << 64) * min_fill_percent as u128 / 100
+}
 
 //TODO: FIXME: remove the clone copy, this is not needed, only used in ~ one place, need to update to not use clone or copy
 //TODO: regarding clone note, Update when refactoring the codebase
@@ -19,7 +29,6 @@ pub struct SandboxLimitOrder {
     pub fee_remaining: u128,
     pub amount_in_remaining: u128,
     pub amount_out_remaining: u128,
-    pub price: f64,
     pub execution_credit_remaining: u128,
     pub owner: H160,
     pub token_in: H160,
@@ -28,6 +37,7 @@ pub struct SandboxLimitOrder {
 }
 
 impl SandboxLimitOrder {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         last_refresh_timestamp: u32,
         expiration_timestamp: u32,
@@ -35,7 +45,6 @@ impl SandboxLimitOrder {
         fee_remaining: u128,
         amount_in_remaining: u128,
         amount_out_remaining: u128,
-        price: f64,
         execution_credit_remaining: u128,
         owner: H160,
         token_in: H160,
@@ -49,7 +58,6 @@ impl SandboxLimitOrder {
             fee_remaining,
             amount_in_remaining,
             amount_out_remaining,
-            price,
             execution_credit_remaining,
             owner,
             token_in,
@@ -58,7 +66,7 @@ impl SandboxLimitOrder {
         }
     }
 
-    pub async fn new_from_return_data<M: Middleware>(
+    pub fn new_from_return_data(
         return_data: (
             u32,
             u32,
@@ -72,70 +80,264 @@ impl SandboxLimitOrder {
             H160,
             [u8; 32],
         ),
-        middleware: Arc<M>,
-    ) -> Result<SandboxLimitOrder, ExecutorError<M>> {
-        //TODO: update this to be more efficient
-        //Price is derived by taking the amount_out_remaining / amount_in_remaining
-        //In order to normalize the values, we fetch the token decimals and calculate the amount / 10**token_decimals
-        let price = BigFloat::from(return_data.5)
-            .div(&BigFloat::from(
-                10_f64.powf(
-                    abi::IErc20::new(return_data.9, middleware.clone())
-                        .decimals()
-                        .call()
-                        .await? as f64,
-                ),
-            ))
-            .div(
-                &BigFloat::from(return_data.4).div(&BigFloat::from(
-                    10_f64.powf(
-                        abi::IErc20::new(return_data.8, middleware.clone())
-                            .decimals()
-                            .call()
-                            .await? as f64,
-                    ),
-                )),
-            )
-            .to_f64();
-
-        Ok(SandboxLimitOrder::new(
+    ) -> SandboxLimitOrder {
+        SandboxLimitOrder::new(
             return_data.0,
             return_data.1,
             return_data.2,
             return_data.3,
             return_data.4,
             return_data.5,
-            price,
             return_data.6,
             return_data.7,
             return_data.8,
             return_data.9,
             return_data.10.into(),
-        ))
+        )
     }
-    pub fn can_execute(&self, markets: &HashMap<U256, HashMap<H160, Pool>>, weth: H160) -> bool {
-        self.get_best_market_price(markets, weth) >= self.price
+
+    //Orders can execute if the smallest fill meets the order price, since smaller fills have less price impact.
+    //Orders whose amounts overflow the fixed point math of the contract are skipped
+    pub fn can_execute(
+        &self,
+        markets: &HashMap<U256, HashMap<H160, Pool>>,
+        connectors: &Connectors,
+        min_fill_percent: u128,
+    ) -> bool {
+        match self
+            .min_fill_amount(min_fill_percent)
+            .and_then(|min_fill_amount| self.simulate_fill(min_fill_amount, markets, connectors))
+        {
+            Ok(simulation) => simulation.is_some_and(|simulation| simulation.can_execute()),
+            Err(err) => {
+                tracing::warn!(
+                    "Skipping sandbox limit order {:?}: {:?}",
+                    self.order_id,
+                    err
+                );
+                false
+            }
+        }
     }
 
-    pub fn get_best_market_price(
+    //Simulates the amount in remaining along the best path through the connector tokens with local pool math
+    pub fn simulate_execution(
         &self,
         markets: &HashMap<U256, HashMap<H160, Pool>>,
-        weth: H160,
-    ) -> f64 {
-        //Check if the order is at execution price
-
-        //Check a -> b price
-        let a_to_b_price = get_best_market_price(true, self.token_in, self.token_out, markets);
-
-        //Check a -> weth -> b price
-        let a_to_weth_price = get_best_market_price(true, self.token_in, weth, markets);
-        let weth_to_b_price = get_best_market_price(true, weth, self.token_out, markets);
-        let a_to_weth_to_b_price = a_to_weth_price * weth_to_b_price;
-
-        if a_to_weth_to_b_price > a_to_b_price {
-            a_to_weth_to_b_price
-        } else {
-            a_to_b_price
+        connectors: &Connectors,
+    ) -> Option<ExecutionSimulation> {
+        self.simulate_fill(U256::from(self.amount_in_remaining), markets, connectors)
+            .ok()
+            .flatten()
+    }
+
+    //Simulates the fill amount along the best path through the connector tokens with local pool math, against the amount out required for the fill
+    pub fn simulate_fill(
+        &self,
+        fill_amount: U256,
+        markets: &HashMap<U256, HashMap<H160, Pool>>,
+        connectors: &Connectors,
+    ) -> Result<Option<ExecutionSimulation>, FixedPointError> {
+        if !self.is_valid_fill(fill_amount)? {
+            return Ok(None);
         }
+
+        let amount_out_required = self.amount_out_required(fill_amount)?;
+
+        Ok(graph::get_best_path_amount_out(
+            self.token_in,
+            self.token_out,
+            fill_amount,
+            connectors,
+            markets,
+        )
+        .map(|amount_out| ExecutionSimulation::new(fill_amount, amount_out, amount_out_required)))
+    }
+
+    //The router reverts fills of zero or of more than the amount in remaining, and fills that require no amount out would pay the owner nothing
+    pub fn is_valid_fill(&self, fill_amount: U256) -> Result<bool, FixedPointError> {
+        Ok(!fill_amount.is_zero()
+            && fill_amount <= U256::from(self.amount_in_remaining)
+            && !self.amount_out_required(fill_amount)?.is_zero())
+    }
+
+    //Smallest amount in that is filled, the amount in remaining if it is smaller. The min fill percent is a Q64.64 fixed point number
+    pub fn min_fill_amount(&self, min_fill_percent: u128) -> Result<U256, FixedPointError> {
+        let amount_in_remaining = U256::from(self.amount_in_remaining);
+
+        Ok(mul_64_u(min_fill_percent, amount_in_remaining)?
+            .max(U256::one())
+            .min(amount_in_remaining))
+    }
+
+    //Fraction of the amount in remaining that is filled as a Q64.64 fixed point number, calculated like the contract does
+    pub fn fill_percent_of(&self, fill_amount: U256) -> Result<u128, FixedPointError> {
+        div_uu(fill_amount, U256::from(self.amount_in_remaining))
+    }
+
+    //Fill percent of the order after the fill, adding the fraction of the amount in remaining that is filled like the contract does on partial fills
+    pub fn fill_percent_after_fill(&self, fill_amount: U256) -> u128 {
+        self.fill_percent
+            .saturating_add(self.fill_percent_of(fill_amount).unwrap_or_default())
+    }
+
+    //Amount out the contract requires the owner to receive for the fill, amount_out_remaining / amount_in_remaining * fill_amount
+    pub fn amount_out_required(&self, fill_amount: U256) -> Result<U256, FixedPointError> {
+        mul_64_u(
+            div_uu(
+                U256::from(self.amount_out_remaining),
+                U256::from(self.amount_in_remaining),
+            )?,
+            fill_amount,
+        )
+    }
+
+    //Returns the order left after the fill, with the amounts remaining and fee remaining reduced like the contract does on partial fills
+    pub fn remaining_after_fill(
+        &self,
+        fill_amount: U256,
+    ) -> Result<SandboxLimitOrder, FixedPointError> {
+        let mut remaining_order = *self;
+        remaining_order.fill_percent = self.fill_percent_after_fill(fill_amount);
+        remaining_order.amount_in_remaining = self
+            .amount_in_remaining
+            .saturating_sub(fill_amount.low_u128());
+        remaining_order.amount_out_remaining = self
+            .amount_out_remaining
+            .saturating_sub(self.amount_out_required(fill_amount)?.low_u128());
+        remaining_order.fee_remaining = self
+            .fee_remaining
+            .saturating_sub(self.execution_fee(fill_amount)?.low_u128());
+
+        Ok(remaining_order)
+    }
+
+    //Execution fee the contract takes for the fill, pro-rated from the fee remaining by the fill percent
+    pub fn execution_fee(&self, fill_amount: U256) -> Result<U256, FixedPointError> {
+        mul_64_u(
+            self.fill_percent_of(fill_amount)?,
+            U256::from(self.fee_remaining),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, H256, U256};
+
+    use std::collections::HashMap;
+
+    use crate::{routing::graph::Connectors, simulation::FixedPointError};
+
+    use super::{min_fill_percent_64_x_64, SandboxLimitOrder, DEFAULT_MIN_FILL_PERCENT};
+
+    fn sandbox_limit_order(
+        fee_remaining: u128,
+        amount_in_remaining: u128,
+        amount_out_remaining: u128,
+    ) -> SandboxLimitOrder {
+        SandboxLimitOrder::new(
+            0,
+            0,
+            0,
+            fee_remaining,
+            amount_in_remaining,
+            amount_out_remaining,
+            0,
+            H160::zero(),
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            H256::zero(),
+        )
+    }
+
+    #[test]
+    fn test_pro_rated_fill() {
+        let order = sandbox_limit_order(1_000, 1_000_000, 2_000_000);
+
+        //A full fill pays the whole fee and amount out remaining
+        let amount_in_remaining = U256::from(order.amount_in_remaining);
+        assert_eq!(order.fill_percent_of(amount_in_remaining).unwrap(), 1 << 64);
+        assert_eq!(
+            order.execution_fee(amount_in_remaining).unwrap(),
+            U256::from(1_000)
+        );
+        assert_eq!(
+            order.amount_out_required(amount_in_remaining).unwrap(),
+            U256::from(2_000_000)
+        );
+
+        //A quarter fill pays a quarter of the fee and amount out remaining
+        let fill_amount = U256::from(250_000);
+        assert_eq!(order.fill_percent_of(fill_amount).unwrap(), 1 << 62);
+        assert_eq!(order.execution_fee(fill_amount).unwrap(), U256::from(250));
+        assert_eq!(
+            order.amount_out_required(fill_amount).unwrap(),
+            U256::from(500_000)
+        );
+
+        let remaining_order = order.remaining_after_fill(fill_amount).unwrap();
+        assert_eq!(remaining_order.amount_in_remaining, 750_000);
+        assert_eq!(remaining_order.amount_out_remaining, 1_500_000);
+        assert_eq!(remaining_order.fee_remaining, 750);
+        assert_eq!(remaining_order.fill_percent, 1 << 62);
+
+        //The fill percent accumulates across partial fills
+        assert_eq!(
+            remaining_order.fill_percent_after_fill(U256::from(375_000)),
+            (1 << 62) + (1 << 63)
+        );
+    }
+
+    #[test]
+    fn test_min_fill_percent_64_x_64() {
+        assert_eq!(min_fill_percent_64_x_64(0), 0);
+        assert_eq!(min_fill_percent_64_x_64(25), 1 << 62);
+        assert_eq!(min_fill_percent_64_x_64(100), 1 << 64);
+    }
+
+    #[test]
+    fn test_is_valid_fill() {
+        let order = sandbox_limit_order(1_000, 1_000_000, 2_000_000);
+
+        assert!(order.is_valid_fill(U256::one()).unwrap());
+        assert!(order.is_valid_fill(U256::from(1_000_000)).unwrap());
+        assert!(!order.is_valid_fill(U256::zero()).unwrap());
+        assert!(!order.is_valid_fill(U256::from(1_000_001)).unwrap());
+
+        //Fills that require no amount out are rejected
+        let order = sandbox_limit_order(1_000, 1_000_000, 3);
+        assert!(!order.is_valid_fill(U256::from(100_000)).unwrap());
+        assert!(order.is_valid_fill(U256::from(1_000_000)).unwrap());
+
+        assert_eq!(
+            sandbox_limit_order(0, 1_000_000, 1)
+                .min_fill_amount(min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT))
+                .unwrap(),
+            U256::from(9_999)
+        );
+        assert_eq!(
+            sandbox_limit_order(0, 10, 1)
+                .min_fill_amount(min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT))
+                .unwrap(),
+            U256::one()
+        );
+
+        //Orders without an amount in remaining are skipped instead of dividing by zero
+        let order = sandbox_limit_order(1_000, 0, 1);
+        assert_eq!(
+            order.amount_out_required(U256::one()),
+            Err(FixedPointError::DivisionByZero)
+        );
+        assert_eq!(
+            order.execution_fee(U256::one()),
+            Err(FixedPointError::DivisionByZero)
+        );
+        assert_eq!(order.is_valid_fill(U256::one()), Ok(false));
+        assert!(!order.can_execute(
+            &HashMap::new(),
+            &Connectors::new(vec![], 1),
+            min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT)
+        ));
     }
 }
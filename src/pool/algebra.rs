@@ -0,0 +1,526 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{AbiEncode, RawLog},
+    prelude::{EthEvent, EthLogDecode},
+    providers::Middleware,
+    types::{Bytes, Log, H160, U256},
+};
+
+use crate::{
+    abi::{
+        self, i_algebra_pool, i_algebra_pool_directional_fee, i_algebra_swap_router,
+        i_uniswap_v3_pool::SwapFilter, AlgebraExactInputSingleParams, BurnFilter, MintFilter,
+    },
+    error::ExecutorError,
+    execution::sandbox_limit_order::Call,
+    markets::sqrt_price_64_x_64,
+    quoter::Quote,
+};
+
+use super::uniswap_v3::{
+    fetch_tick_data, swap_across_ticks, update_position, SwapState, TickData, TickInfo,
+    UniswapV3SimulationError,
+};
+use super::{approve_call, AutomatedMarketMaker};
+
+//Algebra factory (ie. QuickSwap V3, Camelot V3). Algebra pools are not supported by cfmms, so pools are discovered through the factory directly
+#[derive(Debug, Clone, Copy)]
+pub struct AlgebraDex {
+    pub factory_address: H160,
+    //Router used to swap through the pools, since the sandbox router does not implement the Algebra swap callback
+    pub swap_router: H160,
+    //Algebra 1.9 pools have a separate fee for each swap direction
+    pub directional_fee: bool,
+}
+
+impl AlgebraDex {
+    pub fn new(factory_address: H160, swap_router: H160, directional_fee: bool) -> AlgebraDex {
+        AlgebraDex {
+            factory_address,
+            swap_router,
+            directional_fee,
+        }
+    }
+
+    //Algebra factories deploy a single pool per pair
+    pub async fn get_pool_for_pair<M: Middleware>(
+        &self,
+        token_a: H160,
+        token_b: H160,
+        middleware: Arc<M>,
+    ) -> Result<Option<AlgebraPool>, ExecutorError<M>> {
+        let pool_address = abi::IAlgebraFactory::new(self.factory_address, middleware.clone())
+            .pool_by_pair(token_a, token_b)
+            .call()
+            .await?;
+
+        if pool_address.is_zero() {
+            Ok(None)
+        } else {
+            Ok(Some(
+                AlgebraPool::new_from_address(pool_address, self, middleware).await?,
+            ))
+        }
+    }
+
+    //Swap, Mint and Burn share their signatures with UniswapV3, only the dynamic fee event is specific to Algebra
+    pub fn fee_event_signature(&self) -> ethers::types::H256 {
+        if self.directional_fee {
+            i_algebra_pool_directional_fee::FeeFilter::signature()
+        } else {
+            i_algebra_pool::FeeFilter::signature()
+        }
+    }
+}
+
+//Algebra concentrated liquidity pool. Algebra pools have a dynamic fee and expose their state through globalState instead of slot0, but swaps follow the same tick math as UniswapV3
+#[derive(Debug, Clone, Default)]
+pub struct AlgebraPool {
+    pub address: H160,
+    pub token_a: H160,
+    pub token_a_decimals: u8,
+    pub token_b: H160,
+    pub token_b_decimals: u8,
+    pub liquidity: u128,
+    pub sqrt_price: U256,
+    pub tick: i32,
+    pub tick_spacing: i32,
+    //Fees for each swap direction, both are the same for pools without directional fees
+    pub fee_zero_for_one: u32,
+    pub fee_one_for_zero: u32,
+    pub directional_fee: bool,
+    pub swap_router: H160,
+    pub tick_data: Arc<TickData>,
+}
+
+impl AlgebraPool {
+    pub async fn new_from_address<M: Middleware>(
+        address: H160,
+        dex: &AlgebraDex,
+        middleware: Arc<M>,
+    ) -> Result<AlgebraPool, ExecutorError<M>> {
+        let algebra_pool = abi::IAlgebraPool::new(address, middleware.clone());
+
+        let token_a = algebra_pool.token_0().call().await?;
+        let token_b = algebra_pool.token_1().call().await?;
+
+        let mut pool = AlgebraPool {
+            address,
+            token_a,
+            token_a_decimals: abi::IErc20::new(token_a, middleware.clone())
+                .decimals()
+                .call()
+                .await?,
+            token_b,
+            token_b_decimals: abi::IErc20::new(token_b, middleware.clone())
+                .decimals()
+                .call()
+                .await?,
+            tick_spacing: algebra_pool.tick_spacing().call().await?,
+            directional_fee: dex.directional_fee,
+            swap_router: dex.swap_router,
+            ..Default::default()
+        };
+
+        pool.sync(middleware.clone()).await?;
+        pool.populate_tick_data(middleware).await?;
+
+        Ok(pool)
+    }
+
+    //Fetches the price, tick, fees and in range liquidity of the pool
+    pub async fn sync<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<(), ExecutorError<M>> {
+        if self.directional_fee {
+            let (sqrt_price, tick, fee_zero_for_one, fee_one_for_zero, _, _, _, _) =
+                abi::IAlgebraPoolDirectionalFee::new(self.address, middleware.clone())
+                    .global_state()
+                    .call()
+                    .await?;
+
+            self.sqrt_price = sqrt_price;
+            self.tick = tick;
+            self.fee_zero_for_one = fee_zero_for_one as u32;
+            self.fee_one_for_zero = fee_one_for_zero as u32;
+        } else {
+            let (sqrt_price, tick, fee, _, _, _, _) =
+                abi::IAlgebraPool::new(self.address, middleware.clone())
+                    .global_state()
+                    .call()
+                    .await?;
+
+            self.sqrt_price = sqrt_price;
+            self.tick = tick;
+            self.fee_zero_for_one = fee as u32;
+            self.fee_one_for_zero = fee as u32;
+        }
+
+        self.liquidity = abi::IAlgebraPool::new(self.address, middleware)
+            .liquidity()
+            .call()
+            .await?;
+
+        Ok(())
+    }
+
+    //Fetches the tick table rows around the current tick and the liquidity of every initialized tick within those rows
+    pub async fn populate_tick_data<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<(), ExecutorError<M>> {
+        let algebra_pool = abi::IAlgebraPool::new(self.address, middleware);
+
+        let tick_data = fetch_tick_data(
+            self.tick,
+            self.tick_spacing,
+            |word_position| {
+                let algebra_pool = algebra_pool.clone();
+                async move { Ok(algebra_pool.tick_table(word_position).call().await?) }
+            },
+            |tick| {
+                let algebra_pool = algebra_pool.clone();
+                async move {
+                    let (liquidity_gross, liquidity_net) = algebra_pool.ticks(tick).call().await?;
+                    Ok(TickInfo {
+                        liquidity_gross,
+                        liquidity_net,
+                    })
+                }
+            },
+        )
+        .await?;
+
+        self.tick_data = Arc::new(tick_data);
+
+        Ok(())
+    }
+
+    pub fn fee(&self, zero_for_one: bool) -> u32 {
+        if zero_for_one {
+            self.fee_zero_for_one
+        } else {
+            self.fee_one_for_zero
+        }
+    }
+
+    //Simulates an exact input swap using only the local tick data
+    pub fn simulate_swap_local(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, UniswapV3SimulationError> {
+        Ok(self.swap(token_in, amount_in)?.amount_out)
+    }
+
+    pub fn quote_local(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<Quote, UniswapV3SimulationError> {
+        Ok(self.swap(token_in, amount_in)?.quote())
+    }
+
+    pub fn simulate_swap_local_mut(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, UniswapV3SimulationError> {
+        let swap_state = self.swap(token_in, amount_in)?;
+
+        self.sqrt_price = swap_state.sqrt_price;
+        self.tick = swap_state.tick;
+        self.liquidity = swap_state.liquidity;
+
+        Ok(swap_state.amount_out)
+    }
+
+    fn swap(&self, token_in: H160, amount_in: U256) -> Result<SwapState, UniswapV3SimulationError> {
+        let zero_for_one = token_in == self.token_a;
+
+        swap_across_ticks(
+            &self.tick_data,
+            self.tick_spacing,
+            self.fee(zero_for_one),
+            zero_for_one,
+            amount_in,
+            SwapState::new(self.sqrt_price, self.tick, self.liquidity),
+        )
+    }
+
+    //Updates the pool from a Swap, Mint, Burn or Fee log
+    pub fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        let raw_log = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+
+        if log.topics[0] == SwapFilter::signature() {
+            let swap_log = <SwapFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.sqrt_price = swap_log.sqrt_price_x96;
+            self.liquidity = swap_log.liquidity;
+            self.tick = swap_log.tick;
+        } else if log.topics[0] == MintFilter::signature() {
+            let mint_log = <MintFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.update_position(
+                mint_log.tick_lower,
+                mint_log.tick_upper,
+                mint_log.amount as i128,
+            );
+        } else if log.topics[0] == BurnFilter::signature() {
+            let burn_log = <BurnFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.update_position(
+                burn_log.tick_lower,
+                burn_log.tick_upper,
+                -(burn_log.amount as i128),
+            );
+        } else if self.directional_fee
+            && log.topics[0] == i_algebra_pool_directional_fee::FeeFilter::signature()
+        {
+            let fee_log =
+                <i_algebra_pool_directional_fee::FeeFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.fee_zero_for_one = fee_log.fee_zto as u32;
+            self.fee_one_for_zero = fee_log.fee_otz as u32;
+        } else if !self.directional_fee && log.topics[0] == i_algebra_pool::FeeFilter::signature() {
+            let fee_log = <i_algebra_pool::FeeFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.fee_zero_for_one = fee_log.fee as u32;
+            self.fee_one_for_zero = fee_log.fee as u32;
+        }
+
+        Ok(())
+    }
+
+    pub fn update_position(&mut self, tick_lower: i32, tick_upper: i32, liquidity_delta: i128) {
+        update_position(
+            &mut self.tick_data,
+            &mut self.liquidity,
+            self.tick,
+            self.tick_spacing,
+            tick_lower,
+            tick_upper,
+            liquidity_delta,
+        );
+    }
+
+    //Calldata to swap an exact amount in through the pool with the swap router, the swap router must be approved to spend the amount in
+    pub fn swap_calldata(&self, token_in: H160, amount_in: U256, recipient: H160) -> Bytes {
+        let token_out = if token_in == self.token_a {
+            self.token_b
+        } else {
+            self.token_a
+        };
+
+        i_algebra_swap_router::ExactInputSingleCall {
+            params: AlgebraExactInputSingleParams {
+                token_in,
+                token_out,
+                recipient,
+                deadline: U256::MAX,
+                amount_in,
+                amount_out_minimum: U256::zero(),
+                limit_sqrt_price: U256::zero(),
+            },
+        }
+        .encode()
+        .into()
+    }
+}
+
+impl AutomatedMarketMaker for AlgebraPool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    //Algebra pools have a dynamic fee, this is the current fee for swapping token a to token b
+    fn fee(&self) -> u32 {
+        AlgebraPool::fee(self, true)
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        sqrt_price_64_x_64(
+            self.sqrt_price,
+            base_token == self.token_a,
+            self.token_a_decimals,
+            self.token_b_decimals,
+        )
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        AlgebraPool::quote_local(self, token_in, amount_in).ok()
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        AlgebraPool::simulate_swap_local_mut(self, token_in, amount_in).ok()
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        AlgebraPool::update_from_log(self, log)
+    }
+
+    //Algebra pools call back into the swap router, so the sandbox router approves the swap router and swaps through it
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        _amount_out: U256,
+        to: H160,
+        _from: H160,
+    ) -> Vec<Call> {
+        vec![
+            approve_call(token_in, self.swap_router, amount_in),
+            Call::new(
+                self.swap_router,
+                self.swap_calldata(token_in, amount_in, to).to_vec(),
+            ),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ethers::{
+        abi::{encode, AbiDecode, Token},
+        prelude::EthEvent,
+        types::{Bytes, Log, H160, I256, U256},
+    };
+    use uniswap_v3_math::{
+        swap_math,
+        tick_math::{get_sqrt_ratio_at_tick, MAX_SQRT_RATIO, MIN_SQRT_RATIO},
+    };
+
+    use super::AlgebraPool;
+    use crate::{
+        abi::{i_algebra_pool, i_algebra_pool_directional_fee, i_algebra_swap_router},
+        pool::uniswap_v3::TickData,
+    };
+
+    fn token_a() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token_b() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    //Pool at tick 0 with 1e18 liquidity in [-600, 600] and a cheaper fee for zero for one swaps
+    fn pool() -> AlgebraPool {
+        let mut pool = AlgebraPool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_a(),
+            token_a_decimals: 18,
+            token_b: token_b(),
+            token_b_decimals: 18,
+            sqrt_price: get_sqrt_ratio_at_tick(0).unwrap(),
+            tick_spacing: 60,
+            fee_zero_for_one: 100,
+            fee_one_for_zero: 3000,
+            directional_fee: true,
+            swap_router: H160::from_low_u64_be(4),
+            tick_data: Arc::new(TickData {
+                word_range: Some((-2, 2)),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+
+        pool.update_position(-600, 600, 1_000_000_000_000_000_000);
+
+        pool
+    }
+
+    fn fee_log(signature: ethers::types::H256, fees: Vec<Token>) -> Log {
+        Log {
+            topics: vec![signature],
+            data: Bytes::from(encode(&fees)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_swap_local_directional_fee() {
+        let pool = pool();
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        //Each direction is simulated with its own fee
+        for (token_in, sqrt_price_limit, fee) in [
+            (token_a(), MIN_SQRT_RATIO + 1, 100),
+            (token_b(), MAX_SQRT_RATIO - 1, 3000),
+        ] {
+            let (_, _, expected_amount_out, _) = swap_math::compute_swap_step(
+                pool.sqrt_price,
+                sqrt_price_limit,
+                pool.liquidity,
+                I256::from_raw(amount_in),
+                fee,
+            )
+            .unwrap();
+
+            assert_eq!(
+                pool.simulate_swap_local(token_in, amount_in).unwrap(),
+                expected_amount_out
+            );
+        }
+
+        assert!(
+            pool.simulate_swap_local(token_a(), amount_in).unwrap()
+                > pool.simulate_swap_local(token_b(), amount_in).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_update_from_fee_log() {
+        let mut pool = pool();
+
+        pool.update_from_log(&fee_log(
+            i_algebra_pool_directional_fee::FeeFilter::signature(),
+            vec![Token::Uint(U256::from(500)), Token::Uint(U256::from(800))],
+        ))
+        .unwrap();
+        assert_eq!((pool.fee(true), pool.fee(false)), (500, 800));
+
+        //Fee events from the other pool version are ignored
+        pool.update_from_log(&fee_log(
+            i_algebra_pool::FeeFilter::signature(),
+            vec![Token::Uint(U256::from(100))],
+        ))
+        .unwrap();
+        assert_eq!((pool.fee(true), pool.fee(false)), (500, 800));
+
+        //Pools without directional fees apply the fee to both directions
+        pool.directional_fee = false;
+        pool.update_from_log(&fee_log(
+            i_algebra_pool::FeeFilter::signature(),
+            vec![Token::Uint(U256::from(100))],
+        ))
+        .unwrap();
+        assert_eq!((pool.fee(true), pool.fee(false)), (100, 100));
+    }
+
+    #[test]
+    fn test_swap_calldata() {
+        let pool = pool();
+        let recipient = H160::from_low_u64_be(5);
+
+        let call = i_algebra_swap_router::ExactInputSingleCall::decode(pool.swap_calldata(
+            token_b(),
+            U256::from(1000),
+            recipient,
+        ))
+        .unwrap();
+
+        assert_eq!(call.params.token_in, token_b());
+        assert_eq!(call.params.token_out, token_a());
+        assert_eq!(call.params.recipient, recipient);
+        assert_eq!(call.params.amount_in, U256::from(1000));
+        assert_eq!(call.params.amount_out_minimum, U256::zero());
+        assert_eq!(call.params.limit_sqrt_price, U256::zero());
+    }
+}
@@ -0,0 +1,1106 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use ethers::{
+    abi::{AbiEncode, RawLog},
+    prelude::{EthEvent, EthLogDecode},
+    providers::Middleware,
+    types::{Bytes, Filter, Log, H160, H256, U256, U512},
+};
+
+use crate::{
+    abi::{self, i_balancer_pool, i_balancer_vault},
+    error::ExecutorError,
+    execution::sandbox_limit_order::Call,
+    markets::normalized_price_64_x_64,
+    pool::curve,
+    quoter::{Quote, BALANCER_SWAP_GAS_ESTIMATE},
+};
+
+use super::{approve_call, AutomatedMarketMaker};
+
+//Vault swap kind for swaps with an exact amount in
+pub const SWAP_KIND_GIVEN_IN: u8 = 0;
+//Amplification parameters of stable pools are multiplied by AMP_PRECISION
+pub const AMP_PRECISION: u64 = 1000;
+//Weighted pools revert when the amount in is more than 30% of the balance in
+const MAX_IN_RATIO: u128 = 300_000_000_000_000_000;
+const MAX_POW_RELATIVE_ERROR: u64 = 10_000;
+//Number of blocks in each TokensRegistered log request when syncing the registry
+const REGISTRY_SYNC_STEP: u64 = 10_000;
+
+//Pool ids and tokens registered in the vault, synced from TokensRegistered logs
+#[derive(Debug, Default)]
+pub struct BalancerRegistry {
+    pub synced_block: u64,
+    pub pool_tokens: HashMap<H256, Vec<H160>>,
+}
+
+//Balancer V2 vault. The vault does not index pools by pair, so every pool is found from the TokensRegistered logs, which are only fetched once and shared between clones of the dex
+#[derive(Debug, Clone)]
+pub struct BalancerDex {
+    pub vault_address: H160,
+    pub creation_block: u64,
+    pub registry: Arc<Mutex<BalancerRegistry>>,
+}
+
+impl BalancerDex {
+    pub fn new(vault_address: H160, creation_block: u64) -> BalancerDex {
+        BalancerDex {
+            vault_address,
+            creation_block,
+            registry: Arc::new(Mutex::new(BalancerRegistry::default())),
+        }
+    }
+
+    //Fetches the TokensRegistered logs from the last synced block to the current block
+    pub async fn sync_registry<M: Middleware>(
+        &self,
+        middleware: Arc<M>,
+    ) -> Result<(), ExecutorError<M>> {
+        let from_block = {
+            let registry = self
+                .registry
+                .lock()
+                .expect("Could not lock the Balancer registry");
+            (registry.synced_block + 1).max(self.creation_block)
+        };
+
+        let current_block = middleware
+            .get_block_number()
+            .await
+            .map_err(ExecutorError::MiddlewareError)?
+            .as_u64();
+
+        let mut pool_tokens = vec![];
+        for from_block in (from_block..=current_block).step_by(REGISTRY_SYNC_STEP as usize) {
+            let to_block = (from_block + REGISTRY_SYNC_STEP - 1).min(current_block);
+
+            let logs = middleware
+                .get_logs(
+                    &Filter::new()
+                        .address(self.vault_address)
+                        .topic0(i_balancer_vault::TokensRegisteredFilter::signature())
+                        .from_block(from_block)
+                        .to_block(to_block),
+                )
+                .await
+                .map_err(ExecutorError::MiddlewareError)?;
+
+            for log in logs {
+                let tokens_registered_log =
+                    <i_balancer_vault::TokensRegisteredFilter as EthLogDecode>::decode_log(
+                        &RawLog {
+                            topics: log.topics,
+                            data: log.data.to_vec(),
+                        },
+                    )?;
+
+                pool_tokens.push((
+                    H256::from(tokens_registered_log.pool_id),
+                    tokens_registered_log.tokens,
+                ));
+            }
+        }
+
+        let mut registry = self
+            .registry
+            .lock()
+            .expect("Could not lock the Balancer registry");
+        for (pool_id, tokens) in pool_tokens {
+            registry
+                .pool_tokens
+                .entry(pool_id)
+                .or_default()
+                .extend(tokens);
+        }
+        registry.synced_block = registry.synced_block.max(current_block);
+
+        Ok(())
+    }
+
+    //Returns every weighted and stable pool in the vault that holds both tokens and has a balance of each
+    pub async fn get_pools_for_pair<M: Middleware>(
+        &self,
+        token_a: H160,
+        token_b: H160,
+        middleware: Arc<M>,
+    ) -> Result<Vec<BalancerPool>, ExecutorError<M>> {
+        self.sync_registry(middleware.clone()).await?;
+
+        let pool_ids: Vec<H256> = self
+            .registry
+            .lock()
+            .expect("Could not lock the Balancer registry")
+            .pool_tokens
+            .iter()
+            .filter(|(_, tokens)| tokens.contains(&token_a) && tokens.contains(&token_b))
+            .map(|(pool_id, _)| *pool_id)
+            .collect();
+
+        let mut pools = vec![];
+        for pool_id in pool_ids {
+            //Pools with other math (ie. linear or managed pools) are not supported
+            match BalancerPool::new_from_pool_id(pool_id, self.vault_address, middleware.clone())
+                .await
+            {
+                Ok(mut pool) => {
+                    if let (Some(i), Some(j)) =
+                        (pool.token_index(token_a), pool.token_index(token_b))
+                    {
+                        if !pool.balances[i].is_zero() && !pool.balances[j].is_zero() {
+                            pool.token_a = token_a;
+                            pool.token_b = token_b;
+                            pools.push(pool);
+                        }
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("Could not add Balancer pool {:?}: {:?}", pool_id, err);
+                }
+            }
+        }
+
+        Ok(pools)
+    }
+
+    pub fn event_signatures(&self) -> Vec<H256> {
+        vec![
+            i_balancer_vault::SwapFilter::signature(),
+            i_balancer_vault::PoolBalanceChangedFilter::signature(),
+            i_balancer_pool::SwapFeePercentageChangedFilter::signature(),
+        ]
+    }
+}
+
+//The first 20 bytes of a pool id are the pool address
+pub fn pool_address_from_pool_id(pool_id: H256) -> H160 {
+    H160::from_slice(&pool_id.as_bytes()[..20])
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BalancerPoolVariant {
+    #[default]
+    Weighted,
+    Stable,
+}
+
+//Balancer V2 weighted or stable pool. The balances are held by the vault, which emits the Swap and PoolBalanceChanged logs for the pool.
+//A pool with n tokens is added to the market of every pair of tracked tokens it holds, token_a and token_b are the pair of the market the pool is in.
+#[derive(Debug, Clone, Default)]
+pub struct BalancerPool {
+    pub address: H160,
+    pub pool_id: H256,
+    pub vault: H160,
+    pub token_a: H160,
+    pub token_b: H160,
+    pub tokens: Vec<H160>,
+    pub token_decimals: Vec<u8>,
+    pub balances: Vec<U256>,
+    //Multipliers that scale each balance to 18 decimals, with a precision of 1e18. Pools with rate providers include the token rate
+    pub scaling_factors: Vec<U256>,
+    //Normalized weights of weighted pools, empty for stable pools
+    pub weights: Vec<U256>,
+    //Amplification parameter of stable pools multiplied by AMP_PRECISION, zero for weighted pools
+    pub amp: U256,
+    //Swap fee with a precision of 1e18
+    pub swap_fee: U256,
+    pub variant: BalancerPoolVariant,
+}
+
+impl BalancerPool {
+    pub async fn new_from_pool_id<M: Middleware>(
+        pool_id: H256,
+        vault_address: H160,
+        middleware: Arc<M>,
+    ) -> Result<BalancerPool, ExecutorError<M>> {
+        let address = pool_address_from_pool_id(pool_id);
+        let vault = abi::IBalancerVault::new(vault_address, middleware.clone());
+        let balancer_pool = abi::IBalancerPool::new(address, middleware.clone());
+
+        let (tokens, balances, _) = vault.get_pool_tokens(pool_id.0).call().await?;
+
+        //Weighted pools are identified by their weights and stable pools by their amplification parameter
+        let (variant, weights, amp) = match balancer_pool.get_normalized_weights().call().await {
+            Ok(weights) => (BalancerPoolVariant::Weighted, weights, U256::zero()),
+            Err(_) => {
+                let (amp, _, _) = balancer_pool.get_amplification_parameter().call().await?;
+                (BalancerPoolVariant::Stable, vec![], amp)
+            }
+        };
+
+        //Weighted pools deployed before scaling factors were exposed only scale by decimals
+        let scaling_factors = balancer_pool.get_scaling_factors().call().await.ok();
+
+        let mut pool = BalancerPool {
+            address,
+            pool_id,
+            vault: vault_address,
+            amp,
+            swap_fee: balancer_pool.get_swap_fee_percentage().call().await?,
+            variant,
+            ..Default::default()
+        };
+
+        for (k, (token, balance)) in tokens.into_iter().zip(balances).enumerate() {
+            //Composable stable pools register their own pool token, which is only swapped to join or exit the pool
+            if token == address {
+                continue;
+            }
+
+            let decimals = abi::IErc20::new(token, middleware.clone())
+                .decimals()
+                .call()
+                .await?;
+
+            pool.tokens.push(token);
+            pool.token_decimals.push(decimals);
+            pool.balances.push(balance);
+            pool.scaling_factors.push(
+                scaling_factors
+                    .as_ref()
+                    .and_then(|scaling_factors| scaling_factors.get(k).copied())
+                    .unwrap_or_else(|| U256::exp10(36 - decimals as usize)),
+            );
+
+            if variant == BalancerPoolVariant::Weighted {
+                pool.weights.push(
+                    weights
+                        .get(k)
+                        .copied()
+                        .ok_or(ExecutorError::PoolSimulationError(address))?,
+                );
+            }
+        }
+
+        Ok(pool)
+    }
+
+    pub fn token_index(&self, token: H160) -> Option<usize> {
+        self.tokens
+            .iter()
+            .position(|pool_token| *pool_token == token)
+    }
+
+    //Returns the index of the token in and the other token in the pair
+    fn swap_indices(&self, token_in: H160) -> Option<(usize, usize)> {
+        let token_out = if token_in == self.token_a {
+            self.token_b
+        } else if token_in == self.token_b {
+            self.token_a
+        } else {
+            return None;
+        };
+
+        Some((self.token_index(token_in)?, self.token_index(token_out)?))
+    }
+
+    //Swap fee in hundredths of a bip, the same unit as UniswapV3 fees
+    pub fn fee(&self) -> u32 {
+        (self.swap_fee / U256::exp10(12)).as_u32()
+    }
+
+    //Mirrors the pool's onSwap for an exact amount in. Returns None if the swap would revert
+    pub fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let (i, j) = self.swap_indices(token_in)?;
+
+        //The swap fee is taken from the amount in before it is scaled
+        let amount_in = amount_in.checked_sub(mul_up(amount_in, self.swap_fee)?)?;
+        let amount_in = mul_down(amount_in, self.scaling_factors[i])?;
+        let balances = self.upscaled_balances()?;
+
+        let amount_out = match self.variant {
+            BalancerPoolVariant::Weighted => weighted_out_given_in(
+                balances[i],
+                self.weights[i],
+                balances[j],
+                self.weights[j],
+                amount_in,
+            )?,
+            BalancerPoolVariant::Stable => {
+                stable_out_given_in(self.amp, &balances, i, j, amount_in)?
+            }
+        };
+
+        div_down(amount_out, self.scaling_factors[j])
+    }
+
+    pub fn quote(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        Some(Quote::new(
+            self.simulate_swap(token_in, amount_in)?,
+            U256::from(BALANCER_SWAP_GAS_ESTIMATE),
+        ))
+    }
+
+    //The swap fee stays in the pool, so the full amount in is added to the balance
+    pub fn simulate_swap_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let (i, j) = self.swap_indices(token_in)?;
+        let amount_out = self.simulate_swap(token_in, amount_in)?;
+
+        self.balances[i] = self.balances[i].checked_add(amount_in)?;
+        self.balances[j] = self.balances[j].checked_sub(amount_out)?;
+
+        Some(amount_out)
+    }
+
+    fn upscaled_balances(&self) -> Option<Vec<U256>> {
+        self.balances
+            .iter()
+            .zip(self.scaling_factors.iter())
+            .map(|(balance, scaling_factor)| mul_down(*balance, *scaling_factor))
+            .collect()
+    }
+
+    //Spot price of the base token in the other token of the pair as a Q64.64 fixed point number, excluding the swap fee
+    pub fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        let (i, j) = self.swap_indices(base_token)?;
+        let balances = self.upscaled_balances()?;
+
+        //Scales one whole token to its upscaled balance
+        let scale = |k: usize| -> Option<U512> {
+            U512::from(self.scaling_factors[k])
+                .checked_mul(U512::exp10(self.token_decimals[k] as usize))?
+                .checked_div(U512::exp10(18))
+        };
+
+        let (amount_base, amount_quote) = match self.variant {
+            //The price of token i in token j is (balance_j / weight_j) / (balance_i / weight_i)
+            BalancerPoolVariant::Weighted => (
+                U512::from(balances[i]).checked_mul(U512::from(self.weights[j]))?,
+                U512::from(balances[j]).checked_mul(U512::from(self.weights[i]))?,
+            ),
+            BalancerPoolVariant::Stable => curve::stableswap_marginal_amounts(
+                &balances,
+                self.amp,
+                U256::from(AMP_PRECISION),
+                i,
+                j,
+            )?,
+        };
+
+        normalized_price_64_x_64(
+            amount_base.checked_mul(scale(j)?)?,
+            0,
+            amount_quote.checked_mul(scale(i)?)?,
+            0,
+            false,
+        )
+    }
+
+    //Updates the pool from vault Swap and PoolBalanceChanged logs for the pool id and SwapFeePercentageChanged logs from the pool
+    pub fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        let raw_log = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+
+        if log.topics[0] == i_balancer_vault::SwapFilter::signature() {
+            let swap_log = <i_balancer_vault::SwapFilter as EthLogDecode>::decode_log(&raw_log)?;
+            if H256::from(swap_log.pool_id) != self.pool_id {
+                return Ok(());
+            }
+
+            //Swaps to and from the pool token of composable stable pools only update the balance of the other token
+            if let Some(i) = self.token_index(swap_log.token_in) {
+                self.balances[i] += swap_log.amount_in;
+            }
+            if let Some(j) = self.token_index(swap_log.token_out) {
+                self.balances[j] = self.balances[j].saturating_sub(swap_log.amount_out);
+            }
+        } else if log.topics[0] == i_balancer_vault::PoolBalanceChangedFilter::signature() {
+            let pool_balance_changed_log =
+                <i_balancer_vault::PoolBalanceChangedFilter as EthLogDecode>::decode_log(&raw_log)?;
+            if H256::from(pool_balance_changed_log.pool_id) != self.pool_id {
+                return Ok(());
+            }
+
+            //Protocol fees are paid out of the pool balance when joining or exiting
+            for ((token, delta), protocol_fee_amount) in pool_balance_changed_log
+                .tokens
+                .iter()
+                .zip(pool_balance_changed_log.deltas)
+                .zip(pool_balance_changed_log.protocol_fee_amounts)
+            {
+                if let Some(k) = self.token_index(*token) {
+                    let balance = if delta.is_negative() {
+                        self.balances[k].saturating_sub(delta.unsigned_abs())
+                    } else {
+                        self.balances[k] + delta.into_raw()
+                    };
+
+                    self.balances[k] = balance.saturating_sub(protocol_fee_amount);
+                }
+            }
+        } else if log.topics[0] == i_balancer_pool::SwapFeePercentageChangedFilter::signature()
+            && log.address == self.address
+        {
+            let swap_fee_log =
+                <i_balancer_pool::SwapFeePercentageChangedFilter as EthLogDecode>::decode_log(
+                    &raw_log,
+                )?;
+            self.swap_fee = swap_fee_log.swap_fee_percentage;
+        }
+
+        Ok(())
+    }
+
+    //Swaps an exact amount in through the vault. The vault pulls the amount in from the sender, which must approve the vault first, and sends the amount out to the recipient
+    pub fn swap_calldata(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        min_amount_out: U256,
+        sender: H160,
+        recipient: H160,
+    ) -> Bytes {
+        i_balancer_vault::SwapCall {
+            single_swap: i_balancer_vault::SingleSwap {
+                pool_id: self.pool_id.0,
+                kind: SWAP_KIND_GIVEN_IN,
+                asset_in: token_in,
+                asset_out: self.token_out(token_in),
+                amount: amount_in,
+                user_data: Bytes::new(),
+            },
+            funds: i_balancer_vault::FundManagement {
+                sender,
+                from_internal_balance: false,
+                recipient,
+                to_internal_balance: false,
+            },
+            limit: min_amount_out,
+            deadline: U256::MAX,
+        }
+        .encode()
+        .into()
+    }
+}
+
+impl AutomatedMarketMaker for BalancerPool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    fn fee(&self) -> u32 {
+        BalancerPool::fee(self)
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        BalancerPool::price_64_x_64(self, base_token)
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        self.quote(token_in, amount_in)
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.simulate_swap_mut(token_in, amount_in)
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        BalancerPool::update_from_log(self, log)
+    }
+
+    //Balancer swaps go through the vault, which pulls the amount in from the router and sends the amount out to the recipient.
+    //The simulated amount out is used as the limit so that the swap reverts instead of filling below the simulation
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        from: H160,
+    ) -> Vec<Call> {
+        vec![
+            approve_call(token_in, self.vault, amount_in),
+            Call::new(
+                self.vault,
+                self.swap_calldata(token_in, amount_in, amount_out, from, to)
+                    .to_vec(),
+            ),
+        ]
+    }
+}
+
+//Mirrors WeightedMath._calcOutGivenIn with all amounts scaled to 18 decimals
+fn weighted_out_given_in(
+    balance_in: U256,
+    weight_in: U256,
+    balance_out: U256,
+    weight_out: U256,
+    amount_in: U256,
+) -> Option<U256> {
+    if amount_in > mul_down(balance_in, U256::from(MAX_IN_RATIO))? {
+        return None;
+    }
+
+    let base = div_up(balance_in, balance_in.checked_add(amount_in)?)?;
+    let exponent = div_down(weight_in, weight_out)?;
+    let power = pow_up(base, exponent)?;
+
+    mul_down(balance_out, complement(power))
+}
+
+//Mirrors StableMath._calcOutGivenIn with all amounts scaled to 18 decimals
+fn stable_out_given_in(
+    amp: U256,
+    balances: &[U256],
+    i: usize,
+    j: usize,
+    amount_in: U256,
+) -> Option<U256> {
+    let invariant = curve::get_d(balances, amp, U256::from(AMP_PRECISION))?;
+
+    let mut balances = balances.to_vec();
+    balances[i] = balances[i].checked_add(amount_in)?;
+    let final_balance_out = get_token_balance_given_invariant(amp, &balances, invariant, j)?;
+
+    balances[j]
+        .checked_sub(final_balance_out)?
+        .checked_sub(U256::one())
+}
+
+//Mirrors StableMath._getTokenBalanceGivenInvariantAndAllOtherBalances
+fn get_token_balance_given_invariant(
+    amp: U256,
+    balances: &[U256],
+    invariant: U256,
+    token_index: usize,
+) -> Option<U256> {
+    let amp_precision = U256::from(AMP_PRECISION);
+    let n_tokens = U256::from(balances.len());
+    let amp_times_total = amp.checked_mul(n_tokens)?;
+
+    let mut sum = balances[0];
+    let mut p_d = balances[0].checked_mul(n_tokens)?;
+    for balance in balances.iter().skip(1) {
+        p_d = p_d
+            .checked_mul(*balance)?
+            .checked_mul(n_tokens)?
+            .checked_div(invariant)?;
+        sum = sum.checked_add(*balance)?;
+    }
+    sum -= balances[token_index];
+
+    let inv2 = invariant.checked_mul(invariant)?;
+    let c = math_div_up(inv2, amp_times_total.checked_mul(p_d)?)?
+        .checked_mul(amp_precision)?
+        .checked_mul(balances[token_index])?;
+    let b = sum.checked_add(
+        invariant
+            .checked_div(amp_times_total)?
+            .checked_mul(amp_precision)?,
+    )?;
+
+    let mut token_balance = math_div_up(inv2.checked_add(c)?, invariant.checked_add(b)?)?;
+    for _ in 0..255 {
+        let prev_token_balance = token_balance;
+
+        token_balance = math_div_up(
+            token_balance.checked_mul(token_balance)?.checked_add(c)?,
+            token_balance
+                .checked_mul(U256::from(2))?
+                .checked_add(b)?
+                .checked_sub(invariant)?,
+        )?;
+
+        if token_balance > prev_token_balance {
+            if token_balance - prev_token_balance <= U256::one() {
+                return Some(token_balance);
+            }
+        } else if prev_token_balance - token_balance <= U256::one() {
+            return Some(token_balance);
+        }
+    }
+
+    //The pool reverts if the balance does not converge
+    None
+}
+
+//Math.divUp, rounding up without fixed point scaling
+fn math_div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        None
+    } else if a.is_zero() {
+        Some(U256::zero())
+    } else {
+        Some((a - 1) / b + 1)
+    }
+}
+
+//FixedPoint functions with a precision of 1e18
+fn mul_down(a: U256, b: U256) -> Option<U256> {
+    Some(a.checked_mul(b)? / U256::exp10(18))
+}
+
+fn mul_up(a: U256, b: U256) -> Option<U256> {
+    let product = a.checked_mul(b)?;
+    if product.is_zero() {
+        Some(U256::zero())
+    } else {
+        Some((product - 1) / U256::exp10(18) + 1)
+    }
+}
+
+fn div_down(a: U256, b: U256) -> Option<U256> {
+    a.checked_mul(U256::exp10(18))?.checked_div(b)
+}
+
+fn div_up(a: U256, b: U256) -> Option<U256> {
+    if b.is_zero() {
+        None
+    } else if a.is_zero() {
+        Some(U256::zero())
+    } else {
+        Some((a.checked_mul(U256::exp10(18))? - 1) / b + 1)
+    }
+}
+
+fn complement(x: U256) -> U256 {
+    U256::exp10(18).saturating_sub(x)
+}
+
+//Rounds the power up by the maximum relative error of LogExpMath.pow, with the exponents used by 50/50 and 80/20 pools computed exactly
+fn pow_up(x: U256, y: U256) -> Option<U256> {
+    let one = U256::exp10(18);
+
+    if y == one {
+        Some(x)
+    } else if y == one * 2 {
+        mul_up(x, x)
+    } else if y == one * 4 {
+        let square = mul_up(x, x)?;
+        mul_up(square, square)
+    } else {
+        let raw = log_exp_math::pow(x, y)?;
+        let max_error = mul_up(raw, U256::from(MAX_POW_RELATIVE_ERROR))?.checked_add(U256::one())?;
+        raw.checked_add(max_error)
+    }
+}
+
+//Port of Balancer's LogExpMath, which computes x^y as exp(ln(x) * y) with 18 decimal fixed point numbers.
+//Division truncates towards zero like Solidity's signed division, so results match the pools to the wei
+mod log_exp_math {
+    use ethers::types::{I256, U256};
+
+    const ONE_18: i128 = 1_000_000_000_000_000_000;
+    const ONE_20: i128 = 100_000_000_000_000_000_000;
+    const ONE_36: i128 = 1_000_000_000_000_000_000_000_000_000_000_000_000;
+
+    const MAX_NATURAL_EXPONENT: i128 = 130 * ONE_18;
+    const MIN_NATURAL_EXPONENT: i128 = -41 * ONE_18;
+
+    const LN_36_LOWER_BOUND: i128 = ONE_18 - 100_000_000_000_000_000;
+    const LN_36_UPPER_BOUND: i128 = ONE_18 + 100_000_000_000_000_000;
+
+    //e^128, which does not fit in an i128
+    const A0: I256 = I256::from_raw(U256([
+        0x0262_8270_0000_0000,
+        0xf53a_2717_2fa9_ec63,
+        0x0195_e54c_5dd4_2177,
+        0,
+    ]));
+    const X0: i128 = 128 * ONE_18;
+    const X1: i128 = 64 * ONE_18;
+    const A1: i128 = 6_235_149_080_811_616_882_910_000_000;
+
+    //x_n and e^(x_n) with 20 decimals
+    const X_N: [i128; 10] = [
+        3_200_000_000_000_000_000_000,
+        1_600_000_000_000_000_000_000,
+        800_000_000_000_000_000_000,
+        400_000_000_000_000_000_000,
+        200_000_000_000_000_000_000,
+        100_000_000_000_000_000_000,
+        50_000_000_000_000_000_000,
+        25_000_000_000_000_000_000,
+        12_500_000_000_000_000_000,
+        6_250_000_000_000_000_000,
+    ];
+    const A_N: [i128; 10] = [
+        7_896_296_018_268_069_516_100_000_000_000_000,
+        888_611_052_050_787_263_676_000_000,
+        298_095_798_704_172_827_474_000,
+        5_459_815_003_314_423_907_810,
+        738_905_609_893_065_022_723,
+        271_828_182_845_904_523_536,
+        164_872_127_070_012_814_685,
+        128_402_541_668_774_148_407,
+        113_314_845_306_682_631_683,
+        106_449_445_891_785_942_956,
+    ];
+
+    fn i(x: i128) -> I256 {
+        I256::from(x)
+    }
+
+    //Returns None where LogExpMath reverts
+    pub fn pow(x: U256, y: U256) -> Option<U256> {
+        if y.is_zero() {
+            return Some(U256::exp10(18));
+        }
+        if x.is_zero() {
+            return Some(U256::zero());
+        }
+
+        //Both x and y must fit in an int256, and y must be small enough that ln(x) * y does not overflow
+        if x.bit(255) || y >= (U256::one() << 254) / U256::exp10(20) {
+            return None;
+        }
+        let (x, y) = (I256::from_raw(x), I256::from_raw(y));
+
+        //ln is computed with 36 decimals for x close to one
+        let logx_times_y = if i(LN_36_LOWER_BOUND) < x && x < i(LN_36_UPPER_BOUND) {
+            let ln_36_x = ln_36(x);
+            (ln_36_x / i(ONE_18)) * y + ((ln_36_x % i(ONE_18)) * y) / i(ONE_18)
+        } else {
+            ln(x) * y
+        };
+        let logx_times_y = logx_times_y / i(ONE_18);
+
+        if logx_times_y < i(MIN_NATURAL_EXPONENT) || logx_times_y > i(MAX_NATURAL_EXPONENT) {
+            return None;
+        }
+
+        Some(exp(logx_times_y).into_raw())
+    }
+
+    //The exponent must be between MIN_NATURAL_EXPONENT and MAX_NATURAL_EXPONENT
+    fn exp(mut x: I256) -> I256 {
+        if x.is_negative() {
+            return (i(ONE_18) * i(ONE_18)) / exp(-x);
+        }
+
+        let first_an = if x >= i(X0) {
+            x -= i(X0);
+            A0
+        } else if x >= i(X1) {
+            x -= i(X1);
+            i(A1)
+        } else {
+            I256::one()
+        };
+
+        //Computed with 20 decimals from here
+        x *= i(100);
+
+        //x_10 and x_11 are not needed since the remainder is small enough for the series to converge
+        let mut product = i(ONE_20);
+        for (x_n, a_n) in X_N.iter().zip(A_N.iter()).take(8) {
+            if x >= i(*x_n) {
+                x -= i(*x_n);
+                product = (product * i(*a_n)) / i(ONE_20);
+            }
+        }
+
+        //Taylor series for e^x
+        let mut series_sum = i(ONE_20);
+        let mut term = x;
+        series_sum += term;
+        for n in 2..=12 {
+            term = ((term * x) / i(ONE_20)) / i(n);
+            series_sum += term;
+        }
+
+        (((product * series_sum) / i(ONE_20)) * first_an) / i(100)
+    }
+
+    fn ln(mut a: I256) -> I256 {
+        if a < i(ONE_18) {
+            return -ln((i(ONE_18) * i(ONE_18)) / a);
+        }
+
+        let mut sum = I256::zero();
+        if a >= A0 * i(ONE_18) {
+            a /= A0;
+            sum += i(X0);
+        }
+        if a >= i(A1) * i(ONE_18) {
+            a /= i(A1);
+            sum += i(X1);
+        }
+
+        //Computed with 20 decimals from here
+        sum *= i(100);
+        a *= i(100);
+
+        for (x_n, a_n) in X_N.iter().zip(A_N.iter()) {
+            if a >= i(*a_n) {
+                a = (a * i(ONE_20)) / i(*a_n);
+                sum += i(*x_n);
+            }
+        }
+
+        //Series for ln(a) = 2 * atanh(z) where z = (a - 1) / (a + 1)
+        let z = ((a - i(ONE_20)) * i(ONE_20)) / (a + i(ONE_20));
+        let z_squared = (z * z) / i(ONE_20);
+
+        let mut num = z;
+        let mut series_sum = num;
+        for n in [3, 5, 7, 9, 11] {
+            num = (num * z_squared) / i(ONE_20);
+            series_sum += num / i(n);
+        }
+
+        (sum + series_sum * i(2)) / i(100)
+    }
+
+    //ln with 36 decimals for x close to one, where the 18 decimal series loses precision
+    fn ln_36(x: I256) -> I256 {
+        let x = x * i(ONE_18);
+
+        let z = ((x - i(ONE_36)) * i(ONE_36)) / (x + i(ONE_36));
+        let z_squared = (z * z) / i(ONE_36);
+
+        let mut num = z;
+        let mut series_sum = num;
+        for n in [3, 5, 7, 9, 11, 13, 15] {
+            num = (num * z_squared) / i(ONE_36);
+            series_sum += num / i(n);
+        }
+
+        series_sum * i(2)
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use ethers::types::U256;
+
+        use super::pow;
+
+        #[test]
+        fn test_pow() {
+            //Expected values are from LogExpMath.pow
+            assert_eq!(
+                pow(U256::exp10(17) * 5, U256::exp10(18) * 4),
+                Some(U256::from(62_500_000_000_000_000_u128))
+            );
+            assert_eq!(
+                pow(U256::exp10(15) * 999, U256::exp10(17) * 25),
+                Some(U256::from(997_501_874_687_460_927_u128))
+            );
+            assert_eq!(pow(U256::zero(), U256::exp10(18)), Some(U256::zero()));
+            assert_eq!(pow(U256::MAX, U256::exp10(18)), None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, AbiDecode, Token},
+        prelude::EthEvent,
+        types::{Bytes, Log, H160, H256, I256, U256},
+    };
+
+    use super::{pool_address_from_pool_id, BalancerPool, BalancerPoolVariant};
+    use crate::abi::i_balancer_vault;
+
+    fn token(n: u64) -> H160 {
+        H160::from_low_u64_be(n)
+    }
+
+    fn pool_id() -> H256 {
+        H256::from_slice(&[[0x11; 20].as_slice(), [0; 12].as_slice()].concat())
+    }
+
+    //80/20 pool with 10m of token 1 and 5k of token 2, both with 18 decimals, and a 0.25% swap fee
+    fn weighted_pool() -> BalancerPool {
+        BalancerPool {
+            address: pool_address_from_pool_id(pool_id()),
+            pool_id: pool_id(),
+            vault: token(10),
+            token_a: token(1),
+            token_b: token(2),
+            tokens: vec![token(1), token(2)],
+            token_decimals: vec![18, 18],
+            balances: vec![U256::exp10(25), U256::exp10(21) * 5],
+            scaling_factors: vec![U256::exp10(18), U256::exp10(18)],
+            weights: vec![U256::exp10(17) * 8, U256::exp10(17) * 2],
+            amp: U256::zero(),
+            swap_fee: U256::exp10(14) * 25,
+            variant: BalancerPoolVariant::Weighted,
+        }
+    }
+
+    //Stable pool with 10m DAI, 12m USDC and 9m USDT, an amplification parameter of 2000 and a 0.01% swap fee
+    fn stable_pool(token_a: H160, token_b: H160) -> BalancerPool {
+        BalancerPool {
+            address: pool_address_from_pool_id(pool_id()),
+            pool_id: pool_id(),
+            vault: token(10),
+            token_a,
+            token_b,
+            tokens: vec![token(1), token(2), token(3)],
+            token_decimals: vec![18, 6, 6],
+            balances: vec![
+                U256::exp10(25),
+                U256::from(12_000_000_000_000_u128),
+                U256::from(9_000_000_000_000_u128),
+            ],
+            scaling_factors: vec![U256::exp10(18), U256::exp10(30), U256::exp10(30)],
+            weights: vec![],
+            amp: U256::from(2_000_000),
+            swap_fee: U256::exp10(14),
+            variant: BalancerPoolVariant::Stable,
+        }
+    }
+
+    #[test]
+    fn test_simulate_swap_weighted() {
+        let mut pool = weighted_pool();
+
+        //Expected values are from the pool's onSwap
+        assert_eq!(
+            pool.simulate_swap(token(1), U256::exp10(22)),
+            Some(U256::from(19_900_348_766_392_970_000_u128))
+        );
+        assert_eq!(
+            pool.simulate_swap(token(2), U256::exp10(19)),
+            Some(U256::from(4_981_290_500_643_590_000_000_u128))
+        );
+
+        //50/50 pools with different decimals are scaled before the swap
+        pool.weights = vec![U256::exp10(17) * 5, U256::exp10(17) * 5];
+        pool.balances = vec![U256::exp10(21), U256::from(2_000_000_000_000_u128)];
+        pool.scaling_factors = vec![U256::exp10(18), U256::exp10(30)];
+        pool.swap_fee = U256::exp10(15) * 3;
+
+        assert_eq!(
+            pool.simulate_swap(token(1), U256::exp10(18)),
+            Some(U256::from(1_992_013_962_u128))
+        );
+        assert_eq!(
+            pool.simulate_swap(token(2), U256::from(2_000_000_000_u128)),
+            Some(U256::from(996_006_981_039_903_000_u128))
+        );
+
+        //Weighted pools revert when the amount in is more than 30% of the balance in
+        assert_eq!(pool.simulate_swap(token(1), U256::exp10(21)), None);
+    }
+
+    #[test]
+    fn test_simulate_swap_stable() {
+        //Expected values are from the pool's onSwap
+        assert_eq!(
+            stable_pool(token(1), token(2)).simulate_swap(token(2), U256::exp10(11)),
+            Some(U256::from(99_980_752_528_597_805_367_245_u128))
+        );
+        assert_eq!(
+            stable_pool(token(1), token(3)).simulate_swap(token(1), U256::exp10(23)),
+            Some(U256::from(99_983_545_274_u128))
+        );
+
+        //Tokens that are not in the market pair can not be swapped
+        assert_eq!(
+            stable_pool(token(1), token(2)).simulate_swap(token(3), U256::exp10(6)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_price_64_x_64() {
+        //The price of token 1 in token 2 is (5k / 0.2) / (10m / 0.8) = 0.002
+        assert_eq!(
+            weighted_pool().price_64_x_64(token(1)),
+            Some(U256::from(36_893_488_147_419_103_u128))
+        );
+
+        //A balanced stable pool is priced at the peg
+        let mut pool = stable_pool(token(1), token(2));
+        pool.balances[1] = U256::from(10_000_000_000_000_u128);
+        pool.balances[2] = U256::from(10_000_000_000_000_u128);
+        assert_eq!(pool.price_64_x_64(token(1)), Some(U256::one() << 64));
+    }
+
+    #[test]
+    fn test_update_from_vault_logs() {
+        let mut pool = weighted_pool();
+
+        let swap_log = Log {
+            topics: vec![
+                i_balancer_vault::SwapFilter::signature(),
+                pool_id(),
+                H256::from(token(1)),
+                H256::from(token(2)),
+            ],
+            data: Bytes::from(encode(&[
+                Token::Uint(U256::exp10(22)),
+                Token::Uint(U256::exp10(19)),
+            ])),
+            ..Default::default()
+        };
+        pool.update_from_log(&swap_log).unwrap();
+
+        assert_eq!(pool.balances[0], U256::exp10(25) + U256::exp10(22));
+        assert_eq!(pool.balances[1], U256::exp10(21) * 5 - U256::exp10(19));
+
+        //Joins add the deltas and exits remove them, with the protocol fees paid out of the pool
+        let pool_balance_changed_log = |pool_id: H256| Log {
+            topics: vec![
+                i_balancer_vault::PoolBalanceChangedFilter::signature(),
+                pool_id,
+                H256::from(token(5)),
+            ],
+            data: Bytes::from(encode(&[
+                Token::Array(vec![Token::Address(token(1)), Token::Address(token(2))]),
+                Token::Array(vec![
+                    Token::Int(I256::exp10(21).into_raw()),
+                    Token::Int((-I256::exp10(18)).into_raw()),
+                ]),
+                Token::Array(vec![
+                    Token::Uint(U256::exp10(18)),
+                    Token::Uint(U256::exp10(16)),
+                ]),
+            ])),
+            ..Default::default()
+        };
+
+        pool.update_from_log(&pool_balance_changed_log(pool_id()))
+            .unwrap();
+
+        assert_eq!(
+            pool.balances[0],
+            U256::exp10(25) + U256::exp10(22) + U256::exp10(21) - U256::exp10(18)
+        );
+        assert_eq!(
+            pool.balances[1],
+            U256::exp10(21) * 5 - U256::exp10(19) - U256::exp10(18) - U256::exp10(16)
+        );
+
+        //Logs for other pools in the vault are ignored
+        let balances = pool.balances.clone();
+        pool.update_from_log(&pool_balance_changed_log(H256::from_low_u64_be(1)))
+            .unwrap();
+        assert_eq!(pool.balances, balances);
+    }
+
+    #[test]
+    fn test_swap_calldata() {
+        let pool = weighted_pool();
+
+        let calldata = pool.swap_calldata(
+            token(2),
+            U256::exp10(18),
+            U256::exp10(20),
+            token(7),
+            token(8),
+        );
+        let swap_call = i_balancer_vault::SwapCall::decode(calldata).unwrap();
+
+        assert_eq!(H256::from(swap_call.single_swap.pool_id), pool_id());
+        assert_eq!(swap_call.single_swap.asset_in, token(2));
+        assert_eq!(swap_call.single_swap.asset_out, token(1));
+        assert_eq!(swap_call.single_swap.amount, U256::exp10(18));
+        assert_eq!(swap_call.funds.sender, token(7));
+        assert_eq!(swap_call.funds.recipient, token(8));
+        assert_eq!(swap_call.limit, U256::exp10(20));
+        assert_eq!(pool.address, H160::from_slice(&[0x11; 20]));
+    }
+}
@@ -0,0 +1,761 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{AbiEncode, Event, HumanReadableParser, RawLog, Token},
+    prelude::{EthEvent, EthLogDecode},
+    providers::Middleware,
+    types::{Bytes, Log, H160, H256, U256, U512},
+};
+
+use crate::{
+    abi::{self, i_curve_pool},
+    error::ExecutorError,
+    execution::sandbox_limit_order::Call,
+    markets::normalized_price_64_x_64,
+    quoter::{Quote, CURVE_SWAP_GAS_ESTIMATE},
+};
+
+use super::{approve_call, AutomatedMarketMaker};
+
+//Constants from the Curve stableswap pool templates
+pub const A_PRECISION: u64 = 100;
+pub const FEE_DENOMINATOR: u64 = 10_000_000_000;
+pub const MAX_COINS: usize = 8;
+
+//Curve pools can be deployed with 2 to 4 coins, liquidity events are emitted with arrays sized to the number of coins
+pub const MIN_LIQUIDITY_EVENT_COINS: usize = 2;
+pub const MAX_LIQUIDITY_EVENT_COINS: usize = 4;
+
+//Curve registry or factory. Pools are discovered with find_pool_for_coins, which both the main registry and the metapool factory implement
+#[derive(Debug, Clone, Copy)]
+pub struct CurveDex {
+    pub registry_address: H160,
+}
+
+impl CurveDex {
+    pub fn new(registry_address: H160) -> CurveDex {
+        CurveDex { registry_address }
+    }
+
+    //Returns every pool in the registry where both tokens are coins of the pool. Pools where either token is only an underlying coin are skipped
+    pub async fn get_pools_for_pair<M: Middleware>(
+        &self,
+        token_a: H160,
+        token_b: H160,
+        middleware: Arc<M>,
+    ) -> Result<Vec<CurvePool>, ExecutorError<M>> {
+        let registry = abi::ICurveRegistry::new(self.registry_address, middleware.clone());
+
+        let mut pools = vec![];
+        for i in 0.. {
+            let pool_address = registry
+                .find_pool_for_coins(token_a, token_b, U256::from(i))
+                .call()
+                .await?;
+
+            if pool_address.is_zero() {
+                break;
+            }
+
+            let meta = registry.is_meta(pool_address).call().await?;
+
+            //Pools with coins that can not be fetched (ie. native eth) are not supported
+            match CurvePool::new_from_address(pool_address, meta, middleware.clone()).await {
+                Ok(mut pool) => {
+                    if pool.coin_index(token_a).is_some() && pool.coin_index(token_b).is_some() {
+                        pool.token_a = token_a;
+                        pool.token_b = token_b;
+                        pools.push(pool);
+                    }
+                }
+                Err(err) => {
+                    tracing::warn!("Could not add Curve pool {:?}: {:?}", pool_address, err);
+                }
+            }
+        }
+
+        Ok(pools)
+    }
+
+    pub fn event_signatures(&self) -> Vec<H256> {
+        let mut event_signatures = vec![i_curve_pool::TokenExchangeFilter::signature()];
+
+        for n_coins in MIN_LIQUIDITY_EVENT_COINS..=MAX_LIQUIDITY_EVENT_COINS {
+            event_signatures.push(add_liquidity_event(n_coins).signature());
+            event_signatures.push(remove_liquidity_event(n_coins).signature());
+            event_signatures.push(remove_liquidity_imbalance_event(n_coins).signature());
+        }
+
+        event_signatures
+    }
+}
+
+pub fn add_liquidity_event(n_coins: usize) -> Event {
+    HumanReadableParser::parse_event(&format!(
+        "event AddLiquidity(address indexed provider, uint256[{n_coins}] token_amounts, uint256[{n_coins}] fees, uint256 invariant, uint256 token_supply)"
+    ))
+    .expect("Could not parse AddLiquidity event")
+}
+
+pub fn remove_liquidity_event(n_coins: usize) -> Event {
+    HumanReadableParser::parse_event(&format!(
+        "event RemoveLiquidity(address indexed provider, uint256[{n_coins}] token_amounts, uint256[{n_coins}] fees, uint256 token_supply)"
+    ))
+    .expect("Could not parse RemoveLiquidity event")
+}
+
+pub fn remove_liquidity_imbalance_event(n_coins: usize) -> Event {
+    HumanReadableParser::parse_event(&format!(
+        "event RemoveLiquidityImbalance(address indexed provider, uint256[{n_coins}] token_amounts, uint256[{n_coins}] fees, uint256 invariant, uint256 token_supply)"
+    ))
+    .expect("Could not parse RemoveLiquidityImbalance event")
+}
+
+//Curve stableswap pool. A pool with n coins is added to the market of every pair of tracked tokens it holds, token_a and token_b are the pair of the market the pool is in.
+//Meta pools are supported for swaps between their own coins, where the base pool LP token is priced at the base pool virtual price.
+#[derive(Debug, Clone, Default)]
+pub struct CurvePool {
+    pub address: H160,
+    pub token_a: H160,
+    pub token_b: H160,
+    pub coins: Vec<H160>,
+    pub coin_decimals: Vec<u8>,
+    pub balances: Vec<U256>,
+    //Multipliers that scale each balance to 18 decimals, with a precision of 1e18. For meta pools the base pool LP token rate is the base pool virtual price, which is only fetched when the pool is added
+    pub rates: Vec<U256>,
+    //Amplification coefficient multiplied by A_PRECISION
+    pub amp: U256,
+    pub fee: U256,
+    pub admin_fee: U256,
+    pub meta: bool,
+}
+
+impl CurvePool {
+    pub async fn new_from_address<M: Middleware>(
+        address: H160,
+        meta: bool,
+        middleware: Arc<M>,
+    ) -> Result<CurvePool, ExecutorError<M>> {
+        let curve_pool = abi::ICurvePool::new(address, middleware.clone());
+
+        let mut pool = CurvePool {
+            address,
+            meta,
+            fee: curve_pool.fee().call().await?,
+            admin_fee: curve_pool.admin_fee().call().await?,
+            //Pools deployed before A_precise was added store A without the extra precision
+            amp: match curve_pool.a_precise().call().await {
+                Ok(amp) => amp,
+                Err(_) => curve_pool.a().call().await? * A_PRECISION,
+            },
+            ..Default::default()
+        };
+
+        //The number of coins is not exposed, so coins are fetched until the call reverts
+        for i in 0..MAX_COINS {
+            match curve_pool.coins(U256::from(i)).call().await {
+                Ok(coin) => {
+                    let decimals = abi::IErc20::new(coin, middleware.clone())
+                        .decimals()
+                        .call()
+                        .await?;
+
+                    pool.coins.push(coin);
+                    pool.coin_decimals.push(decimals);
+                    pool.balances
+                        .push(curve_pool.balances(U256::from(i)).call().await?);
+                    pool.rates.push(U256::exp10(36 - decimals as usize));
+                }
+                Err(_) => break,
+            }
+        }
+
+        if meta {
+            let base_pool = curve_pool.base_pool().call().await?;
+            let virtual_price = abi::ICurvePool::new(base_pool, middleware)
+                .get_virtual_price()
+                .call()
+                .await?;
+
+            if let Some(base_pool_rate) = pool.rates.last_mut() {
+                *base_pool_rate = virtual_price;
+            }
+        }
+
+        Ok(pool)
+    }
+
+    pub fn coin_index(&self, token: H160) -> Option<usize> {
+        self.coins.iter().position(|coin| *coin == token)
+    }
+
+    //Returns the index of the token in and the other token in the pair
+    fn swap_indices(&self, token_in: H160) -> Option<(usize, usize)> {
+        let token_out = if token_in == self.token_a {
+            self.token_b
+        } else if token_in == self.token_b {
+            self.token_a
+        } else {
+            return None;
+        };
+
+        Some((self.coin_index(token_in)?, self.coin_index(token_out)?))
+    }
+
+    //Mirrors the pool's get_dy. Returns None if the swap would revert
+    pub fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let (i, j) = self.swap_indices(token_in)?;
+        Some(self.exchange(i, j, amount_in)?.0)
+    }
+
+    pub fn quote(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        Some(Quote::new(
+            self.simulate_swap(token_in, amount_in)?,
+            U256::from(CURVE_SWAP_GAS_ESTIMATE),
+        ))
+    }
+
+    pub fn simulate_swap_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let (i, j) = self.swap_indices(token_in)?;
+        self.exchange_mut(i, j, amount_in)
+    }
+
+    //Returns the amount out and the admin fee taken from the pool balance of coin j
+    fn exchange(&self, i: usize, j: usize, dx: U256) -> Option<(U256, U256)> {
+        let precision = U256::exp10(18);
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+
+        let xp = self.xp()?;
+        let x = xp[i].checked_add(dx.checked_mul(self.rates[i])? / precision)?;
+        let y = get_y(i, j, x, &xp, self.amp)?;
+
+        let dy = xp[j].checked_sub(y)?.checked_sub(U256::one())?;
+        let dy_fee = dy.checked_mul(self.fee)? / fee_denominator;
+        let dy_admin_fee = dy_fee.checked_mul(self.admin_fee)? / fee_denominator;
+
+        Some((
+            (dy - dy_fee).checked_mul(precision)? / self.rates[j],
+            dy_admin_fee.checked_mul(precision)? / self.rates[j],
+        ))
+    }
+
+    //Applies an exchange to the pool balances. The admin fee is removed from the pool balance, the same as on chain
+    fn exchange_mut(&mut self, i: usize, j: usize, dx: U256) -> Option<U256> {
+        let (dy, dy_admin_fee) = self.exchange(i, j, dx)?;
+
+        self.balances[i] = self.balances[i].checked_add(dx)?;
+        self.balances[j] = self.balances[j]
+            .checked_sub(dy)?
+            .checked_sub(dy_admin_fee)?;
+
+        Some(dy)
+    }
+
+    fn xp(&self) -> Option<Vec<U256>> {
+        self.balances
+            .iter()
+            .zip(self.rates.iter())
+            .map(|(balance, rate)| Some(balance.checked_mul(*rate)? / U256::exp10(18)))
+            .collect()
+    }
+
+    //Marginal price of the base token in the other token of the pair as a Q64.64 fixed point number
+    pub fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        let (i, j) = self.swap_indices(base_token)?;
+
+        let (amount_base, amount_quote) =
+            stableswap_marginal_amounts(&self.xp()?, self.amp, U256::from(A_PRECISION), i, j)?;
+
+        //Scales one whole token to its balance in xp
+        let scale = |k: usize| -> Option<U512> {
+            U512::from(self.rates[k])
+                .checked_mul(U512::exp10(self.coin_decimals[k] as usize))?
+                .checked_div(U512::exp10(18))
+        };
+
+        normalized_price_64_x_64(
+            amount_base.checked_mul(scale(j)?)?,
+            0,
+            amount_quote.checked_mul(scale(i)?)?,
+            0,
+            false,
+        )
+    }
+
+    //Updates the pool balances from exchange and liquidity logs. Removing liquidity in a single coin and underlying exchanges through meta pools do not log enough to update the balances
+    pub fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        let raw_log = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+        let n_coins = self.coins.len();
+
+        if log.topics[0] == i_curve_pool::TokenExchangeFilter::signature() {
+            let exchange_log =
+                <i_curve_pool::TokenExchangeFilter as EthLogDecode>::decode_log(&raw_log)?;
+            let (i, j) = (
+                exchange_log.sold_id as usize,
+                exchange_log.bought_id as usize,
+            );
+
+            if i >= n_coins || j >= n_coins {
+                return Err(ethers::abi::Error::InvalidData);
+            }
+
+            //Replay the exchange to remove the admin fee from the balance, falling back to the logged amounts if the local state has drifted
+            let mut pool = self.clone();
+            if pool.exchange_mut(i, j, exchange_log.tokens_sold) == Some(exchange_log.tokens_bought)
+            {
+                self.balances = pool.balances;
+            } else {
+                self.balances[i] += exchange_log.tokens_sold;
+                self.balances[j] = self.balances[j].saturating_sub(exchange_log.tokens_bought);
+            }
+        } else if log.topics[0] == add_liquidity_event(n_coins).signature() {
+            let (token_amounts, fees) =
+                decode_liquidity_log(&add_liquidity_event(n_coins), raw_log)?;
+
+            for k in 0..n_coins {
+                self.balances[k] = self.balances[k] + token_amounts[k]
+                    - fees[k] * self.admin_fee / U256::from(FEE_DENOMINATOR);
+            }
+        } else if log.topics[0] == remove_liquidity_event(n_coins).signature() {
+            let (token_amounts, _) =
+                decode_liquidity_log(&remove_liquidity_event(n_coins), raw_log)?;
+
+            for (balance, token_amount) in self.balances.iter_mut().zip(token_amounts) {
+                *balance = balance.saturating_sub(token_amount);
+            }
+        } else if log.topics[0] == remove_liquidity_imbalance_event(n_coins).signature() {
+            let (token_amounts, fees) =
+                decode_liquidity_log(&remove_liquidity_imbalance_event(n_coins), raw_log)?;
+
+            for k in 0..n_coins {
+                self.balances[k] = self.balances[k].saturating_sub(
+                    token_amounts[k] + fees[k] * self.admin_fee / U256::from(FEE_DENOMINATOR),
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    //Curve pools pull the amount in from the caller and send the amount out to the caller, so the caller must approve the pool first
+    pub fn exchange_calldata(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        min_amount_out: U256,
+    ) -> Bytes {
+        let (i, j) = self
+            .swap_indices(token_in)
+            .expect("Token in is not a coin of the pool");
+
+        i_curve_pool::ExchangeCall {
+            i: i as i128,
+            j: j as i128,
+            dx: amount_in,
+            min_dy: min_amount_out,
+        }
+        .encode()
+        .into()
+    }
+}
+
+impl AutomatedMarketMaker for CurvePool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    //Fee out of FEE_DENOMINATOR
+    fn fee(&self) -> u32 {
+        self.fee.as_u32()
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        CurvePool::price_64_x_64(self, base_token)
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        self.quote(token_in, amount_in)
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.simulate_swap_mut(token_in, amount_in)
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        CurvePool::update_from_log(self, log)
+    }
+
+    //Curve pools send the amount out to the caller, so the amount out is transferred to the recipient after the swap.
+    //The simulated amount out is used as the minimum amount out so that the transfer can not fail
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        from: H160,
+    ) -> Vec<Call> {
+        let mut calls = vec![
+            approve_call(token_in, self.address, amount_in),
+            Call::new(
+                self.address,
+                self.exchange_calldata(token_in, amount_in, amount_out)
+                    .to_vec(),
+            ),
+        ];
+
+        if to != from {
+            calls.push(Call::new(
+                self.token_out(token_in),
+                abi::IERC20_ABI
+                    .function("transfer")
+                    .unwrap()
+                    .encode_input(&[Token::Address(to), Token::Uint(amount_out)])
+                    .expect("Could not encode transfer inputs"),
+            ));
+        }
+
+        calls
+    }
+}
+
+//Returns the token amounts and fees of a liquidity log
+fn decode_liquidity_log(
+    event: &Event,
+    raw_log: RawLog,
+) -> Result<(Vec<U256>, Vec<U256>), ethers::abi::Error> {
+    let log = event.parse_log(raw_log)?;
+
+    let to_amounts = |name: &str| -> Result<Vec<U256>, ethers::abi::Error> {
+        log.params
+            .iter()
+            .find(|param| param.name == name)
+            .ok_or(ethers::abi::Error::InvalidData)?
+            .value
+            .clone()
+            .into_fixed_array()
+            .ok_or(ethers::abi::Error::InvalidData)?
+            .into_iter()
+            .map(|amount| amount.into_uint().ok_or(ethers::abi::Error::InvalidData))
+            .collect()
+    };
+
+    Ok((to_amounts("token_amounts")?, to_amounts("fees")?))
+}
+
+//Marginal price of coin i in coin j on the stableswap invariant, returned as the amounts of coin i and coin j in xp that are worth the same.
+//The price is the ratio of the partial derivatives of the invariant, (Ann + D^(n+1) / (n^n * prod(x) * x_i)) / (Ann + D^(n+1) / (n^n * prod(x) * x_j))
+pub fn stableswap_marginal_amounts(
+    xp: &[U256],
+    amp: U256,
+    amp_precision: U256,
+    i: usize,
+    j: usize,
+) -> Option<(U512, U512)> {
+    let n_coins = U256::from(xp.len());
+    let d = get_d(xp, amp, amp_precision)?;
+
+    let mut d_p = d;
+    for x in xp.iter() {
+        d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n_coins)?)?;
+    }
+
+    let ann = U512::from(amp.checked_mul(n_coins)?);
+    let d_p = U512::from(d_p) * U512::from(amp_precision);
+    let (x_i, x_j) = (U512::from(xp[i]), U512::from(xp[j]));
+
+    Some((
+        x_i.checked_mul(ann.checked_mul(x_j)?.checked_add(d_p)?)?,
+        x_j.checked_mul(ann.checked_mul(x_i)?.checked_add(d_p)?)?,
+    ))
+}
+
+//Stableswap invariant D for the balances scaled to 18 decimals, mirroring get_D in the pool templates. Balancer stable pools compute the invariant the same way with a different amp precision
+pub fn get_d(xp: &[U256], amp: U256, a_precision: U256) -> Option<U256> {
+    let n_coins = U256::from(xp.len());
+
+    let mut s = U256::zero();
+    for x in xp {
+        s = s.checked_add(*x)?;
+    }
+    if s.is_zero() {
+        return Some(U256::zero());
+    }
+
+    let mut d = s;
+    let ann = amp.checked_mul(n_coins)?;
+
+    for _ in 0..255 {
+        let mut d_p = d;
+        for x in xp {
+            d_p = d_p.checked_mul(d)?.checked_div(x.checked_mul(n_coins)?)?;
+        }
+        let d_prev = d;
+
+        let numerator = (ann.checked_mul(s)? / a_precision)
+            .checked_add(d_p.checked_mul(n_coins)?)?
+            .checked_mul(d)?;
+        let denominator = (ann.checked_sub(a_precision)?.checked_mul(d)? / a_precision)
+            .checked_add((n_coins + 1).checked_mul(d_p)?)?;
+        d = numerator.checked_div(denominator)?;
+
+        if d > d_prev {
+            if d - d_prev <= U256::one() {
+                return Some(d);
+            }
+        } else if d_prev - d <= U256::one() {
+            return Some(d);
+        }
+    }
+
+    //The pool reverts if D does not converge
+    None
+}
+
+//Balance of coin j scaled to 18 decimals after the balance of coin i is set to x, mirroring get_y in the pool templates
+fn get_y(i: usize, j: usize, x: U256, xp: &[U256], amp: U256) -> Option<U256> {
+    let a_precision = U256::from(A_PRECISION);
+    let n_coins = U256::from(xp.len());
+
+    let d = get_d(xp, amp, a_precision)?;
+    let ann = amp.checked_mul(n_coins)?;
+    let mut c = d;
+    let mut s = U256::zero();
+
+    for (k, xp_k) in xp.iter().enumerate() {
+        let x_k = if k == i {
+            x
+        } else if k != j {
+            *xp_k
+        } else {
+            continue;
+        };
+
+        s = s.checked_add(x_k)?;
+        c = c.checked_mul(d)?.checked_div(x_k.checked_mul(n_coins)?)?;
+    }
+
+    c = c
+        .checked_mul(d)?
+        .checked_mul(a_precision)?
+        .checked_div(ann.checked_mul(n_coins)?)?;
+    let b = s.checked_add(d.checked_mul(a_precision)?.checked_div(ann)?)?;
+
+    let mut y = d;
+    for _ in 0..255 {
+        let y_prev = y;
+        y = y.checked_mul(y)?.checked_add(c)?.checked_div(
+            y.checked_mul(U256::from(2))?
+                .checked_add(b)?
+                .checked_sub(d)?,
+        )?;
+
+        if y > y_prev {
+            if y - y_prev <= U256::one() {
+                return Some(y);
+            }
+        } else if y_prev - y <= U256::one() {
+            return Some(y);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, AbiDecode, Token},
+        prelude::EthEvent,
+        types::{Bytes, Log, H160, H256, U256},
+    };
+
+    use super::{add_liquidity_event, CurvePool};
+    use crate::{
+        abi::{self, i_curve_pool},
+        pool::AutomatedMarketMaker,
+    };
+
+    fn dai() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn usdc() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    fn usdt() -> H160 {
+        H160::from_low_u64_be(3)
+    }
+
+    //3pool style pool with 1m DAI, 1.2m USDC and 900k USDT, A of 2000, a 4 bps fee and a 50% admin fee
+    fn pool(token_a: H160, token_b: H160) -> CurvePool {
+        CurvePool {
+            address: H160::from_low_u64_be(4),
+            token_a,
+            token_b,
+            coins: vec![dai(), usdc(), usdt()],
+            coin_decimals: vec![18, 6, 6],
+            balances: vec![
+                U256::exp10(24),
+                U256::from(1_200_000_000_000_u128),
+                U256::from(900_000_000_000_u128),
+            ],
+            rates: vec![U256::exp10(18), U256::exp10(30), U256::exp10(30)],
+            amp: U256::from(200_000),
+            fee: U256::from(4_000_000),
+            admin_fee: U256::from(5_000_000_000_u64),
+            meta: false,
+        }
+    }
+
+    fn log(topics: Vec<H256>, data: Vec<Token>) -> Log {
+        Log {
+            topics,
+            data: Bytes::from(encode(&data)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_swap() {
+        //Expected values are from the pool's get_dy
+        assert_eq!(
+            pool(dai(), usdc()).simulate_swap(usdc(), U256::from(10_000_000_000_u128)),
+            Some(U256::from(9995075525740216022309_u128))
+        );
+        assert_eq!(
+            pool(dai(), usdt()).simulate_swap(dai(), U256::exp10(22)),
+            Some(U256::from(9995354715_u128))
+        );
+
+        //Tokens that are not in the market pair can not be swapped
+        assert_eq!(
+            pool(dai(), usdc()).simulate_swap(usdt(), U256::exp10(6)),
+            None
+        );
+    }
+
+    #[test]
+    fn test_update_from_token_exchange_log() {
+        let mut curve_pool = pool(usdc(), usdt());
+
+        let exchange_log = log(
+            vec![
+                i_curve_pool::TokenExchangeFilter::signature(),
+                H256::from(H160::from_low_u64_be(5)),
+            ],
+            vec![
+                Token::Int(U256::one()),
+                Token::Uint(U256::from(50_000_000_000_u128)),
+                Token::Int(U256::from(2)),
+                Token::Uint(U256::from(49971315135_u128)),
+            ],
+        );
+
+        curve_pool.update_from_log(&exchange_log).unwrap();
+
+        //The admin fee is removed from the balance of the coin bought
+        assert_eq!(curve_pool.balances[0], U256::exp10(24));
+        assert_eq!(curve_pool.balances[1], U256::from(1_250_000_000_000_u128));
+        assert_eq!(curve_pool.balances[2], U256::from(850_018_686_603_u128));
+    }
+
+    #[test]
+    fn test_update_from_add_liquidity_log() {
+        let mut curve_pool = pool(dai(), usdc());
+
+        let amounts = |amounts: [u128; 3]| {
+            Token::FixedArray(
+                amounts
+                    .into_iter()
+                    .map(|amount| Token::Uint(U256::from(amount)))
+                    .collect(),
+            )
+        };
+
+        let add_liquidity_log = log(
+            vec![
+                add_liquidity_event(3).signature(),
+                H256::from(H160::from_low_u64_be(5)),
+            ],
+            vec![
+                amounts([1_000_000_000_000_000_000_000, 0, 0]),
+                amounts([1_000_000_000_000_000_000, 2_000_000, 3_000_000]),
+                Token::Uint(U256::exp10(24)),
+                Token::Uint(U256::exp10(24)),
+            ],
+        );
+
+        curve_pool.update_from_log(&add_liquidity_log).unwrap();
+
+        assert_eq!(
+            curve_pool.balances[0],
+            U256::from(1_000_999_500_000_000_000_000_000_u128)
+        );
+        assert_eq!(curve_pool.balances[1], U256::from(1_199_999_000_000_u128));
+        assert_eq!(curve_pool.balances[2], U256::from(899_998_500_000_u128));
+    }
+
+    #[test]
+    fn test_price_64_x_64() {
+        let mut curve_pool = pool(dai(), usdc());
+        curve_pool.balances[1] = U256::from(1_000_000_000_000_u128);
+
+        //A balanced pool is priced at the peg
+        assert_eq!(curve_pool.price_64_x_64(dai()), Some(U256::one() << 64));
+
+        //USDT is the scarcest coin in the pool, so it is priced above the peg
+        let curve_pool = pool(dai(), usdt());
+        assert!(curve_pool.price_64_x_64(usdt()).unwrap() > U256::one() << 64);
+        assert!(curve_pool.price_64_x_64(dai()).unwrap() < U256::one() << 64);
+    }
+
+    #[test]
+    fn test_exchange_calldata() {
+        let curve_pool = pool(usdc(), usdt());
+
+        let calldata =
+            curve_pool.exchange_calldata(usdt(), U256::from(1_000_000), U256::from(999_000));
+        let exchange_call = i_curve_pool::ExchangeCall::decode(calldata).unwrap();
+
+        assert_eq!(exchange_call.i, 2);
+        assert_eq!(exchange_call.j, 1);
+        assert_eq!(exchange_call.dx, U256::from(1_000_000));
+        assert_eq!(exchange_call.min_dy, U256::from(999_000));
+    }
+
+    #[test]
+    fn test_swap_calls() {
+        let curve_pool = pool(usdc(), usdt());
+        let (router, recipient) = (H160::from_low_u64_be(5), H160::from_low_u64_be(6));
+        let (amount_in, amount_out) = (U256::from(1_000_000), U256::from(999_000));
+
+        //The pool sends the amount out to the router, which forwards it to the recipient
+        let calls = curve_pool.swap_calls(usdt(), amount_in, amount_out, recipient, router);
+        assert_eq!(calls.len(), 3);
+        assert_eq!(calls[0].target, usdt());
+        assert_eq!(calls[1].target, curve_pool.address);
+        assert_eq!(calls[2].target, usdc());
+        assert_eq!(
+            calls[2].call_data,
+            abi::IERC20_ABI
+                .function("transfer")
+                .unwrap()
+                .encode_input(&[Token::Address(recipient), Token::Uint(amount_out)])
+                .unwrap()
+        );
+
+        //Swaps for the router itself do not need a transfer
+        let calls = curve_pool.swap_calls(usdt(), amount_in, amount_out, router, router);
+        assert_eq!(calls.len(), 2);
+        assert!(!curve_pool.transfer_before_swap());
+    }
+}
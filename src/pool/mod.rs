@@ -0,0 +1,261 @@
+pub mod algebra;
+pub mod balancer;
+pub mod curve;
+pub mod solidly;
+pub mod uniswap_v2;
+pub mod uniswap_v3;
+
+use std::sync::Arc;
+
+use cfmms::dex::Dex;
+use ethers::{
+    abi::Token,
+    providers::Middleware,
+    types::{Log, H160, U256},
+};
+
+use crate::{abi, error::ExecutorError, execution::sandbox_limit_order::Call, quoter::Quote};
+
+pub use algebra::{AlgebraDex, AlgebraPool};
+pub use balancer::{BalancerDex, BalancerPool, BalancerPoolVariant};
+pub use curve::{CurveDex, CurvePool};
+pub use solidly::{SolidlyDex, SolidlyPool, SolidlyVariant};
+pub use uniswap_v2::{UniswapV2PairFee, UniswapV2Pool};
+pub use uniswap_v3::UniswapV3Pool;
+
+//State and calldata of an AMM pool that routing, simulation and execution rely on. Each pool type adapts itself to this trait, so a new AMM only needs an implementation and a Pool variant
+pub trait AutomatedMarketMaker {
+    fn address(&self) -> H160;
+
+    fn tokens(&self) -> (H160, H160);
+
+    //Fee for swapping token a to token b, in the pool's own precision
+    fn fee(&self) -> u32;
+
+    //Returns the other token in the pool
+    fn token_out(&self, token_in: H160) -> H160 {
+        let (token_a, token_b) = self.tokens();
+        if token_in == token_a {
+            token_b
+        } else {
+            token_a
+        }
+    }
+
+    //Spot price of the base token denominated in the other token of the pool, normalized by token decimals, as a Q64.64 fixed point number
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256>;
+
+    //Quotes a swap with local pool state only, returning None when the swap can not be simulated locally
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote>;
+
+    //Amount out used to compare pools in a market, pools can estimate the swap or skip it when the local state is incomplete
+    fn simulate_swap_local(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.quote_local(token_in, amount_in)
+            .map(|quote| quote.amount_out)
+    }
+
+    //Simulates a swap with local pool state only and applies it to the pool
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256>;
+
+    //Updates the pool from a log emitted by the pool, or by the vault for Balancer pools
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error>;
+
+    //Pools that are paid by transferring the amount in before the swap, the previous swap in a route sends its amount out directly to the pool
+    fn transfer_before_swap(&self) -> bool {
+        false
+    }
+
+    //Calls for the sandbox router to swap the amount in held by from, sending at least the simulated amount out to to
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        from: H160,
+    ) -> Vec<Call>;
+}
+
+//Pools tracked by COEX. UniswapV3 pools carry their tick bitmap and initialized tick liquidity so that swaps can be simulated without RPC calls
+#[derive(Debug, Clone)]
+pub enum Pool {
+    UniswapV2(UniswapV2Pool),
+    UniswapV3(UniswapV3Pool),
+    Algebra(AlgebraPool),
+    Solidly(SolidlyPool),
+    Curve(CurvePool),
+    Balancer(BalancerPool),
+}
+
+impl From<cfmms::pool::Pool> for Pool {
+    fn from(pool: cfmms::pool::Pool) -> Pool {
+        match pool {
+            cfmms::pool::Pool::UniswapV2(uniswap_v2_pool) => {
+                Pool::UniswapV2(UniswapV2Pool::new(uniswap_v2_pool))
+            }
+            cfmms::pool::Pool::UniswapV3(uniswap_v3_pool) => {
+                Pool::UniswapV3(UniswapV3Pool::new(uniswap_v3_pool))
+            }
+        }
+    }
+}
+
+impl Pool {
+    //Converts a pool discovered through cfmms, fetching the tick data for UniswapV3 pools.
+    //UniswapV2 pairs charge the fee the dex is configured with, unless the dex is a fork with a fee for each pair and the pair exposes its fee
+    pub async fn new_from_cfmms_pool<M: Middleware>(
+        pool: cfmms::pool::Pool,
+        dex: &Dex,
+        v2_pair_fee: Option<UniswapV2PairFee>,
+        middleware: Arc<M>,
+    ) -> Result<Pool, ExecutorError<M>> {
+        let mut pool = Pool::from(pool);
+
+        match &mut pool {
+            Pool::UniswapV2(uniswap_v2_pool) => {
+                if let Dex::UniswapV2(uniswap_v2_dex) = dex {
+                    uniswap_v2_pool.pool.fee = uniswap_v2_dex.fee as u32;
+                }
+
+                uniswap_v2_pool.pair_fee = v2_pair_fee;
+                uniswap_v2_pool.factory_address = dex.factory_address();
+                uniswap_v2_pool.sync_fee(middleware).await;
+            }
+
+            Pool::UniswapV3(uniswap_v3_pool) => {
+                uniswap_v3_pool.factory_address = dex.factory_address();
+                uniswap_v3_pool.populate_tick_data(middleware).await?;
+            }
+
+            _ => {}
+        }
+
+        Ok(pool)
+    }
+
+    //Fetches the on chain data that local simulation needs and is not kept up to date from logs, returning true if the pool was updated.
+    //UniswapV3 and Algebra pools fetch the ticks around the current tick again once the price moves close to the edge of the tracked ticks
+    pub async fn prefetch<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<bool, ExecutorError<M>> {
+        match self {
+            Pool::UniswapV3(uniswap_v3_pool)
+                if uniswap_v3_pool
+                    .tick_data
+                    .needs_refresh(uniswap_v3_pool.tick, uniswap_v3_pool.tick_spacing) =>
+            {
+                uniswap_v3_pool.populate_tick_data(middleware).await?;
+                Ok(true)
+            }
+
+            Pool::Algebra(algebra_pool)
+                if algebra_pool
+                    .tick_data
+                    .needs_refresh(algebra_pool.tick, algebra_pool.tick_spacing) =>
+            {
+                algebra_pool.populate_tick_data(middleware).await?;
+                Ok(true)
+            }
+
+            _ => Ok(false),
+        }
+    }
+
+    //Pool as the AutomatedMarketMaker it adapts
+    pub fn amm(&self) -> &dyn AutomatedMarketMaker {
+        match self {
+            Pool::UniswapV2(uniswap_v2_pool) => uniswap_v2_pool,
+            Pool::UniswapV3(uniswap_v3_pool) => uniswap_v3_pool,
+            Pool::Algebra(algebra_pool) => algebra_pool,
+            Pool::Solidly(solidly_pool) => solidly_pool,
+            Pool::Curve(curve_pool) => curve_pool,
+            Pool::Balancer(balancer_pool) => balancer_pool,
+        }
+    }
+
+    pub fn amm_mut(&mut self) -> &mut dyn AutomatedMarketMaker {
+        match self {
+            Pool::UniswapV2(uniswap_v2_pool) => uniswap_v2_pool,
+            Pool::UniswapV3(uniswap_v3_pool) => uniswap_v3_pool,
+            Pool::Algebra(algebra_pool) => algebra_pool,
+            Pool::Solidly(solidly_pool) => solidly_pool,
+            Pool::Curve(curve_pool) => curve_pool,
+            Pool::Balancer(balancer_pool) => balancer_pool,
+        }
+    }
+}
+
+impl AutomatedMarketMaker for Pool {
+    fn address(&self) -> H160 {
+        self.amm().address()
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        self.amm().tokens()
+    }
+
+    fn fee(&self) -> u32 {
+        self.amm().fee()
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        self.amm().price_64_x_64(base_token)
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        self.amm().quote_local(token_in, amount_in)
+    }
+
+    fn simulate_swap_local(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.amm().simulate_swap_local(token_in, amount_in)
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.amm_mut().simulate_swap_local_mut(token_in, amount_in)
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        self.amm_mut().update_from_log(log)
+    }
+
+    fn transfer_before_swap(&self) -> bool {
+        self.amm().transfer_before_swap()
+    }
+
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        from: H160,
+    ) -> Vec<Call> {
+        self.amm()
+            .swap_calls(token_in, amount_in, amount_out, to, from)
+    }
+}
+
+//Approves the spender to pull the amount in from the sandbox router
+pub fn approve_call(token: H160, spender: H160, amount: U256) -> Call {
+    Call::new(
+        token,
+        abi::IERC20_ABI
+            .function("approve")
+            .unwrap()
+            .encode_input(&[Token::Address(spender), Token::Uint(amount)])
+            .expect("Could not encode approve inputs"),
+    )
+}
+
+//Transfers the amount from the sandbox router, used to pay pools that are paid before the swap
+pub fn transfer_call(token: H160, to: H160, amount: U256) -> Call {
+    Call::new(
+        token,
+        abi::IERC20_ABI
+            .function("transfer")
+            .unwrap()
+            .encode_input(&[Token::Address(to), Token::Uint(amount)])
+            .expect("Could not encode transfer inputs"),
+    )
+}
@@ -0,0 +1,716 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{AbiEncode, RawLog},
+    prelude::{EthEvent, EthLogDecode},
+    providers::Middleware,
+    types::{Bytes, Log, H160, H256, U256, U512},
+};
+
+use crate::{
+    abi::{self, i_camelot_pair, i_solidly_pair},
+    error::ExecutorError,
+    execution::sandbox_limit_order::Call,
+    markets::normalized_price_64_x_64,
+    quoter::{Quote, SOLIDLY_STABLE_SWAP_GAS_ESTIMATE, UNISWAP_V2_SWAP_GAS_ESTIMATE},
+};
+
+use super::AutomatedMarketMaker;
+
+//Fees are stored out of 100_000, the same denominator used by cfmms for UniswapV2 pools
+pub const FEE_DENOMINATOR: u32 = 100_000;
+
+//Solidly forks differ in how pairs are discovered and where the fee is stored
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SolidlyVariant {
+    //Velodrome V1 and Thena. Stable and volatile pairs are deployed separately through getPair(a, b, stable), and the factory sets one fee in bps for each pair type
+    #[default]
+    Velodrome,
+    //Camelot V2. One pair per token pair, which can be switched between stable and volatile, with a fee out of 100_000 for each token in
+    Camelot,
+}
+
+//Solidly style factory (ie. Velodrome, Thena, Camelot). Solidly pairs are not supported by cfmms, so pairs are discovered through the factory directly
+#[derive(Debug, Clone, Copy)]
+pub struct SolidlyDex {
+    pub factory_address: H160,
+    pub variant: SolidlyVariant,
+}
+
+impl SolidlyDex {
+    pub fn new(factory_address: H160, variant: SolidlyVariant) -> SolidlyDex {
+        SolidlyDex {
+            factory_address,
+            variant,
+        }
+    }
+
+    //Returns the stable and volatile pairs for the tokens that have been deployed
+    pub async fn get_pools_for_pair<M: Middleware>(
+        &self,
+        token_a: H160,
+        token_b: H160,
+        middleware: Arc<M>,
+    ) -> Result<Vec<SolidlyPool>, ExecutorError<M>> {
+        let pair_addresses = match self.variant {
+            SolidlyVariant::Velodrome => {
+                let factory = abi::ISolidlyFactory::new(self.factory_address, middleware.clone());
+
+                vec![
+                    factory.get_pair(token_a, token_b, false).call().await?,
+                    factory.get_pair(token_a, token_b, true).call().await?,
+                ]
+            }
+
+            SolidlyVariant::Camelot => {
+                vec![
+                    abi::ICamelotFactory::new(self.factory_address, middleware.clone())
+                        .get_pair(token_a, token_b)
+                        .call()
+                        .await?,
+                ]
+            }
+        };
+
+        let mut pools = vec![];
+        for pair_address in pair_addresses {
+            if !pair_address.is_zero() {
+                pools.push(
+                    SolidlyPool::new_from_address(pair_address, self, middleware.clone()).await?,
+                );
+            }
+        }
+
+        Ok(pools)
+    }
+
+    //Camelot pairs emit the same Sync event as UniswapV2 pairs, along with events when the fees or the curve change
+    pub fn event_signatures(&self) -> Vec<H256> {
+        match self.variant {
+            SolidlyVariant::Velodrome => vec![i_solidly_pair::SyncFilter::signature()],
+            SolidlyVariant::Camelot => vec![
+                i_camelot_pair::SyncFilter::signature(),
+                i_camelot_pair::FeePercentUpdatedFilter::signature(),
+                i_camelot_pair::SetStableSwapFilter::signature(),
+            ],
+        }
+    }
+}
+
+//Solidly style pair. Volatile pairs use the x * y = k invariant and stable pairs use the x^3 * y + y^3 * x = k invariant
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SolidlyPool {
+    pub address: H160,
+    pub token_a: H160,
+    pub token_a_decimals: u8,
+    pub token_b: H160,
+    pub token_b_decimals: u8,
+    pub reserve_0: u128,
+    pub reserve_1: u128,
+    pub stable: bool,
+    //Fees out of FEE_DENOMINATOR charged on the amount in, for token_a in and token_b in
+    pub fee_0: u32,
+    pub fee_1: u32,
+    pub variant: SolidlyVariant,
+}
+
+impl SolidlyPool {
+    pub async fn new_from_address<M: Middleware>(
+        address: H160,
+        dex: &SolidlyDex,
+        middleware: Arc<M>,
+    ) -> Result<SolidlyPool, ExecutorError<M>> {
+        let mut pool = SolidlyPool {
+            address,
+            variant: dex.variant,
+            ..Default::default()
+        };
+
+        match dex.variant {
+            SolidlyVariant::Velodrome => {
+                let (_, _, reserve_0, reserve_1, stable, token_a, token_b) =
+                    abi::ISolidlyPair::new(address, middleware.clone())
+                        .metadata()
+                        .call()
+                        .await?;
+
+                //Velodrome fees are in bps
+                let fee = abi::ISolidlyFactory::new(dex.factory_address, middleware.clone())
+                    .get_fee(stable)
+                    .call()
+                    .await?
+                    .as_u32()
+                    * 10;
+
+                pool.token_a = token_a;
+                pool.token_b = token_b;
+                pool.reserve_0 = reserve_0.as_u128();
+                pool.reserve_1 = reserve_1.as_u128();
+                pool.stable = stable;
+                pool.fee_0 = fee;
+                pool.fee_1 = fee;
+            }
+
+            SolidlyVariant::Camelot => {
+                let camelot_pair = abi::ICamelotPair::new(address, middleware.clone());
+                let (reserve_0, reserve_1, fee_0, fee_1) =
+                    camelot_pair.get_reserves().call().await?;
+
+                pool.token_a = camelot_pair.token_0().call().await?;
+                pool.token_b = camelot_pair.token_1().call().await?;
+                pool.reserve_0 = reserve_0;
+                pool.reserve_1 = reserve_1;
+                pool.stable = camelot_pair.stable_swap().call().await?;
+                pool.fee_0 = fee_0 as u32;
+                pool.fee_1 = fee_1 as u32;
+            }
+        }
+
+        pool.token_a_decimals = abi::IErc20::new(pool.token_a, middleware.clone())
+            .decimals()
+            .call()
+            .await?;
+        pool.token_b_decimals = abi::IErc20::new(pool.token_b, middleware)
+            .decimals()
+            .call()
+            .await?;
+
+        Ok(pool)
+    }
+
+    pub fn fee(&self, token_in: H160) -> u32 {
+        if token_in == self.token_a {
+            self.fee_0
+        } else {
+            self.fee_1
+        }
+    }
+
+    //Mirrors the pair's getAmountOut. Returns None if the pair has no reserves or the swap would revert
+    pub fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        if self.reserve_0 == 0 || self.reserve_1 == 0 {
+            return None;
+        }
+
+        let (reserve_in, reserve_out, decimals_in, decimals_out) = if token_in == self.token_a {
+            (
+                U256::from(self.reserve_0),
+                U256::from(self.reserve_1),
+                self.token_a_decimals,
+                self.token_b_decimals,
+            )
+        } else {
+            (
+                U256::from(self.reserve_1),
+                U256::from(self.reserve_0),
+                self.token_b_decimals,
+                self.token_a_decimals,
+            )
+        };
+
+        let fee = U256::from(self.fee(token_in));
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+
+        if self.stable {
+            let amount_in = amount_in.checked_sub(amount_in.checked_mul(fee)? / fee_denominator)?;
+
+            get_stable_amount_out(
+                amount_in,
+                reserve_in,
+                reserve_out,
+                U256::exp10(decimals_in as usize),
+                U256::exp10(decimals_out as usize),
+            )
+        } else {
+            match self.variant {
+                SolidlyVariant::Velodrome => {
+                    let amount_in =
+                        amount_in.checked_sub(amount_in.checked_mul(fee)? / fee_denominator)?;
+
+                    amount_in
+                        .checked_mul(reserve_out)?
+                        .checked_div(reserve_in.checked_add(amount_in)?)
+                }
+
+                SolidlyVariant::Camelot => {
+                    let amount_in = amount_in.checked_mul(fee_denominator.checked_sub(fee)?)?;
+
+                    amount_in.checked_mul(reserve_out)?.checked_div(
+                        reserve_in
+                            .checked_mul(fee_denominator)?
+                            .checked_add(amount_in)?,
+                    )
+                }
+            }
+        }
+    }
+
+    pub fn quote(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        let gas_estimate = if self.stable {
+            SOLIDLY_STABLE_SWAP_GAS_ESTIMATE
+        } else {
+            UNISWAP_V2_SWAP_GAS_ESTIMATE
+        };
+
+        Some(Quote::new(
+            self.simulate_swap(token_in, amount_in)?,
+            U256::from(gas_estimate),
+        ))
+    }
+
+    //Returns None and leaves the reserves unchanged if the reserves would overflow, like the pair reverts
+    pub fn simulate_swap_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let amount_out = self.simulate_swap(token_in, amount_in)?;
+
+        //Velodrome pairs transfer the fee out of the pair, Camelot pairs keep the fee in the reserves
+        let reserve_in_delta = match self.variant {
+            SolidlyVariant::Velodrome => amount_in.checked_sub(
+                amount_in.checked_mul(U256::from(self.fee(token_in)))?
+                    / U256::from(FEE_DENOMINATOR),
+            )?,
+            SolidlyVariant::Camelot => amount_in,
+        };
+        let (reserve_in_delta, amount_out_delta) = (
+            u128::try_from(reserve_in_delta).ok()?,
+            u128::try_from(amount_out).ok()?,
+        );
+
+        let (reserve_0, reserve_1) = if token_in == self.token_a {
+            (
+                self.reserve_0.checked_add(reserve_in_delta)?,
+                self.reserve_1.checked_sub(amount_out_delta)?,
+            )
+        } else {
+            (
+                self.reserve_0.checked_sub(amount_out_delta)?,
+                self.reserve_1.checked_add(reserve_in_delta)?,
+            )
+        };
+
+        self.reserve_0 = reserve_0;
+        self.reserve_1 = reserve_1;
+
+        Some(amount_out)
+    }
+
+    //Updates the pair from a Sync log, or a fee or stable swap update on Camelot pairs
+    pub fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        let raw_log = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+
+        if log.topics[0] == i_solidly_pair::SyncFilter::signature() {
+            let sync_log = <i_solidly_pair::SyncFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.reserve_0 = sync_log.reserve_0.as_u128();
+            self.reserve_1 = sync_log.reserve_1.as_u128();
+        } else if log.topics[0] == i_camelot_pair::SyncFilter::signature() {
+            let sync_log = <i_camelot_pair::SyncFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.reserve_0 = sync_log.reserve_0;
+            self.reserve_1 = sync_log.reserve_1;
+        } else if log.topics[0] == i_camelot_pair::FeePercentUpdatedFilter::signature() {
+            let fee_log =
+                <i_camelot_pair::FeePercentUpdatedFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.fee_0 = fee_log.token_0_fee_percent as u32;
+            self.fee_1 = fee_log.token_1_fee_percent as u32;
+        } else if log.topics[0] == i_camelot_pair::SetStableSwapFilter::signature() {
+            let stable_swap_log =
+                <i_camelot_pair::SetStableSwapFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.stable = stable_swap_log.stable_swap;
+        }
+
+        Ok(())
+    }
+
+    //Solidly pairs are swapped the same way as UniswapV2 pairs, the amount in is transferred to the pair before calling swap
+    pub fn swap_calldata(&self, amount_0_out: U256, amount_1_out: U256, to: H160) -> Bytes {
+        i_solidly_pair::SwapCall {
+            amount_0_out,
+            amount_1_out,
+            to,
+            data: Bytes::new(),
+        }
+        .encode()
+        .into()
+    }
+}
+
+impl AutomatedMarketMaker for SolidlyPool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    //Solidly pools can have a fee for each token in, this is the fee for swapping token a to token b
+    fn fee(&self) -> u32 {
+        self.fee_0
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        let (reserve_base, base_decimals, reserve_quote, quote_decimals) =
+            if base_token == self.token_a {
+                (
+                    self.reserve_0,
+                    self.token_a_decimals,
+                    self.reserve_1,
+                    self.token_b_decimals,
+                )
+            } else {
+                (
+                    self.reserve_1,
+                    self.token_b_decimals,
+                    self.reserve_0,
+                    self.token_a_decimals,
+                )
+            };
+
+        if self.stable {
+            stable_price_64_x_64(reserve_base, base_decimals, reserve_quote, quote_decimals)
+        } else {
+            normalized_price_64_x_64(
+                U512::from(reserve_base),
+                base_decimals,
+                U512::from(reserve_quote),
+                quote_decimals,
+                false,
+            )
+        }
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        self.quote(token_in, amount_in)
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.simulate_swap_mut(token_in, amount_in)
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        SolidlyPool::update_from_log(self, log)
+    }
+
+    fn transfer_before_swap(&self) -> bool {
+        true
+    }
+
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        _amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        _from: H160,
+    ) -> Vec<Call> {
+        let (amount_0_out, amount_1_out) = if self.token_a == token_in {
+            (U256::zero(), amount_out)
+        } else {
+            (amount_out, U256::zero())
+        };
+
+        vec![Call::new(
+            self.address,
+            self.swap_calldata(amount_0_out, amount_1_out, to).to_vec(),
+        )]
+    }
+}
+
+//Calculates the marginal price of the base token on the x^3 * y + y^3 * x = k curve of a Solidly stable pair as a Q64.64 fixed point number.
+//The price of x in y is (3x^2y + y^3) / (x^3 + 3xy^2), with both reserves scaled to the same number of decimals
+fn stable_price_64_x_64(
+    reserve_base: u128,
+    base_decimals: u8,
+    reserve_quote: u128,
+    quote_decimals: u8,
+) -> Option<U256> {
+    let decimals = base_decimals.max(quote_decimals);
+    let x = U512::from(reserve_base)
+        .checked_mul(U512::from(10).checked_pow(U512::from(decimals - base_decimals))?)?;
+    let y = U512::from(reserve_quote)
+        .checked_mul(U512::from(10).checked_pow(U512::from(decimals - quote_decimals))?)?;
+
+    let x_squared = x.checked_mul(x)?;
+    let y_squared = y.checked_mul(y)?;
+
+    let numerator = U512::from(3)
+        .checked_mul(x_squared)?
+        .checked_mul(y)?
+        .checked_add(y_squared.checked_mul(y)?)?;
+    let denominator = x_squared
+        .checked_mul(x)?
+        .checked_add(U512::from(3).checked_mul(x)?.checked_mul(y_squared)?)?;
+
+    normalized_price_64_x_64(denominator, 0, numerator, 0, false)
+}
+
+//Stable swap amount out, mirroring the Solidly pair's _getAmountOut. Reserves and amounts are scaled to 18 decimals before solving the invariant
+fn get_stable_amount_out(
+    amount_in: U256,
+    reserve_in: U256,
+    reserve_out: U256,
+    decimals_in: U256,
+    decimals_out: U256,
+) -> Option<U256> {
+    let e18 = U256::exp10(18);
+
+    let xy = k(reserve_in, reserve_out, decimals_in, decimals_out)?;
+    let reserve_in = reserve_in.checked_mul(e18)? / decimals_in;
+    let reserve_out = reserve_out.checked_mul(e18)? / decimals_out;
+    let amount_in = amount_in.checked_mul(e18)? / decimals_in;
+
+    let y = reserve_out.checked_sub(get_y(amount_in.checked_add(reserve_in)?, xy, reserve_out)?)?;
+
+    Some(y.checked_mul(decimals_out)? / e18)
+}
+
+//x^3 * y + y^3 * x with both reserves scaled to 18 decimals
+fn k(x: U256, y: U256, decimals_x: U256, decimals_y: U256) -> Option<U256> {
+    let e18 = U256::exp10(18);
+
+    let x = x.checked_mul(e18)? / decimals_x;
+    let y = y.checked_mul(e18)? / decimals_y;
+    let a = x.checked_mul(y)? / e18;
+    let b = (x.checked_mul(x)? / e18).checked_add(y.checked_mul(y)? / e18)?;
+
+    Some(a.checked_mul(b)? / e18)
+}
+
+fn f(x_0: U256, y: U256) -> Option<U256> {
+    let e18 = U256::exp10(18);
+
+    let y_cubed = (y.checked_mul(y)? / e18).checked_mul(y)? / e18;
+    let x_cubed = (x_0.checked_mul(x_0)? / e18).checked_mul(x_0)? / e18;
+
+    (x_0.checked_mul(y_cubed)? / e18).checked_add(x_cubed.checked_mul(y)? / e18)
+}
+
+fn d(x_0: U256, y: U256) -> Option<U256> {
+    let e18 = U256::exp10(18);
+
+    let x_cubed = (x_0.checked_mul(x_0)? / e18).checked_mul(x_0)? / e18;
+
+    (U256::from(3)
+        .checked_mul(x_0)?
+        .checked_mul(y.checked_mul(y)? / e18)?
+        / e18)
+        .checked_add(x_cubed)
+}
+
+//Solves the invariant for y with Newton's method, stopping once y moves by at most one unit
+fn get_y(x_0: U256, xy: U256, mut y: U256) -> Option<U256> {
+    let e18 = U256::exp10(18);
+
+    for _ in 0..255 {
+        let y_prev = y;
+        let k = f(x_0, y)?;
+
+        if k < xy {
+            let dy = (xy - k).checked_mul(e18)?.checked_div(d(x_0, y)?)?;
+            y = y.checked_add(dy)?;
+        } else {
+            let dy = (k - xy).checked_mul(e18)?.checked_div(d(x_0, y)?)?;
+            y = y.checked_sub(dy)?;
+        }
+
+        if y > y_prev {
+            if y - y_prev <= U256::one() {
+                return Some(y);
+            }
+        } else if y_prev - y <= U256::one() {
+            return Some(y);
+        }
+    }
+
+    Some(y)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, AbiDecode, Token},
+        prelude::EthEvent,
+        types::{Bytes, Log, H160, H256, U256},
+    };
+
+    use super::{SolidlyPool, SolidlyVariant};
+    use crate::abi::{i_camelot_pair, i_solidly_pair};
+
+    fn token_a() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token_b() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    //1m token a with 18 decimals and 1.1m token b with 6 decimals, with a 2 bps fee
+    fn pool(stable: bool, variant: SolidlyVariant) -> SolidlyPool {
+        SolidlyPool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_a(),
+            token_a_decimals: 18,
+            token_b: token_b(),
+            token_b_decimals: 6,
+            reserve_0: 1_000_000_000_000_000_000_000_000,
+            reserve_1: 1_100_000_000_000,
+            stable,
+            fee_0: 20,
+            fee_1: 20,
+            variant,
+        }
+    }
+
+    fn log(signature: H256, data: Vec<Token>) -> Log {
+        Log {
+            topics: vec![signature],
+            data: Bytes::from(encode(&data)),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_simulate_swap_stable() {
+        let stable_pool = pool(true, SolidlyVariant::Velodrome);
+
+        //Expected values are from the pair's _getAmountOut
+        assert_eq!(
+            stable_pool.simulate_swap(token_a(), U256::exp10(22)),
+            Some(U256::from(9999593649_u128))
+        );
+        assert_eq!(
+            stable_pool.simulate_swap(token_b(), U256::from(10_000_000_000_u128)),
+            Some(U256::from(9995103117622070301408_u128))
+        );
+
+        //Stable pairs have much less price impact than volatile pairs around the peg
+        assert!(
+            stable_pool
+                .simulate_swap(token_a(), U256::exp10(22))
+                .unwrap()
+                > pool(false, SolidlyVariant::Velodrome)
+                    .simulate_swap(token_a(), U256::exp10(22))
+                    .unwrap()
+                    * 9
+                    / 10
+        );
+    }
+
+    #[test]
+    fn test_simulate_swap_volatile() {
+        //Velodrome takes the fee from the amount in, Camelot scales the reserves by the fee denominator
+        assert_eq!(
+            pool(false, SolidlyVariant::Velodrome).simulate_swap(token_a(), U256::exp10(22)),
+            Some(U256::from(10888932453_u128))
+        );
+
+        let mut camelot_pool = pool(false, SolidlyVariant::Camelot);
+        camelot_pool.fee_0 = 300;
+        assert_eq!(
+            camelot_pool.simulate_swap(token_a(), U256::exp10(22)),
+            Some(U256::from(10858738378_u128))
+        );
+
+        //Pairs without reserves can not be simulated
+        camelot_pool.reserve_0 = 0;
+        assert_eq!(camelot_pool.simulate_swap(token_a(), U256::exp10(22)), None);
+    }
+
+    #[test]
+    fn test_simulate_swap_mut() {
+        //Velodrome pairs send the fee out of the pair, Camelot pairs keep it in the reserves
+        let mut velodrome_pool = pool(true, SolidlyVariant::Velodrome);
+        let amount_out = velodrome_pool
+            .simulate_swap_mut(token_a(), U256::exp10(22))
+            .unwrap();
+        assert_eq!(
+            velodrome_pool.reserve_0,
+            1_010_000_000_000_000_000_000_000 - 2_000_000_000_000_000_000
+        );
+        assert_eq!(
+            velodrome_pool.reserve_1,
+            1_100_000_000_000 - amount_out.as_u128()
+        );
+
+        let mut camelot_pool = pool(true, SolidlyVariant::Camelot);
+        camelot_pool
+            .simulate_swap_mut(token_a(), U256::exp10(22))
+            .unwrap();
+        assert_eq!(camelot_pool.reserve_0, 1_010_000_000_000_000_000_000_000);
+
+        //Swaps that would overflow the reserves revert in the pair, so the reserves are left unchanged
+        let mut camelot_pool = pool(false, SolidlyVariant::Camelot);
+        let reserves = (camelot_pool.reserve_0, camelot_pool.reserve_1);
+        assert!(camelot_pool
+            .simulate_swap(token_b(), U256::from(u128::MAX))
+            .is_some());
+        assert_eq!(
+            camelot_pool.simulate_swap_mut(token_b(), U256::from(u128::MAX)),
+            None
+        );
+        assert_eq!((camelot_pool.reserve_0, camelot_pool.reserve_1), reserves);
+    }
+
+    #[test]
+    fn test_update_from_log() {
+        let mut camelot_pool = pool(false, SolidlyVariant::Camelot);
+
+        camelot_pool
+            .update_from_log(&log(
+                i_camelot_pair::SyncFilter::signature(),
+                vec![Token::Uint(U256::from(100)), Token::Uint(U256::from(200))],
+            ))
+            .unwrap();
+        assert_eq!((camelot_pool.reserve_0, camelot_pool.reserve_1), (100, 200));
+
+        camelot_pool
+            .update_from_log(&log(
+                i_camelot_pair::FeePercentUpdatedFilter::signature(),
+                vec![Token::Uint(U256::from(300)), Token::Uint(U256::from(50))],
+            ))
+            .unwrap();
+        assert_eq!(
+            (camelot_pool.fee(token_a()), camelot_pool.fee(token_b())),
+            (300, 50)
+        );
+
+        camelot_pool
+            .update_from_log(&log(
+                i_camelot_pair::SetStableSwapFilter::signature(),
+                vec![Token::Bool(false), Token::Bool(true)],
+            ))
+            .unwrap();
+        assert!(camelot_pool.stable);
+
+        let mut velodrome_pool = pool(true, SolidlyVariant::Velodrome);
+        velodrome_pool
+            .update_from_log(&log(
+                i_solidly_pair::SyncFilter::signature(),
+                vec![Token::Uint(U256::from(300)), Token::Uint(U256::from(400))],
+            ))
+            .unwrap();
+        assert_eq!(
+            (velodrome_pool.reserve_0, velodrome_pool.reserve_1),
+            (300, 400)
+        );
+    }
+
+    #[test]
+    fn test_swap_calldata() {
+        let stable_pool = pool(true, SolidlyVariant::Velodrome);
+        let to = H160::from_low_u64_be(4);
+
+        let call = i_solidly_pair::SwapCall::decode(stable_pool.swap_calldata(
+            U256::zero(),
+            U256::from(1000),
+            to,
+        ))
+        .unwrap();
+
+        assert_eq!(call.amount_0_out, U256::zero());
+        assert_eq!(call.amount_1_out, U256::from(1000));
+        assert_eq!(call.to, to);
+        assert!(call.data.is_empty());
+    }
+}
@@ -0,0 +1,437 @@
+use std::{
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use ethers::{
+    abi::{Event, HumanReadableParser},
+    providers::Middleware,
+    types::{Log, H160, H256, U256, U512},
+};
+
+use crate::{
+    abi,
+    execution::sandbox_limit_order::Call,
+    markets::normalized_price_64_x_64,
+    quoter::{Quote, UNISWAP_V2_SWAP_GAS_ESTIMATE},
+};
+
+use super::AutomatedMarketMaker;
+
+//Fees are stored out of 100_000, the denominator of the factory fee that UniswapV2 dexes are configured with
+pub const FEE_DENOMINATOR: u32 = 100_000;
+//Number of blocks between reading the fees of pairs on forks with a fee for each pair that do not log fee changes
+pub const PAIR_FEE_REFRESH_INTERVAL: u64 = 100;
+
+pub fn fee_update_event(fee_event: &str) -> Event {
+    HumanReadableParser::parse_event(fee_event).expect("Could not parse pair fee event")
+}
+
+//UniswapV2 fork that sets the fee of each pair instead of charging one fee on every pair (ie. Biswap). Pairs expose their fee through swapFee(), out of the fee denominator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UniswapV2PairFee {
+    pub fee_denominator: u32,
+    //Human readable event that pairs log when their fee changes, with the new fee as the first non indexed parameter.
+    //Pairs on forks without a fee event are polled every PAIR_FEE_REFRESH_INTERVAL blocks instead
+    pub fee_event: Option<&'static str>,
+}
+
+impl UniswapV2PairFee {
+    pub fn new(fee_denominator: u32, fee_event: Option<&'static str>) -> UniswapV2PairFee {
+        UniswapV2PairFee {
+            fee_denominator,
+            fee_event,
+        }
+    }
+
+    pub fn fee_event_signature(&self) -> Option<H256> {
+        self.fee_event
+            .map(|fee_event| fee_update_event(fee_event).signature())
+    }
+
+    //Converts the fee of a pair to a fee out of FEE_DENOMINATOR, returning None if the pair would not charge a valid fee
+    pub fn to_fee(&self, swap_fee: u32) -> Option<u32> {
+        if self.fee_denominator == 0 || swap_fee >= self.fee_denominator {
+            return None;
+        }
+
+        Some((swap_fee as u64 * FEE_DENOMINATOR as u64 / self.fee_denominator as u64) as u32)
+    }
+}
+
+//UniswapV2 pair with the fee it charges. cfmms assumes a 0.3% fee on every pair, so swaps are simulated with the fee of the pair instead
+#[derive(Debug, Clone, Default)]
+pub struct UniswapV2Pool {
+    pub pool: cfmms::pool::UniswapV2Pool,
+    //Set for pairs on forks with a fee for each pair, the fee of the dex is kept when the pair does not expose a fee
+    pub pair_fee: Option<UniswapV2PairFee>,
+    //Factory of the dex that the pair was discovered through
+    pub factory_address: H160,
+}
+
+impl Deref for UniswapV2Pool {
+    type Target = cfmms::pool::UniswapV2Pool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl DerefMut for UniswapV2Pool {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.pool
+    }
+}
+
+impl UniswapV2Pool {
+    pub fn new(pool: cfmms::pool::UniswapV2Pool) -> UniswapV2Pool {
+        UniswapV2Pool {
+            pool,
+            pair_fee: None,
+            factory_address: H160::zero(),
+        }
+    }
+
+    //Reads the fee of the pair for forks with a fee for each pair, keeping the current fee if the pair does not expose a valid fee. Returns true if the fee changed
+    pub async fn sync_fee<M: Middleware>(&mut self, middleware: Arc<M>) -> bool {
+        let pair_fee = match self.pair_fee {
+            Some(pair_fee) => pair_fee,
+            None => return false,
+        };
+
+        let fee = match abi::IUniswapV2PairSwapFee::new(self.address, middleware)
+            .swap_fee()
+            .call()
+            .await
+        {
+            Ok(swap_fee) => pair_fee.to_fee(swap_fee),
+            Err(err) => {
+                tracing::warn!(
+                    "Could not get the swap fee of pair {:?}: {:?}",
+                    self.address,
+                    err
+                );
+                None
+            }
+        };
+
+        match fee {
+            Some(fee) if fee != self.pool.fee => {
+                self.pool.fee = fee;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    //Pairs on forks with a fee for each pair that do not log fee changes have their fee read from the pair every PAIR_FEE_REFRESH_INTERVAL blocks
+    pub fn polls_fee(&self) -> bool {
+        self.pair_fee
+            .is_some_and(|pair_fee| pair_fee.fee_event.is_none())
+    }
+
+    //Updates the fee of the pair from the first non indexed parameter of the fee event, keeping the current fee if the pair would not charge the new fee
+    fn update_fee_from_log(
+        &mut self,
+        pair_fee: UniswapV2PairFee,
+        log: &Log,
+    ) -> Result<(), ethers::abi::Error> {
+        if log.data.len() < 32 {
+            return Err(ethers::abi::Error::InvalidData);
+        }
+
+        let swap_fee = U256::from_big_endian(&log.data[0..32]);
+        if swap_fee > U256::from(u32::MAX) {
+            return Err(ethers::abi::Error::InvalidData);
+        }
+
+        if let Some(fee) = pair_fee.to_fee(swap_fee.as_u32()) {
+            self.pool.fee = fee;
+        }
+
+        Ok(())
+    }
+
+    //Mirrors getAmountOut with the fee of the pair. Returns zero if the pair has no reserves, or None if the amount in overflows the math of the pair
+    pub fn get_amount_out(
+        &self,
+        amount_in: U256,
+        reserve_in: U256,
+        reserve_out: U256,
+    ) -> Option<U256> {
+        if amount_in.is_zero() || reserve_in.is_zero() || reserve_out.is_zero() {
+            return Some(U256::zero());
+        }
+
+        let fee_denominator = U256::from(FEE_DENOMINATOR);
+        let amount_in_with_fee =
+            amount_in.checked_mul(fee_denominator.checked_sub(U256::from(self.fee))?)?;
+        let numerator = amount_in_with_fee.checked_mul(reserve_out)?;
+        let denominator = reserve_in
+            .checked_mul(fee_denominator)?
+            .checked_add(amount_in_with_fee)?;
+
+        numerator.checked_div(denominator)
+    }
+
+    pub fn simulate_swap(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let (reserve_in, reserve_out) = self.reserves(token_in);
+        self.get_amount_out(amount_in, reserve_in, reserve_out)
+    }
+
+    //The fee stays in the pair, so the full amount in is added to the reserves. Returns None and leaves the reserves unchanged if the reserves would overflow, like the pair reverts
+    pub fn simulate_swap_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        let amount_out = self.simulate_swap(token_in, amount_in)?;
+        let (amount_in, amount_out) = (
+            u128::try_from(amount_in).ok()?,
+            u128::try_from(amount_out).ok()?,
+        );
+
+        let (reserve_0, reserve_1) = if self.token_a == token_in {
+            (
+                self.reserve_0.checked_add(amount_in)?,
+                self.reserve_1.checked_sub(amount_out)?,
+            )
+        } else {
+            (
+                self.reserve_0.checked_sub(amount_out)?,
+                self.reserve_1.checked_add(amount_in)?,
+            )
+        };
+
+        self.pool.reserve_0 = reserve_0;
+        self.pool.reserve_1 = reserve_1;
+
+        Some(U256::from(amount_out))
+    }
+
+    //Returns the reserve in and reserve out for a swap
+    fn reserves(&self, token_in: H160) -> (U256, U256) {
+        if self.token_a == token_in {
+            (U256::from(self.reserve_0), U256::from(self.reserve_1))
+        } else {
+            (U256::from(self.reserve_1), U256::from(self.reserve_0))
+        }
+    }
+}
+
+impl AutomatedMarketMaker for UniswapV2Pool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    fn fee(&self) -> u32 {
+        self.fee
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        let (reserve_a, reserve_b) = (U512::from(self.reserve_0), U512::from(self.reserve_1));
+
+        if base_token == self.token_a {
+            normalized_price_64_x_64(
+                reserve_a,
+                self.token_a_decimals,
+                reserve_b,
+                self.token_b_decimals,
+                false,
+            )
+        } else {
+            normalized_price_64_x_64(
+                reserve_b,
+                self.token_b_decimals,
+                reserve_a,
+                self.token_a_decimals,
+                false,
+            )
+        }
+    }
+
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        Some(Quote::new(
+            self.simulate_swap(token_in, amount_in)?,
+            U256::from(UNISWAP_V2_SWAP_GAS_ESTIMATE),
+        ))
+    }
+
+    //Pairs without reserves are skipped when comparing pools
+    fn simulate_swap_local(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        if self.reserve_0 == 0 || self.reserve_1 == 0 {
+            return None;
+        }
+
+        self.simulate_swap(token_in, amount_in)
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        self.simulate_swap_mut(token_in, amount_in)
+    }
+
+    //Updates the reserves from a Sync log, or the fee of the pair from the fee event of forks with a fee for each pair
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        if log.topics[0] == cfmms::pool::uniswap_v2::SYNC_EVENT_SIGNATURE {
+            self.pool.update_pool_from_sync_log(log);
+        } else if let Some(pair_fee) = self.pair_fee {
+            if pair_fee.fee_event_signature() == Some(log.topics[0]) {
+                self.update_fee_from_log(pair_fee, log)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn transfer_before_swap(&self) -> bool {
+        true
+    }
+
+    //The amount in has already been transferred to the pair, so the pair only needs to be called with the amount out
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        _amount_in: U256,
+        amount_out: U256,
+        to: H160,
+        _from: H160,
+    ) -> Vec<Call> {
+        let (amount_0_out, amount_1_out) = if self.token_a == token_in {
+            (U256::zero(), amount_out)
+        } else {
+            (amount_out, U256::zero())
+        };
+
+        vec![Call::new(
+            self.address,
+            self.swap_calldata(amount_0_out, amount_1_out, to, vec![]),
+        )]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::{encode, Token},
+        types::{Bytes, Log, H160, U256},
+    };
+
+    use super::{UniswapV2PairFee, UniswapV2Pool};
+    use crate::pool::AutomatedMarketMaker;
+
+    fn token_a() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token_b() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    fn pool(fee: u32) -> UniswapV2Pool {
+        UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(3),
+            token_a: token_a(),
+            token_a_decimals: 6,
+            token_b: token_b(),
+            token_b_decimals: 18,
+            reserve_0: 47092140895915,
+            reserve_1: 28396598565590008529300,
+            fee,
+        })
+    }
+
+    #[test]
+    fn test_simulate_swap_matches_cfmms() {
+        let pool = pool(300);
+        let amount_in = U256::from(1_000_000_000);
+
+        assert_eq!(
+            pool.simulate_swap(token_a(), amount_in),
+            Some(pool.pool.simulate_swap(token_a(), amount_in))
+        );
+        assert_eq!(
+            pool.simulate_swap(token_b(), amount_in),
+            Some(pool.pool.simulate_swap(token_b(), amount_in))
+        );
+    }
+
+    #[test]
+    fn test_simulate_swap_pair_fee() {
+        //Biswap style pair charging 0.2%, with swapFee() out of 1000
+        let pair_fee = UniswapV2PairFee::new(1000, None);
+        let fee = pair_fee.to_fee(2).unwrap();
+        assert_eq!(fee, 200);
+
+        let mut pool = pool(fee);
+        let amount_in = U256::from(1_000_000_000);
+        let (reserve_in, reserve_out) = (U256::from(pool.reserve_0), U256::from(pool.reserve_1));
+
+        //getAmountOut of the pair, amountIn * (1000 - swapFee) * reserveOut / (reserveIn * 1000 + amountIn * (1000 - swapFee))
+        let amount_in_with_fee = amount_in * 998;
+        let expected_amount_out =
+            amount_in_with_fee * reserve_out / (reserve_in * 1000 + amount_in_with_fee);
+
+        assert_eq!(
+            pool.simulate_swap(token_a(), amount_in),
+            Some(expected_amount_out)
+        );
+        assert!(expected_amount_out > pool.pool.simulate_swap(token_a(), amount_in));
+
+        assert_eq!(
+            pool.simulate_swap_mut(token_a(), amount_in),
+            Some(expected_amount_out)
+        );
+        assert_eq!(U256::from(pool.reserve_0), reserve_in + amount_in);
+        assert_eq!(
+            U256::from(pool.reserve_1),
+            reserve_out - expected_amount_out
+        );
+
+        //Fees that the pair would not charge are ignored
+        assert_eq!(pair_fee.to_fee(1000), None);
+        assert_eq!(UniswapV2PairFee::new(0, None).to_fee(0), None);
+    }
+
+    #[test]
+    fn test_simulate_swap_overflow() {
+        let mut pool = pool(300);
+        let (reserve_0, reserve_1) = (pool.reserve_0, pool.reserve_1);
+
+        //Amounts in that overflow the math of the pair are not simulated
+        assert_eq!(pool.simulate_swap(token_a(), U256::MAX), None);
+        assert_eq!(pool.simulate_swap_mut(token_a(), U256::MAX), None);
+
+        //Amounts in that overflow the reserves revert in the pair, so the reserves are left unchanged
+        let amount_in = U256::from(u128::MAX);
+        assert!(pool.simulate_swap(token_b(), amount_in).is_some());
+        assert_eq!(pool.simulate_swap_mut(token_b(), amount_in), None);
+        assert_eq!((pool.reserve_0, pool.reserve_1), (reserve_0, reserve_1));
+    }
+
+    #[test]
+    fn test_update_from_fee_log() {
+        let pair_fee = UniswapV2PairFee::new(1000, Some("event FeeUpdated(uint32 swapFee)"));
+        let mut pool = pool(200);
+        pool.pair_fee = Some(pair_fee);
+        assert!(!pool.polls_fee());
+
+        let fee_log = |swap_fee: u64| Log {
+            topics: vec![pair_fee.fee_event_signature().unwrap()],
+            data: Bytes::from(encode(&[Token::Uint(U256::from(swap_fee))])),
+            ..Default::default()
+        };
+
+        pool.update_from_log(&fee_log(3)).unwrap();
+        assert_eq!(pool.fee, 300);
+
+        //Fees that the pair would not charge are ignored
+        pool.update_from_log(&fee_log(1000)).unwrap();
+        assert_eq!(pool.fee, 300);
+
+        //Pairs on forks without a fee event ignore the log and poll the fee instead
+        pool.pair_fee = Some(UniswapV2PairFee::new(1000, None));
+        assert!(pool.polls_fee());
+        pool.update_from_log(&fee_log(2)).unwrap();
+        assert_eq!(pool.fee, 300);
+    }
+}
@@ -0,0 +1,947 @@
+use std::{
+    collections::HashMap,
+    future::Future,
+    ops::{Deref, DerefMut},
+    sync::Arc,
+};
+
+use ethers::{
+    abi::{RawLog, Token},
+    prelude::{EthEvent, EthLogDecode},
+    providers::Middleware,
+    types::{Log, H160, I256, U256},
+};
+use futures::future::join_all;
+use thiserror::Error;
+use uniswap_v3_math::{
+    error::UniswapV3MathError,
+    liquidity_math, swap_math, tick_bit_map,
+    tick_math::{self, MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK},
+};
+
+use crate::{
+    abi::{self, i_uniswap_v3_pool::SwapFilter, BurnFilter, MintFilter},
+    error::ExecutorError,
+    execution::sandbox_limit_order::Call,
+    markets::sqrt_price_64_x_64,
+    quoter::{Quote, UNISWAP_V3_INITIALIZED_TICK_GAS_ESTIMATE, UNISWAP_V3_SWAP_GAS_ESTIMATE},
+};
+
+use super::AutomatedMarketMaker;
+
+//Number of tick bitmap words tracked on each side of the word containing the current tick
+pub const TICK_BITMAP_WORDS_PER_SIDE: i16 = 8;
+//Tick data is prefetched again once the current tick is within this many words of the edge of the tracked words
+pub const TICK_BITMAP_REFRESH_MARGIN: i16 = 2;
+
+#[derive(Error, Debug)]
+pub enum UniswapV3SimulationError {
+    #[error("Tick data is not populated")]
+    TickDataNotPopulated,
+    #[error("Tick bitmap word {0} is outside of the tracked tick data")]
+    WordOutOfRange(i16),
+    #[error("Uniswap V3 math error")]
+    MathError(#[from] UniswapV3MathError),
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct TickInfo {
+    pub liquidity_gross: u128,
+    pub liquidity_net: i128,
+}
+
+//Local copy of the tick bitmap and initialized ticks of a pool, within a range of bitmap words around the price when the data was fetched
+#[derive(Debug, Clone, Default)]
+pub struct TickData {
+    pub tick_bitmap: HashMap<i16, U256>,
+    pub ticks: HashMap<i32, TickInfo>,
+    //Inclusive range of tick bitmap words that are tracked, None until the tick data is populated
+    pub word_range: Option<(i16, i16)>,
+}
+
+impl TickData {
+    pub fn contains_word(&self, word_position: i16) -> bool {
+        if let Some((min_word, max_word)) = self.word_range {
+            min_word <= word_position && word_position <= max_word
+        } else {
+            false
+        }
+    }
+
+    //Returns true if the tick data is not populated, or if the current tick has moved close enough to the edge of the tracked words that swaps could move past them
+    pub fn needs_refresh(&self, tick: i32, tick_spacing: i32) -> bool {
+        if self.word_range.is_none() || tick_spacing == 0 {
+            return true;
+        }
+
+        let (current_word, _) = tick_bit_map::position(compress(tick, tick_spacing));
+        let (min_word, _) = tick_bit_map::position(compress(MIN_TICK, tick_spacing));
+        let (max_word, _) = tick_bit_map::position(compress(MAX_TICK, tick_spacing));
+
+        !self.contains_word(
+            current_word
+                .saturating_sub(TICK_BITMAP_REFRESH_MARGIN)
+                .max(min_word),
+        ) || !self.contains_word(
+            current_word
+                .saturating_add(TICK_BITMAP_REFRESH_MARGIN)
+                .min(max_word),
+        )
+    }
+
+    pub fn get_word(&self, word_position: i16) -> Result<U256, UniswapV3SimulationError> {
+        if self.word_range.is_none() {
+            Err(UniswapV3SimulationError::TickDataNotPopulated)
+        } else if self.contains_word(word_position) {
+            Ok(self
+                .tick_bitmap
+                .get(&word_position)
+                .copied()
+                .unwrap_or_default())
+        } else {
+            Err(UniswapV3SimulationError::WordOutOfRange(word_position))
+        }
+    }
+
+    pub fn liquidity_net(&self, tick: i32) -> i128 {
+        self.ticks
+            .get(&tick)
+            .map(|tick_info| tick_info.liquidity_net)
+            .unwrap_or_default()
+    }
+
+    //Mirrors Tick.update and TickBitmap.flipTick, ticks outside of the tracked words are ignored
+    pub fn update_tick(
+        &mut self,
+        tick: i32,
+        tick_spacing: i32,
+        liquidity_delta: i128,
+        upper: bool,
+    ) -> Result<(), UniswapV3MathError> {
+        if tick_spacing == 0 {
+            return Ok(());
+        }
+
+        let (word_position, bit_position) = tick_bit_map::position(tick / tick_spacing);
+        if !self.contains_word(word_position) {
+            return Ok(());
+        }
+
+        let tick_info = self.ticks.entry(tick).or_default();
+        let liquidity_gross_before = tick_info.liquidity_gross;
+
+        tick_info.liquidity_gross =
+            liquidity_math::add_delta(liquidity_gross_before, liquidity_delta)?;
+        tick_info.liquidity_net = if upper {
+            tick_info.liquidity_net - liquidity_delta
+        } else {
+            tick_info.liquidity_net + liquidity_delta
+        };
+
+        let liquidity_gross_after = tick_info.liquidity_gross;
+        if liquidity_gross_after == 0 {
+            self.ticks.remove(&tick);
+        }
+
+        //Flip the tick in the bitmap when it is initialized or uninitialized
+        if (liquidity_gross_before == 0) != (liquidity_gross_after == 0) {
+            *self.tick_bitmap.entry(word_position).or_default() ^= U256::one() << bit_position;
+        }
+
+        Ok(())
+    }
+}
+
+//State of a pool that changes during a swap
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SwapState {
+    pub amount_out: U256,
+    pub sqrt_price: U256,
+    pub tick: i32,
+    pub liquidity: u128,
+    pub initialized_ticks_crossed: u64,
+}
+
+impl SwapState {
+    pub fn new(sqrt_price: U256, tick: i32, liquidity: u128) -> SwapState {
+        SwapState {
+            sqrt_price,
+            tick,
+            liquidity,
+            ..Default::default()
+        }
+    }
+
+    //Estimates gas from the initialized ticks crossed like the QuoterV2
+    pub fn quote(&self) -> Quote {
+        Quote::new(
+            self.amount_out,
+            U256::from(
+                UNISWAP_V3_SWAP_GAS_ESTIMATE
+                    + UNISWAP_V3_INITIALIZED_TICK_GAS_ESTIMATE * self.initialized_ticks_crossed,
+            ),
+        )
+    }
+}
+
+//UniswapV3 pool with a local copy of its tick data. The tick data is shared between clones of the pool since swaps do not modify it
+#[derive(Debug, Clone, Default)]
+pub struct UniswapV3Pool {
+    pub pool: cfmms::pool::UniswapV3Pool,
+    pub tick_data: Arc<TickData>,
+    //Factory of the dex that the pool was discovered through
+    pub factory_address: H160,
+}
+
+impl Deref for UniswapV3Pool {
+    type Target = cfmms::pool::UniswapV3Pool;
+
+    fn deref(&self) -> &Self::Target {
+        &self.pool
+    }
+}
+
+impl DerefMut for UniswapV3Pool {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        &mut self.pool
+    }
+}
+
+impl UniswapV3Pool {
+    pub fn new(pool: cfmms::pool::UniswapV3Pool) -> UniswapV3Pool {
+        UniswapV3Pool {
+            pool,
+            tick_data: Arc::new(TickData::default()),
+            factory_address: H160::zero(),
+        }
+    }
+
+    //Fetches the tick bitmap words around the current tick and the liquidity of every initialized tick within those words
+    pub async fn populate_tick_data<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> Result<(), ExecutorError<M>> {
+        let uniswap_v3_pool = abi::IUniswapV3Pool::new(self.address, middleware);
+
+        if self.tick_spacing == 0 {
+            self.pool.tick_spacing = uniswap_v3_pool.tick_spacing().call().await?;
+        }
+
+        let tick_data = fetch_tick_data(
+            self.tick,
+            self.tick_spacing,
+            |word_position| {
+                let uniswap_v3_pool = uniswap_v3_pool.clone();
+                async move { Ok(uniswap_v3_pool.tick_bitmap(word_position).call().await?) }
+            },
+            |tick| {
+                let uniswap_v3_pool = uniswap_v3_pool.clone();
+                async move {
+                    let (liquidity_gross, liquidity_net, _, _, _, _, _, _) =
+                        uniswap_v3_pool.ticks(tick).call().await?;
+                    Ok(TickInfo {
+                        liquidity_gross,
+                        liquidity_net,
+                    })
+                }
+            },
+        )
+        .await?;
+
+        self.tick_data = Arc::new(tick_data);
+
+        Ok(())
+    }
+
+    //Simulates an exact input swap using only the local tick data
+    pub fn simulate_swap_local(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, UniswapV3SimulationError> {
+        Ok(self.swap(token_in, amount_in)?.amount_out)
+    }
+
+    //Simulates an exact input swap using only the local tick data, estimating gas from the initialized ticks crossed like the QuoterV2
+    pub fn quote_local(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<Quote, UniswapV3SimulationError> {
+        Ok(self.swap(token_in, amount_in)?.quote())
+    }
+
+    pub fn simulate_swap_local_mut(
+        &mut self,
+        token_in: H160,
+        amount_in: U256,
+    ) -> Result<U256, UniswapV3SimulationError> {
+        let swap_state = self.swap(token_in, amount_in)?;
+
+        self.pool.sqrt_price = swap_state.sqrt_price;
+        self.pool.tick = swap_state.tick;
+        self.pool.liquidity = swap_state.liquidity;
+
+        Ok(swap_state.amount_out)
+    }
+
+    fn swap(&self, token_in: H160, amount_in: U256) -> Result<SwapState, UniswapV3SimulationError> {
+        swap_across_ticks(
+            &self.tick_data,
+            self.tick_spacing,
+            self.fee,
+            token_in == self.token_a,
+            amount_in,
+            SwapState::new(self.sqrt_price, self.tick, self.liquidity),
+        )
+    }
+
+    //Updates the pool from a Swap, Mint or Burn log
+    pub fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        let raw_log = RawLog {
+            topics: log.topics.clone(),
+            data: log.data.to_vec(),
+        };
+
+        if log.topics[0] == SwapFilter::signature() {
+            let swap_log = <SwapFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.pool.sqrt_price = swap_log.sqrt_price_x96;
+            self.pool.liquidity = swap_log.liquidity;
+            self.pool.tick = swap_log.tick;
+        } else if log.topics[0] == MintFilter::signature() {
+            let mint_log = <MintFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.update_position(
+                mint_log.tick_lower,
+                mint_log.tick_upper,
+                mint_log.amount as i128,
+            );
+        } else if log.topics[0] == BurnFilter::signature() {
+            let burn_log = <BurnFilter as EthLogDecode>::decode_log(&raw_log)?;
+            self.update_position(
+                burn_log.tick_lower,
+                burn_log.tick_upper,
+                -(burn_log.amount as i128),
+            );
+        }
+
+        Ok(())
+    }
+
+    //Applies a change in position liquidity to the ticks and to the in range liquidity
+    pub fn update_position(&mut self, tick_lower: i32, tick_upper: i32, liquidity_delta: i128) {
+        let tick_spacing = self.tick_spacing;
+        let tick = self.tick;
+
+        update_position(
+            &mut self.tick_data,
+            &mut self.pool.liquidity,
+            tick,
+            tick_spacing,
+            tick_lower,
+            tick_upper,
+            liquidity_delta,
+        );
+    }
+}
+
+impl AutomatedMarketMaker for UniswapV3Pool {
+    fn address(&self) -> H160 {
+        self.address
+    }
+
+    fn tokens(&self) -> (H160, H160) {
+        (self.token_a, self.token_b)
+    }
+
+    fn fee(&self) -> u32 {
+        self.fee
+    }
+
+    fn price_64_x_64(&self, base_token: H160) -> Option<U256> {
+        sqrt_price_64_x_64(
+            self.sqrt_price,
+            base_token == self.token_a,
+            self.token_a_decimals,
+            self.token_b_decimals,
+        )
+    }
+
+    //Returns None when the swap moves past the locally tracked ticks, so that the pool is skipped until its ticks are prefetched again
+    fn quote_local(&self, token_in: H160, amount_in: U256) -> Option<Quote> {
+        UniswapV3Pool::quote_local(self, token_in, amount_in).ok()
+    }
+
+    //Returns None when the tick data is not populated or the swap moves past the locally tracked ticks, like quote_local
+    fn simulate_swap_local(&self, token_in: H160, amount_in: U256) -> Option<U256> {
+        UniswapV3Pool::simulate_swap_local(self, token_in, amount_in).ok()
+    }
+
+    fn simulate_swap_local_mut(&mut self, token_in: H160, amount_in: U256) -> Option<U256> {
+        UniswapV3Pool::simulate_swap_local_mut(self, token_in, amount_in).ok()
+    }
+
+    fn update_from_log(&mut self, log: &Log) -> Result<(), ethers::abi::Error> {
+        UniswapV3Pool::update_from_log(self, log)
+    }
+
+    //The pool pulls the amount in from the sandbox router in the swap callback, which is passed the token in and the payer
+    fn swap_calls(
+        &self,
+        token_in: H160,
+        amount_in: U256,
+        _amount_out: U256,
+        to: H160,
+        from: H160,
+    ) -> Vec<Call> {
+        let zero_for_one = self.token_a == token_in;
+
+        let sqrt_price_limit_x_96 = if zero_for_one {
+            MIN_SQRT_RATIO + 1
+        } else {
+            MAX_SQRT_RATIO - 1
+        };
+
+        let calldata = ethers::abi::encode(&[
+            Token::Bool(zero_for_one),
+            Token::Address(token_in),
+            Token::Address(from),
+        ]);
+
+        vec![Call::new(
+            self.address,
+            self.swap_calldata(
+                to,
+                zero_for_one,
+                I256::from_raw(amount_in),
+                sqrt_price_limit_x_96,
+                calldata,
+            ),
+        )]
+    }
+}
+
+//Applies a change in position liquidity to the ticks and to the in range liquidity of a concentrated liquidity pool
+pub fn update_position(
+    tick_data: &mut Arc<TickData>,
+    liquidity: &mut u128,
+    tick: i32,
+    tick_spacing: i32,
+    tick_lower: i32,
+    tick_upper: i32,
+    liquidity_delta: i128,
+) {
+    if liquidity_delta == 0 {
+        return;
+    }
+
+    let tick_data = Arc::make_mut(tick_data);
+
+    //Failing to update the ticks means the local tick data no longer matches the pool, so it is dropped and fetched again the next time the market is prefetched
+    if tick_data
+        .update_tick(tick_lower, tick_spacing, liquidity_delta, false)
+        .and_then(|_| tick_data.update_tick(tick_upper, tick_spacing, liquidity_delta, true))
+        .is_err()
+    {
+        *tick_data = TickData::default();
+    }
+
+    if tick_lower <= tick && tick < tick_upper {
+        *liquidity = liquidity_math::add_delta(*liquidity, liquidity_delta).unwrap_or_default();
+    }
+}
+
+//Fetches the tick bitmap words within TICK_BITMAP_WORDS_PER_SIDE of the current tick and every initialized tick within those words
+pub async fn fetch_tick_data<M, W, WF, T, TF>(
+    tick: i32,
+    tick_spacing: i32,
+    fetch_word: W,
+    fetch_tick: T,
+) -> Result<TickData, ExecutorError<M>>
+where
+    M: Middleware,
+    W: Fn(i16) -> WF,
+    WF: Future<Output = Result<U256, ExecutorError<M>>>,
+    T: Fn(i32) -> TF,
+    TF: Future<Output = Result<TickInfo, ExecutorError<M>>>,
+{
+    let (current_word, _) = tick_bit_map::position(compress(tick, tick_spacing));
+    let (min_word, _) = tick_bit_map::position(compress(MIN_TICK, tick_spacing));
+    let (max_word, _) = tick_bit_map::position(compress(MAX_TICK, tick_spacing));
+
+    let word_range = (
+        current_word
+            .saturating_sub(TICK_BITMAP_WORDS_PER_SIDE)
+            .max(min_word),
+        current_word
+            .saturating_add(TICK_BITMAP_WORDS_PER_SIDE)
+            .min(max_word),
+    );
+
+    let mut tick_data = TickData {
+        word_range: Some(word_range),
+        ..Default::default()
+    };
+
+    let words = join_all((word_range.0..=word_range.1).map(|word_position| {
+        let word = fetch_word(word_position);
+        async move { Result::<(i16, U256), ExecutorError<M>>::Ok((word_position, word.await?)) }
+    }))
+    .await;
+
+    let mut initialized_ticks = vec![];
+    for word in words {
+        let (word_position, word) = word?;
+        if word.is_zero() {
+            continue;
+        }
+
+        for bit_position in 0..=255_u8 {
+            if word.bit(bit_position as usize) {
+                initialized_ticks
+                    .push(((word_position as i32) * 256 + bit_position as i32) * tick_spacing);
+            }
+        }
+
+        tick_data.tick_bitmap.insert(word_position, word);
+    }
+
+    let ticks = join_all(initialized_ticks.into_iter().map(|tick| {
+        let tick_info = fetch_tick(tick);
+        async move { Result::<(i32, TickInfo), ExecutorError<M>>::Ok((tick, tick_info.await?)) }
+    }))
+    .await;
+
+    for tick in ticks {
+        let (tick, tick_info) = tick?;
+        tick_data.ticks.insert(tick, tick_info);
+    }
+
+    Ok(tick_data)
+}
+
+//Mirrors the swap loop in UniswapV3Pool.swap for exact input swaps without a price limit
+pub fn swap_across_ticks(
+    tick_data: &TickData,
+    tick_spacing: i32,
+    fee: u32,
+    zero_for_one: bool,
+    amount_in: U256,
+    mut swap_state: SwapState,
+) -> Result<SwapState, UniswapV3SimulationError> {
+    if tick_data.word_range.is_none() || tick_spacing == 0 {
+        return Err(UniswapV3SimulationError::TickDataNotPopulated);
+    }
+
+    let sqrt_price_limit = if zero_for_one {
+        MIN_SQRT_RATIO + 1
+    } else {
+        MAX_SQRT_RATIO - 1
+    };
+
+    let mut amount_remaining = I256::from_raw(amount_in);
+
+    while !amount_remaining.is_zero() && swap_state.sqrt_price != sqrt_price_limit {
+        let sqrt_price_start = swap_state.sqrt_price;
+
+        let (tick_next, initialized) = next_initialized_tick_within_one_word(
+            tick_data,
+            tick_spacing,
+            swap_state.tick,
+            zero_for_one,
+        )?;
+        let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next = tick_math::get_sqrt_ratio_at_tick(tick_next)?;
+
+        let sqrt_price_target = if zero_for_one {
+            sqrt_price_next.max(sqrt_price_limit)
+        } else {
+            sqrt_price_next.min(sqrt_price_limit)
+        };
+
+        let (sqrt_price, step_amount_in, step_amount_out, step_fee_amount) =
+            swap_math::compute_swap_step(
+                swap_state.sqrt_price,
+                sqrt_price_target,
+                swap_state.liquidity,
+                amount_remaining,
+                fee,
+            )?;
+
+        swap_state.sqrt_price = sqrt_price;
+        amount_remaining -= I256::from_raw(step_amount_in + step_fee_amount);
+        swap_state.amount_out += step_amount_out;
+
+        if swap_state.sqrt_price == sqrt_price_next {
+            //Cross the tick, updating the in range liquidity
+            if initialized {
+                let liquidity_net = tick_data.liquidity_net(tick_next);
+                let liquidity_net = if zero_for_one {
+                    -liquidity_net
+                } else {
+                    liquidity_net
+                };
+
+                swap_state.liquidity =
+                    liquidity_math::add_delta(swap_state.liquidity, liquidity_net)?;
+                swap_state.initialized_ticks_crossed += 1;
+            }
+
+            swap_state.tick = if zero_for_one {
+                tick_next - 1
+            } else {
+                tick_next
+            };
+        } else if swap_state.sqrt_price != sqrt_price_start {
+            swap_state.tick = tick_math::get_tick_at_sqrt_ratio(swap_state.sqrt_price)?;
+        }
+    }
+
+    Ok(swap_state)
+}
+
+fn next_initialized_tick_within_one_word(
+    tick_data: &TickData,
+    tick_spacing: i32,
+    tick: i32,
+    lte: bool,
+) -> Result<(i32, bool), UniswapV3SimulationError> {
+    let compressed = compress(tick, tick_spacing);
+
+    let (word_position, bit_position) = if lte {
+        tick_bit_map::position(compressed)
+    } else {
+        tick_bit_map::position(compressed + 1)
+    };
+
+    let word = tick_data.get_word(word_position)?;
+
+    Ok(tick_bit_map::next_initialized_tick_within_one_word(
+        tick_spacing,
+        lte,
+        compressed,
+        bit_position,
+        word,
+    )?)
+}
+
+//Mirrors the tick compression in TickBitmap, rounding towards negative infinity
+pub fn compress(tick: i32, tick_spacing: i32) -> i32 {
+    if tick < 0 && tick % tick_spacing != 0 {
+        tick / tick_spacing - 1
+    } else {
+        tick / tick_spacing
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{str::FromStr, sync::Arc};
+
+    use ethers::{
+        abi::{encode, Token},
+        prelude::EthEvent,
+        providers::{Http, Provider},
+        types::{Bytes, Log, H160, H256, I256, U256},
+    };
+    use uniswap_v3_math::{
+        swap_math,
+        tick_math::{get_sqrt_ratio_at_tick, MIN_SQRT_RATIO},
+    };
+
+    use super::{TickData, UniswapV3Pool, UniswapV3SimulationError};
+    use crate::{
+        abi::{i_uniswap_v3_pool::SwapFilter, IUniswapV3Quoter, MintFilter},
+        quoter::{UNISWAP_V3_INITIALIZED_TICK_GAS_ESTIMATE, UNISWAP_V3_SWAP_GAS_ESTIMATE},
+    };
+
+    const FEE: u32 = 3000;
+    const TICK_SPACING: i32 = 60;
+
+    fn token_a() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token_b() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    //Pool at tick 0 with 1e18 liquidity in [-600, 600] and 2e18 liquidity in [-1200, -600]
+    fn pool() -> UniswapV3Pool {
+        let mut pool = UniswapV3Pool::new(cfmms::pool::UniswapV3Pool {
+            token_a: token_a(),
+            token_b: token_b(),
+            sqrt_price: get_sqrt_ratio_at_tick(0).unwrap(),
+            fee: FEE,
+            tick: 0,
+            tick_spacing: TICK_SPACING,
+            ..Default::default()
+        });
+
+        pool.tick_data = Arc::new(TickData {
+            word_range: Some((-2, 2)),
+            ..Default::default()
+        });
+
+        pool.update_position(-600, 600, 1_000_000_000_000_000_000);
+        pool.update_position(-1200, -600, 2_000_000_000_000_000_000);
+
+        pool
+    }
+
+    fn amount_to_tick(sqrt_price: U256, tick: i32, liquidity: u128) -> (U256, U256) {
+        let (_, amount_in, amount_out, fee_amount) = swap_math::compute_swap_step(
+            sqrt_price,
+            get_sqrt_ratio_at_tick(tick).unwrap(),
+            liquidity,
+            I256::MAX,
+            FEE,
+        )
+        .unwrap();
+
+        (amount_in + fee_amount, amount_out)
+    }
+
+    #[test]
+    fn test_update_position() {
+        let mut pool = pool();
+
+        //Only the position containing the current tick is in range
+        assert_eq!(pool.liquidity, 1_000_000_000_000_000_000);
+        assert_eq!(
+            pool.tick_data.liquidity_net(-1200),
+            2_000_000_000_000_000_000
+        );
+        assert_eq!(
+            pool.tick_data.liquidity_net(-600),
+            -1_000_000_000_000_000_000
+        );
+        assert_eq!(
+            pool.tick_data.liquidity_net(600),
+            -1_000_000_000_000_000_000
+        );
+
+        //-600 and -1200 are compressed to -10 and -20, which are bits 246 and 236 of word -1
+        assert_eq!(
+            pool.tick_data.tick_bitmap[&-1],
+            (U256::one() << 246) | (U256::one() << 236)
+        );
+        assert_eq!(pool.tick_data.tick_bitmap[&0], U256::one() << 10);
+
+        //Burning all liquidity from a position uninitializes its ticks
+        pool.update_position(-1200, -600, -2_000_000_000_000_000_000);
+        assert!(!pool.tick_data.ticks.contains_key(&-1200));
+        assert_eq!(
+            pool.tick_data.liquidity_net(-600),
+            1_000_000_000_000_000_000
+        );
+        assert_eq!(pool.tick_data.tick_bitmap[&-1], U256::one() << 246);
+
+        pool.update_position(-600, 600, -1_000_000_000_000_000_000);
+        assert!(pool.tick_data.ticks.is_empty());
+        assert!(pool
+            .tick_data
+            .tick_bitmap
+            .values()
+            .all(|word| word.is_zero()));
+        assert_eq!(pool.liquidity, 0);
+    }
+
+    #[test]
+    fn test_simulate_swap_local_within_range() {
+        let pool = pool();
+        let amount_in = U256::from(1_000_000_000_000_000_u128);
+
+        let (_, _, expected_amount_out, _) = swap_math::compute_swap_step(
+            pool.sqrt_price,
+            MIN_SQRT_RATIO + 1,
+            pool.liquidity,
+            I256::from_raw(amount_in),
+            FEE,
+        )
+        .unwrap();
+
+        assert_eq!(
+            pool.simulate_swap_local(token_a(), amount_in).unwrap(),
+            expected_amount_out
+        );
+    }
+
+    #[test]
+    fn test_simulate_swap_local_crossing_ticks() {
+        let mut pool = pool();
+
+        //Swap through the first range, then half of the remaining amount needed to reach -1200 in the second range
+        let (first_amount_in, first_amount_out) =
+            amount_to_tick(pool.sqrt_price, -600, 1_000_000_000_000_000_000);
+        let (second_range_amount_in, _) = amount_to_tick(
+            get_sqrt_ratio_at_tick(-600).unwrap(),
+            -1200,
+            2_000_000_000_000_000_000,
+        );
+        let second_amount_in = second_range_amount_in / 2;
+
+        let (sqrt_price, _, second_amount_out, _) = swap_math::compute_swap_step(
+            get_sqrt_ratio_at_tick(-600).unwrap(),
+            get_sqrt_ratio_at_tick(-1200).unwrap(),
+            2_000_000_000_000_000_000,
+            I256::from_raw(second_amount_in),
+            FEE,
+        )
+        .unwrap();
+
+        //Crossing -600 is priced into the gas estimate like the QuoterV2
+        let quote = pool
+            .quote_local(token_a(), first_amount_in + second_amount_in)
+            .unwrap();
+        assert_eq!(
+            quote.gas_estimate,
+            U256::from(UNISWAP_V3_SWAP_GAS_ESTIMATE + UNISWAP_V3_INITIALIZED_TICK_GAS_ESTIMATE)
+        );
+
+        let amount_out = pool
+            .simulate_swap_local_mut(token_a(), first_amount_in + second_amount_in)
+            .unwrap();
+        assert_eq!(amount_out, quote.amount_out);
+
+        assert_eq!(amount_out, first_amount_out + second_amount_out);
+        assert_eq!(pool.sqrt_price, sqrt_price);
+        assert_eq!(pool.liquidity, 2_000_000_000_000_000_000);
+        assert!(-1200 < pool.tick && pool.tick < -600);
+
+        //Swapping the amount out back crosses -600 in the other direction, but the fees keep the price below the start
+        pool.simulate_swap_local_mut(token_b(), amount_out).unwrap();
+        assert_eq!(pool.liquidity, 1_000_000_000_000_000_000);
+        assert!(-600 <= pool.tick && pool.tick < 0);
+    }
+
+    #[test]
+    fn test_simulate_swap_local_out_of_range() {
+        let pool = pool();
+
+        //Swapping past the tracked words can not be simulated locally
+        assert!(matches!(
+            pool.simulate_swap_local(token_a(), U256::from(u128::MAX)),
+            Err(UniswapV3SimulationError::WordOutOfRange(-3))
+        ));
+
+        let pool = UniswapV3Pool::new(pool.pool);
+        assert!(matches!(
+            pool.simulate_swap_local(token_a(), U256::one()),
+            Err(UniswapV3SimulationError::TickDataNotPopulated)
+        ));
+    }
+
+    fn int24_topic(value: i32) -> H256 {
+        H256::from_slice(&encode(&[Token::Int(I256::from(value).into_raw())]))
+    }
+
+    #[test]
+    fn test_needs_refresh() {
+        assert!(TickData::default().needs_refresh(0, TICK_SPACING));
+
+        let tick_data = TickData {
+            word_range: Some((-3, 3)),
+            ..Default::default()
+        };
+        assert!(!tick_data.needs_refresh(0, TICK_SPACING));
+
+        //Each tick bitmap word covers 256 tick spacings, so swaps from word 2 could move past the tracked words
+        assert!(tick_data.needs_refresh(2 * 256 * TICK_SPACING, TICK_SPACING));
+        assert!(tick_data.needs_refresh(-2 * 256 * TICK_SPACING, TICK_SPACING));
+    }
+
+    #[test]
+    fn test_update_from_log() {
+        let mut pool = pool();
+
+        let mint_log = Log {
+            topics: vec![
+                MintFilter::signature(),
+                H256::from(H160::zero()),
+                int24_topic(-60),
+                int24_topic(60),
+            ],
+            data: Bytes::from(encode(&[
+                Token::Address(H160::zero()),
+                Token::Uint(U256::from(500)),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+            ])),
+            ..Default::default()
+        };
+
+        pool.update_from_log(&mint_log).unwrap();
+        assert_eq!(pool.liquidity, 1_000_000_000_000_000_500);
+        assert_eq!(pool.tick_data.liquidity_net(-60), 500);
+        assert_eq!(pool.tick_data.liquidity_net(60), -500);
+
+        let sqrt_price = get_sqrt_ratio_at_tick(-900).unwrap();
+        let swap_log = Log {
+            topics: vec![
+                SwapFilter::signature(),
+                H256::from(H160::zero()),
+                H256::from(H160::zero()),
+            ],
+            data: Bytes::from(encode(&[
+                Token::Int(U256::zero()),
+                Token::Int(U256::zero()),
+                Token::Uint(sqrt_price),
+                Token::Uint(U256::from(2_000_000_000_000_000_000_u128)),
+                Token::Int(I256::from(-900).into_raw()),
+            ])),
+            ..Default::default()
+        };
+
+        pool.update_from_log(&swap_log).unwrap();
+        assert_eq!(pool.sqrt_price, sqrt_price);
+        assert_eq!(pool.liquidity, 2_000_000_000_000_000_000);
+        assert_eq!(pool.tick, -900);
+    }
+
+    //Cross checks the local simulation against the quoter, run with ETHEREUM_MAINNET_ENDPOINT set and --ignored
+    #[tokio::test]
+    #[ignore]
+    async fn test_simulate_swap_local_matches_quoter() {
+        let endpoint = std::env::var("ETHEREUM_MAINNET_ENDPOINT")
+            .expect("ETHEREUM_MAINNET_ENDPOINT is not set");
+        let middleware = Arc::new(Provider::<Http>::try_from(endpoint).unwrap());
+
+        //USDC/WETH 0.05%
+        let mut pool = UniswapV3Pool::new(
+            cfmms::pool::UniswapV3Pool::new_from_address(
+                H160::from_str("0x88e6A0c2dDD26FEEb64F039a2c41296FcB3f5640").unwrap(),
+                middleware.clone(),
+            )
+            .await
+            .unwrap(),
+        );
+        pool.populate_tick_data(middleware.clone()).await.unwrap();
+
+        let quoter = IUniswapV3Quoter::new(
+            H160::from_str("0xb27308f9F90D607463bb33eA1BeBb41C27CE5AB6").unwrap(),
+            middleware.clone(),
+        );
+
+        for (token_in, token_out, amount_in) in [
+            (pool.token_a, pool.token_b, U256::from(1_000_000_u128)),
+            (
+                pool.token_a,
+                pool.token_b,
+                U256::from(10_000_000_000_000_u128),
+            ),
+            (pool.token_b, pool.token_a, U256::exp10(18)),
+            (pool.token_b, pool.token_a, U256::exp10(21)),
+        ] {
+            let expected_amount_out = quoter
+                .quote_exact_input_single(token_in, token_out, pool.fee, amount_in, U256::zero())
+                .call()
+                .await
+                .unwrap();
+
+            assert_eq!(
+                pool.simulate_swap_local(token_in, amount_in).unwrap(),
+                expected_amount_out
+            );
+        }
+    }
+}
@@ -0,0 +1,25 @@
+use ethers::types::U256;
+
+//Gas estimates used when a swap is simulated locally
+pub const UNISWAP_V2_SWAP_GAS_ESTIMATE: u64 = 60_000;
+pub const UNISWAP_V3_SWAP_GAS_ESTIMATE: u64 = 80_000;
+pub const UNISWAP_V3_INITIALIZED_TICK_GAS_ESTIMATE: u64 = 31_000;
+pub const SOLIDLY_STABLE_SWAP_GAS_ESTIMATE: u64 = 110_000;
+pub const CURVE_SWAP_GAS_ESTIMATE: u64 = 150_000;
+pub const BALANCER_SWAP_GAS_ESTIMATE: u64 = 120_000;
+
+//Amount out and estimated gas of a single swap
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Quote {
+    pub amount_out: U256,
+    pub gas_estimate: U256,
+}
+
+impl Quote {
+    pub fn new(amount_out: U256, gas_estimate: U256) -> Quote {
+        Quote {
+            amount_out,
+            gas_estimate,
+        }
+    }
+}
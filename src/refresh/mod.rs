@@ -24,6 +24,11 @@ pub async fn check_orders_for_refresh<M: Middleware>(
 ) -> Result<(), ExecutorError<M>> {
     //TODO: make this async
     for (order_id, order) in state.active_orders.iter() {
+        //Orders on tokens that failed screening can not be executed, so they are not kept alive
+        if !state.order_tokens_are_tradable(order) {
+            continue;
+        }
+
         if block_timestamp - U256::from(order.last_refresh_timestamp()) >= THIRTY_DAYS_IN_SECONDS {
             let order_variant = match order {
                 Order::LimitOrder(_) => OrderVariant::LimitOrder,
@@ -0,0 +1,313 @@
+use std::collections::{HashMap, HashSet};
+
+use ethers::types::{H160, U256};
+
+use crate::markets::{self, Market};
+
+//Default max number of swaps in a route, ie. a -> usdc -> weth -> b
+pub const DEFAULT_MAX_HOPS: usize = 3;
+
+//Tokens that routes can pass through between the token in and token out (ie. weth, usdc, usdt) and the max number of swaps in a route
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Connectors {
+    pub tokens: Vec<H160>,
+    pub max_hops: usize,
+}
+
+impl Default for Connectors {
+    fn default() -> Connectors {
+        Connectors {
+            tokens: vec![],
+            max_hops: DEFAULT_MAX_HOPS,
+        }
+    }
+}
+
+impl Connectors {
+    pub fn new(tokens: Vec<H160>, max_hops: usize) -> Connectors {
+        Connectors { tokens, max_hops }
+    }
+
+    //Returns the pairs of every market that a route from the token in to the token out can swap through
+    pub fn market_pairs(&self, token_in: H160, token_out: H160) -> Vec<(H160, H160)> {
+        let mut pairs = vec![(token_in, token_out)];
+
+        if self.max_hops > 1 {
+            for connector in self.tokens.iter() {
+                pairs.push((token_in, *connector));
+                pairs.push((*connector, token_out));
+            }
+        }
+
+        //Routes with more than two swaps can also swap between connectors
+        if self.max_hops > 2 {
+            for (i, connector_a) in self.tokens.iter().enumerate() {
+                for connector_b in self.tokens.iter().skip(i + 1) {
+                    pairs.push((*connector_a, *connector_b));
+                }
+            }
+        }
+
+        let mut market_ids = HashSet::new();
+        pairs.retain(|(token_a, token_b)| {
+            token_a != token_b && market_ids.insert(markets::get_market_id(*token_a, *token_b))
+        });
+
+        pairs
+    }
+}
+
+//Graph of the token in, token out and connector tokens, where two tokens are connected if there is a tracked market between them
+#[derive(Debug)]
+pub struct RoutingGraph {
+    pub token_in: H160,
+    pub token_out: H160,
+    pub max_hops: usize,
+    pub edges: HashMap<H160, Vec<H160>>,
+}
+
+impl RoutingGraph {
+    pub fn new(
+        token_in: H160,
+        token_out: H160,
+        connectors: &Connectors,
+        markets: &HashMap<U256, Market>,
+    ) -> RoutingGraph {
+        let mut edges: HashMap<H160, Vec<H160>> = HashMap::new();
+
+        for (token_a, token_b) in connectors.market_pairs(token_in, token_out) {
+            if markets.contains_key(&markets::get_market_id(token_a, token_b)) {
+                edges.entry(token_a).or_default().push(token_b);
+                edges.entry(token_b).or_default().push(token_a);
+            }
+        }
+
+        RoutingGraph {
+            token_in,
+            token_out,
+            max_hops: connectors.max_hops,
+            edges,
+        }
+    }
+
+    //Returns every path from the token in to the token out with at most max hops swaps, that does not pass through the same token twice
+    pub fn paths(&self) -> Vec<Vec<H160>> {
+        let mut paths = vec![];
+
+        if self.token_in != self.token_out {
+            self.extend_paths(&mut vec![self.token_in], &mut paths);
+        }
+
+        paths
+    }
+
+    fn extend_paths(&self, path: &mut Vec<H160>, paths: &mut Vec<Vec<H160>>) {
+        //Paths have one more token than swaps
+        if path.len() > self.max_hops {
+            return;
+        }
+
+        let token = *path.last().unwrap();
+        if let Some(neighbors) = self.edges.get(&token) {
+            for neighbor in neighbors {
+                if *neighbor == self.token_out {
+                    let mut completed_path = path.clone();
+                    completed_path.push(*neighbor);
+                    paths.push(completed_path);
+                } else if !path.contains(neighbor) {
+                    path.push(*neighbor);
+                    self.extend_paths(path, paths);
+                    path.pop();
+                }
+            }
+        }
+    }
+}
+
+//Returns the markets along the path, or None if a market in the path is not tracked
+pub fn get_markets_along_path<'a>(
+    path: &[H160],
+    markets: &'a HashMap<U256, Market>,
+) -> Option<Vec<&'a Market>> {
+    path.windows(2)
+        .map(|pair| markets.get(&markets::get_market_id(pair[0], pair[1])))
+        .collect()
+}
+
+//Returns the best amount out across every path from the token in to the token out, simulated with local pool state only.
+//Returns None if there is no path or no path can be simulated.
+pub fn get_best_path_amount_out(
+    token_in: H160,
+    token_out: H160,
+    amount_in: U256,
+    connectors: &Connectors,
+    markets: &HashMap<U256, Market>,
+) -> Option<U256> {
+    if token_in == token_out {
+        return Some(amount_in);
+    }
+
+    RoutingGraph::new(token_in, token_out, connectors, markets)
+        .paths()
+        .iter()
+        .filter_map(|path| {
+            path.windows(2).try_fold(amount_in, |amount_in, pair| {
+                markets::get_best_market_amount_out(pair[0], pair[1], amount_in, markets)
+            })
+        })
+        .max()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ethers::types::{H160, U256};
+
+    use crate::{
+        markets::{self, Market},
+        pool::{Pool, UniswapV2Pool},
+    };
+
+    use super::{get_best_path_amount_out, Connectors, RoutingGraph};
+
+    fn token(id: u64) -> H160 {
+        H160::from_low_u64_be(id)
+    }
+
+    fn weth() -> H160 {
+        token(1)
+    }
+
+    fn usdc() -> H160 {
+        token(2)
+    }
+
+    fn usdt() -> H160 {
+        token(3)
+    }
+
+    fn token_in() -> H160 {
+        token(4)
+    }
+
+    fn token_out() -> H160 {
+        token(5)
+    }
+
+    fn add_market(
+        markets: &mut HashMap<U256, Market>,
+        token_a: H160,
+        token_b: H160,
+        reserve_0: u128,
+        reserve_1: u128,
+    ) {
+        let address = H160::from_low_u64_be(markets.len() as u64 + 100);
+        let pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address,
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+        }));
+
+        markets.insert(
+            markets::get_market_id(token_a, token_b),
+            HashMap::from([(address, pool)]),
+        );
+    }
+
+    //token_in is only paired with usdc, and token_out is only paired with usdt
+    fn markets() -> HashMap<U256, Market> {
+        let mut markets = HashMap::new();
+        add_market(&mut markets, token_in(), usdc(), 1_000_000, 1_000_000);
+        add_market(&mut markets, usdc(), usdt(), 1_000_000, 1_000_000);
+        add_market(&mut markets, usdc(), weth(), 1_000_000, 1_000_000);
+        add_market(&mut markets, weth(), usdt(), 1_000_000, 1_000_000);
+        add_market(&mut markets, usdt(), token_out(), 1_000_000, 1_000_000);
+        markets
+    }
+
+    fn connectors(max_hops: usize) -> Connectors {
+        Connectors::new(vec![weth(), usdc(), usdt()], max_hops)
+    }
+
+    #[test]
+    fn test_market_pairs() {
+        assert_eq!(
+            connectors(1).market_pairs(token_in(), token_out()),
+            vec![(token_in(), token_out())]
+        );
+        //a -> b, a -> connector and connector -> b
+        assert_eq!(connectors(2).market_pairs(token_in(), token_out()).len(), 7);
+        //Pairs between connectors are added once, and pairs of the same token are skipped
+        assert_eq!(connectors(3).market_pairs(weth(), token_out()).len(), 6);
+    }
+
+    #[test]
+    fn test_paths() {
+        let markets = markets();
+
+        assert!(
+            RoutingGraph::new(token_in(), token_out(), &connectors(2), &markets)
+                .paths()
+                .is_empty()
+        );
+
+        assert_eq!(
+            RoutingGraph::new(token_in(), token_out(), &connectors(3), &markets).paths(),
+            vec![vec![token_in(), usdc(), usdt(), token_out()]]
+        );
+
+        let mut paths =
+            RoutingGraph::new(token_in(), token_out(), &connectors(4), &markets).paths();
+        paths.sort();
+        assert_eq!(
+            paths,
+            vec![
+                vec![token_in(), usdc(), weth(), usdt(), token_out()],
+                vec![token_in(), usdc(), usdt(), token_out()],
+            ]
+        );
+
+        assert!(
+            RoutingGraph::new(token_in(), token_in(), &connectors(3), &markets)
+                .paths()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn test_get_best_path_amount_out() {
+        let markets = markets();
+        let amount_in = U256::from(1000);
+
+        assert_eq!(
+            get_best_path_amount_out(token_in(), token_out(), amount_in, &connectors(2), &markets),
+            None
+        );
+
+        //The direct usdc -> usdt hop loses less to fees and price impact than usdc -> weth -> usdt
+        let expected_amount_out = [
+            (token_in(), usdc()),
+            (usdc(), usdt()),
+            (usdt(), token_out()),
+        ]
+        .iter()
+        .try_fold(amount_in, |amount_in, (token_a, token_b)| {
+            markets::get_best_market_amount_out(*token_a, *token_b, amount_in, &markets)
+        });
+        assert_eq!(
+            get_best_path_amount_out(token_in(), token_out(), amount_in, &connectors(4), &markets),
+            expected_amount_out
+        );
+
+        assert_eq!(
+            get_best_path_amount_out(token_in(), token_in(), amount_in, &connectors(3), &markets),
+            Some(amount_in)
+        );
+    }
+}
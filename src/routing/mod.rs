@@ -1,350 +1,1059 @@
-use std::{collections::HashMap, sync::Arc};
+pub mod graph;
+pub mod prefetch;
+pub mod split;
+pub mod tax;
 
-use cfmms::pool::{Pool, UniswapV2Pool};
-use ethers::{
-    providers::Middleware,
-    types::{H160, U256},
-};
-use futures::future::join_all;
+use std::collections::HashMap;
+
+use ethers::types::{H160, U256};
+use thiserror::Error;
 
 use crate::{
-    abi::IUniswapV3Quoter,
-    error::ExecutorError,
     markets::{self, Market},
+    pool::{AutomatedMarketMaker, Pool, UniswapV2Pool},
+    quoter::Quote,
 };
 
-use crate::order::sandbox_limit_order::SandboxLimitOrder;
+use crate::order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder};
 
-pub async fn find_best_a_to_x_to_b_route<M: 'static + Middleware>(
+use self::{
+    graph::{Connectors, RoutingGraph},
+    split::SplitHops,
+    tax::TransferTaxes,
+};
+
+//Routing only reads the market snapshot, so the on chain data that it needs is fetched beforehand by the prefetch layer
+#[derive(Error, Debug)]
+pub enum RoutingError {
+    #[error("Market does not exist for pair")]
+    MarketDoesNotExistForPair(H160, H160),
+    #[error("Pool does not exist in market")]
+    PoolDoesNotExist(H160),
+    #[error("Could not simulate swap through pool")]
+    PoolSimulationError(H160),
+    #[error("Route amount out is less than the amount due to the order owner")]
+    InsufficientAmountOut(U256, U256),
+}
+
+//Returns the route with the best amount out across every path from the token in to the token out through the connector tokens
+#[allow(clippy::too_many_arguments)]
+pub fn find_best_route(
     token_in: H160,
-    x_token: H160,
     token_out: H160,
     amount_in: U256,
+    connectors: &Connectors,
     simulated_markets: &HashMap<U256, HashMap<H160, Pool>>,
-    middleware: Arc<M>,
-) -> Result<(Vec<U256>, Vec<U256>, Vec<Pool>), ExecutorError<M>> {
-    let markets_in_route: Vec<&Market> = {
-        // Simulate order along route for token_a -> weth -> token_b
-        let a_to_x_market = simulated_markets.get(&markets::get_market_id(token_in, x_token));
-        let x_to_b_market = simulated_markets.get(&markets::get_market_id(x_token, token_out));
-        if a_to_x_market.is_some() && x_to_b_market.is_some() {
-            let a_to_x_market = a_to_x_market.unwrap();
-            let x_to_b_market = x_to_b_market.unwrap();
-
-            vec![a_to_x_market, x_to_b_market]
-        } else if a_to_x_market.is_none() {
-            return Err(ExecutorError::MarketDoesNotExistForPair(token_in, x_token));
-        } else {
-            //x to b market is none
-            return Err(ExecutorError::MarketDoesNotExistForPair(x_token, token_out));
+    weth: H160,
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> Result<RouteSimulation, RoutingError> {
+    let mut best_route: Option<RouteSimulation> = None;
+
+    for path in RoutingGraph::new(token_in, token_out, connectors, simulated_markets).paths() {
+        let markets_in_route = match graph::get_markets_along_path(&path, simulated_markets) {
+            Some(markets_in_route) => markets_in_route,
+            None => continue,
+        };
+
+        //Sandbox limit orders can swap through any number of pools, so the amount in of each hop is split across the pools in the market
+        let route = find_best_route_across_markets(
+            amount_in,
+            token_in,
+            markets_in_route,
+            Some(SplitHops::new(weth, gas_price, simulated_markets)),
+            transfer_taxes,
+        );
+
+        let is_better_route = match &best_route {
+            Some(best_route) => route.1.last() > best_route.1.last(),
+            None => true,
+        };
+
+        if is_better_route {
+            best_route = Some(route);
         }
-    };
+    }
 
-    find_best_route_across_markets(amount_in, token_in, markets_in_route, middleware.clone()).await
+    best_route.ok_or(RoutingError::MarketDoesNotExistForPair(token_in, token_out))
 }
 
-pub async fn find_best_a_to_b_route<M: 'static + Middleware>(
+pub fn find_best_a_to_b_route(
     token_in: H160,
     token_out: H160,
     amount_in: U256,
-    simulated_markets: &mut HashMap<U256, HashMap<H160, Pool>>,
-    middleware: Arc<M>,
-) -> Result<(Vec<U256>, Vec<U256>, Vec<Pool>), ExecutorError<M>> {
-    // Simulate order along route for token_a -> weth -> token_b
-    if let Some(a_to_b_market) = simulated_markets.get(&markets::get_market_id(token_in, token_out))
-    {
-        Ok(find_best_route_across_markets(
+    simulated_markets: &HashMap<U256, HashMap<H160, Pool>>,
+    transfer_taxes: &TransferTaxes,
+) -> Result<RouteSimulation, RoutingError> {
+    let a_to_b_market = simulated_markets
+        .get(&markets::get_market_id(token_in, token_out))
+        .ok_or(RoutingError::MarketDoesNotExistForPair(token_in, token_out))?;
+
+    Ok(find_best_route_across_markets(
+        amount_in,
+        token_in,
+        vec![a_to_b_market],
+        None,
+        transfer_taxes,
+    ))
+}
+
+//Returns the route that the LimitOrderRouter swaps the order through, token in to weth through the pools of the fee in and weth to token out through the pools of the fee out.
+//Orders from or to weth only take a single hop, and the amount in of each hop is never split since the router swaps through a single pool
+pub fn find_limit_order_route(
+    order: &LimitOrder,
+    amount_in: U256,
+    weth: H160,
+    router_dexes: &[H160],
+    simulated_markets: &HashMap<U256, Market>,
+    transfer_taxes: &TransferTaxes,
+) -> Result<RouteSimulation, RoutingError> {
+    let limit_order_markets = get_limit_order_markets(order, weth, router_dexes, simulated_markets);
+
+    //The router swaps each hop through the pool with the best spot price, like `LimitOrder::get_best_market_price`. The price of the route is the product
+    //of the hop prices, so the best price of each hop gives the pool pair with the best combined price. Only those pools are simulated
+    let mut markets_in_route: Vec<Market> = vec![];
+    for (token_a, token_b, _) in get_limit_order_hops(order, weth) {
+        let (_, pool) =
+            markets::get_best_market_price_pool(order.buy, token_b, token_a, &limit_order_markets)
+                .ok_or(RoutingError::MarketDoesNotExistForPair(token_a, token_b))?;
+
+        markets_in_route.push(HashMap::from([(pool.address(), pool.clone())]));
+    }
+
+    Ok(find_best_route_across_markets(
+        amount_in,
+        order.token_in,
+        markets_in_route.iter().collect(),
+        None,
+        transfer_taxes,
+    ))
+}
+
+//Returns the markets of each hop of the order with only the pools that the LimitOrderRouter can swap through.
+//Markets without any of those pools are left out, so the order can not be routed or priced through them
+pub fn get_limit_order_markets(
+    order: &LimitOrder,
+    weth: H160,
+    router_dexes: &[H160],
+    simulated_markets: &HashMap<U256, Market>,
+) -> HashMap<U256, Market> {
+    let mut limit_order_markets = HashMap::new();
+
+    for (token_a, token_b, fee) in get_limit_order_hops(order, weth) {
+        let market_id = markets::get_market_id(token_a, token_b);
+
+        if let Some(market) = simulated_markets.get(&market_id) {
+            let market = market
+                .iter()
+                .filter(|(_, pool)| is_limit_order_router_pool(pool, fee, router_dexes))
+                .map(|(address, pool)| (*address, pool.clone()))
+                .collect::<Market>();
+
+            if !market.is_empty() {
+                limit_order_markets.insert(market_id, market);
+            }
+        }
+    }
+
+    limit_order_markets
+}
+
+//Token in, token out and fee tier of each hop that the LimitOrderRouter swaps the order through
+fn get_limit_order_hops(order: &LimitOrder, weth: H160) -> Vec<(H160, H160, u32)> {
+    let mut hops = vec![];
+
+    if order.token_in != weth {
+        hops.push((order.token_in, weth, order.fee_in));
+    }
+
+    if order.token_out != weth {
+        hops.push((weth, order.token_out, order.fee_out));
+    }
+
+    hops
+}
+
+//The LimitOrderRouter swaps through the UniswapV2 pairs of the dexes it is deployed with, which have a single pool for each pair,
+//and the UniswapV3 pool of those dexes with the fee tier of the hop
+fn is_limit_order_router_pool(pool: &Pool, fee: u32, router_dexes: &[H160]) -> bool {
+    match pool {
+        Pool::UniswapV2(uniswap_v2_pool) => router_dexes.contains(&uniswap_v2_pool.factory_address),
+        Pool::UniswapV3(uniswap_v3_pool) => {
+            uniswap_v3_pool.fee() == fee && router_dexes.contains(&uniswap_v3_pool.factory_address)
+        }
+        _ => false,
+    }
+}
+
+//Swap of part or all of the amount in of a hop through a single pool
+#[derive(Debug, Clone)]
+pub struct Swap {
+    pub pool: Pool,
+    pub amount_in: U256,
+    pub amount_out: U256,
+}
+
+impl Swap {
+    pub fn new(pool: Pool, amount_in: U256, amount_out: U256) -> Swap {
+        Swap {
+            pool,
             amount_in,
-            token_in,
-            vec![a_to_b_market],
-            middleware.clone(),
-        )
-        .await?)
-    } else {
-        Ok((
-            vec![U256::zero()],
-            vec![U256::zero()],
-            vec![Pool::UniswapV2(UniswapV2Pool::default())],
-        ))
+            amount_out,
+        }
     }
 }
 
-pub const V3_QUOTER_ADDRESS: H160 = H160([
-    178, 115, 8, 249, 249, 13, 96, 116, 99, 187, 51, 234, 27, 235, 180, 28, 39, 206, 90, 182,
-]);
+//Hop of a route from one token to the next, with the amount in split across one or more pools in the market
+#[derive(Debug, Clone)]
+pub struct Hop {
+    pub swaps: Vec<Swap>,
+}
+
+impl Hop {
+    pub fn new(swaps: Vec<Swap>) -> Hop {
+        Hop { swaps }
+    }
+
+    pub fn single(pool: Pool, amount_in: U256, amount_out: U256) -> Hop {
+        Hop::new(vec![Swap::new(pool, amount_in, amount_out)])
+    }
 
-//Returns the amounts in, amount out and a reference to the pools that it took through the route
-pub async fn find_best_route_across_markets<M: 'static + Middleware>(
+    pub fn is_split(&self) -> bool {
+        self.swaps.len() > 1
+    }
+
+    pub fn amount_out(&self) -> U256 {
+        self.swaps.iter().fold(U256::zero(), |amount_out, swap| {
+            amount_out + swap.amount_out
+        })
+    }
+
+    //Returns the pool that the amount in is sent to directly, which is only possible when the hop swaps through a single pool that is paid before the swap.
+    //The amount in of split hops is held by the sandbox router, which pays each pool separately
+    pub fn transfer_before_swap_pool(&self) -> Option<&Pool> {
+        match self.swaps.as_slice() {
+            [swap] if swap.pool.transfer_before_swap() => Some(&swap.pool),
+            _ => None,
+        }
+    }
+}
+
+//Amounts in, amounts out, the hops that the route takes and the estimated gas to swap through the route
+pub type RouteSimulation = (Vec<U256>, Vec<U256>, Vec<Hop>, U256);
+
+//Returns the amounts in, amount out, the hops that it took through the route and the estimated gas of the route.
+//When split_hops is set, the amount in of a hop is split across the pools in the market if that swaps to more than the best single pool after paying for the extra gas.
+//Fee on transfer tokens are taxed when the amount in is sent to the first pool and when the amount out of each hop is received, so the amounts out are the amounts received
+pub fn find_best_route_across_markets(
     amount_in: U256,
     mut token_in: H160,
     markets: Vec<&Market>,
-    middleware: Arc<M>,
-) -> Result<(Vec<U256>, Vec<U256>, Vec<Pool>), ExecutorError<M>> {
+    split_hops: Option<SplitHops>,
+    transfer_taxes: &TransferTaxes,
+) -> RouteSimulation {
     let mut amount_in = amount_in;
     let mut amounts_in: Vec<U256> = vec![];
     let mut amounts_out: Vec<U256> = vec![];
-    let mut route: Vec<Pool> = vec![];
+    let mut route: Vec<Hop> = vec![];
+    let mut gas_estimate = U256::zero();
 
     for market in markets {
-        //TODO: apply tax to amount in
-        let mut best_amount_out = U256::zero();
-        let mut best_pool = Pool::UniswapV2(UniswapV2Pool::default());
-
         amounts_in.push(amount_in);
 
-        let mut handles = vec![];
-
-        for pool in market.values() {
-            let pool = *pool;
-            match pool {
-                Pool::UniswapV2(_) => {
-                    let swap_amount_out = pool
-                        .simulate_swap(token_in, amount_in, middleware.clone())
-                        .await?;
-                    if swap_amount_out > best_amount_out {
-                        best_amount_out = swap_amount_out;
-                        best_pool = pool;
-                    }
-                }
+        //The amount out of the previous hop was already taxed when it was received
+        let pool_amount_in = if route.is_empty() {
+            transfer_taxes.apply(token_in, amount_in)
+        } else {
+            amount_in
+        };
 
-                Pool::UniswapV3(uniswap_v3_pool) => {
-                    let uniswap_v3_quoter =
-                        IUniswapV3Quoter::new(V3_QUOTER_ADDRESS, middleware.clone());
-
-                    let (token_in, token_out) = if token_in == uniswap_v3_pool.token_a {
-                        (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b)
-                    } else {
-                        (uniswap_v3_pool.token_b, uniswap_v3_pool.token_a)
-                    };
-
-                    handles.push(tokio::spawn(async move {
-                        let swap_amount_out = uniswap_v3_quoter
-                            .quote_exact_input_single(
-                                token_in,
-                                token_out,
-                                pool.fee(),
-                                amount_in,
-                                U256::zero(),
-                            )
-                            .call()
-                            .await?;
-                        Result::<(U256, Pool), ExecutorError<M>>::Ok((swap_amount_out, pool))
-                    }))
-                }
-            };
-        }
+        let (best_quote, best_pool) = find_best_pool_in_market(token_in, pool_amount_in, market);
+
+        let mut hop = Hop::single(best_pool, pool_amount_in, best_quote.amount_out);
+        let mut hop_gas_estimate = best_quote.gas_estimate;
 
-        for join_result in join_all(handles).await {
-            match join_result {
-                Ok(ok) => {
-                    if let Ok((swap_amount_out, pool)) = ok {
-                        if swap_amount_out > best_amount_out {
-                            best_amount_out = swap_amount_out;
-                            best_pool = pool;
-                        }
-                    }
+        //Get the token out from the market, we can use any pool in the market since the token out and token in for each pool in the market are the same.
+        // Have the same token in and out to be in the same market.
+        let token_out = market.values().next().unwrap().token_out(token_in);
+
+        if let Some(split_hops) = split_hops {
+            if let Some((split_hop, split_gas_estimate)) =
+                split::find_best_split(token_in, pool_amount_in, market)
+            {
+                //The split hop swaps through more pools than the best pool, so its extra amount out has to pay for the extra gas
+                let extra_gas_cost = split_hops.gas_cost(
+                    token_out,
+                    split_gas_estimate.saturating_sub(best_quote.gas_estimate),
+                );
+
+                if split_hop.is_split()
+                    && extra_gas_cost.is_some_and(|extra_gas_cost| {
+                        split_hop.amount_out()
+                            > best_quote.amount_out.saturating_add(extra_gas_cost)
+                    })
+                {
+                    hop = split_hop;
+                    hop_gas_estimate = split_gas_estimate;
                 }
-                Err(_err) => {}
             }
         }
 
-        amount_in = best_amount_out;
-        amounts_out.push(best_amount_out);
-        route.push(best_pool);
-
         //update token in
-        //Get the token out from the market to set as the new token in, we can use any pool in the market since the token out and token in for each pool in the market are the same.
-        // Have the same token in and out to be in the same market.
-        token_in = match market.values().next().unwrap() {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                if uniswap_v2_pool.token_a == token_in {
-                    uniswap_v2_pool.token_b
-                } else {
-                    uniswap_v2_pool.token_a
-                }
-            }
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                if uniswap_v3_pool.token_a == token_in {
-                    uniswap_v3_pool.token_b
-                } else {
-                    uniswap_v3_pool.token_a
-                }
+        token_in = token_out;
+
+        amount_in = transfer_taxes.apply(token_in, hop.amount_out());
+        amounts_out.push(amount_in);
+        route.push(hop);
+        gas_estimate += hop_gas_estimate;
+    }
+
+    (amounts_in, amounts_out, route, gas_estimate)
+}
+
+//Returns the quote and the pool with the best amount out in the market, or a default quote if no pool could be quoted.
+//Pools are quoted against the local pool state, UniswapV3 and Algebra swaps that move past the prefetched ticks are skipped
+fn find_best_pool_in_market(token_in: H160, amount_in: U256, market: &Market) -> (Quote, Pool) {
+    let mut best_quote = Quote::default();
+    let mut best_pool = Pool::UniswapV2(UniswapV2Pool::default());
+
+    for pool in market.values() {
+        if let Some(quote) = pool.quote_local(token_in, amount_in) {
+            if quote.amount_out > best_quote.amount_out {
+                best_quote = quote;
+                best_pool = pool.clone();
             }
-        };
+        }
     }
 
-    Ok((amounts_in, amounts_out, route))
+    (best_quote, best_pool)
 }
 
-//Returns the weth exit amount in, weth amount out and the weth pool
-pub async fn find_best_weth_exit_from_route<M: 'static + Middleware>(
+//Returns the weth exit amount in, weth amount out, the weth pool and the estimated gas of the weth exit when the fill amount of the order is swapped along the route
+pub fn find_best_weth_exit_from_route(
     order: &SandboxLimitOrder,
+    fill_amount: U256,
     amount_due_to_owner: U256,
-    route: Vec<Pool>,
+    route: Vec<Hop>,
     markets: &mut HashMap<U256, Market>,
     weth: H160,
-    middleware: Arc<M>,
-) -> Result<(U256, U256, Pool), ExecutorError<M>> {
-    let mut swap_token = order.token_in;
-    let mut swap_amount = U256::from(order.amount_in_remaining);
+    transfer_taxes: &TransferTaxes,
+) -> Result<(U256, U256, Pool, U256), RoutingError> {
     //We need to clone because we are checking the weth amount exit after the route is completed
     let mut markets = markets.clone();
 
-    for pool in route {
-        let (token_in, token_out) = match pool {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                if swap_token == uniswap_v2_pool.token_a {
-                    (uniswap_v2_pool.token_a, uniswap_v2_pool.token_b)
-                } else {
-                    (uniswap_v2_pool.token_b, uniswap_v2_pool.token_a)
-                }
-            }
-
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                if swap_token == uniswap_v3_pool.token_a {
-                    (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b)
-                } else {
-                    (uniswap_v3_pool.token_b, uniswap_v3_pool.token_a)
-                }
-            }
-        };
-
-        let market_id = markets::get_market_id(token_in, token_out);
-
-        //TODO: handle errors gracefully
-        //simulate the swap and update the swap amount
-        swap_amount = markets
-            .get_mut(&market_id)
-            .unwrap()
-            .get_mut(&pool.address())
-            .unwrap()
-            .simulate_swap_mut(token_in, swap_amount, middleware.clone())
-            .await?;
+    //simulate the swaps and update the swap amount
+    let swap_amount = swap_along_route(
+        order.token_in,
+        fill_amount,
+        &route,
+        &mut markets,
+        transfer_taxes,
+    )?;
 
-        //update token in
-        swap_token = token_out;
-    }
+    //The amount left after paying the owner is swapped to weth
+    let amount_in_to_weth_exit =
+        swap_amount
+            .checked_sub(amount_due_to_owner)
+            .ok_or(RoutingError::InsufficientAmountOut(
+                swap_amount,
+                amount_due_to_owner,
+            ))?;
 
     //Find best token out to weth pool
-    let (_, amounts_out, route) = find_best_a_to_b_route(
+    let (_, amounts_out, route, gas_estimate) = find_best_a_to_b_route(
         order.token_out,
         weth,
-        swap_amount - amount_due_to_owner,
-        &mut markets,
-        middleware.clone(),
-    )
-    .await?;
+        amount_in_to_weth_exit,
+        &markets,
+        transfer_taxes,
+    )?;
 
     Ok((
-        swap_amount - amount_due_to_owner,
+        amount_in_to_weth_exit,
         *amounts_out.last().unwrap(),
-        *route.last().unwrap(),
+        route.last().unwrap().swaps[0].pool.clone(),
+        gas_estimate,
     ))
 }
 
-pub async fn update_pools_along_route<M: Middleware>(
-    mut token_in: H160,
+//Simulates the swap against the pool in the markets and updates the pool state, returning the amount out
+fn swap_through_pool(
+    token_in: H160,
     amount_in: U256,
+    pool: &Pool,
     markets: &mut HashMap<U256, Market>,
-    route: Vec<Pool>,
-    middleware: Arc<M>,
-) -> Result<(), ExecutorError<M>> {
-    let mut amount_in = amount_in;
+) -> Result<U256, RoutingError> {
+    let token_out = pool.token_out(token_in);
 
-    for pool_in_route in route {
-        let (pool_token_in, pool_token_out) = match pool_in_route {
-            Pool::UniswapV2(uniswap_v2_pool) => (uniswap_v2_pool.token_a, uniswap_v2_pool.token_b),
+    markets
+        .get_mut(&markets::get_market_id(token_in, token_out))
+        .ok_or(RoutingError::MarketDoesNotExistForPair(token_in, token_out))?
+        .get_mut(&pool.address())
+        .ok_or(RoutingError::PoolDoesNotExist(pool.address()))?
+        .simulate_swap_local_mut(token_in, amount_in)
+        .ok_or(RoutingError::PoolSimulationError(pool.address()))
+}
 
-            Pool::UniswapV3(uniswap_v3_pool) => (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b),
-        };
+//Simulates the swaps of each hop against the markets and returns the amount received from the route.
+//Pools in a split hop swap the amount in they were allocated, pools in a single pool hop swap the amount out of the previous hop
+fn swap_along_route(
+    mut token_in: H160,
+    amount_in: U256,
+    route: &[Hop],
+    markets: &mut HashMap<U256, Market>,
+    transfer_taxes: &TransferTaxes,
+) -> Result<U256, RoutingError> {
+    let mut amount_in = transfer_taxes.apply(token_in, amount_in);
 
-        let market_id = markets::get_market_id(pool_token_in, pool_token_out);
-        let pool_in_market = markets
-            .get_mut(&market_id)
-            .unwrap()
-            .get_mut(&pool_in_route.address())
-            .unwrap();
+    for hop in route {
+        let mut amount_out = U256::zero();
+
+        for swap in hop.swaps.iter() {
+            let swap_amount_in = if hop.is_split() {
+                swap.amount_in
+            } else {
+                amount_in
+            };
 
-        amount_in = pool_in_market
-            .simulate_swap_mut(token_in, amount_in, middleware.clone())
-            .await?;
+            amount_out += swap_through_pool(token_in, swap_amount_in, &swap.pool, markets)?;
+        }
 
         //update token in
-        token_in = match pool_in_market {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                if uniswap_v2_pool.token_a == token_in {
-                    uniswap_v2_pool.token_b
-                } else {
-                    uniswap_v2_pool.token_a
-                }
-            }
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                if uniswap_v3_pool.token_a == token_in {
-                    uniswap_v3_pool.token_b
-                } else {
-                    uniswap_v3_pool.token_a
-                }
-            }
-        };
+        token_in = hop.swaps[0].pool.token_out(token_in);
+        amount_in = transfer_taxes.apply(token_in, amount_out);
     }
 
-    Ok(())
+    Ok(amount_in)
+}
+
+//Returns the markets of the pools as they are before the pools are swapped through, so that they can be put back if a swap fails after the pools before it were updated
+fn markets_of_pools<'a>(
+    pools: impl Iterator<Item = &'a Pool>,
+    markets: &HashMap<U256, Market>,
+) -> HashMap<U256, Market> {
+    pools
+        .filter_map(|pool| {
+            let (token_a, token_b) = pool.tokens();
+            let market_id = markets::get_market_id(token_a, token_b);
+
+            Some((market_id, markets.get(&market_id)?.clone()))
+        })
+        .collect()
+}
+
+fn route_pools(route: &[Hop]) -> impl Iterator<Item = &Pool> {
+    route
+        .iter()
+        .flat_map(|hop| hop.swaps.iter().map(|swap| &swap.pool))
 }
 
-//TODO: take a look at this and update as needed
-//TODO: handle errors gracefully
+//Updates the pools along the route in place. If a swap along the route fails, the markets are left unchanged so that the order can be skipped
+pub fn update_pools_along_route(
+    token_in: H160,
+    amount_in: U256,
+    markets: &mut HashMap<U256, Market>,
+    route: Vec<Hop>,
+    transfer_taxes: &TransferTaxes,
+) -> Result<(), RoutingError> {
+    let original_markets = markets_of_pools(route_pools(&route), markets);
+
+    if let Err(err) = swap_along_route(token_in, amount_in, &route, markets, transfer_taxes) {
+        markets.extend(original_markets);
+        return Err(err);
+    }
+
+    Ok(())
+}
 
-pub async fn update_pools_along_route_with_weth_exit<M: Middleware>(
+pub fn update_pools_along_route_with_weth_exit(
     order: &SandboxLimitOrder,
+    fill_amount: U256,
     amount_in_to_weth_exit: U256,
-    route: Vec<Pool>,
+    route: Vec<Hop>,
     markets: &mut HashMap<U256, Market>,
-    weth: H160,
-    weth_exit_address: H160,
-    middleware: Arc<M>,
-) -> Result<(), ExecutorError<M>> {
-    let mut swap_token = order.token_in;
-    let mut swap_amount = U256::from(order.amount_in_remaining);
-
-    for pool in route {
-        let (token_in, token_out) = match pool {
-            Pool::UniswapV2(uniswap_v2_pool) => {
-                if swap_token == uniswap_v2_pool.token_a {
-                    (uniswap_v2_pool.token_a, uniswap_v2_pool.token_b)
-                } else {
-                    (uniswap_v2_pool.token_b, uniswap_v2_pool.token_a)
-                }
-            }
-            Pool::UniswapV3(uniswap_v3_pool) => {
-                if swap_token == uniswap_v3_pool.token_a {
-                    (uniswap_v3_pool.token_a, uniswap_v3_pool.token_b)
-                } else {
-                    (uniswap_v3_pool.token_b, uniswap_v3_pool.token_a)
-                }
-            }
+    weth_exit_pool: &Pool,
+    transfer_taxes: &TransferTaxes,
+) -> Result<(), RoutingError> {
+    let original_markets = markets_of_pools(
+        route_pools(&route).chain(std::iter::once(weth_exit_pool)),
+        markets,
+    );
+
+    if let Err(err) = swap_along_route(order.token_in, fill_amount, &route, markets, transfer_taxes)
+        .and_then(|_| {
+            swap_through_pool(
+                order.token_out,
+                transfer_taxes.apply(order.token_out, amount_in_to_weth_exit),
+                weth_exit_pool,
+                markets,
+            )
+        })
+    {
+        markets.extend(original_markets);
+        return Err(err);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ethers::types::{H160, H256, U256};
+
+    use crate::{
+        markets::{self, Market},
+        order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder},
+        pool::{AutomatedMarketMaker, Pool, UniswapV2Pool, UniswapV3Pool},
+    };
+
+    use super::{
+        find_best_a_to_b_route, find_best_route, find_best_weth_exit_from_route,
+        find_limit_order_route, get_limit_order_markets, graph::Connectors, split,
+        tax::TransferTaxes, update_pools_along_route, RoutingError,
+    };
+
+    fn token(id: u64) -> H160 {
+        H160::from_low_u64_be(id)
+    }
+
+    fn weth() -> H160 {
+        token(1)
+    }
+
+    fn token_in() -> H160 {
+        token(2)
+    }
+
+    fn token_out() -> H160 {
+        token(3)
+    }
+
+    //Pools built in tests are not discovered through a factory, so the router is deployed with the zero address
+    fn router_dexes() -> Vec<H160> {
+        vec![H160::zero()]
+    }
+
+    fn add_market(
+        markets: &mut HashMap<U256, Market>,
+        token_a: H160,
+        token_b: H160,
+        reserve_0: u128,
+        reserve_1: u128,
+    ) {
+        let address = H160::from_low_u64_be(markets.len() as u64 + 100);
+        let pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address,
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+        }));
+
+        markets.insert(
+            markets::get_market_id(token_a, token_b),
+            HashMap::from([(address, pool)]),
+        );
+    }
+
+    //token_in and token_out are only paired with weth
+    fn markets() -> HashMap<U256, Market> {
+        let mut markets = HashMap::new();
+        add_market(
+            &mut markets,
+            token_in(),
+            weth(),
+            1_000_000_000,
+            1_000_000_000,
+        );
+        add_market(
+            &mut markets,
+            weth(),
+            token_out(),
+            1_000_000_000,
+            1_000_000_000,
+        );
+        markets
+    }
+
+    fn add_v3_pool(markets: &mut HashMap<U256, Market>, token_a: H160, token_b: H160, fee: u32) {
+        let address = H160::from_low_u64_be(fee as u64);
+        let pool = Pool::UniswapV3(UniswapV3Pool::new(cfmms::pool::UniswapV3Pool {
+            address,
+            token_a,
+            token_a_decimals: 18,
+            token_b,
+            token_b_decimals: 18,
+            fee,
+            ..Default::default()
+        }));
+
+        markets
+            .entry(markets::get_market_id(token_a, token_b))
+            .or_default()
+            .insert(address, pool);
+    }
+
+    fn limit_order(token_in: H160, token_out: H160, fee_in: u32, fee_out: u32) -> LimitOrder {
+        LimitOrder::new(
+            false,
+            false,
+            false,
+            0,
+            u32::MAX,
+            fee_in,
+            fee_out,
+            0,
+            0,
+            0,
+            1_000_000,
+            0,
+            H160::zero(),
+            token_in,
+            token_out,
+            H256::zero(),
+        )
+    }
+
+    #[test]
+    fn test_get_limit_order_markets() {
+        let mut markets = markets();
+        add_v3_pool(&mut markets, token_in(), weth(), 500);
+        add_v3_pool(&mut markets, token_in(), weth(), 3000);
+        add_v3_pool(&mut markets, weth(), token_out(), 500);
+        add_v3_pool(&mut markets, weth(), token_out(), 10000);
+
+        //Pairs from dexes that the router is not deployed with are left out
+        let mut other_dex_pool = UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(200),
+            token_a: token_in(),
+            token_a_decimals: 18,
+            token_b: weth(),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000,
+            reserve_1: 1_000_000_000,
+            fee: 250,
+        });
+        other_dex_pool.factory_address = token(60);
+        markets
+            .get_mut(&markets::get_market_id(token_in(), weth()))
+            .unwrap()
+            .insert(other_dex_pool.address, Pool::UniswapV2(other_dex_pool));
+
+        let order = limit_order(token_in(), token_out(), 3000, 10000);
+        let limit_order_markets =
+            get_limit_order_markets(&order, weth(), &router_dexes(), &markets);
+
+        //Each hop keeps the v2 pair and the v3 pool with the fee tier of the hop
+        let hop_fees = |limit_order_markets: &HashMap<U256, Market>, token_a, token_b| {
+            let mut fees = limit_order_markets[&markets::get_market_id(token_a, token_b)]
+                .values()
+                .map(|pool| pool.fee())
+                .collect::<Vec<u32>>();
+            fees.sort();
+            fees
         };
+        assert_eq!(
+            hop_fees(&limit_order_markets, token_in(), weth()),
+            vec![300, 3000]
+        );
+        assert_eq!(
+            hop_fees(&limit_order_markets, weth(), token_out()),
+            vec![300, 10000]
+        );
 
-        let market_id = markets::get_market_id(token_in, token_out);
-        swap_amount = markets
+        //Orders from weth only swap through the weth to token out market
+        let order = limit_order(weth(), token_out(), 0, 500);
+        let limit_order_markets =
+            get_limit_order_markets(&order, weth(), &router_dexes(), &markets);
+        assert_eq!(limit_order_markets.len(), 1);
+        assert_eq!(
+            hop_fees(&limit_order_markets, weth(), token_out()),
+            vec![300, 500]
+        );
+    }
+
+    #[test]
+    fn test_find_limit_order_route() {
+        let mut markets = markets();
+        let amount_in = U256::from(1_000_000);
+
+        let order = limit_order(weth(), token_out(), 0, 500);
+        let (amounts_in, amounts_out, route, _) = find_limit_order_route(
+            &order,
+            amount_in,
+            weth(),
+            &router_dexes(),
+            &markets,
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        assert_eq!(route.len(), 1);
+        assert_eq!(amounts_in, vec![amount_in]);
+        assert_eq!(
+            amounts_out[0],
+            markets::get_best_market_amount_out(weth(), token_out(), amount_in, &markets).unwrap()
+        );
+
+        //Each hop swaps through the pool with the best spot price, which is not the pool with the best amount out
+        let cheap_pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(200),
+            token_a: weth(),
+            token_a_decimals: 18,
+            token_b: token_out(),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000,
+            reserve_1: 2_000_000_000,
+            fee: 300,
+        }));
+        markets
+            .get_mut(&markets::get_market_id(weth(), token_out()))
+            .unwrap()
+            .insert(cheap_pool.address(), cheap_pool.clone());
+
+        //Sell orders look for the highest price of token out in weth, even though the cheaper pool gives a better amount out
+        let (_, amounts_out, route, _) = find_limit_order_route(
+            &order,
+            amount_in,
+            weth(),
+            &router_dexes(),
+            &markets,
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+        assert_ne!(route[0].swaps[0].pool.address(), cheap_pool.address());
+        assert!(
+            amounts_out[0]
+                < markets::get_best_market_amount_out(weth(), token_out(), amount_in, &markets)
+                    .unwrap()
+        );
+
+        //Buy orders look for the lowest price of token out in weth
+        let mut buy_order = order;
+        buy_order.buy = true;
+        let (_, amounts_out, route, _) = find_limit_order_route(
+            &buy_order,
+            amount_in,
+            weth(),
+            &router_dexes(),
+            &markets,
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+        assert_eq!(route[0].swaps[0].pool.address(), cheap_pool.address());
+        assert_eq!(
+            amounts_out[0],
+            cheap_pool
+                .quote_local(weth(), amount_in)
+                .unwrap()
+                .amount_out
+        );
+
+        //Markets without a pool that the router can swap through can not be routed through, even if they have other pools
+        let market_id = markets::get_market_id(token_in(), weth());
+        markets.remove(&market_id);
+        add_v3_pool(&mut markets, token_in(), weth(), 500);
+
+        assert!(matches!(
+            find_limit_order_route(
+                &limit_order(token_in(), token_out(), 3000, 500),
+                amount_in,
+                weth(),
+                &router_dexes(),
+                &markets,
+                &TransferTaxes::new(),
+            ),
+            Err(RoutingError::MarketDoesNotExistForPair(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_find_best_route() {
+        let markets = markets();
+        let amount_in = U256::from(1_000_000);
+
+        let (amounts_in, amounts_out, route, _) = find_best_route(
+            token_in(),
+            token_out(),
+            amount_in,
+            &Connectors::new(vec![weth()], 2),
+            &markets,
+            weth(),
+            U256::zero(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        assert_eq!(route.len(), 2);
+        assert_eq!(amounts_in, vec![amount_in, amounts_out[0]]);
+        assert_eq!(
+            amounts_out[1],
+            markets::get_best_market_amount_out(weth(), token_out(), amounts_out[0], &markets)
+                .unwrap()
+        );
+
+        assert!(matches!(
+            find_best_route(
+                token_in(),
+                token_out(),
+                amount_in,
+                &Connectors::new(vec![weth()], 1),
+                &markets,
+                weth(),
+                U256::zero(),
+                &TransferTaxes::new(),
+            ),
+            Err(RoutingError::MarketDoesNotExistForPair(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_find_best_weth_exit_from_route() {
+        let mut markets = markets();
+        let amount_in = U256::from(1_000_000);
+
+        let (_, amounts_out, route, _) = find_best_route(
+            token_in(),
+            token_out(),
+            amount_in,
+            &Connectors::new(vec![weth()], 2),
+            &markets,
+            weth(),
+            U256::zero(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        let order = SandboxLimitOrder::new(
+            0,
+            0,
+            0,
+            0,
+            amount_in.as_u128(),
+            0,
+            0,
+            H160::zero(),
+            token_in(),
+            token_out(),
+            H256::zero(),
+        );
+
+        //The amount left after paying the owner is swapped to weth
+        let amount_due_to_owner = amounts_out[1] / 2;
+        let (amount_in_to_weth_exit, weth_amount_out, _, _) = find_best_weth_exit_from_route(
+            &order,
+            amount_in,
+            amount_due_to_owner,
+            route.clone(),
+            &mut markets,
+            weth(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+        assert_eq!(amount_in_to_weth_exit, amounts_out[1] - amount_due_to_owner);
+        assert!(!weth_amount_out.is_zero());
+
+        //Routes that do not cover the amount due to the owner have no weth exit
+        assert!(matches!(
+            find_best_weth_exit_from_route(
+                &order,
+                amount_in,
+                amounts_out[1] + 1,
+                route,
+                &mut markets,
+                weth(),
+                &TransferTaxes::new(),
+            ),
+            Err(RoutingError::InsufficientAmountOut(_, _))
+        ));
+
+        //Tokens without a market to weth have no weth exit
+        assert!(matches!(
+            find_best_a_to_b_route(
+                token_in(),
+                token_out(),
+                amount_in,
+                &markets,
+                &TransferTaxes::new(),
+            ),
+            Err(RoutingError::MarketDoesNotExistForPair(_, _))
+        ));
+    }
+
+    #[test]
+    fn test_update_pools_along_route() {
+        let mut markets = markets();
+        let amount_in = U256::from(1_000_000);
+
+        let (_, amounts_out, route, _) = find_best_route(
+            token_in(),
+            token_out(),
+            amount_in,
+            &Connectors::new(vec![weth()], 2),
+            &markets,
+            weth(),
+            U256::zero(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        update_pools_along_route(
+            token_in(),
+            amount_in,
+            &mut markets,
+            route.clone(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        //The pools along the route are updated in place, so the same swap now quotes less
+        let updated_pool = &markets[&markets::get_market_id(weth(), token_out())]
+            [&route[1].swaps[0].pool.address()];
+        assert!(
+            updated_pool
+                .quote_local(weth(), amounts_out[0])
+                .unwrap()
+                .amount_out
+                < amounts_out[1]
+        );
+
+        //Routes that fail part of the way leave the pools before the failed swap unchanged
+        let first_market_id = markets::get_market_id(token_in(), weth());
+        let first_market = markets[&first_market_id].clone();
+        markets.remove(&markets::get_market_id(weth(), token_out()));
+        assert!(matches!(
+            update_pools_along_route(
+                token_in(),
+                amount_in,
+                &mut markets,
+                route.clone(),
+                &TransferTaxes::new(),
+            ),
+            Err(RoutingError::MarketDoesNotExistForPair(_, _))
+        ));
+        assert_eq!(
+            markets[&first_market_id][&route[0].swaps[0].pool.address()]
+                .quote_local(token_in(), amount_in),
+            first_market[&route[0].swaps[0].pool.address()].quote_local(token_in(), amount_in)
+        );
+    }
+
+    #[test]
+    fn test_find_best_route_split_gas() {
+        let mut markets = markets();
+        let amount_in = U256::from(100_000_000);
+        let connectors = Connectors::new(vec![weth()], 2);
+
+        //A second token in to weth pool, so that the amount in of the first hop can be split
+        let market_id = markets::get_market_id(token_in(), weth());
+        let pool = markets[&market_id].values().next().unwrap().clone();
+        let mut second_pool = pool.clone();
+        if let Pool::UniswapV2(uniswap_v2_pool) = &mut second_pool {
+            uniswap_v2_pool.pool.address = H160::from_low_u64_be(200);
+        }
+        markets
             .get_mut(&market_id)
             .unwrap()
-            .get_mut(&pool.address())
+            .insert(second_pool.address(), second_pool);
+
+        let best_quote = pool.quote_local(token_in(), amount_in).unwrap();
+        let (split_hop, split_gas_estimate) =
+            split::find_best_split(token_in(), amount_in, &markets[&market_id]).unwrap();
+        let extra_amount_out = split_hop.amount_out() - best_quote.amount_out;
+        let extra_gas = split_gas_estimate - best_quote.gas_estimate;
+
+        let find_route = |gas_price: U256| {
+            find_best_route(
+                token_in(),
+                token_out(),
+                amount_in,
+                &connectors,
+                &markets,
+                weth(),
+                gas_price,
+                &TransferTaxes::new(),
+            )
             .unwrap()
-            .simulate_swap_mut(token_in, swap_amount, middleware.clone())
-            .await?;
-        //update token in
-        swap_token = token_out;
+        };
+
+        //The hop is split while the extra amount out pays for the gas of the extra swap
+        let (_, _, route, _) = find_route((extra_amount_out - 1) / extra_gas);
+        assert!(route[0].is_split());
+        assert_eq!(route[0].amount_out(), split_hop.amount_out());
+
+        //Once the extra gas costs more than the extra amount out, the hop swaps through the best pool
+        let (_, _, route, _) = find_route(extra_amount_out / extra_gas + 1);
+        assert!(!route[0].is_split());
+        assert_eq!(route[0].amount_out(), best_quote.amount_out);
     }
 
-    let token_out_to_weth_market_id = markets::get_market_id(order.token_out, weth);
+    #[test]
+    fn test_find_best_route_with_transfer_taxes() {
+        let mut markets = markets();
+        let amount_in = U256::from(1_000_000);
+        let connectors = Connectors::new(vec![weth()], 2);
 
-    markets
-        .get_mut(&token_out_to_weth_market_id)
-        .unwrap()
-        .get_mut(&weth_exit_address)
-        .unwrap()
-        .simulate_swap_mut(order.token_out, amount_in_to_weth_exit, middleware.clone())
-        .await?;
+        let mut transfer_taxes = TransferTaxes::new();
+        transfer_taxes.rates.insert(token_in(), 1000);
+        transfer_taxes.rates.insert(token_out(), 1000);
 
-    Ok(())
+        let (_, untaxed_amounts_out, _, _) = find_best_route(
+            token_in(),
+            token_out(),
+            amount_in,
+            &connectors,
+            &markets,
+            weth(),
+            U256::zero(),
+            &TransferTaxes::new(),
+        )
+        .unwrap();
+
+        let (amounts_in, amounts_out, route, _) = find_best_route(
+            token_in(),
+            token_out(),
+            amount_in,
+            &connectors,
+            &markets,
+            weth(),
+            U256::zero(),
+            &transfer_taxes,
+        )
+        .unwrap();
+
+        //The first pool receives the amount in after tax, and the amount out is taxed when it is received
+        assert_eq!(amounts_in[0], amount_in);
+        assert_eq!(route[0].swaps[0].amount_in, U256::from(990_000));
+        assert_eq!(
+            amounts_out[1],
+            transfer_taxes.apply(token_out(), route[1].amount_out())
+        );
+        assert!(amounts_out[1] < untaxed_amounts_out[1]);
+
+        //Pools are updated with the taxed amounts
+        update_pools_along_route(
+            token_in(),
+            amount_in,
+            &mut markets,
+            route.clone(),
+            &transfer_taxes,
+        )
+        .unwrap();
+
+        let updated_pool = &markets[&markets::get_market_id(token_in(), weth())]
+            [&route[0].swaps[0].pool.address()];
+        let mut expected_pool = route[0].swaps[0].pool.clone();
+        expected_pool
+            .simulate_swap_local_mut(token_in(), U256::from(990_000))
+            .unwrap();
+        assert_eq!(
+            updated_pool.quote_local(token_in(), amount_in),
+            expected_pool.quote_local(token_in(), amount_in)
+        );
+    }
 }
@@ -0,0 +1,46 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use ethers::{providers::Middleware, types::U256};
+use futures::future::join_all;
+
+use crate::{
+    markets::Market,
+    pool::{AutomatedMarketMaker, Pool},
+};
+
+//Fetches the on chain data that the routing engine needs for the pools in the markets, so that routing can run against the market snapshot without any RPC calls.
+//Pools that could not be prefetched are logged and left as they are, routing skips swaps that it can not simulate locally. Returns the ids of the markets that were updated
+pub async fn prefetch_markets<M: Middleware>(
+    market_ids: &HashSet<U256>,
+    markets: &mut HashMap<U256, Market>,
+    middleware: Arc<M>,
+) -> HashSet<U256> {
+    let mut prefetched_markets = HashSet::new();
+
+    for market_id in market_ids {
+        if let Some(market) = markets.get_mut(market_id) {
+            let prefetched_pools = join_all(market.values_mut().map(|pool: &mut Pool| {
+                let middleware = middleware.clone();
+                async move { (pool.address(), pool.prefetch(middleware).await) }
+            }))
+            .await;
+
+            for (pool_address, prefetched) in prefetched_pools {
+                match prefetched {
+                    Ok(true) => {
+                        prefetched_markets.insert(*market_id);
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        tracing::warn!("Could not prefetch pool {:?}: {:?}", pool_address, err);
+                    }
+                }
+            }
+        }
+    }
+
+    prefetched_markets
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+
+use ethers::types::{H160, U256};
+
+use crate::{
+    markets::{self, Market},
+    pool::{AutomatedMarketMaker, Pool},
+    quoter::Quote,
+};
+
+use super::{Hop, Swap};
+
+//Number of equal parts that the amount in of a hop is split into when allocating it across the pools in a market
+pub const SPLIT_STEPS: u64 = 20;
+
+//Gas price that the extra swaps of a split hop are paid at. Hops are only split when the extra amount out pays for the extra gas,
+//which is paid in weth and priced in the token out of the hop through the best weth market
+#[derive(Debug, Clone, Copy)]
+pub struct SplitHops<'a> {
+    pub weth: H160,
+    pub gas_price: U256,
+    pub markets: &'a HashMap<U256, Market>,
+}
+
+impl<'a> SplitHops<'a> {
+    pub fn new(weth: H160, gas_price: U256, markets: &'a HashMap<U256, Market>) -> SplitHops<'a> {
+        SplitHops {
+            weth,
+            gas_price,
+            markets,
+        }
+    }
+
+    //Returns the cost of the gas in the token, or None if the token can not be priced in weth
+    pub fn gas_cost(&self, token: H160, gas: U256) -> Option<U256> {
+        let weth_cost = gas.checked_mul(self.gas_price)?;
+        if weth_cost.is_zero() {
+            return Some(U256::zero());
+        }
+
+        markets::get_best_market_amount_out(self.weth, token, weth_cost, self.markets)
+    }
+}
+
+//Splits the amount in across the pools in the market with a fixed step greedy search. Each step is allocated to the pool with the best marginal amount out,
+//which converges on the allocation where the marginal prices of the pools are equal. Only pools that can be quoted with local state are split across.
+//Returns the hop and the estimated gas of its swaps, or None if no pool can be quoted
+pub fn find_best_split(token_in: H160, amount_in: U256, market: &Market) -> Option<(Hop, U256)> {
+    if amount_in.is_zero() {
+        return None;
+    }
+
+    let pools: Vec<&Pool> = market.values().collect();
+    let mut amounts_in = vec![U256::zero(); pools.len()];
+    let mut quotes = vec![Quote::default(); pools.len()];
+
+    let step = amount_in / SPLIT_STEPS;
+    for i in 0..SPLIT_STEPS {
+        //The last step also allocates the remainder of the division
+        let step_amount = if i == SPLIT_STEPS - 1 {
+            amount_in - step * (SPLIT_STEPS - 1)
+        } else {
+            step
+        };
+
+        if step_amount.is_zero() {
+            continue;
+        }
+
+        let mut best_step: Option<(usize, Quote, U256)> = None;
+        for (j, pool) in pools.iter().enumerate() {
+            if let Some(quote) = pool.quote_local(token_in, amounts_in[j] + step_amount) {
+                let marginal_amount_out = quote.amount_out.saturating_sub(quotes[j].amount_out);
+
+                if best_step.is_none_or(|(_, _, best_marginal_amount_out)| {
+                    marginal_amount_out > best_marginal_amount_out
+                }) {
+                    best_step = Some((j, quote, marginal_amount_out));
+                }
+            }
+        }
+
+        let (j, quote, _) = best_step?;
+        amounts_in[j] += step_amount;
+        quotes[j] = quote;
+    }
+
+    let mut gas_estimate = U256::zero();
+    let mut swaps = vec![];
+    for ((pool, amount_in), quote) in pools.into_iter().zip(amounts_in).zip(quotes) {
+        if !amount_in.is_zero() {
+            gas_estimate += quote.gas_estimate;
+            swaps.push(Swap::new(pool.clone(), amount_in, quote.amount_out));
+        }
+    }
+
+    Some((Hop::new(swaps), gas_estimate))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use ethers::types::{H160, U256};
+
+    use crate::{
+        markets::Market,
+        pool::{AutomatedMarketMaker, Pool, UniswapV2Pool},
+    };
+
+    use super::find_best_split;
+
+    fn token_a() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token_b() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    fn pool(address: u64, reserve_0: u128, reserve_1: u128) -> Pool {
+        Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(address),
+            token_a: token_a(),
+            token_a_decimals: 18,
+            token_b: token_b(),
+            token_b_decimals: 18,
+            reserve_0,
+            reserve_1,
+            fee: 300,
+        }))
+    }
+
+    fn market(pools: Vec<Pool>) -> Market {
+        pools
+            .into_iter()
+            .map(|pool| (pool.address(), pool))
+            .collect::<HashMap<H160, Pool>>()
+    }
+
+    #[test]
+    fn test_find_best_split() {
+        let deep_pool = pool(3, 2_000_000_000, 2_000_000_000);
+        let shallow_pool = pool(4, 1_000_000_000, 1_000_000_000);
+        let market = market(vec![deep_pool.clone(), shallow_pool.clone()]);
+
+        //A large swap loses less to price impact when split across both pools
+        let amount_in = U256::from(300_000_000);
+        let (hop, _) = find_best_split(token_a(), amount_in, &market).unwrap();
+
+        assert!(hop.is_split());
+        assert_eq!(
+            hop.swaps
+                .iter()
+                .fold(U256::zero(), |total, swap| total + swap.amount_in),
+            amount_in
+        );
+        assert!(
+            hop.amount_out()
+                > deep_pool
+                    .quote_local(token_a(), amount_in)
+                    .unwrap()
+                    .amount_out
+        );
+
+        //Pools with the same price receive more of the amount in the deeper they are
+        let amount_in_of = |address: H160| {
+            hop.swaps
+                .iter()
+                .find(|swap| swap.pool.address() == address)
+                .unwrap()
+                .amount_in
+        };
+        assert!(amount_in_of(deep_pool.address()) > amount_in_of(shallow_pool.address()));
+    }
+
+    #[test]
+    fn test_find_best_split_single_pool() {
+        let market = market(vec![pool(3, 2_000_000_000, 2_000_000_000)]);
+        let (hop, _) = find_best_split(token_a(), U256::from(1_000_000), &market).unwrap();
+        assert!(!hop.is_split());
+
+        assert!(find_best_split(token_a(), U256::zero(), &market).is_none());
+        assert!(find_best_split(token_a(), U256::one(), &HashMap::new()).is_none());
+    }
+}
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use ethers::types::{H160, U256, U512};
+
+use crate::order::Order;
+
+//Denominator of transfer tax rates, matching the tax in of limit orders, ie. a rate of 1000 is a 1% tax
+pub const TAX_DENOMINATOR: u32 = 100_000;
+
+//Transfer tax rates of fee on transfer tokens, which take a fraction of every transfer before it is received
+#[derive(Debug, Clone, Default)]
+pub struct TransferTaxes {
+    pub rates: HashMap<H160, u16>,
+}
+
+impl TransferTaxes {
+    pub fn new() -> TransferTaxes {
+        TransferTaxes {
+            rates: HashMap::new(),
+        }
+    }
+
+    //Records the tax of the token in from taxed limit orders, which is checked when the order is placed on chain
+    pub fn update_from_order(&mut self, order: &Order) {
+        if let Order::LimitOrder(limit_order) = order {
+            if limit_order.taxed && limit_order.tax_in > 0 {
+                self.rates.insert(limit_order.token_in, limit_order.tax_in);
+            }
+        }
+    }
+
+    pub fn is_taxed(&self, token: H160) -> bool {
+        self.rates.contains_key(&token)
+    }
+
+    //Returns the amount that is received when the amount of the token is transferred
+    pub fn apply(&self, token: H160, amount: U256) -> U256 {
+        if let Some(rate) = self.rates.get(&token) {
+            let tax = amount.full_mul(U256::from(*rate)) / U512::from(TAX_DENOMINATOR);
+            //The tax is at most the amount since the rate is less than the denominator
+            amount.saturating_sub(U256::try_from(tax).unwrap_or(amount))
+        } else {
+            amount
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, H256, U256};
+
+    use crate::order::{limit_order::LimitOrder, Order};
+
+    use super::TransferTaxes;
+
+    fn limit_order(taxed: bool, tax_in: u16) -> Order {
+        Order::LimitOrder(LimitOrder::new(
+            true,
+            taxed,
+            false,
+            0,
+            0,
+            0,
+            0,
+            tax_in,
+            0,
+            0,
+            0,
+            0,
+            H160::zero(),
+            H160::from_low_u64_be(1),
+            H160::from_low_u64_be(2),
+            H256::zero(),
+        ))
+    }
+
+    #[test]
+    fn test_update_from_order() {
+        let mut transfer_taxes = TransferTaxes::new();
+
+        transfer_taxes.update_from_order(&limit_order(false, 1000));
+        transfer_taxes.update_from_order(&limit_order(true, 0));
+        assert!(!transfer_taxes.is_taxed(H160::from_low_u64_be(1)));
+
+        transfer_taxes.update_from_order(&limit_order(true, 1000));
+        assert!(transfer_taxes.is_taxed(H160::from_low_u64_be(1)));
+        assert!(!transfer_taxes.is_taxed(H160::from_low_u64_be(2)));
+    }
+
+    #[test]
+    fn test_apply() {
+        let mut transfer_taxes = TransferTaxes::new();
+        transfer_taxes.update_from_order(&limit_order(true, 1000));
+
+        //1% of each transfer of the taxed token is taken
+        assert_eq!(
+            transfer_taxes.apply(H160::from_low_u64_be(1), U256::from(1_000_000)),
+            U256::from(990_000)
+        );
+        assert_eq!(
+            transfer_taxes.apply(H160::from_low_u64_be(1), U256::MAX),
+            U256::MAX - U256::MAX / 100
+        );
+        assert_eq!(
+            transfer_taxes.apply(H160::from_low_u64_be(2), U256::from(1_000_000)),
+            U256::from(1_000_000)
+        );
+    }
+}
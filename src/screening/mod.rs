@@ -0,0 +1,274 @@
+use std::sync::Arc;
+
+use ethers::{
+    abi::{encode, ParamType, Token},
+    providers::{call_raw::spoof, Middleware, ProviderError, RawCall, RpcError},
+    types::{transaction::eip2718::TypedTransaction, Bytes, H160, H256, U256},
+    utils::keccak256,
+};
+
+use crate::{abi, error::ExecutorError};
+
+//Number of storage slots that are searched for the balances mapping of a token
+pub const BALANCE_SLOT_SEARCH_DEPTH: u64 = 30;
+//When a transfer of the screened amount reverts, the amount is divided by this and transferred again to tell transfer limits apart from blocked transfers
+pub const TRANSFER_LIMIT_DIVISOR: u64 = 100;
+//Balance written to the balances mapping while searching for its storage slot
+pub const BALANCE_SLOT_MARKER: u64 = 0xc0ffee;
+
+//Verdict of simulating transfers of a token between a pool and the router that executes orders on it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenVerdict {
+    //Transfers from the pool to the router and back into the pool succeed
+    Tradable,
+    //Transfers from the pool to the router succeed but transfers back into the pool revert, so the token can be bought but not sold
+    Honeypot,
+    //Transfers from the pool to the router revert
+    TransferRestricted,
+    //Transfers of the screened amount revert but transfers of the smaller amount succeed, so orders of up to the smaller amount are still tradable
+    MaxTransactionLimit(U256),
+    //The token could not be screened, ie. the storage slot of its balances mapping could not be found
+    Unscreened,
+}
+
+impl TokenVerdict {
+    //Tokens that could not be screened are still executed, tokens with a transfer limit are executed for amounts up to the limit
+    pub fn is_tradable(&self, amount: U256) -> bool {
+        match self {
+            TokenVerdict::Tradable | TokenVerdict::Unscreened => true,
+            TokenVerdict::MaxTransactionLimit(limit) => amount <= *limit,
+            TokenVerdict::Honeypot | TokenVerdict::TransferRestricted => false,
+        }
+    }
+}
+
+//Storage slot of the balances mapping of a token. Solidity hashes the holder before the slot, Vyper hashes the slot before the holder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BalanceSlot {
+    pub slot: U256,
+    pub vyper: bool,
+}
+
+impl BalanceSlot {
+    pub fn new(slot: U256, vyper: bool) -> BalanceSlot {
+        BalanceSlot { slot, vyper }
+    }
+
+    //Returns the storage key of the balance of the holder
+    pub fn storage_key(&self, holder: H160) -> H256 {
+        let key = if self.vyper {
+            encode(&[Token::Uint(self.slot), Token::Address(holder)])
+        } else {
+            encode(&[Token::Address(holder), Token::Uint(self.slot)])
+        };
+
+        H256::from(keccak256(key))
+    }
+}
+
+//Screens the token by simulating a buy and sell round trip of the amount with eth_call. The pool transfers the amount to the router and the router transfers it back into the pool,
+//with the balances of the sender overridden so that each transfer is funded.
+pub async fn screen_token<M: Middleware>(
+    token: H160,
+    pool: H160,
+    router: H160,
+    amount: U256,
+    middleware: Arc<M>,
+) -> Result<TokenVerdict, ExecutorError<M>> {
+    let balance_slot = match find_balance_slot(token, router, middleware.clone()).await? {
+        Some(balance_slot) => balance_slot,
+        None => return Ok(TokenVerdict::Unscreened),
+    };
+
+    let limited_amount = (amount / TRANSFER_LIMIT_DIVISOR).max(U256::one());
+
+    //Buy the token from the pool
+    if !simulate_transfer(
+        token,
+        balance_slot,
+        pool,
+        router,
+        amount,
+        middleware.clone(),
+    )
+    .await?
+    {
+        if simulate_transfer(
+            token,
+            balance_slot,
+            pool,
+            router,
+            limited_amount,
+            middleware.clone(),
+        )
+        .await?
+        {
+            return Ok(TokenVerdict::MaxTransactionLimit(limited_amount));
+        } else {
+            return Ok(TokenVerdict::TransferRestricted);
+        }
+    }
+
+    //Sell the token back into the pool
+    if !simulate_transfer(
+        token,
+        balance_slot,
+        router,
+        pool,
+        amount,
+        middleware.clone(),
+    )
+    .await?
+    {
+        if simulate_transfer(
+            token,
+            balance_slot,
+            router,
+            pool,
+            limited_amount,
+            middleware.clone(),
+        )
+        .await?
+        {
+            return Ok(TokenVerdict::MaxTransactionLimit(limited_amount));
+        } else {
+            return Ok(TokenVerdict::Honeypot);
+        }
+    }
+
+    Ok(TokenVerdict::Tradable)
+}
+
+//Finds the storage slot of the balances mapping by overriding the balance of the holder at each candidate slot until balanceOf returns the overridden balance
+pub async fn find_balance_slot<M: Middleware>(
+    token: H160,
+    holder: H160,
+    middleware: Arc<M>,
+) -> Result<Option<BalanceSlot>, ExecutorError<M>> {
+    let tx = abi::IErc20::new(token, middleware.clone())
+        .balance_of(holder)
+        .tx;
+
+    for slot in 0..BALANCE_SLOT_SEARCH_DEPTH {
+        for vyper in [false, true] {
+            let balance_slot = BalanceSlot::new(U256::from(slot), vyper);
+
+            let mut state = spoof::state();
+            state.account(token).store(
+                balance_slot.storage_key(holder),
+                u256_to_h256(U256::from(BALANCE_SLOT_MARKER)),
+            );
+
+            if let Some(return_data) = call_with_state(&tx, &state, middleware.clone()).await? {
+                if return_data.len() == 32
+                    && U256::from_big_endian(&return_data) == U256::from(BALANCE_SLOT_MARKER)
+                {
+                    return Ok(Some(balance_slot));
+                }
+            }
+        }
+    }
+
+    Ok(None)
+}
+
+//Simulates a transfer of the amount from the sender with eth_call, with the token balance of the sender overridden to the amount.
+//Returns false if the transfer reverts or returns false
+pub async fn simulate_transfer<M: Middleware>(
+    token: H160,
+    balance_slot: BalanceSlot,
+    from: H160,
+    to: H160,
+    amount: U256,
+    middleware: Arc<M>,
+) -> Result<bool, ExecutorError<M>> {
+    let tx = abi::IErc20::new(token, middleware.clone())
+        .transfer(to, amount)
+        .from(from)
+        .tx;
+
+    let mut state = spoof::state();
+    state
+        .account(token)
+        .store(balance_slot.storage_key(from), u256_to_h256(amount));
+
+    match call_with_state(&tx, &state, middleware).await? {
+        //Tokens that do not return a value from transfer only signal failure by reverting
+        Some(return_data) if return_data.is_empty() => Ok(true),
+        Some(return_data) => Ok(ethers::abi::decode(&[ParamType::Bool], &return_data)
+            .is_ok_and(|tokens| tokens == vec![Token::Bool(true)])),
+        None => Ok(false),
+    }
+}
+
+//Calls the transaction with the state overrides, returning None if the call reverts
+async fn call_with_state<M: Middleware>(
+    tx: &TypedTransaction,
+    state: &spoof::State,
+    middleware: Arc<M>,
+) -> Result<Option<Bytes>, ExecutorError<M>> {
+    match middleware.provider().call_raw(tx).state(state).await {
+        Ok(return_data) => Ok(Some(return_data)),
+        Err(provider_error) if is_revert(&provider_error) => Ok(None),
+        Err(provider_error) => Err(ExecutorError::ProviderError(provider_error)),
+    }
+}
+
+fn is_revert(provider_error: &ProviderError) -> bool {
+    provider_error
+        .as_error_response()
+        .is_some_and(|error_response| error_response.message.contains("revert"))
+}
+
+fn u256_to_h256(value: U256) -> H256 {
+    let mut bytes = [0u8; 32];
+    value.to_big_endian(&mut bytes);
+    H256::from(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        types::{H160, H256, U256},
+        utils::keccak256,
+    };
+
+    use super::{BalanceSlot, TokenVerdict};
+
+    #[test]
+    fn test_storage_key() {
+        let holder = H160::from_low_u64_be(0xabc);
+
+        let mut solidity_key = [0u8; 64];
+        solidity_key[12..32].copy_from_slice(holder.as_bytes());
+        solidity_key[63] = 9;
+        assert_eq!(
+            BalanceSlot::new(U256::from(9), false).storage_key(holder),
+            H256::from(keccak256(solidity_key))
+        );
+
+        let mut vyper_key = [0u8; 64];
+        vyper_key[31] = 9;
+        vyper_key[44..64].copy_from_slice(holder.as_bytes());
+        assert_eq!(
+            BalanceSlot::new(U256::from(9), true).storage_key(holder),
+            H256::from(keccak256(vyper_key))
+        );
+    }
+
+    #[test]
+    fn test_is_tradable() {
+        let amount = U256::from(1_000);
+
+        assert!(TokenVerdict::Tradable.is_tradable(amount));
+        assert!(TokenVerdict::Unscreened.is_tradable(amount));
+        assert!(!TokenVerdict::Honeypot.is_tradable(amount));
+        assert!(!TokenVerdict::TransferRestricted.is_tradable(amount));
+
+        //Orders are compared against the transfer limit found when the token was screened
+        let verdict = TokenVerdict::MaxTransactionLimit(U256::from(10));
+        assert!(verdict.is_tradable(U256::from(10)));
+        assert!(!verdict.is_tradable(U256::from(11)));
+        assert!(!verdict.is_tradable(amount));
+    }
+}
@@ -0,0 +1,365 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use ethers::types::{H160, U256, U512};
+
+use crate::{
+    execution::sandbox_limit_order::{
+        SandboxLimitOrderExecutionBundle, SANDBOX_ORDER_GAS_OVERHEAD,
+    },
+    order::sandbox_limit_order::SandboxLimitOrder,
+    pool,
+    routing::tax::TransferTaxes,
+};
+
+//Matches sandbox limit orders that sell weth for a token against orders that sell the token for weth, at a price that satisfies both orders.
+//The fill amounts of both orders are transferred to the sandbox router, which pays each owner with the fill amount of the other order instead of routing through pools.
+//Fees are paid in weth, so only orders between weth and an untaxed token are matched, and the weth left after paying the owner and the fees is the executor surplus.
+//Returns the bundles of the matched orders and the orders left to route through the markets, where partially matched orders are reduced to their residual
+#[allow(clippy::too_many_arguments)]
+pub fn match_opposing_sandbox_limit_orders(
+    sandbox_limit_orders: Vec<&SandboxLimitOrder>,
+    weth: H160,
+    min_fill_percent: u128,
+    executor_address: H160,
+    sandbox_limit_order_router: H160,
+    wallet_address: H160,
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> (
+    Vec<SandboxLimitOrderExecutionBundle>,
+    Vec<SandboxLimitOrder>,
+) {
+    let mut matched_bundles = vec![];
+    let mut unmatched_orders = vec![];
+
+    //Orders selling weth and orders buying weth, keyed by the other token
+    let mut weth_sell_orders: HashMap<H160, Vec<&SandboxLimitOrder>> = HashMap::new();
+    let mut weth_buy_orders: HashMap<H160, Vec<&SandboxLimitOrder>> = HashMap::new();
+
+    for order in sandbox_limit_orders {
+        if order.token_in == weth && !transfer_taxes.is_taxed(order.token_out) {
+            weth_sell_orders
+                .entry(order.token_out)
+                .or_default()
+                .push(order);
+        } else if order.token_out == weth && !transfer_taxes.is_taxed(order.token_in) {
+            weth_buy_orders
+                .entry(order.token_in)
+                .or_default()
+                .push(order);
+        } else {
+            unmatched_orders.push(*order);
+        }
+    }
+
+    for (token, mut sell_orders) in weth_sell_orders {
+        let mut buy_orders = weth_buy_orders.remove(&token).unwrap_or_default();
+
+        //Match the orders that ask the least for their amount in first, since they leave the most surplus
+        sell_orders.sort_by(|a, b| cmp_order_price(a, b));
+        buy_orders.sort_by(|a, b| cmp_order_price(a, b));
+
+        //Each order is matched at most once, the residual of a partially matched order is routed through the markets
+        let mut matched = 0;
+        for (sell_order, buy_order) in sell_orders.iter().zip(buy_orders.iter()) {
+            match match_orders(
+                sell_order,
+                buy_order,
+                weth,
+                min_fill_percent,
+                executor_address,
+                sandbox_limit_order_router,
+                wallet_address,
+                gas_price,
+            ) {
+                Some((bundle, residual_orders)) => {
+                    matched_bundles.push(bundle);
+                    unmatched_orders.extend(residual_orders);
+                    matched += 1;
+                }
+                None => break,
+            }
+        }
+
+        unmatched_orders.extend(sell_orders[matched..].iter().map(|order| **order));
+        unmatched_orders.extend(buy_orders[matched..].iter().map(|order| **order));
+    }
+
+    for buy_orders in weth_buy_orders.into_values() {
+        unmatched_orders.extend(buy_orders.into_iter().copied());
+    }
+
+    (matched_bundles, unmatched_orders)
+}
+
+//Compares amount_out_remaining / amount_in_remaining of the orders, cross multiplying instead of dividing so that no precision is lost
+fn cmp_order_price(a: &SandboxLimitOrder, b: &SandboxLimitOrder) -> Ordering {
+    U256::from(a.amount_out_remaining)
+        .full_mul(U256::from(b.amount_in_remaining))
+        .cmp(&U256::from(b.amount_out_remaining).full_mul(U256::from(a.amount_in_remaining)))
+}
+
+//Matches the order selling weth for the token against the order selling the token for weth. The side with less liquidity is filled completely
+//and the other side is filled with the amount that it exchanges at its own price. Returns None if the weth of the sell order does not cover
+//the amount due to the owner of the buy order, the fees of both orders and the gas of the match
+#[allow(clippy::too_many_arguments)]
+pub fn match_orders(
+    weth_sell_order: &SandboxLimitOrder,
+    weth_buy_order: &SandboxLimitOrder,
+    weth: H160,
+    min_fill_percent: u128,
+    executor_address: H160,
+    sandbox_limit_order_router: H160,
+    wallet_address: H160,
+    gas_price: U256,
+) -> Option<(SandboxLimitOrderExecutionBundle, Vec<SandboxLimitOrder>)> {
+    let sell_amount_in_remaining = U256::from(weth_sell_order.amount_in_remaining);
+    let buy_amount_in_remaining = U256::from(weth_buy_order.amount_in_remaining);
+
+    //Weth filled from the sell order and tokens filled from the buy order. Orders whose amounts overflow the fixed point math are not matched
+    let sell_amount_out_required = weth_sell_order
+        .amount_out_required(sell_amount_in_remaining)
+        .ok()?;
+    let (weth_fill_amount, token_fill_amount) =
+        if sell_amount_out_required <= buy_amount_in_remaining {
+            //The buy order sells the tokens that the sell order requires
+            (sell_amount_in_remaining, sell_amount_out_required)
+        } else {
+            //The sell order is filled with the weth that buys all of the tokens of the buy order at the price of the sell order
+            let weth_fill_amount = buy_amount_in_remaining.full_mul(sell_amount_in_remaining)
+                / U512::from(weth_sell_order.amount_out_remaining);
+
+            (
+                U256::try_from(weth_fill_amount).ok()?,
+                buy_amount_in_remaining,
+            )
+        };
+
+    if !is_matched_fill(weth_sell_order, weth_fill_amount, min_fill_percent)?
+        || !is_matched_fill(weth_buy_order, token_fill_amount, min_fill_percent)?
+        || token_fill_amount < weth_sell_order.amount_out_required(weth_fill_amount).ok()?
+    {
+        return None;
+    }
+
+    let weth_due_to_owner = weth_buy_order.amount_out_required(token_fill_amount).ok()?;
+    let execution_fees = weth_sell_order.execution_fee(weth_fill_amount).ok()?
+        + weth_buy_order.execution_fee(token_fill_amount).ok()?;
+    let gas_estimate = U256::from(2 * SANDBOX_ORDER_GAS_OVERHEAD);
+
+    if weth_fill_amount <= weth_due_to_owner + execution_fees + gas_estimate * gas_price {
+        return None;
+    }
+
+    //Construct call for execution, both fill amounts are held by the sandbox router
+    let mut execution_bundle = SandboxLimitOrderExecutionBundle::new();
+    execution_bundle.add_order_id_to_current_bundle(weth_sell_order.order_id);
+    execution_bundle.add_fill_amount(weth_fill_amount.as_u128());
+    execution_bundle.add_transfer_address(sandbox_limit_order_router);
+
+    execution_bundle.add_new_order_id_bundle();
+    execution_bundle.add_order_id_to_current_bundle(weth_buy_order.order_id);
+    execution_bundle.add_fill_amount(token_fill_amount.as_u128());
+    execution_bundle.add_transfer_address(sandbox_limit_order_router);
+
+    //Pay each owner with the fill amount of the other order
+    execution_bundle.add_call(pool::transfer_call(
+        weth_sell_order.token_out,
+        weth_sell_order.owner,
+        token_fill_amount,
+    ));
+    execution_bundle.add_call(pool::transfer_call(
+        weth,
+        weth_buy_order.owner,
+        weth_due_to_owner,
+    ));
+
+    //pay protocol fee
+    execution_bundle.add_call(pool::transfer_call(weth, executor_address, execution_fees));
+
+    //pay coex the remainder
+    execution_bundle.add_call(pool::transfer_call(
+        weth,
+        wallet_address,
+        weth_fill_amount - (weth_due_to_owner + execution_fees),
+    ));
+
+    //The residual of the partially matched order is left to route through the markets
+    let residual_orders = [
+        (weth_sell_order, weth_fill_amount),
+        (weth_buy_order, token_fill_amount),
+    ]
+    .into_iter()
+    .filter(|(order, fill_amount)| *fill_amount < U256::from(order.amount_in_remaining))
+    .map(|(order, fill_amount)| order.remaining_after_fill(fill_amount))
+    .collect::<Result<Vec<SandboxLimitOrder>, _>>()
+    .ok()?;
+
+    Some((execution_bundle, residual_orders))
+}
+
+fn is_matched_fill(
+    order: &SandboxLimitOrder,
+    fill_amount: U256,
+    min_fill_percent: u128,
+) -> Option<bool> {
+    Some(
+        order.is_valid_fill(fill_amount).ok()?
+            && fill_amount >= order.min_fill_amount(min_fill_percent).ok()?,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::{H160, H256, U256};
+
+    use crate::{
+        order::sandbox_limit_order::{
+            min_fill_percent_64_x_64, SandboxLimitOrder, DEFAULT_MIN_FILL_PERCENT,
+        },
+        routing::tax::TransferTaxes,
+    };
+
+    use super::{match_opposing_sandbox_limit_orders, match_orders};
+
+    fn weth() -> H160 {
+        H160::from_low_u64_be(1)
+    }
+
+    fn token() -> H160 {
+        H160::from_low_u64_be(2)
+    }
+
+    fn router() -> H160 {
+        H160::from_low_u64_be(3)
+    }
+
+    fn min_fill_percent() -> u128 {
+        min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT)
+    }
+
+    fn sandbox_limit_order(
+        order_id: u64,
+        token_in: H160,
+        token_out: H160,
+        amount_in_remaining: u128,
+        amount_out_remaining: u128,
+    ) -> SandboxLimitOrder {
+        SandboxLimitOrder::new(
+            0,
+            0,
+            0,
+            1_000,
+            amount_in_remaining,
+            amount_out_remaining,
+            0,
+            H160::from_low_u64_be(order_id + 100),
+            token_in,
+            token_out,
+            H256::from_low_u64_be(order_id),
+        )
+    }
+
+    #[test]
+    fn test_match_orders() {
+        //Sells 1_000_000 weth for at least 2_000_000 tokens
+        let sell_order = sandbox_limit_order(1, weth(), token(), 1_000_000, 2_000_000);
+        //Sells 3_000_000 tokens for at least 1_200_000 weth
+        let buy_order = sandbox_limit_order(2, token(), weth(), 3_000_000, 1_200_000);
+
+        let (bundle, residual_orders) = match_orders(
+            &sell_order,
+            &buy_order,
+            weth(),
+            min_fill_percent(),
+            H160::zero(),
+            router(),
+            H160::zero(),
+            U256::zero(),
+        )
+        .unwrap();
+
+        //The sell order is filled completely and the buy order sells the 2_000_000 tokens that it requires for 800_000 weth
+        assert_eq!(bundle.fill_amounts, vec![1_000_000, 2_000_000]);
+        assert_eq!(bundle.transfer_addresses, vec![router(), router()]);
+        assert_eq!(
+            bundle.order_id_bundles,
+            vec![
+                vec![H256::from_low_u64_be(1)],
+                vec![H256::from_low_u64_be(2)]
+            ]
+        );
+        //Owner payouts, fees and the surplus
+        assert_eq!(bundle.calls.len(), 4);
+
+        assert_eq!(residual_orders.len(), 1);
+        assert_eq!(residual_orders[0].order_id, buy_order.order_id);
+        assert_eq!(residual_orders[0].amount_in_remaining, 1_000_000);
+        //The amount due to the owner is rounded down by the Q64.64 math, so the amount out remaining is rounded up
+        assert_eq!(residual_orders[0].amount_out_remaining, 400_001);
+
+        //Orders whose prices do not cross are not matched
+        let buy_order = sandbox_limit_order(2, token(), weth(), 3_000_000, 2_000_000);
+        assert!(match_orders(
+            &sell_order,
+            &buy_order,
+            weth(),
+            min_fill_percent(),
+            H160::zero(),
+            router(),
+            H160::zero(),
+            U256::zero(),
+        )
+        .is_none());
+    }
+
+    #[test]
+    fn test_match_orders_partial_sell_order() {
+        //Sells 1_000_000 weth for at least 2_000_000 tokens
+        let sell_order = sandbox_limit_order(1, weth(), token(), 1_000_000, 2_000_000);
+        //Sells 500_000 tokens for at least 200_000 weth
+        let buy_order = sandbox_limit_order(2, token(), weth(), 500_000, 200_000);
+
+        let (bundle, residual_orders) = match_orders(
+            &sell_order,
+            &buy_order,
+            weth(),
+            min_fill_percent(),
+            H160::zero(),
+            router(),
+            H160::zero(),
+            U256::zero(),
+        )
+        .unwrap();
+
+        //The buy order is filled completely with the 250_000 weth that buys its tokens at the price of the sell order
+        assert_eq!(bundle.fill_amounts, vec![250_000, 500_000]);
+        assert_eq!(residual_orders.len(), 1);
+        assert_eq!(residual_orders[0].order_id, sell_order.order_id);
+        assert_eq!(residual_orders[0].amount_in_remaining, 750_000);
+    }
+
+    #[test]
+    fn test_match_opposing_sandbox_limit_orders() {
+        let orders = [
+            sandbox_limit_order(1, weth(), token(), 1_000_000, 2_000_000),
+            sandbox_limit_order(2, token(), weth(), 2_000_000, 800_000),
+            sandbox_limit_order(3, token(), H160::from_low_u64_be(4), 1_000_000, 1_000_000),
+        ];
+
+        let (bundles, unmatched_orders) = match_opposing_sandbox_limit_orders(
+            orders.iter().collect(),
+            weth(),
+            min_fill_percent(),
+            H160::zero(),
+            router(),
+            H160::zero(),
+            U256::zero(),
+            &TransferTaxes::new(),
+        );
+
+        assert_eq!(bundles.len(), 1);
+        assert_eq!(unmatched_orders.len(), 1);
+        assert_eq!(unmatched_orders[0].order_id, H256::from_low_u64_be(3));
+    }
+}
@@ -1,280 +1,233 @@
+pub mod matching;
+pub mod ranking;
+
 use std::{
-    borrow::BorrowMut,
-    collections::{HashMap, HashSet},
+    collections::HashMap,
     ops::{BitAnd, Div, Shl, ShlAssign, Shr, ShrAssign},
     str::FromStr,
-    sync::Arc,
 };
 
-use cfmms::pool::Pool;
-use ethers::{
-    abi::Token,
-    providers::Middleware,
-    types::{H160, H256, U256},
-    utils::keccak256,
-};
+use ethers::types::{H160, H256, U256};
+use thiserror::Error;
 
 use crate::{
-    abi,
-    error::ExecutorError,
-    execution::{self},
+    execution::{self, limit_order_group},
     order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder},
-    routing,
+    pool::{self, AutomatedMarketMaker, Pool},
+    routing::{self, graph::Connectors, tax::TransferTaxes},
 };
 
+//Errors of the Q64.64 fixed point math, where the contract reverts
+#[derive(Error, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixedPointError {
+    #[error("Division by zero")]
+    DivisionByZero,
+    #[error("Fixed point overflow")]
+    Overflow,
+}
+
+//Number of fill amounts that are simulated when searching for the largest partial fill of a sandbox limit order
+pub const FILL_AMOUNT_SEARCH_ITERATIONS: usize = 32;
+
 //Takes a hashmap of market to sandbox limit orders that are ready to execute
-pub async fn simulate_and_batch_sandbox_limit_orders<M: 'static + Middleware>(
+#[allow(clippy::too_many_arguments)]
+pub fn simulate_and_batch_sandbox_limit_orders(
     sandbox_limit_orders: HashMap<H256, &SandboxLimitOrder>,
     simulated_markets: &mut HashMap<U256, HashMap<H160, Pool>>,
     weth: H160,
+    connectors: &Connectors,
+    min_fill_percent: u128,
     executor_address: H160,
     sandbox_limit_order_router: H160,
     wallet_address: H160,
-    middleware: Arc<M>,
-) -> Result<Vec<execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle>, ExecutorError<M>>
-{
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> Vec<execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle> {
     //TODO: update this comment later, but we add order ids to this hashset so that we dont recalc orders for execution viability if they are already in an order group
     // since orders can be affected by multiple markets changing, its possible that the same order is in here twice, hence why we need to check if the order is already
     // in the execution calldata
 
-    let mut sandbox_execution_bundles = vec![];
+    if sandbox_limit_orders.is_empty() {
+        return vec![];
+    }
 
-    //For each order that can execute, add it to the execution calldata, including partial fills
+    //Net opposing orders against each other before routing through the markets, the bundles of matched orders are executed before the residuals are filled
+    let (mut sandbox_execution_bundles, unmatched_orders) =
+        matching::match_opposing_sandbox_limit_orders(
+            sandbox_limit_orders.into_values().collect(),
+            weth,
+            min_fill_percent,
+            executor_address,
+            sandbox_limit_order_router,
+            wallet_address,
+            gas_price,
+            transfer_taxes,
+        );
 
-    for order in sandbox_limit_orders.into_values() {
-        let middleware = middleware.clone();
+    //For each order that can execute, add it to the execution calldata, including partial fills
 
+    //Simulate the most profitable orders first so that they get the first access to the liquidity they share
+    let ranked_orders = ranking::rank_orders_by_profit(unmatched_orders.iter(), |order| {
+        ranking::estimate_sandbox_limit_order_profit(
+            order,
+            simulated_markets,
+            weth,
+            connectors,
+            min_fill_percent,
+            gas_price,
+            transfer_taxes,
+        )
+    });
+
+    for order in ranked_orders {
         //Check if the order can execute within the updated simulated markets
-        if order.can_execute(simulated_markets, weth) {
-            let (mut amounts_in, mut amounts_out, mut route) =
-                match routing::find_best_a_to_b_route(
-                    order.token_in,
-                    order.token_out,
-                    U256::from(order.amount_in_remaining),
-                    simulated_markets,
-                    middleware.clone(),
-                )
-                .await
-                {
-                    Ok((a_to_b_amounts_in, a_to_b_amounts_out, a_to_b_route)) => {
-                        (a_to_b_amounts_in, a_to_b_amounts_out, a_to_b_route)
-                    }
-
-                    Err(_) => (vec![], vec![], vec![]),
-                };
-
-            match routing::find_best_a_to_x_to_b_route(
-                order.token_in,
-                weth,
-                order.token_out,
-                U256::from(order.amount_in_remaining),
+        if order.can_execute(simulated_markets, connectors, min_fill_percent) {
+            //Find the largest amount in that fills at the order price along the best path from a to b through the connector tokens
+            let (fill_amount, (_, amounts_out, route, gas_estimate)) = match find_max_fill_amount(
+                order,
+                connectors,
+                min_fill_percent,
                 simulated_markets,
-                middleware.clone(),
-            )
-            .await
-            {
-                Ok((
-                    a_to_weth_to_b_amounts_in,
-                    a_to_weth_to_b_amounts_out,
-                    a_to_weth_to_b_route,
-                )) => {
-                    if let Some(a_to_weth_to_b_last_amount_out) = a_to_weth_to_b_amounts_out.last()
-                    {
-                        if let Some(a_to_b_last_amount_out) = amounts_out.last() {
-                            if a_to_weth_to_b_last_amount_out > a_to_b_last_amount_out {
-                                (amounts_in, amounts_out, route) = (
-                                    a_to_weth_to_b_amounts_in,
-                                    a_to_weth_to_b_amounts_out,
-                                    a_to_weth_to_b_route,
-                                )
-                            }
-                        }
-                    }
+                weth,
+                gas_price,
+                transfer_taxes,
+            ) {
+                Ok(Some(fill)) => fill,
+                Ok(None) => continue,
+                Err(err) => {
+                    tracing::warn!(
+                        "Skipping sandbox limit order {:?}: {:?}",
+                        order.order_id,
+                        err
+                    );
+                    continue;
                 }
-
-                Err(_) => {}
             };
 
             let last_amount_out = amounts_out.last().unwrap();
 
-            //:: If that amount out is greater than or equal to the amount out min of the order update the pools along the route and add the order Id to the order group read for execution
-            if last_amount_out.as_u128() >= order.amount_out_remaining {
+            //The owner payout and the execution fee are pro-rated by the fill amount like the contract does on partial fills
+            let (amount_due_to_owner, execution_fee) = match (
+                order.amount_out_required(fill_amount),
+                order.execution_fee(fill_amount),
+            ) {
+                (Ok(amount_due_to_owner), Ok(execution_fee)) => {
+                    (amount_due_to_owner, execution_fee)
+                }
+                (Err(err), _) | (_, Err(err)) => {
+                    tracing::warn!(
+                        "Skipping sandbox limit order {:?}: {:?}",
+                        order.order_id,
+                        err
+                    );
+                    continue;
+                }
+            };
+
+            //:: If that amount out is greater than or equal to the amount due to the owner update the pools along the route and add the order Id to the order group read for execution
+            if *last_amount_out >= amount_due_to_owner {
                 if order.token_out == weth {
-                    if last_amount_out.as_u128() - order.amount_out_remaining > order.fee_remaining
+                    if *last_amount_out - amount_due_to_owner
+                        > execution_fee + gas_estimate * gas_price
                     {
-                        routing::update_pools_along_route(
+                        if let Err(err) = routing::update_pools_along_route(
                             order.token_in,
-                            U256::from(order.amount_in_remaining),
+                            fill_amount,
                             simulated_markets,
                             route.clone(),
-                            middleware,
-                        )
-                        .await?;
-
-                        //Construct call for execution
-                        let mut execution_bundle =
-                            execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle::new();
-                        execution_bundle.add_order_id_to_current_bundle(order.order_id);
-                        execution_bundle.add_fill_amount(order.amount_in_remaining);
-
-                        //If the pool is v2, add the pool address as the first transfer address
-                        match route[0] {
-                            Pool::UniswapV2(uniswap_v2_pool) => {
-                                execution_bundle.add_transfer_address(uniswap_v2_pool.address);
-                            }
-                            _ => {}
+                            transfer_taxes,
+                        ) {
+                            tracing::warn!(
+                                "Skipping sandbox limit order {:?}: {:?}",
+                                order.order_id,
+                                err
+                            );
+                            continue;
                         }
 
-                        //TODO: have to fix the to address for v3
-                        //Add the route to the calls
-                        execution_bundle.add_route_to_calls(
-                            route,
-                            &amounts_in,
-                            &amounts_out,
+                        let mut execution_bundle = new_route_execution_bundle(
                             order,
+                            fill_amount,
+                            route,
+                            gas_estimate,
                             sandbox_limit_order_router,
                         );
-
-                        // FIXME: we are using the mul_64_u function to calc the amount sent to the user, but in the future the contract will change
-                        // Where we will only calc this value on partial fills
-                        // Add a call to send the exact amount to the user
-
-                        let amount_due_to_owner = calculate_amount_due_to_order_owner(
-                            U256::from(order.amount_in_remaining),
-                            U256::from(order.amount_out_remaining),
-                        );
-
-                        //FIXME: corresponds with order above
-                        execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
-                            order.token_out,
-                            abi::IERC20_ABI
-                                .function("transfer")
-                                .unwrap()
-                                .encode_input(&[
-                                    Token::Address(order.owner),
-                                    Token::Uint(amount_due_to_owner),
-                                ])
-                                .expect("Could not encode Weth transfer inputs"),
-                        ));
-
-                        //pay protocol fee
-                        execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
+                        add_payout_calls(
+                            &mut execution_bundle,
+                            order,
+                            amount_due_to_owner,
+                            execution_fee,
+                            *last_amount_out - amount_due_to_owner,
                             weth,
-                            abi::IERC20_ABI
-                                .function("transfer")
-                                .unwrap()
-                                .encode_input(&[
-                                    Token::Address(executor_address),
-                                    Token::Uint(U256::from(order.fee_remaining)),
-                                ])
-                                .expect("Could not encode Weth transfer inputs"),
-                        ));
-
-                        if *last_amount_out > U256::from(order.fee_remaining) + amount_due_to_owner
-                        {
-                            //pay coex the remainder
-                            execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
-                                weth,
-                                abi::IERC20_ABI
-                                    .function("transfer")
-                                    .unwrap()
-                                    .encode_input(&[
-                                        Token::Address(wallet_address),
-                                        Token::Uint(
-                                            last_amount_out
-                                                - (U256::from(order.fee_remaining)
-                                                    + amount_due_to_owner),
-                                        ),
-                                    ])
-                                    .expect("Could not encode Weth transfer inputs"),
-                            ));
+                            executor_address,
+                            wallet_address,
+                        );
 
-                            sandbox_execution_bundles.push(execution_bundle);
-                        }
+                        sandbox_execution_bundles.push(execution_bundle);
                     }
                 } else {
-                    // FIXME: we are using the mul_64_u function to calc the amount sent to the user, but in the future the contract will change
-                    // Where we will only calc this value on partial fills
-                    // Add a call to send the exact amount to the user
-                    let amount_due_to_owner = calculate_amount_due_to_order_owner(
-                        U256::from(order.amount_in_remaining),
-                        U256::from(order.amount_out_remaining),
-                    );
-
-                    let (amount_in_to_weth_exit, weth_exit_amount_out, weth_exit_pool) =
-                        routing::find_best_weth_exit_from_route(
-                            order,
-                            amount_due_to_owner,
-                            route.clone(),
-                            simulated_markets,
-                            weth,
-                            middleware.clone(),
-                        )
-                        .await?;
+                    //Orders whose token out has no weth market can not pay the execution fee in weth
+                    let (
+                        amount_in_to_weth_exit,
+                        weth_exit_amount_out,
+                        weth_exit_pool,
+                        weth_exit_gas_estimate,
+                    ) = match routing::find_best_weth_exit_from_route(
+                        order,
+                        fill_amount,
+                        amount_due_to_owner,
+                        route.clone(),
+                        simulated_markets,
+                        weth,
+                        transfer_taxes,
+                    ) {
+                        Ok(weth_exit) => weth_exit,
+                        Err(err) => {
+                            tracing::warn!(
+                                "Skipping sandbox limit order {:?}: {:?}",
+                                order.order_id,
+                                err
+                            );
+                            continue;
+                        }
+                    };
 
-                    if weth_exit_amount_out.as_u128() > order.fee_remaining {
-                        routing::update_pools_along_route_with_weth_exit(
+                    if weth_exit_amount_out
+                        > execution_fee + (gas_estimate + weth_exit_gas_estimate) * gas_price
+                    {
+                        if let Err(err) = routing::update_pools_along_route_with_weth_exit(
                             order,
+                            fill_amount,
                             amount_in_to_weth_exit,
                             route.clone(),
                             simulated_markets,
-                            weth,
-                            weth_exit_pool.address(),
-                            middleware,
-                        )
-                        .await?;
-
-                        //Construct call for execution
-                        let mut execution_bundle =
-                            execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle::new();
-                        execution_bundle.add_order_id_to_current_bundle(order.order_id);
-                        execution_bundle.add_fill_amount(order.amount_in_remaining);
-
-                        //If the pool is v2, add the pool address as the first transfer address
-                        match route[0] {
-                            Pool::UniswapV2(uniswap_v2_pool) => {
-                                execution_bundle.add_transfer_address(uniswap_v2_pool.address);
-                            }
-                            _ => {}
+                            &weth_exit_pool,
+                            transfer_taxes,
+                        ) {
+                            tracing::warn!(
+                                "Skipping sandbox limit order {:?}: {:?}",
+                                order.order_id,
+                                err
+                            );
+                            continue;
                         }
 
-                        execution_bundle.add_route_to_calls(
-                            route,
-                            &amounts_in,
-                            &amounts_out,
+                        let mut execution_bundle = new_route_execution_bundle(
                             order,
+                            fill_amount,
+                            route,
+                            gas_estimate + weth_exit_gas_estimate,
                             sandbox_limit_order_router,
                         );
 
-                        //FIXME: corresponds with order above
-                        execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
-                            order.token_out,
-                            abi::IERC20_ABI
-                                .function("transfer")
-                                .unwrap()
-                                .encode_input(&[
-                                    Token::Address(order.owner),
-                                    Token::Uint(amount_due_to_owner),
-                                ])
-                                .expect("Could not encode Weth transfer inputs"),
-                        ));
-
-                        if let Pool::UniswapV2(_) = weth_exit_pool {
-                            //Transfer the amount in to the weth exit pool
-                            execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
+                        //Swap the amount left after paying the owner to weth, transferring it to the weth exit pool first if the pool is paid before the swap
+                        if weth_exit_pool.transfer_before_swap() {
+                            execution_bundle.add_call(pool::transfer_call(
                                 order.token_out,
-                                abi::IERC20_ABI
-                                    .function("transfer")
-                                    .unwrap()
-                                    .encode_input(&[
-                                        Token::Address(weth_exit_pool.address()),
-                                        Token::Uint(amount_in_to_weth_exit),
-                                    ])
-                                    .expect("Could not encode Weth transfer inputs"),
+                                weth_exit_pool.address(),
+                                amount_in_to_weth_exit,
                             ));
                         }
-
-                        //swap to weth exit
                         execution_bundle.add_swap_to_calls(
                             order.token_out,
                             amount_in_to_weth_exit,
@@ -284,31 +237,16 @@ pub async fn simulate_and_batch_sandbox_limit_orders<M: 'static + Middleware>(
                             &weth_exit_pool,
                         );
 
-                        //pay protocol fee
-                        execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
-                            weth,
-                            abi::IERC20_ABI
-                                .function("transfer")
-                                .unwrap()
-                                .encode_input(&[
-                                    Token::Address(executor_address),
-                                    Token::Uint(U256::from(order.fee_remaining)),
-                                ])
-                                .expect("Could not encode Weth transfer inputs"),
-                        ));
-
-                        //Send remainder to coex
-                        execution_bundle.add_call(execution::sandbox_limit_order::Call::new(
+                        add_payout_calls(
+                            &mut execution_bundle,
+                            order,
+                            amount_due_to_owner,
+                            execution_fee,
+                            weth_exit_amount_out,
                             weth,
-                            abi::IERC20_ABI
-                                .function("transfer")
-                                .unwrap()
-                                .encode_input(&[
-                                    Token::Address(wallet_address),
-                                    Token::Uint(weth_exit_amount_out - order.fee_remaining),
-                                ])
-                                .expect("Could not encode Weth transfer inputs"),
-                        ));
+                            executor_address,
+                            wallet_address,
+                        );
 
                         sandbox_execution_bundles.push(execution_bundle);
                     }
@@ -319,25 +257,147 @@ pub async fn simulate_and_batch_sandbox_limit_orders<M: 'static + Middleware>(
 
     //When the market is tapped out for the orders, move onto the next market
 
-    Ok(sandbox_execution_bundles)
+    sandbox_execution_bundles
+}
+
+//Starts the bundle of a sandbox limit order that is filled along the route. The fill amount is transferred to the first pool if the pool is paid before the swap,
+//otherwise it is held by the sandbox router, which pays the pools of split hops separately and pays other pools during the swap.
+//Every order needs a transfer address so that the fill amounts and transfer addresses line up when bundles are batched
+fn new_route_execution_bundle(
+    order: &SandboxLimitOrder,
+    fill_amount: U256,
+    route: Vec<routing::Hop>,
+    gas_estimate: U256,
+    sandbox_limit_order_router: H160,
+) -> execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle {
+    let mut execution_bundle =
+        execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle::new();
+    execution_bundle.add_order_id_to_current_bundle(order.order_id);
+    execution_bundle.add_fill_amount(fill_amount.as_u128());
+
+    match route[0].transfer_before_swap_pool() {
+        Some(pool) => execution_bundle.add_transfer_address(pool.address()),
+        None => execution_bundle.add_transfer_address(sandbox_limit_order_router),
+    }
+
+    execution_bundle.add_route_to_calls(route, order, sandbox_limit_order_router);
+    execution_bundle.add_gas_estimate(gas_estimate);
+
+    execution_bundle
+}
+
+//Pays the owner the amount due in the token out, then pays the execution fee from the weth amount out to the executor and the remainder to the coex wallet
+#[allow(clippy::too_many_arguments)]
+fn add_payout_calls(
+    execution_bundle: &mut execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle,
+    order: &SandboxLimitOrder,
+    amount_due_to_owner: U256,
+    execution_fee: U256,
+    weth_amount_out: U256,
+    weth: H160,
+    executor_address: H160,
+    wallet_address: H160,
+) {
+    execution_bundle.add_call(pool::transfer_call(
+        order.token_out,
+        order.owner,
+        amount_due_to_owner,
+    ));
+    execution_bundle.add_call(pool::transfer_call(weth, executor_address, execution_fee));
+
+    if weth_amount_out > execution_fee {
+        execution_bundle.add_call(pool::transfer_call(
+            weth,
+            wallet_address,
+            weth_amount_out - execution_fee,
+        ));
+    }
+}
+
+//Binary searches the largest amount in that fills at the order price along the best route, returning the fill amount and the route simulation.
+//Smaller fills have less price impact, so every amount below the largest fillable amount also fills at the order price
+pub fn find_max_fill_amount(
+    order: &SandboxLimitOrder,
+    connectors: &Connectors,
+    min_fill_percent: u128,
+    markets: &HashMap<U256, HashMap<H160, Pool>>,
+    weth: H160,
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> Result<Option<(U256, routing::RouteSimulation)>, FixedPointError> {
+    let simulate_fill =
+        |fill_amount: U256| -> Result<Option<routing::RouteSimulation>, FixedPointError> {
+            if !order.is_valid_fill(fill_amount)? {
+                return Ok(None);
+            }
+
+            let amount_out_required = order.amount_out_required(fill_amount)?;
+
+            Ok(routing::find_best_route(
+                order.token_in,
+                order.token_out,
+                fill_amount,
+                connectors,
+                markets,
+                weth,
+                gas_price,
+                transfer_taxes,
+            )
+            .ok()
+            .filter(|route_simulation| {
+                route_simulation
+                    .1
+                    .last()
+                    .is_some_and(|amount_out| *amount_out >= amount_out_required)
+            }))
+        };
+
+    //Fill the whole order if the route can
+    let amount_in_remaining = U256::from(order.amount_in_remaining);
+    if let Some(route_simulation) = simulate_fill(amount_in_remaining)? {
+        return Ok(Some((amount_in_remaining, route_simulation)));
+    }
+
+    let mut low = order.min_fill_amount(min_fill_percent)?;
+    let mut best_fill = match simulate_fill(low)? {
+        Some(route_simulation) => (low, route_simulation),
+        None => return Ok(None),
+    };
+    let mut high = amount_in_remaining;
+
+    for _ in 0..FILL_AMOUNT_SEARCH_ITERATIONS {
+        if high - low <= U256::one() {
+            break;
+        }
+
+        let fill_amount = (low + high) / 2;
+        if let Some(route_simulation) = simulate_fill(fill_amount)? {
+            low = fill_amount;
+            best_fill = (fill_amount, route_simulation);
+        } else {
+            high = fill_amount;
+        }
+    }
+
+    Ok(Some(best_fill))
 }
 
 pub fn calculate_amount_due_to_order_owner(
     amount_in_remaining: U256,
     amount_out_remaining: U256,
-) -> U256 {
+) -> Result<U256, FixedPointError> {
     mul_64_u(
-        div_uu(amount_out_remaining, amount_in_remaining),
+        div_uu(amount_out_remaining, amount_in_remaining)?,
         amount_in_remaining,
     )
 }
 
-//x is a 64.64, y is a uint, returns uint
-pub fn mul_64_u(x: u128, y: U256) -> U256 {
+//x is a 64.64, y is a uint, returns uint. Matches mulu from ABDKMath64x64, returning an error where the contract reverts
+pub fn mul_64_u(x: u128, y: U256) -> Result<U256, FixedPointError> {
     let x = U256::from(x);
 
     if y.is_zero() || x.is_zero() {
-        return U256::zero();
+        return Ok(U256::zero());
     }
 
     let lo = x
@@ -348,31 +408,29 @@ pub fn mul_64_u(x: u128, y: U256) -> U256 {
     let mut hi = x.overflowing_mul(y.shr(128)).0;
 
     if hi > U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap() {
-        //TODO: handle this error
-        panic!("hi is <= 0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF");
+        return Err(FixedPointError::Overflow);
     }
 
     hi.shl_assign(64);
 
     if hi > U256::MAX - lo {
-        //TODO: handle this error
-        panic!("hi is <= MAX_128x128 - lo");
+        return Err(FixedPointError::Overflow);
     }
 
-    hi + lo
+    Ok(hi + lo)
 }
 
-pub fn div_uu(x: U256, y: U256) -> u128 {
+//Divides x by y and returns a 64.64. Matches divuu from ABDKMath64x64, returning an error where the contract reverts
+pub fn div_uu(x: U256, y: U256) -> Result<u128, FixedPointError> {
     if y.is_zero() {
-        //TODO: handle this error
-        panic!("y == 0")
+        return Err(FixedPointError::DivisionByZero);
     }
 
     _div_uu(x, y)
 }
 
-fn _div_uu(x: U256, y: U256) -> u128 {
-    let mut answer = U256::zero();
+fn _div_uu(x: U256, y: U256) -> Result<u128, FixedPointError> {
+    let mut answer;
 
     if x <= U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap() {
         answer = x.shl(64).div(y);
@@ -411,170 +469,391 @@ fn _div_uu(x: U256, y: U256) -> u128 {
 
         answer =
             (x.shl(U256::from(255) - msb)) / (((y - U256::one()) >> (msb - U256::from(191))) + 1);
+
+        if answer > U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap() {
+            return Err(FixedPointError::Overflow);
+        }
+
+        let hi = answer * (y.shr(128));
+        let mut lo =
+            answer * y.bitand(U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap());
+
+        let mut xh = x.shr(192);
+        let mut xl = x.shl(64);
+
+        if xl < lo {
+            xh = xh
+                .checked_sub(U256::one())
+                .ok_or(FixedPointError::Overflow)?;
+        }
+
+        //Relies on overflow like the contract
+        xl = xl.overflowing_sub(lo).0;
+        lo = hi.shl(128);
+
+        if xl < lo {
+            xh = xh
+                .checked_sub(U256::one())
+                .ok_or(FixedPointError::Overflow)?;
+        }
+
+        xl = xl.overflowing_sub(lo).0;
+
+        //The contract asserts that the high bits of the remainder are cleared, which always holds
+        if xh != hi.shr(128) {
+            return Err(FixedPointError::Overflow);
+        }
+
+        answer += xl / y;
     }
 
     if answer > U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap() {
-        //TODO: handle this error
-        panic!("overflow in divuu")
+        return Err(FixedPointError::Overflow);
     }
 
-    let hi = answer * (y.shr(128));
-    let mut lo = answer * y.bitand(U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap());
+    Ok(answer.as_u128())
+}
 
-    let mut xh = x.shr(192);
-    let mut xl = x.shl(64);
+//Takes a hashmap of order ids to limit orders that are ready to execute
+pub fn simulate_and_batch_limit_orders(
+    limit_orders: HashMap<H256, &LimitOrder>,
+    simulated_markets: &mut HashMap<U256, HashMap<H160, Pool>>,
+    weth: H160,
+    router_dexes: &[H160],
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> execution::limit_order::LimitOrderExecutionBundle {
+    //Orders share the overhead of the group that they are executed in, so the size of each group is taken from every order at execution price
+    let group_sizes =
+        limit_order_group::group_limit_orders(limit_orders.values().copied().collect())
+            .into_iter()
+            .flat_map(|order_group| {
+                let group_size = order_group.len();
+                order_group
+                    .into_iter()
+                    .map(move |order| (order.order_id, group_size))
+            })
+            .collect::<HashMap<H256, usize>>();
+
+    //Simulate the most profitable orders first so that they get the first access to the liquidity they share
+    let ranked_orders = ranking::rank_orders_by_profit(limit_orders.into_values(), |order| {
+        ranking::estimate_limit_order_profit(
+            order,
+            simulated_markets,
+            weth,
+            router_dexes,
+            group_sizes[&order.order_id],
+            gas_price,
+            transfer_taxes,
+        )
+    });
+
+    let mut executable_orders = vec![];
+    let mut gas_estimates: HashMap<H256, U256> = HashMap::new();
+
+    for order in ranked_orders {
+        //Check if the order can execute within the updated simulated markets
+        if order.can_execute(order.buy, simulated_markets, weth, router_dexes) {
+            //Simulate the order through the same pools as the router so that the simulated markets match the markets after execution
+            let (_, amount_out, route, gas_estimate) = match routing::find_limit_order_route(
+                order,
+                U256::from(order.quantity),
+                weth,
+                router_dexes,
+                simulated_markets,
+                transfer_taxes,
+            ) {
+                Ok(route_simulation) => route_simulation,
+                Err(err) => {
+                    tracing::warn!("Skipping limit order {:?}: {:?}", order.order_id, err);
+                    continue;
+                }
+            };
 
-    if xl < lo {
-        xh -= U256::one();
+            // If that amount out is greater than or equal to the amount out min of the order update the pools along the route and add the order to the orders ready for exectuion
+            if amount_out.last().unwrap().as_u128() >= order.amount_out_min {
+                if let Err(err) = routing::update_pools_along_route(
+                    order.token_in,
+                    U256::from(order.quantity),
+                    simulated_markets,
+                    route,
+                    transfer_taxes,
+                ) {
+                    tracing::warn!("Skipping limit order {:?}: {:?}", order.order_id, err);
+                    continue;
+                }
+
+                executable_orders.push(order);
+                gas_estimates.insert(order.order_id, gas_estimate);
+            }
+        }
     }
 
-    xl = xl.overflowing_sub(lo).0;
-    lo = hi.shl(128);
+    //Group the orders so that every group passes the checks of the router, skipping any group that would still revert
+    let mut execution_calldata = execution::limit_order::LimitOrderExecutionBundle::new();
+    for order_group in limit_order_group::group_limit_orders(executable_orders) {
+        if let Err(err) = limit_order_group::validate_limit_order_group(&order_group) {
+            tracing::warn!("Skipping invalid limit order group: {:?}", err);
+            continue;
+        }
+
+        let mut order_ids = limit_order_group::to_execution_order_ids(&order_group);
+        for order in order_group {
+            order_ids.add_gas_estimate(gas_estimates[&order.order_id]);
+        }
 
-    if xl < lo {
-        xh -= U256::one();
+        execution_calldata.add_order_group(order_ids);
     }
 
-    xl = xl.overflowing_sub(lo).0;
+    execution_calldata
+}
 
-    if xh != hi.shr(128) {
-        //TODO: handle this error
-        panic!("assert(xh == hi >> 128);")
-    }
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
 
-    answer += xl / y;
+    use ethers::types::{H160, H256, U256, U512};
+    use rand::{rngs::StdRng, Rng, SeedableRng};
 
-    if answer > U256::from_str("0xFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFFF").unwrap() {
-        //TODO: handle error
-        panic!("overflow in divuu last");
-    }
+    use crate::{
+        markets::{self, Market},
+        order::sandbox_limit_order::{
+            min_fill_percent_64_x_64, SandboxLimitOrder, DEFAULT_MIN_FILL_PERCENT,
+        },
+        pool::{Pool, UniswapV2Pool},
+        routing::{graph::Connectors, tax::TransferTaxes},
+    };
 
-    answer.as_u128()
-}
+    use super::{div_uu, find_max_fill_amount, mul_64_u, FixedPointError};
 
-fn group_sandbox_limit_orders(
-    sandbox_limit_orders: HashMap<H256, &SandboxLimitOrder>,
-) -> HashMap<U256, Vec<&SandboxLimitOrder>> {
-    let mut grouped_orders: HashMap<U256, Vec<&SandboxLimitOrder>> = HashMap::new();
-    for (_, order) in sandbox_limit_orders {
-        let hash = U256::from_little_endian(&keccak256(
-            vec![
-                order.token_in.as_bytes(),
-                order.token_out.as_bytes(),
-                &order.owner.to_fixed_bytes(),
-            ]
-            .concat(),
-        ));
+    //Number of random inputs that the fixed point math is checked against the reference implementation with
+    const DIFFERENTIAL_TEST_ITERATIONS: usize = 20_000;
 
-        if let Some(order_group) = grouped_orders.get_mut(&hash) {
-            order_group.push(order);
+    //Reference implementation of mulu from ABDKMath64x64, which returns floor(x * y / 2^64) and reverts if it does not fit in 256 bits
+    fn reference_mul_64_u(x: u128, y: U256) -> Result<U256, FixedPointError> {
+        U256::try_from(U256::from(x).full_mul(y) >> 64).map_err(|_| FixedPointError::Overflow)
+    }
+
+    //Reference implementation of divuu from ABDKMath64x64, which returns floor(x * 2^64 / y) and reverts if it does not fit in 128 bits
+    fn reference_div_uu(x: U256, y: U256) -> Result<u128, FixedPointError> {
+        if y.is_zero() {
+            return Err(FixedPointError::DivisionByZero);
+        }
+
+        let answer = (U512::from(x) << 64) / U512::from(y);
+        if answer > U512::from(u128::MAX) {
+            Err(FixedPointError::Overflow)
         } else {
-            grouped_orders.insert(hash, vec![order]);
+            Ok(answer.low_u128())
         }
     }
 
-    grouped_orders
-}
+    //Random number with a random bit length, so that small and large inputs are both covered
+    fn random_u256(rng: &mut StdRng) -> U256 {
+        let bits = rng.gen_range(0..=256);
+        if bits == 0 {
+            return U256::zero();
+        }
 
-fn sort_sandbox_limit_orders_by_amount_in(
-    mut orders_grouped_by_market: HashMap<U256, Vec<&SandboxLimitOrder>>,
-) -> HashMap<U256, Vec<&SandboxLimitOrder>> {
-    //Go through each group of orders and sort it by amount_in
-    for (_, order_group) in orders_grouped_by_market.borrow_mut() {
-        order_group.sort_by(|a, b| a.amount_in_remaining.cmp(&b.amount_in_remaining))
+        U256([rng.gen(), rng.gen(), rng.gen(), rng.gen()]) >> (256 - bits)
     }
-    orders_grouped_by_market
-}
 
-//Takes a hashmap of market to sandbox limit orders that are ready to execute
-pub async fn simulate_and_batch_limit_orders<M: 'static + Middleware>(
-    limit_orders: HashMap<H256, &LimitOrder>,
-    simulated_markets: &mut HashMap<U256, HashMap<H160, Pool>>,
-    weth: H160,
-    middleware: Arc<M>,
-) -> Result<execution::limit_order::LimitOrderExecutionBundle, ExecutorError<M>> {
-    let orders_grouped_by_market = group_limit_orders(limit_orders);
-    let sorted_orders_grouped_by_market = sort_limit_orders_by_amount_in(orders_grouped_by_market);
-    let mut order_ids_in_calldata: HashSet<H256> = HashSet::new();
+    //Powers of two and their neighbours around the boundaries of the fixed point math
+    fn edge_cases() -> Vec<U256> {
+        let mut edge_cases = vec![U256::zero(), U256::MAX];
+        for bits in [1, 63, 64, 65, 127, 128, 129, 191, 192, 193, 255] {
+            let power_of_two = U256::one() << bits;
+            edge_cases.extend([power_of_two - 1, power_of_two, power_of_two + 1]);
+        }
 
-    let mut execution_calldata = execution::limit_order::LimitOrderExecutionBundle::new();
-    // Go through each sorted order group, and simulate the order. If the order can execute, add it to the batch
+        edge_cases
+    }
 
-    for (_, orders) in sorted_orders_grouped_by_market {
-        // Create a new order group which will hold all the order IDs
-        execution_calldata.add_empty_order_group();
+    #[test]
+    fn test_mul_64_u_differential() {
+        let mut rng = StdRng::seed_from_u64(64);
+
+        for x in edge_cases().into_iter().filter(|x| x.bits() <= 128) {
+            for y in edge_cases() {
+                assert_eq!(
+                    mul_64_u(x.as_u128(), y),
+                    reference_mul_64_u(x.as_u128(), y),
+                    "mul_64_u({x}, {y})"
+                );
+            }
+        }
 
-        for order in orders {
-            let middleware = middleware.clone();
+        for _ in 0..DIFFERENTIAL_TEST_ITERATIONS {
+            let x = random_u256(&mut rng).low_u128();
+            let y = random_u256(&mut rng);
+            assert_eq!(
+                mul_64_u(x, y),
+                reference_mul_64_u(x, y),
+                "mul_64_u({x}, {y})"
+            );
+        }
+    }
 
-            // If the order is not already added to calldata, continue simulating and checking for execution
-            if order_ids_in_calldata.get(&order.order_id).is_none() {
-                order_ids_in_calldata.insert(order.order_id);
+    #[test]
+    fn test_div_uu_differential() {
+        let mut rng = StdRng::seed_from_u64(64);
 
-                //Check if the order can execute within the updated simulated markets
-                if order.can_execute(order.buy, simulated_markets, weth) {
-                    let (_, amount_out, route) = routing::find_best_a_to_x_to_b_route(
-                        order.token_in,
-                        weth,
-                        order.token_out,
-                        U256::from(order.quantity),
-                        simulated_markets,
-                        middleware.clone(),
-                    )
-                    .await?;
+        for x in edge_cases() {
+            for y in edge_cases() {
+                assert_eq!(div_uu(x, y), reference_div_uu(x, y), "div_uu({x}, {y})");
+            }
+        }
 
-                    // If that amount out is greater than or equal to the amount out min of the order update the pools along the route and add the order Id to the order group read for exectuion
-                    if amount_out.last().unwrap().as_u128() >= order.amount_out_min {
-                        routing::update_pools_along_route(
-                            order.token_in,
-                            U256::from(order.quantity),
-                            simulated_markets,
-                            route,
-                            middleware,
-                        )
-                        .await?;
+        for _ in 0..DIFFERENTIAL_TEST_ITERATIONS {
+            let x = random_u256(&mut rng);
+            let y = random_u256(&mut rng);
+            assert_eq!(div_uu(x, y), reference_div_uu(x, y), "div_uu({x}, {y})");
+        }
+    }
 
-                        execution_calldata.append_order_id_to_latest_order_group(order.order_id);
-                    }
+    #[test]
+    fn test_q64_64_properties() {
+        let mut rng = StdRng::seed_from_u64(64);
+
+        assert_eq!(
+            div_uu(U256::one(), U256::zero()),
+            Err(FixedPointError::DivisionByZero)
+        );
+        assert_eq!(
+            div_uu(U256::MAX, U256::one()),
+            Err(FixedPointError::Overflow)
+        );
+        assert_eq!(
+            mul_64_u(u128::MAX, U256::MAX),
+            Err(FixedPointError::Overflow)
+        );
+
+        for _ in 0..DIFFERENTIAL_TEST_ITERATIONS {
+            let x = random_u256(&mut rng);
+            let y = random_u256(&mut rng);
+
+            //Multiplying by one is the identity
+            assert_eq!(mul_64_u(1 << 64, x), Ok(x));
+
+            if !x.is_zero() {
+                //A number divided by itself is exactly one
+                assert_eq!(div_uu(x, x), Ok(1 << 64));
+
+                //The amount due to the owner is rounded down, so it never exceeds the amount out remaining
+                if let Ok(price) = div_uu(y, x) {
+                    assert!(mul_64_u(price, x).unwrap() <= y);
                 }
             }
         }
     }
 
-    Ok(execution_calldata)
-}
-
-fn group_limit_orders(limit_orders: HashMap<H256, &LimitOrder>) -> HashMap<U256, Vec<&LimitOrder>> {
-    let mut grouped_orders: HashMap<U256, Vec<&LimitOrder>> = HashMap::new();
-    for (_, order) in limit_orders {
-        let hash = U256::from_little_endian(&keccak256(
-            vec![
-                order.token_in.as_bytes(),
-                order.token_out.as_bytes(),
-                &order.fee_in.to_le_bytes(),
-                &order.fee_out.to_le_bytes(),
-                &[order.taxed as u8],
-            ]
-            .concat(),
-        ));
+    fn token_in() -> H160 {
+        H160::from_low_u64_be(2)
+    }
 
-        if let Some(order_group) = grouped_orders.get_mut(&hash) {
-            order_group.push(order);
-        } else {
-            grouped_orders.insert(hash, vec![order]);
-        }
+    fn token_out() -> H160 {
+        H160::from_low_u64_be(3)
     }
 
-    grouped_orders
-}
+    fn markets() -> HashMap<U256, Market> {
+        let address = H160::from_low_u64_be(100);
+        let pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address,
+            token_a: token_in(),
+            token_a_decimals: 18,
+            token_b: token_out(),
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000,
+            reserve_1: 1_000_000_000,
+            fee: 300,
+        }));
+
+        HashMap::from([(
+            markets::get_market_id(token_in(), token_out()),
+            HashMap::from([(address, pool)]),
+        )])
+    }
 
-fn sort_limit_orders_by_amount_in(
-    mut orders_grouped_by_market: HashMap<U256, Vec<&LimitOrder>>,
-) -> HashMap<U256, Vec<&LimitOrder>> {
-    //Go through each group of orders and sort it by amount_in
-    for (_, order_group) in orders_grouped_by_market.borrow_mut() {
-        order_group.sort_by(|a, b| a.quantity.cmp(&b.quantity))
+    fn sandbox_limit_order(
+        amount_in_remaining: u128,
+        amount_out_remaining: u128,
+    ) -> SandboxLimitOrder {
+        SandboxLimitOrder::new(
+            0,
+            0,
+            0,
+            1_000,
+            amount_in_remaining,
+            amount_out_remaining,
+            0,
+            H160::zero(),
+            token_in(),
+            token_out(),
+            H256::zero(),
+        )
     }
 
-    orders_grouped_by_market
+    #[test]
+    fn test_find_max_fill_amount() {
+        let markets = markets();
+        let connectors = Connectors::new(vec![], 1);
+        let min_fill_percent = min_fill_percent_64_x_64(DEFAULT_MIN_FILL_PERCENT);
+        let transfer_taxes = TransferTaxes::new();
+
+        //Orders that the pool can fill entirely are not partially filled
+        let order = sandbox_limit_order(1_000_000, 990_000);
+        let (fill_amount, _) = find_max_fill_amount(
+            &order,
+            &connectors,
+            min_fill_percent,
+            &markets,
+            H160::zero(),
+            U256::zero(),
+            &transfer_taxes,
+        )
+        .unwrap()
+        .unwrap();
+        assert_eq!(fill_amount, U256::from(1_000_000));
+
+        //Price impact makes the whole order unfillable at a price of 0.99, so the largest fill at the order price is found
+        let order = sandbox_limit_order(100_000_000, 99_000_000);
+        let (fill_amount, (_, amounts_out, _, _)) = find_max_fill_amount(
+            &order,
+            &connectors,
+            min_fill_percent,
+            &markets,
+            H160::zero(),
+            U256::zero(),
+            &transfer_taxes,
+        )
+        .unwrap()
+        .unwrap();
+        assert!(fill_amount > order.min_fill_amount(min_fill_percent).unwrap());
+        assert!(fill_amount < U256::from(order.amount_in_remaining));
+        assert!(*amounts_out.last().unwrap() >= order.amount_out_required(fill_amount).unwrap());
+
+        let next_fill_amount = fill_amount + 1;
+        let next_amount_out = markets::get_best_market_amount_out(
+            token_in(),
+            token_out(),
+            next_fill_amount,
+            &markets,
+        )
+        .unwrap();
+        assert!(next_amount_out < order.amount_out_required(next_fill_amount).unwrap());
+
+        //Orders above the market price cannot be filled at all
+        let order = sandbox_limit_order(1_000_000, 1_000_000);
+        assert!(find_max_fill_amount(
+            &order,
+            &connectors,
+            min_fill_percent,
+            &markets,
+            H160::zero(),
+            U256::zero(),
+            &transfer_taxes,
+        )
+        .unwrap()
+        .is_none());
+    }
 }
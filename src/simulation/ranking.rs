@@ -0,0 +1,250 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use ethers::types::{H160, U256};
+
+use crate::{
+    execution::limit_order::order_gas_overhead,
+    markets,
+    order::{limit_order::LimitOrder, sandbox_limit_order::SandboxLimitOrder},
+    pool::Pool,
+    routing::{self, graph::Connectors, tax::TransferTaxes},
+};
+
+use super::find_max_fill_amount;
+
+//Estimated net profit of executing an order in weth and the weth value of the liquidity that the order consumes from the markets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfitEstimate {
+    pub profit: U256,
+    pub liquidity: U256,
+}
+
+impl ProfitEstimate {
+    //Returns None if the revenue does not cover the cost, since the order is not worth executing
+    pub fn new(revenue: U256, cost: U256, liquidity: U256) -> Option<ProfitEstimate> {
+        if revenue <= cost {
+            return None;
+        }
+
+        Some(ProfitEstimate {
+            profit: revenue - cost,
+            liquidity: liquidity.max(U256::one()),
+        })
+    }
+
+    //Compares the profit per unit of liquidity consumed, cross multiplying instead of dividing so that no precision is lost
+    pub fn cmp_profit_per_liquidity(&self, other: &ProfitEstimate) -> Ordering {
+        self.profit
+            .full_mul(other.liquidity)
+            .cmp(&other.profit.full_mul(self.liquidity))
+    }
+}
+
+//Ranks the orders by estimated profit per unit of liquidity consumed, dropping orders that are not profitable.
+//Orders are simulated greedily in this order, so the most profitable orders get the first access to the liquidity they share with other orders
+pub fn rank_orders_by_profit<T>(
+    orders: impl IntoIterator<Item = T>,
+    estimate_profit: impl Fn(&T) -> Option<ProfitEstimate>,
+) -> Vec<T> {
+    let mut estimated_orders = orders
+        .into_iter()
+        .filter_map(|order| estimate_profit(&order).map(|estimate| (order, estimate)))
+        .collect::<Vec<(T, ProfitEstimate)>>();
+
+    estimated_orders.sort_by(|(_, a), (_, b)| b.cmp_profit_per_liquidity(a));
+
+    estimated_orders
+        .into_iter()
+        .map(|(order, _)| order)
+        .collect()
+}
+
+//The executor is paid the execution credit of the order for the gas of the route that the router takes, along with the gas of the order transfers and its share
+//of the overhead of the group of the size that it is executed in. The order consumes the weth that it routes through
+pub fn estimate_limit_order_profit(
+    order: &LimitOrder,
+    markets: &HashMap<U256, HashMap<H160, Pool>>,
+    weth: H160,
+    router_dexes: &[H160],
+    group_size: usize,
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> Option<ProfitEstimate> {
+    let (_, amounts_out, _, gas_estimate) = routing::find_limit_order_route(
+        order,
+        U256::from(order.quantity),
+        weth,
+        router_dexes,
+        markets,
+        transfer_taxes,
+    )
+    .ok()?;
+
+    if *amounts_out.last()? < U256::from(order.amount_out_min) {
+        return None;
+    }
+
+    //Orders from weth skip the token in to weth hop
+    let liquidity = if order.token_in == weth {
+        U256::from(order.quantity)
+    } else {
+        amounts_out[0]
+    };
+
+    ProfitEstimate::new(
+        U256::from(order.execution_credit),
+        (gas_estimate + order_gas_overhead(group_size)) * gas_price,
+        liquidity,
+    )
+}
+
+//The executor keeps the surplus of the largest fill over the amount due to the owner, after paying the execution fee and the gas of the route.
+//The order consumes the weth value of the fill amount, and orders on tokens without a weth market are ranked last
+pub fn estimate_sandbox_limit_order_profit(
+    order: &SandboxLimitOrder,
+    markets: &HashMap<U256, HashMap<H160, Pool>>,
+    weth: H160,
+    connectors: &Connectors,
+    min_fill_percent: u128,
+    gas_price: U256,
+    transfer_taxes: &TransferTaxes,
+) -> Option<ProfitEstimate> {
+    let (fill_amount, (_, amounts_out, _, gas_estimate)) = find_max_fill_amount(
+        order,
+        connectors,
+        min_fill_percent,
+        markets,
+        weth,
+        gas_price,
+        transfer_taxes,
+    )
+    .ok()??;
+
+    let surplus = *amounts_out.last()? - order.amount_out_required(fill_amount).ok()?;
+    let surplus_value =
+        markets::get_best_market_amount_out(order.token_out, weth, surplus, markets)?;
+    let liquidity = markets::get_best_market_amount_out(order.token_in, weth, fill_amount, markets)
+        .unwrap_or(U256::MAX);
+
+    ProfitEstimate::new(
+        surplus_value,
+        order.execution_fee(fill_amount).ok()? + gas_estimate * gas_price,
+        liquidity,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cmp::Ordering, collections::HashMap};
+
+    use ethers::types::{H160, H256, U256};
+
+    use crate::{
+        execution::limit_order::{LIMIT_ORDER_GAS_OVERHEAD, LIMIT_ORDER_GROUP_GAS_OVERHEAD},
+        markets,
+        order::limit_order::LimitOrder,
+        pool::{Pool, UniswapV2Pool},
+        quoter::UNISWAP_V2_SWAP_GAS_ESTIMATE,
+        routing::tax::TransferTaxes,
+    };
+
+    use super::{estimate_limit_order_profit, rank_orders_by_profit, ProfitEstimate};
+
+    #[test]
+    fn test_profit_estimate() {
+        assert!(ProfitEstimate::new(U256::from(100), U256::from(100), U256::one()).is_none());
+
+        let estimate = ProfitEstimate::new(U256::from(150), U256::from(100), U256::zero()).unwrap();
+        assert_eq!(estimate.profit, U256::from(50));
+        assert_eq!(estimate.liquidity, U256::one());
+
+        //50 per 10 liquidity is more than 60 per 20 liquidity
+        let a = ProfitEstimate::new(U256::from(50), U256::zero(), U256::from(10)).unwrap();
+        let b = ProfitEstimate::new(U256::from(60), U256::zero(), U256::from(20)).unwrap();
+        assert_eq!(a.cmp_profit_per_liquidity(&b), Ordering::Greater);
+        assert_eq!(
+            a.cmp_profit_per_liquidity(
+                &ProfitEstimate::new(U256::from(100), U256::zero(), U256::from(20)).unwrap()
+            ),
+            Ordering::Equal
+        );
+
+        let c = ProfitEstimate::new(U256::MAX, U256::zero(), U256::MAX).unwrap();
+        assert_eq!(c.cmp_profit_per_liquidity(&b), Ordering::Less);
+    }
+
+    #[test]
+    fn test_rank_orders_by_profit() {
+        //(profit, liquidity) of each order, the order with no profit is dropped
+        let orders = vec![(60, 20), (0, 1), (50, 10), (10, 1)];
+
+        let ranked_orders = rank_orders_by_profit(orders, |(profit, liquidity)| {
+            ProfitEstimate::new(U256::from(*profit), U256::zero(), U256::from(*liquidity))
+        });
+
+        assert_eq!(ranked_orders, vec![(10, 1), (50, 10), (60, 20)]);
+    }
+
+    #[test]
+    fn test_estimate_limit_order_profit() {
+        let weth = H160::from_low_u64_be(1);
+        let token_out = H160::from_low_u64_be(2);
+        let pool = Pool::UniswapV2(UniswapV2Pool::new(cfmms::pool::UniswapV2Pool {
+            address: H160::from_low_u64_be(100),
+            token_a: weth,
+            token_a_decimals: 18,
+            token_b: token_out,
+            token_b_decimals: 18,
+            reserve_0: 1_000_000_000_000,
+            reserve_1: 1_000_000_000_000,
+            fee: 300,
+        }));
+        let markets = HashMap::from([(
+            markets::get_market_id(weth, token_out),
+            HashMap::from([(H160::from_low_u64_be(100), pool)]),
+        )]);
+
+        //The execution credit pays for the swap, the order transfers and half of the group overhead
+        let execution_credit = UNISWAP_V2_SWAP_GAS_ESTIMATE as u128
+            + LIMIT_ORDER_GAS_OVERHEAD as u128
+            + LIMIT_ORDER_GROUP_GAS_OVERHEAD as u128 / 2;
+        let order = LimitOrder::new(
+            false,
+            false,
+            false,
+            0,
+            u32::MAX,
+            0,
+            3000,
+            0,
+            0,
+            0,
+            1_000_000,
+            execution_credit,
+            H160::zero(),
+            weth,
+            token_out,
+            H256::zero(),
+        );
+
+        let estimate = |group_size| {
+            estimate_limit_order_profit(
+                &order,
+                &markets,
+                weth,
+                &[H160::zero()],
+                group_size,
+                U256::one(),
+                &TransferTaxes::new(),
+            )
+        };
+
+        //Orders in smaller groups pay a larger share of the group overhead, which leaves no profit
+        assert!(estimate(1).is_none());
+        assert!(estimate(2).is_none());
+        assert_eq!(
+            estimate(4).unwrap().profit,
+            U256::from(LIMIT_ORDER_GROUP_GAS_OVERHEAD / 4)
+        );
+    }
+}
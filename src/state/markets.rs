@@ -1,4 +1,7 @@
-use std::{collections::HashSet, sync::Arc};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
 
 use cfmms::dex::Dex;
 use ethers::{
@@ -6,63 +9,54 @@ use ethers::{
     types::{H160, H256, U256},
 };
 
-use crate::{error::ExecutorError, markets, order::Order};
+use crate::{
+    error::ExecutorError,
+    markets,
+    order::Order,
+    pool::{AlgebraDex, BalancerDex, CurveDex, SolidlyDex, UniswapV2PairFee},
+    routing::graph::Connectors,
+};
 
 use super::State;
 
 impl State {
+    #[allow(clippy::too_many_arguments)]
     pub async fn add_markets_for_order<M: 'static + Middleware>(
         &mut self,
         order: &Order,
         weth: H160,
+        connectors: &Connectors,
         dexes: &[Dex],
+        v2_pair_fees: &HashMap<H160, UniswapV2PairFee>,
+        algebra_dexes: &[AlgebraDex],
+        solidly_dexes: &[SolidlyDex],
+        curve_dexes: &[CurveDex],
+        balancer_dexes: &[BalancerDex],
         middleware: Arc<M>,
     ) -> Result<(), ExecutorError<M>> {
-        let token_in = order.token_in();
-        let token_out = order.token_out();
-
-        if token_in != weth {
-            let a_to_weth_market_id = markets::get_market_id(token_in, weth);
-            if !self.market_id_exists_in_markets(a_to_weth_market_id) {
-                let a_to_weth_market =
-                    markets::get_market(token_in, weth, dexes, middleware.clone()).await?;
-
-                if a_to_weth_market.is_some() {
-                    self.add_market_to_state(a_to_weth_market_id, a_to_weth_market.unwrap());
-                }
-            }
-        }
-        if token_out != weth {
-            let weth_to_b_market_id = markets::get_market_id(weth, token_out);
-            if !self.market_id_exists_in_markets(weth_to_b_market_id) {
-                let weth_to_b_market =
-                    markets::get_market(weth, token_out, dexes, middleware.clone()).await?;
-
-                if weth_to_b_market.is_some() {
-                    self.add_market_to_state(weth_to_b_market_id, weth_to_b_market.unwrap());
+        //Add the markets of every pair that the order can route through, markets between connector tokens are shared across orders
+        for (token_a, token_b) in order.market_pairs(weth, connectors) {
+            let market_id = markets::get_market_id(token_a, token_b);
+            if !self.market_id_exists_in_markets(market_id) {
+                let market = markets::get_market(
+                    token_a,
+                    token_b,
+                    dexes,
+                    v2_pair_fees,
+                    algebra_dexes,
+                    solidly_dexes,
+                    curve_dexes,
+                    balancer_dexes,
+                    middleware.clone(),
+                )
+                .await?;
+
+                if let Some(market) = market {
+                    self.add_market_to_state(market_id, market);
                 }
             }
         }
 
-        //Add a to b market if the order is a sandbox order
-        match order {
-            Order::SandboxLimitOrder(_) => {
-                if token_in != token_out {
-                    let a_to_b_market_id = markets::get_market_id(token_in, token_out);
-                    if !self.market_id_exists_in_markets(a_to_b_market_id) {
-                        let a_to_b_market =
-                            markets::get_market(token_in, token_out, dexes, middleware.clone())
-                                .await?;
-
-                        if a_to_b_market.is_some() {
-                            self.add_market_to_state(a_to_b_market_id, a_to_b_market.unwrap());
-                        }
-                    }
-                }
-            }
-            _ => {}
-        }
-
         Ok(())
     }
 
@@ -78,101 +72,55 @@ impl State {
         self.markets.entry(market_id).or_insert(market.clone());
 
         for (pool_address, _) in market {
-            self.pool_address_to_market_id
-                .insert(pool_address.to_owned(), market_id);
+            self.pool_address_to_market_ids
+                .entry(pool_address.to_owned())
+                .or_default()
+                .insert(market_id);
         }
     }
 
-    pub fn add_order_to_market_to_affected_orders(&mut self, order: &Order, weth: H160) {
-        let token_in = order.token_in();
-        let token_out = order.token_out();
-
-        let a_to_weth_market_id = markets::get_market_id(token_in, weth);
-        if self.markets.get(&a_to_weth_market_id).is_some() {
-            self.market_to_affected_orders
-                .entry(a_to_weth_market_id)
-                .or_insert(HashSet::new())
-                .insert(order.order_id());
-        }
-
-        let weth_to_b_market_id = markets::get_market_id(weth, token_out);
-        if self.markets.get(&weth_to_b_market_id).is_some() {
-            self.market_to_affected_orders
-                .entry(weth_to_b_market_id)
-                .or_insert(HashSet::new())
-                .insert(order.order_id());
-        }
-        //Add order as affected by a to b market if the order is a sandbox order
-        match order {
-            Order::SandboxLimitOrder(_sandbox_limit_order) => {
-                let a_to_b_market_id = markets::get_market_id(token_in, token_out);
-                if self.markets.get(&weth_to_b_market_id).is_some() {
-                    self.market_to_affected_orders
-                        .entry(a_to_b_market_id)
-                        .or_insert(HashSet::new())
-                        .insert(order.order_id());
-                }
+    pub fn add_order_to_market_to_affected_orders(
+        &mut self,
+        order: &Order,
+        weth: H160,
+        connectors: &Connectors,
+    ) {
+        for market_id in order.market_ids(weth, connectors) {
+            if self.markets.contains_key(&market_id) {
+                self.market_to_affected_orders
+                    .entry(market_id)
+                    .or_default()
+                    .insert(order.order_id());
             }
-            _ => {}
         }
     }
 
-    pub fn get_affected_markets_for_order(&mut self, order_id: &H256, weth: H160) -> HashSet<U256> {
+    pub fn get_affected_markets_for_order(
+        &mut self,
+        order_id: &H256,
+        weth: H160,
+        connectors: &Connectors,
+    ) -> HashSet<U256> {
         let mut affected_markets = HashSet::new();
 
         if let Some(order) = self.active_orders.get(order_id) {
-            let token_in = order.token_in();
-            let token_out = order.token_out();
-
-            let a_to_weth_market_id = markets::get_market_id(token_in, weth);
-            let weth_to_b_market_id = markets::get_market_id(weth, token_out);
-
-            affected_markets.insert(weth_to_b_market_id);
-            affected_markets.insert(a_to_weth_market_id);
-
-            //Remove order as affected by a to b market if the order is a sandbox order
-            match order {
-                Order::SandboxLimitOrder(_) => {
-                    let a_to_b_market_id = markets::get_market_id(token_in, token_out);
-
-                    affected_markets.insert(a_to_b_market_id);
-                }
-                _ => {}
-            }
+            affected_markets.extend(order.market_ids(weth, connectors));
         }
+
         affected_markets
     }
 
-    pub fn remove_order_from_market_to_affected_orders(&mut self, order_id: &H256, weth: H160) {
+    pub fn remove_order_from_market_to_affected_orders(
+        &mut self,
+        order_id: &H256,
+        weth: H160,
+        connectors: &Connectors,
+    ) {
         if let Some(order) = self.active_orders.get(order_id) {
-            let token_in = order.token_in();
-            let token_out = order.token_out();
-
-            let a_to_weth_market_id = markets::get_market_id(token_in, weth);
-            if let Some(affected_orders) =
-                self.market_to_affected_orders.get_mut(&a_to_weth_market_id)
-            {
-                affected_orders.remove(&order.order_id());
-            }
-
-            let weth_to_b_market_id = markets::get_market_id(weth, token_out);
-            if let Some(affected_orders) =
-                self.market_to_affected_orders.get_mut(&weth_to_b_market_id)
-            {
-                affected_orders.remove(&order.order_id());
-            }
-
-            //Remove order as affected by a to b market if the order is a sandbox order
-            match order {
-                Order::SandboxLimitOrder(_sandbox_limit_order) => {
-                    let a_to_b_market_id = markets::get_market_id(token_in, token_out);
-                    if let Some(affected_orders) =
-                        self.market_to_affected_orders.get_mut(&a_to_b_market_id)
-                    {
-                        affected_orders.remove(&order.order_id());
-                    }
+            for market_id in order.market_ids(weth, connectors) {
+                if let Some(affected_orders) = self.market_to_affected_orders.get_mut(&market_id) {
+                    affected_orders.remove(&order.order_id());
                 }
-                _ => {}
             }
         }
     }
@@ -1,19 +1,21 @@
 pub mod markets;
 pub mod orders;
+pub mod tokens;
 
 use std::{
     collections::{HashMap, HashSet},
     sync::{Arc, Mutex},
 };
 
-use cfmms::{dex::Dex, pool::Pool};
+use cfmms::dex::Dex;
 use ethers::{
-    abi::{decode, ParamType, RawLog},
+    abi::RawLog,
     prelude::EthLogDecode,
     providers::Middleware,
     types::{Log, H160, H256, U256},
 };
-use tracing::info;
+use futures::future::join_all;
+use tracing::{error, info};
 
 use crate::{
     abi::{
@@ -21,18 +23,26 @@ use crate::{
         OrderPartialFilledFilter, OrderPlacedFilter, OrderRefreshedFilter, OrderUpdatedFilter,
     },
     error::ExecutorError,
-    events::BeltEvent,
+    events::{self, BeltEvent},
     markets::Market,
     order::OrderVariant,
+    pool::{
+        AlgebraDex, AutomatedMarketMaker, BalancerDex, CurveDex, Pool, SolidlyDex, UniswapV2PairFee,
+    },
+    routing::{graph::Connectors, tax::TransferTaxes},
+    screening::TokenVerdict,
 };
 
 #[derive(Debug)]
 pub struct State {
     pub active_orders: HashMap<H256, crate::order::Order>, //active orders
     pub pending_order_ids: Arc<Mutex<HashSet<H256>>>,      //pending_order_ids
-    pub pool_address_to_market_id: HashMap<H160, U256>,    //pool_address_to_market_id
-    pub markets: HashMap<U256, Market>,                    //markets
-    pub market_to_affected_orders: HashMap<U256, HashSet<H256>>, //market to affected orders
+    //A Curve pool can be in the market of every pair of its coins, so a pool can map to multiple markets
+    pub pool_address_to_market_ids: HashMap<H160, HashSet<U256>>, //pool_address_to_market_ids
+    pub markets: HashMap<U256, Market>,                           //markets
+    pub market_to_affected_orders: HashMap<U256, HashSet<H256>>,  //market to affected orders
+    pub transfer_taxes: TransferTaxes,                            //transfer taxes of fee on transfer tokens
+    pub token_verdicts: HashMap<H160, TokenVerdict>,              //screening verdicts of order tokens
 }
 
 impl State {
@@ -40,19 +50,30 @@ impl State {
         State {
             active_orders: HashMap::new(),
             pending_order_ids: Arc::new(Mutex::new(HashSet::new())),
-            pool_address_to_market_id: HashMap::new(),
+            pool_address_to_market_ids: HashMap::new(),
             markets: HashMap::new(),
             market_to_affected_orders: HashMap::new(),
+            transfer_taxes: TransferTaxes::new(),
+            token_verdicts: HashMap::new(),
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn handle_order_updates<M: 'static + Middleware>(
         &mut self,
         order_events: Vec<(BeltEvent, Log)>,
         sandbox_limit_order_book_address: H160,
         limit_order_book_address: H160,
+        sandbox_limit_order_router: H160,
+        executor_address: H160,
         weth: H160,
+        connectors: &Connectors,
         dexes: &[Dex],
+        v2_pair_fees: &HashMap<H160, UniswapV2PairFee>,
+        algebra_dexes: &[AlgebraDex],
+        solidly_dexes: &[SolidlyDex],
+        curve_dexes: &[CurveDex],
+        balancer_dexes: &[BalancerDex],
         middleware: Arc<M>,
     ) -> Result<HashSet<U256>, ExecutorError<M>> {
         let mut affected_markets = HashSet::new();
@@ -96,14 +117,37 @@ impl State {
                         )
                         .await?;
 
-                        affected_markets
-                            .extend(self.get_affected_markets_for_order(&order.order_id(), weth));
+                        affected_markets.extend(self.get_affected_markets_for_order(
+                            &order.order_id(),
+                            weth,
+                            connectors,
+                        ));
 
                         //Add markets for order
-                        self.add_markets_for_order(&order, weth, dexes, middleware.clone())
-                            .await?;
+                        self.add_markets_for_order(
+                            &order,
+                            weth,
+                            connectors,
+                            dexes,
+                            v2_pair_fees,
+                            algebra_dexes,
+                            solidly_dexes,
+                            curve_dexes,
+                            balancer_dexes,
+                            middleware.clone(),
+                        )
+                        .await?;
+                        //Screen the tokens of the order now that the markets that hold them are tracked
+                        self.screen_tokens_for_order(
+                            &order,
+                            weth,
+                            sandbox_limit_order_router,
+                            executor_address,
+                            middleware.clone(),
+                        )
+                        .await;
                         //Add order to market to affected orders
-                        self.add_order_to_market_to_affected_orders(&order, weth);
+                        self.add_order_to_market_to_affected_orders(&order, weth, connectors);
                         //Add the order to active orders
                         self.place_order(order);
                     }
@@ -123,7 +167,11 @@ impl State {
                             H256::from(order_id)
                         );
 
-                        self.remove_order_from_market_to_affected_orders(&order_id.into(), weth);
+                        self.remove_order_from_market_to_affected_orders(
+                            &order_id.into(),
+                            weth,
+                            connectors,
+                        );
                         self.remove_order(order_id.into());
                     }
                 }
@@ -151,8 +199,11 @@ impl State {
                         )
                         .await?;
 
-                        affected_markets
-                            .extend(self.get_affected_markets_for_order(&order.order_id(), weth));
+                        affected_markets.extend(self.get_affected_markets_for_order(
+                            &order.order_id(),
+                            weth,
+                            connectors,
+                        ));
 
                         self.update_order(order);
                     }
@@ -171,7 +222,11 @@ impl State {
                             H256::from(order_id)
                         );
 
-                        self.remove_order_from_market_to_affected_orders(&order_id.into(), weth);
+                        self.remove_order_from_market_to_affected_orders(
+                            &order_id.into(),
+                            weth,
+                            connectors,
+                        );
                         self.remove_order(order_id.into());
                     }
                 }
@@ -241,7 +296,22 @@ impl State {
 
                 //Handling these to explicitly handle every BeltEvent. We could also use _=> {} but we are explicitly handling them to make sure we are not missing anything
                 BeltEvent::UniswapV2PoolUpdate => {}
+                BeltEvent::UniswapV2PoolFeeUpdate(_) => {}
                 BeltEvent::UniswapV3PoolUpdate => {}
+                BeltEvent::UniswapV3PoolMint => {}
+                BeltEvent::UniswapV3PoolBurn => {}
+                BeltEvent::AlgebraPoolFee => {}
+                BeltEvent::AlgebraPoolDirectionalFee => {}
+                BeltEvent::SolidlyPoolUpdate => {}
+                BeltEvent::CamelotPoolFeeUpdate => {}
+                BeltEvent::CamelotPoolStableSwapUpdate => {}
+                BeltEvent::CurvePoolTokenExchange => {}
+                BeltEvent::CurvePoolAddLiquidity(_) => {}
+                BeltEvent::CurvePoolRemoveLiquidity(_) => {}
+                BeltEvent::CurvePoolRemoveLiquidityImbalance(_) => {}
+                BeltEvent::BalancerVaultSwap => {}
+                BeltEvent::BalancerVaultPoolBalanceChanged => {}
+                BeltEvent::BalancerPoolSwapFeeUpdate => {}
             }
         }
 
@@ -249,51 +319,26 @@ impl State {
     }
 
     //Returns markets affected
-    pub fn handle_market_updates(&self, pool_events: &[Log]) -> HashSet<U256> {
+    pub fn handle_market_updates(&mut self, pool_events: &[Log]) -> HashSet<U256> {
         let mut markets_updated: HashSet<U256> = HashSet::new();
 
         for event_log in pool_events {
-            if let Some(market_id) = self.pool_address_to_market_id.get(&event_log.address) {
-                if let Some(market) = self.markets.get(market_id) {
-                    markets_updated.insert(*market_id);
-
-                    if let Some(pool) = market.get(&event_log.address) {
-                        match pool {
-                            Pool::UniswapV2(mut uniswap_v2_pool) => {
-                                let log_data = decode(
-                                    &[
-                                        ParamType::Uint(128), //reserve0
-                                        ParamType::Uint(128),
-                                    ],
-                                    &event_log.data,
-                                )
-                                .expect("Could not get log data");
-
-                                uniswap_v2_pool.reserve_0 =
-                                    log_data[0].clone().into_uint().unwrap().as_u128();
-
-                                uniswap_v2_pool.reserve_1 =
-                                    log_data[1].clone().into_uint().unwrap().as_u128();
-                            }
-                            Pool::UniswapV3(mut uniswap_v3_pool) => {
-                                // decode log data, get liquidity and sqrt price
-                                let log_data = decode(
-                                    &[
-                                        ParamType::Int(256),  //amount0
-                                        ParamType::Int(256),  //amount1
-                                        ParamType::Uint(160), //sqrtPriceX96
-                                        ParamType::Uint(128), //liquidity
-                                        ParamType::Int(24),
-                                    ],
-                                    &event_log.data,
-                                )
-                                .expect("Could not get log data");
-
-                                //Update the pool data
-                                uniswap_v3_pool.sqrt_price =
-                                    log_data[2].to_owned().into_uint().unwrap();
-                                uniswap_v3_pool.liquidity =
-                                    log_data[3].to_owned().into_uint().unwrap().as_u128();
+            let pool_address = events::get_pool_address_from_log(event_log);
+
+            if let Some(market_ids) = self.pool_address_to_market_ids.get(&pool_address) {
+                for market_id in market_ids {
+                    if let Some(market) = self.markets.get_mut(market_id) {
+                        if let Some(pool) = market.get_mut(&pool_address) {
+                            match pool.update_from_log(event_log) {
+                                Ok(_) => {
+                                    markets_updated.insert(*market_id);
+                                }
+                                Err(err) => {
+                                    error!(
+                                        "Could not update pool {:?} from log: {:?}",
+                                        pool_address, err
+                                    );
+                                }
                             }
                         }
                     }
@@ -303,4 +348,28 @@ impl State {
 
         markets_updated
     }
+
+    //Fallback for UniswapV2 forks with a fee for each pair that do not log fee changes, the fees are read from every such pair concurrently. Returns markets affected
+    pub async fn refresh_uniswap_v2_fees<M: Middleware>(
+        &mut self,
+        middleware: Arc<M>,
+    ) -> HashSet<U256> {
+        let middleware = &middleware;
+        let synced_fees = join_all(self.markets.iter_mut().flat_map(|(market_id, market)| {
+            market.values_mut().filter_map(move |pool| match pool {
+                Pool::UniswapV2(uniswap_v2_pool) if uniswap_v2_pool.polls_fee() => {
+                    let middleware = middleware.clone();
+                    Some(async move { (*market_id, uniswap_v2_pool.sync_fee(middleware).await) })
+                }
+                _ => None,
+            })
+        }))
+        .await;
+
+        synced_fees
+            .into_iter()
+            .filter(|(_, fee_changed)| *fee_changed)
+            .map(|(market_id, _)| market_id)
+            .collect()
+    }
 }
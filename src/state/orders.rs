@@ -1,4 +1,4 @@
-use ethers::types::H256;
+use ethers::types::{H256, U256};
 
 use crate::order::{self};
 
@@ -6,10 +6,12 @@ use super::State;
 
 impl State {
     pub fn place_order(&mut self, order: order::Order) {
+        self.transfer_taxes.update_from_order(&order);
         self.active_orders.insert(order.order_id(), order);
     }
 
     pub fn update_order(&mut self, order: order::Order) {
+        self.transfer_taxes.update_from_order(&order);
         self.active_orders.insert(order.order_id(), order);
     }
 
@@ -21,18 +23,30 @@ impl State {
         self.active_orders.remove(&order_id);
     }
 
-    //TODO:
+    //Only sandbox limit orders are partially filled, so the amounts remaining of limit orders are not updated
     pub fn partial_fill_order(
         &mut self,
         order_id: H256,
-        _amount_in_remaining: u128,
-        _amount_out_remaining: u128,
-        _execution_credit_remaining: u128,
-        _fee_remaining: u128,
+        amount_in_remaining: u128,
+        amount_out_remaining: u128,
+        execution_credit_remaining: u128,
+        fee_remaining: u128,
     ) {
         if let Some(order) = self.active_orders.get_mut(&order_id) {
             match order {
-                order::Order::SandboxLimitOrder(_sandbox_limit_order) => {}
+                order::Order::SandboxLimitOrder(sandbox_limit_order) => {
+                    //The fill percent accumulates the fraction of the amount in remaining that each partial fill filled
+                    sandbox_limit_order.fill_percent =
+                        sandbox_limit_order.fill_percent_after_fill(U256::from(
+                            sandbox_limit_order
+                                .amount_in_remaining
+                                .saturating_sub(amount_in_remaining),
+                        ));
+                    sandbox_limit_order.amount_in_remaining = amount_in_remaining;
+                    sandbox_limit_order.amount_out_remaining = amount_out_remaining;
+                    sandbox_limit_order.execution_credit_remaining = execution_credit_remaining;
+                    sandbox_limit_order.fee_remaining = fee_remaining;
+                }
 
                 order::Order::LimitOrder(_limit_order) => {}
             }
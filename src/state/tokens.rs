@@ -0,0 +1,96 @@
+use std::sync::Arc;
+
+use ethers::{
+    providers::Middleware,
+    types::{H160, U256},
+};
+
+use crate::{
+    markets,
+    order::Order,
+    pool::AutomatedMarketMaker,
+    screening::{self, TokenVerdict},
+};
+
+use super::State;
+
+impl State {
+    //Screens the tokens of the order that have not been screened yet, by simulating transfers of the order amounts between a pool that holds the token and the router that executes the order.
+    //Tokens that could not be screened because of an error are screened again with the next order on the token
+    pub async fn screen_tokens_for_order<M: Middleware>(
+        &mut self,
+        order: &Order,
+        weth: H160,
+        sandbox_limit_order_router: H160,
+        executor_address: H160,
+        middleware: Arc<M>,
+    ) {
+        let router = match order {
+            Order::SandboxLimitOrder(_) => sandbox_limit_order_router,
+            Order::LimitOrder(_) => executor_address,
+        };
+
+        for (token, amount) in [
+            (order.token_in(), order.amount_in()),
+            (order.token_out(), order.amount_out()),
+        ] {
+            if token == weth || self.token_verdicts.contains_key(&token) {
+                continue;
+            }
+
+            let verdict = if let Some(pool) = self.get_screening_pool(token, weth) {
+                match screening::screen_token(
+                    token,
+                    pool,
+                    router,
+                    U256::from(amount),
+                    middleware.clone(),
+                )
+                .await
+                {
+                    Ok(verdict) => verdict,
+                    Err(err) => {
+                        tracing::warn!("Could not screen token {:?}: {:?}", token, err);
+                        continue;
+                    }
+                }
+            } else {
+                TokenVerdict::Unscreened
+            };
+
+            if !verdict.is_tradable(U256::from(amount)) {
+                tracing::warn!("Token {:?} failed screening: {:?}", token, verdict);
+            }
+
+            self.token_verdicts.insert(token, verdict);
+        }
+    }
+
+    //Returns the address of a pool that holds the token, preferring the pool in the market with weth
+    fn get_screening_pool(&self, token: H160, weth: H160) -> Option<H160> {
+        self.markets
+            .get(&markets::get_market_id(token, weth))
+            .into_iter()
+            .chain(self.markets.values())
+            .flat_map(|market| market.values())
+            .find(|pool| {
+                let (token_a, token_b) = pool.tokens();
+                token_a == token || token_b == token
+            })
+            .map(|pool| pool.address())
+    }
+
+    //Returns false if a token of the order failed screening, or if the order amount of a token is above the transfer limit found when it was screened
+    pub fn order_tokens_are_tradable(&self, order: &Order) -> bool {
+        [
+            (order.token_in(), order.amount_in()),
+            (order.token_out(), order.amount_out()),
+        ]
+        .iter()
+        .all(|(token, amount)| {
+            self.token_verdicts
+                .get(token)
+                .is_none_or(|verdict| verdict.is_tradable(U256::from(*amount)))
+        })
+    }
+}
@@ -8,8 +8,8 @@ use ethers::{
     providers::Middleware,
     signers::LocalWallet,
     types::{
-        transaction::eip2718::TypedTransaction, Bytes, Eip1559TransactionRequest,
-        TransactionRequest, H160, H256,
+        transaction::eip2718::TypedTransaction, Bytes, Eip1559TransactionRequest, Transaction,
+        TransactionRequest, H160, H256, U256,
     },
 };
 use tokio::time::sleep;
@@ -358,6 +358,126 @@ pub async fn fill_and_simulate_legacy_transaction<M: Middleware>(
     Ok(tx)
 }
 
+//Backrun transactions can not be simulated because they depend on state changes from the pending trigger transaction,
+//so the gas limit is the gas estimated by the local simulation of the orders with this margin in percent, like the margin on simulated execution transactions
+pub const BACKRUN_GAS_LIMIT_MARGIN: u64 = 150;
+
+//Returns the gas limit of a backrun transaction from the gas estimated by the local simulation
+pub fn backrun_gas_limit(gas_estimate: U256) -> U256 {
+    gas_estimate * BACKRUN_GAS_LIMIT_MARGIN / 100
+}
+
+//Construct a limit order execution transaction that is priced to land directly after the trigger transaction
+pub async fn construct_lo_backrun_transaction<M: Middleware>(
+    configuration: &config::Config,
+    order_ids: Vec<[u8; 32]>,
+    gas_estimate: U256,
+    trigger_tx: &Transaction,
+    middleware: Arc<M>,
+) -> Result<TypedTransaction, ExecutorError<M>> {
+    let gas_limit = backrun_gas_limit(gas_estimate);
+
+    let calldata = abi::ILimitOrderRouter::new(configuration.limit_order_book, middleware.clone())
+        .execute_limit_orders(order_ids)
+        .calldata()
+        .unwrap();
+
+    fill_backrun_transaction(
+        calldata,
+        configuration.limit_order_book,
+        configuration.wallet_address,
+        configuration.chain,
+        trigger_tx,
+        gas_limit,
+        middleware,
+    )
+    .await
+}
+
+//Construct a sandbox limit order execution transaction that is priced to land directly after the trigger transaction
+pub async fn construct_slo_backrun_transaction<M: Middleware>(
+    configuration: &config::Config,
+    slo_bundle: execution::sandbox_limit_order::SandboxLimitOrderExecutionBundle,
+    trigger_tx: &Transaction,
+    middleware: Arc<M>,
+) -> Result<TypedTransaction, ExecutorError<M>> {
+    let gas_limit = backrun_gas_limit(slo_bundle.multicall_gas_estimate());
+
+    let calldata = abi::ISandboxLimitOrderRouter::new(
+        configuration.sandbox_limit_order_router,
+        middleware.clone(),
+    )
+    .execute_sandbox_multicall(slo_bundle.to_sandbox_multicall())
+    .calldata()
+    .unwrap();
+
+    fill_backrun_transaction(
+        calldata,
+        configuration.sandbox_limit_order_router,
+        configuration.wallet_address,
+        configuration.chain,
+        trigger_tx,
+        gas_limit,
+        middleware,
+    )
+    .await
+}
+
+//Fills a transaction with the same gas pricing as the trigger transaction so that it is ordered directly after the trigger.
+//The transaction is not simulated since it depends on state changes from the pending trigger transaction, the gas limit comes from the local simulation instead.
+pub async fn fill_backrun_transaction<M: Middleware>(
+    calldata: Bytes,
+    to: H160,
+    from: H160,
+    chain: Chain,
+    trigger_tx: &Transaction,
+    gas_limit: U256,
+    middleware: Arc<M>,
+) -> Result<TypedTransaction, ExecutorError<M>> {
+    let mut tx: TypedTransaction = if chain.is_eip1559() {
+        let (max_fee_per_gas, max_priority_fee_per_gas) = match (
+            trigger_tx.max_fee_per_gas,
+            trigger_tx.max_priority_fee_per_gas,
+        ) {
+            (Some(max_fee_per_gas), Some(max_priority_fee_per_gas)) => {
+                (max_fee_per_gas, max_priority_fee_per_gas)
+            }
+            //Legacy trigger transactions pay their entire gas price as priority fee
+            _ => {
+                let gas_price = trigger_tx.gas_price.unwrap_or_default();
+                (gas_price, gas_price)
+            }
+        };
+
+        Eip1559TransactionRequest::new()
+            .data(calldata)
+            .to(to)
+            .from(from)
+            .chain_id(chain.chain_id())
+            .max_priority_fee_per_gas(max_priority_fee_per_gas)
+            .max_fee_per_gas(max_fee_per_gas)
+            .gas(gas_limit)
+            .into()
+    } else {
+        TransactionRequest::new()
+            .data(calldata)
+            .to(to)
+            .from(from)
+            .chain_id(chain.chain_id())
+            .gas_price(trigger_tx.gas_price.unwrap_or_default())
+            .gas(gas_limit)
+            .into()
+    };
+
+    //The gas limit and pricing are already set, so this only fills the nonce
+    middleware
+        .fill_transaction(&mut tx, None)
+        .await
+        .map_err(ExecutorError::MiddlewareError)?;
+
+    Ok(tx)
+}
+
 pub fn raw_signed_transaction(tx: TypedTransaction, wallet_key: &LocalWallet) -> Bytes {
     tx.rlp_signed(&wallet_key.sign_transaction_sync(&tx))
 }